@@ -0,0 +1,181 @@
+use crate::normalize_url::normalize_history_url;
+use crate::{read_readable_json, write_readable_json, DataPaths};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+
+#[derive(Args, Debug)]
+#[command(after_help = "Examples:
+  mind-search annotate https://without.boats/blog/pin/ 'the article that finally explained pinning'
+  mind-search annotate --list
+  mind-search annotate --remove https://without.boats/blog/pin/
+
+Then index the changes, which only reindexes the annotated pages:
+    mind-search index-contents --only-new")]
+pub struct AnnotateArguments {
+    /// The URL of the page, which doesn't need to be downloaded
+    #[arg(required_unless_present_any = ["list", "remove"])]
+    url: Option<String>,
+    /// The note, added to the previous ones of the page
+    #[arg(required_unless_present_any = ["list", "remove"])]
+    note: Option<String>,
+    /// List the notes, by page
+    #[arg(long, conflicts_with_all = ["url", "remove"])]
+    list: bool,
+    /// Remove all the notes of this URL
+    #[arg(long, conflicts_with = "url")]
+    remove: Option<String>,
+}
+
+/// A note written about a page
+#[derive(Deserialize, Serialize)]
+struct Annotation {
+    url: String,
+    note: String,
+    created_at: DateTime<Utc>,
+}
+
+/// The content of the annotations file
+#[derive(Deserialize, Serialize, Default)]
+struct Annotations {
+    /// In the order they were written
+    annotations: Vec<Annotation>,
+    /// The URLs whose notes changed since each index was built, by index name
+    #[serde(default)]
+    changed_urls: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub fn annotate(arguments: AnnotateArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let mut annotations = read_annotations(data_paths)?;
+
+    if arguments.list {
+        if annotations.annotations.is_empty() {
+            println!("No annotations, add one with: mind-search annotate URL NOTE");
+        }
+        let mut notes_by_url: BTreeMap<&str, Vec<&Annotation>> = BTreeMap::new();
+        for annotation in &annotations.annotations {
+            notes_by_url
+                .entry(&annotation.url)
+                .or_default()
+                .push(annotation);
+        }
+        for (url, url_annotations) in notes_by_url {
+            println!("{}", url);
+            for annotation in url_annotations {
+                println!(
+                    "  {}: {}",
+                    annotation.created_at.format("%Y-%m-%d"),
+                    annotation.note
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let url = if let Some(url) = &arguments.remove {
+        let url = normalize_annotated_url(url)?;
+        let annotation_count = annotations.annotations.len();
+        annotations
+            .annotations
+            .retain(|annotation| annotation.url != url);
+        let removed = annotation_count - annotations.annotations.len();
+        if removed == 0 {
+            anyhow::bail!("{} has no notes, see them with annotate --list", url);
+        }
+        println!("Removed {} notes of {}", removed, url);
+        url
+    } else {
+        let url = normalize_annotated_url(arguments.url.as_deref().unwrap_or_default())?;
+        let note = arguments.note.unwrap_or_default();
+        if note.trim().is_empty() {
+            anyhow::bail!("the note is empty");
+        }
+        annotations.annotations.push(Annotation {
+            url: url.clone(),
+            note: note.trim().to_string(),
+            created_at: Utc::now(),
+        });
+        println!("Added a note to {}", url);
+        url
+    };
+
+    // The indexes created later index all the notes anyway
+    for index_name in data_paths.list_index_names()? {
+        annotations
+            .changed_urls
+            .entry(index_name)
+            .or_default()
+            .insert(url.clone());
+    }
+    write_annotations(&annotations, data_paths)?;
+    println!("Run index-contents --only-new to search the changes");
+    Ok(())
+}
+
+/// The notes of each annotated URL, in the order they were written
+pub fn read_notes_by_url(data_paths: &DataPaths) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let mut notes_by_url: HashMap<String, Vec<String>> = HashMap::new();
+    for annotation in read_annotations(data_paths)?.annotations {
+        notes_by_url
+            .entry(annotation.url)
+            .or_default()
+            .push(annotation.note);
+    }
+    Ok(notes_by_url)
+}
+
+/// The URLs whose notes changed since the index was built
+pub fn changed_urls(data_paths: &DataPaths, index_name: &str) -> anyhow::Result<BTreeSet<String>> {
+    Ok(read_annotations(data_paths)?
+        .changed_urls
+        .remove(index_name)
+        .unwrap_or_default())
+}
+
+/// Forget that the notes of these URLs changed, once the index has them. With `None`, all the
+/// URLs are forgotten, like after a full run.
+pub fn mark_indexed(
+    data_paths: &DataPaths,
+    index_name: &str,
+    urls: Option<&BTreeSet<String>>,
+) -> anyhow::Result<()> {
+    let mut annotations = read_annotations(data_paths)?;
+    let Some(changed_urls) = annotations.changed_urls.get_mut(index_name) else {
+        return Ok(());
+    };
+    match urls {
+        None => changed_urls.clear(),
+        Some(urls) => changed_urls.retain(|url| !urls.contains(url)),
+    }
+    if changed_urls.is_empty() {
+        annotations.changed_urls.remove(index_name);
+    }
+    write_annotations(&annotations, data_paths)
+}
+
+/// Normalize the URL like the ones of the history, so that the notes find their page
+fn normalize_annotated_url(url: &str) -> anyhow::Result<String> {
+    let mut parsed_url = Url::parse(url).with_context(|| format!("invalid URL {:?}", url))?;
+    normalize_history_url(&mut parsed_url);
+    Ok(parsed_url.to_string())
+}
+
+fn read_annotations(data_paths: &DataPaths) -> anyhow::Result<Annotations> {
+    let path = data_paths.annotations();
+    if !path.exists() {
+        return Ok(Annotations::default());
+    }
+    read_readable_json(&path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn write_annotations(annotations: &Annotations, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let path = data_paths.annotations();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_readable_json(&path, annotations)
+}
@@ -0,0 +1,430 @@
+use crate::download_pages::{
+    download_pages, DownloadPagesArguments, DownloadSummary, QueueOrder, DEFAULT_BUNDLE_SIZE,
+    DEFAULT_DISK_FULL_TIMEOUT_MINUTES, DEFAULT_MIN_FREE_SPACE_MB, DEFAULT_PARALLELISM,
+    DEFAULT_TIMEOUT_SECONDS,
+};
+use crate::extract_firefox_history::extract_firefox_history;
+use crate::index_contents::{index_contents, IndexContentsArguments, IndexSummary};
+use crate::search::{
+    self, open_indexes, read_synonyms, run_search, stream_search, HitStream, OpenedIndex,
+    SearchArguments, SearchQuery,
+};
+use crate::{DataPaths, FirefoxHistoryItem, DEFAULT_INDEX_NAME};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The browser history, as extracted into a data directory
+pub struct History {
+    pub items: Vec<FirefoxHistoryItem>,
+}
+
+impl History {
+    /// Read the history extracted before into the data directory
+    pub fn load(data_dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let data_paths = DataPaths::new(data_dir.into());
+        Ok(History {
+            items: data_paths.read_history()?,
+        })
+    }
+
+    /// Extract the history of a Firefox profile into the data directory. The path of the profile
+    /// is shown in the page "about:profiles" of Firefox.
+    pub fn extract(
+        profile_path: impl Into<PathBuf>,
+        data_dir: impl Into<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let data_dir = data_dir.into();
+        extract_firefox_history(
+            profile_path.into(),
+            false,
+            &DataPaths::new(data_dir.clone()),
+        )?;
+        History::load(data_dir)
+    }
+}
+
+/// How to download the pages of the history
+#[derive(Clone, Debug)]
+pub struct DownloaderConfig {
+    pub data_dir: PathBuf,
+    /// How many requests to do at once
+    pub parallelism: usize,
+    /// How long to wait for each page to answer
+    pub timeout: Duration,
+    /// How many pages to store in each bundle
+    pub bundle_size: usize,
+    /// Whether to try once more the pages that failed with a connection error or a timeout, at
+    /// the end of the run
+    pub final_retry_pass: bool,
+    /// Which pages to download first
+    pub order: QueueOrder,
+    /// Pause the downloads when the disk has less free space than this, in MB
+    pub min_free_space_mb: u64,
+    /// How long to wait for some disk space to be freed before failing
+    pub disk_full_timeout: Duration,
+}
+
+impl DownloaderConfig {
+    /// The same defaults as the command line
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        DownloaderConfig {
+            data_dir: data_dir.into(),
+            parallelism: DEFAULT_PARALLELISM,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+            bundle_size: DEFAULT_BUNDLE_SIZE,
+            final_retry_pass: true,
+            order: QueueOrder::Priority,
+            min_free_space_mb: DEFAULT_MIN_FREE_SPACE_MB,
+            disk_full_timeout: Duration::from_secs(DEFAULT_DISK_FULL_TIMEOUT_MINUTES * 60),
+        }
+    }
+}
+
+/// Downloads the pages of the extracted history
+pub struct Downloader;
+
+impl Downloader {
+    /// Download the pages of the history that were not downloaded yet
+    pub fn run(config: &DownloaderConfig) -> anyhow::Result<DownloadSummary> {
+        let arguments = DownloadPagesArguments {
+            parallelism: config.parallelism,
+            timeout_seconds: config.timeout.as_secs(),
+            bundle_size: config.bundle_size,
+            final_retry_pass: config.final_retry_pass,
+            order: config.order,
+            verbose: false,
+            min_free_space_mb: config.min_free_space_mb,
+            disk_full_timeout_minutes: config.disk_full_timeout.as_secs() / 60,
+        };
+        download_pages(&arguments, &DataPaths::new(config.data_dir.clone()))
+    }
+}
+
+/// How to index the downloaded pages
+#[derive(Clone, Debug)]
+pub struct IndexerConfig {
+    pub data_dir: PathBuf,
+    /// The name of the index to create, so that different corpora can be kept apart
+    pub index_name: String,
+    /// When a page doesn't declare its publication date, use dates in the URL path like
+    /// "/2021/05/12/"
+    pub infer_date_from_url: bool,
+    /// Remove the lines repeated across many pages of the same site, like headers and footers
+    pub strip_repeated_boilerplate: bool,
+    /// Merge the index segments into one at the end, which makes the first queries faster
+    pub optimize: bool,
+    /// Index every download of each URL as a separate version, instead of only the newest one
+    pub keep_versions: bool,
+}
+
+impl IndexerConfig {
+    /// The same defaults as the command line
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        IndexerConfig {
+            data_dir: data_dir.into(),
+            index_name: DEFAULT_INDEX_NAME.to_string(),
+            infer_date_from_url: false,
+            strip_repeated_boilerplate: false,
+            optimize: false,
+            keep_versions: false,
+        }
+    }
+}
+
+/// Indexes the downloaded pages for search
+pub struct Indexer;
+
+impl Indexer {
+    /// Build the index again from all the downloaded pages
+    pub fn run(config: &IndexerConfig) -> anyhow::Result<IndexSummary> {
+        let mut options = vec![format!("--index-name={}", config.index_name)];
+        for (enabled, flag) in [
+            (config.infer_date_from_url, "--infer-date-from-url"),
+            (
+                config.strip_repeated_boilerplate,
+                "--strip-repeated-boilerplate",
+            ),
+            (config.optimize, "--optimize"),
+            (config.keep_versions, "--keep-versions"),
+        ] {
+            if enabled {
+                options.push(flag.to_string());
+            }
+        }
+        let arguments = IndexContentsArguments::parse_options(options.iter().map(String::as_str))?;
+        index_contents(arguments, &DataPaths::new(config.data_dir.clone()))
+    }
+}
+
+/// What to keep of the matches of a query
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// How many results to return
+    pub limit: usize,
+    /// How many of the best results to skip, to get the next page of results
+    pub offset: usize,
+    /// Only return pages of this site, like "docs.rs"
+    pub site: Option<String>,
+    /// Only return pages last visited on or after this day
+    pub after: Option<NaiveDate>,
+    /// Only return pages last visited before this day
+    pub before: Option<NaiveDate>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            limit: 10,
+            offset: 0,
+            site: None,
+            after: None,
+            before: None,
+        }
+    }
+}
+
+/// A page that matches a query
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub url: String,
+    /// The title of the page, or one built from its URL when it has none
+    pub title: Option<String>,
+    pub last_visit: Option<DateTime<Utc>>,
+    /// The part of the content with the matches
+    pub snippet: String,
+    /// The relevance score, higher for better matches
+    pub score: Option<f32>,
+}
+
+impl From<search::SearchHit> for SearchHit {
+    fn from(hit: search::SearchHit) -> Self {
+        SearchHit {
+            url: hit.url,
+            title: hit.title.or(hit.synthetic_title),
+            last_visit: hit.last_visit,
+            snippet: hit.snippet.text,
+            score: hit.score,
+        }
+    }
+}
+
+/// Searches an index, which is kept open between the queries
+///
+/// ```no_run
+/// use mind_search::{SearchOptions, Searcher};
+///
+/// let searcher = Searcher::open("data", "default")?;
+/// let options = SearchOptions {
+///     site: Some("docs.rs".to_string()),
+///     ..SearchOptions::default()
+/// };
+/// for hit in searcher.query("borrow of moved value", &options)? {
+///     println!("{} {}", hit.url, hit.title.unwrap_or_default());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Searcher {
+    data_paths: DataPaths,
+    indexes: Vec<OpenedIndex>,
+}
+
+impl Searcher {
+    /// Open the index with this name in the data directory, "default" unless another name was
+    /// given to the indexer
+    pub fn open(data_dir: impl Into<PathBuf>, index_name: &str) -> anyhow::Result<Self> {
+        let data_paths = DataPaths::new(data_dir.into());
+        let index_option = format!("--index-name={}", index_name);
+        let indexes = open_indexes(
+            &SearchArguments::parse_options([index_option.as_str()])?,
+            &data_paths,
+        )?;
+        Ok(Searcher {
+            data_paths,
+            indexes,
+        })
+    }
+
+    /// Return the best matches of the query, written like in the command line. For example,
+    /// `"moved value" site:docs.rs -async`
+    ///
+    /// ```no_run
+    /// # use mind_search::{SearchOptions, Searcher};
+    /// # let searcher = Searcher::open("data", "default")?;
+    /// let hits = searcher.query("tokio runtime", &SearchOptions::default())?;
+    /// if let Some(best_hit) = hits.first() {
+    ///     println!("{}: {}", best_hit.url, best_hit.snippet);
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn query(&self, query: &str, options: &SearchOptions) -> anyhow::Result<Vec<SearchHit>> {
+        let results = run_search(
+            &self.indexes,
+            &SearchQuery::Text(query.to_string()),
+            &search_arguments(options)?,
+            &self.data_paths,
+        )?;
+        Ok(results.hits.into_iter().map(SearchHit::from).collect())
+    }
+
+    /// Iterate over all the matches of the query, in the same order as [Searcher::query], reading
+    /// them from the index only as they are needed. The limit and the offset of the options are
+    /// ignored, use [Iterator::take] and [Iterator::skip] instead. The stream keeps reading the
+    /// index as it was when the stream started, even if the index is updated meanwhile.
+    ///
+    /// The snapshots of the same page under different URLs are collapsed at the rank of the best
+    /// one, like in [Searcher::query]. But since the other snapshots only come later, the stream
+    /// gives the best ranked snapshot, where the query gives the most recently visited one.
+    ///
+    /// ```no_run
+    /// # use mind_search::{SearchOptions, Searcher};
+    /// # let searcher = Searcher::open("data", "default")?;
+    /// for hit in searcher.stream("tokio", &SearchOptions::default()) {
+    ///     println!("{}", hit?.url);
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn stream(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> impl Iterator<Item = anyhow::Result<SearchHit>> {
+        // An error to start the stream is its only item
+        let (stream, error) = match self.start_stream(query, options) {
+            Ok(stream) => (Some(stream), None),
+            Err(error) => (None, Some(Err(error))),
+        };
+        error.into_iter().chain(
+            stream
+                .into_iter()
+                .flatten()
+                .map(|hit| hit.map(SearchHit::from)),
+        )
+    }
+
+    fn start_stream(&self, query: &str, options: &SearchOptions) -> anyhow::Result<HitStream> {
+        let arguments = search_arguments(options)?;
+        let query = match read_synonyms(&arguments, &self.data_paths)? {
+            Some(synonyms) => synonyms.expand(query),
+            None => query.to_string(),
+        };
+        stream_search(&self.indexes, &SearchQuery::Text(query), &arguments)
+    }
+}
+
+/// The search arguments of the command line with the same options
+fn search_arguments(options: &SearchOptions) -> anyhow::Result<SearchArguments> {
+    let mut search_options = vec![
+        format!("--limit={}", options.limit),
+        format!("--offset={}", options.offset),
+    ];
+    if let Some(site) = &options.site {
+        search_options.push(format!("--site={}", site));
+    }
+    if let Some(after) = options.after {
+        search_options.push(format!("--after={}", after));
+    }
+    if let Some(before) = options.before {
+        search_options.push(format!("--before={}", before));
+    }
+    SearchArguments::parse_options(search_options.iter().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{date, visited_page, TestData};
+
+    /// What tells the hits apart
+    fn describe(hit: &SearchHit) -> (String, Option<DateTime<Utc>>, Option<f32>) {
+        (hit.url.clone(), hit.last_visit, hit.score)
+    }
+
+    fn stream_urls(searcher: &Searcher, query: &str) -> Vec<String> {
+        searcher
+            .stream(query, &SearchOptions::default())
+            .map(|hit| hit.unwrap().url)
+            .collect()
+    }
+
+    fn query_urls(searcher: &Searcher, query: &str) -> Vec<String> {
+        let options = SearchOptions {
+            limit: 10,
+            ..SearchOptions::default()
+        };
+        let hits = searcher.query(query, &options).unwrap();
+        hits.into_iter().map(|hit| hit.url).collect()
+    }
+
+    #[test]
+    fn streams_the_hits_of_the_query() {
+        let data = TestData::new();
+        // Enough matches for a few pages of the stream, with their own scores
+        let mut pages: Vec<_> = (0..1200)
+            .map(|i| {
+                let words = "tokio ".repeat(i % 13 + 1) + &"filler ".repeat(i % 7);
+                visited_page(
+                    &format!("https://example.com/{}", i),
+                    "Page",
+                    &format!("<p>{}</p>", words),
+                )
+            })
+            .collect();
+        // Older snapshots of some pages, which rank lower
+        for i in (0..1200).step_by(97) {
+            let mut older = visited_page(
+                &format!("https://example.com/{}?utm_source=feed", i),
+                "Page",
+                "<p>tokio filler filler filler filler filler filler filler filler</p>",
+            );
+            older.0.last_visit = Some(date(2023, 1, 1));
+            pages.push(older);
+        }
+        data.index_pages(pages, &[]);
+        let searcher = Searcher::open(data.data_paths.data_dir(), DEFAULT_INDEX_NAME).unwrap();
+
+        for limit in [1, 10, 1100] {
+            let options = SearchOptions {
+                limit,
+                ..SearchOptions::default()
+            };
+            let hits = searcher.query("tokio", &options).unwrap();
+            let streamed: Vec<_> = searcher
+                .stream("tokio", &options)
+                .take(limit)
+                .collect::<anyhow::Result<_>>()
+                .unwrap();
+            assert_eq!(hits.len(), limit);
+            assert_eq!(
+                streamed.iter().map(describe).collect::<Vec<_>>(),
+                hits.iter().map(describe).collect::<Vec<_>>()
+            );
+        }
+        let streamed = searcher.stream("tokio", &SearchOptions::default()).count();
+        assert_eq!(streamed, 1200);
+    }
+
+    #[test]
+    fn streams_the_best_ranked_snapshot() {
+        let data = TestData::new();
+        let mut older = visited_page(
+            "https://example.com/post?utm_source=feed",
+            "Post",
+            "<p>tokio tokio tokio</p>",
+        );
+        older.0.last_visit = Some(date(2023, 1, 1));
+        data.index_pages(
+            vec![
+                older,
+                visited_page("https://example.com/post", "Post", "<p>tokio runtime</p>"),
+            ],
+            &[],
+        );
+        let searcher = Searcher::open(data.data_paths.data_dir(), DEFAULT_INDEX_NAME).unwrap();
+        assert_eq!(
+            stream_urls(&searcher, "tokio"),
+            ["https://example.com/post?utm_source=feed"]
+        );
+        assert_eq!(query_urls(&searcher, "tokio"), ["https://example.com/post"]);
+    }
+}
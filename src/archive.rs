@@ -0,0 +1,615 @@
+use crate::index_stats::format_size;
+use crate::integrity::is_temporary_file;
+use crate::{metadata, DataPaths};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tar::{Archive, Builder, EntryType, Header};
+use tracing::info;
+use xxhash_rust::xxh64::Xxh64;
+
+/// The name of the last entry of the archive, which lists the other ones
+const MANIFEST_NAME: &str = "manifest.json";
+/// Incremented when an archive could no longer be imported by older versions
+const FORMAT_VERSION: u32 = 1;
+/// Where the archive is unpacked before its content is moved into the data directory. Hidden, so
+/// that a failed import is not taken for data.
+const IMPORT_DIR_NAME: &str = ".importing";
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Args, Debug)]
+pub struct ExportArchiveArguments {
+    /// The archive to write, like "mind-search.tar.zst"
+    path: PathBuf,
+    /// Leave the indexes out, which makes the archive much smaller. They can be built again with
+    /// index-contents after the import
+    #[arg(long)]
+    no_index: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArchiveArguments {
+    /// The archive written by export-archive
+    path: PathBuf,
+    /// Replace the data that is already in the data directory
+    #[arg(long)]
+    force: bool,
+}
+
+/// Describes the content of the archive, to verify it before importing it
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format_version: u32,
+    /// The version of mind-search that wrote the archive
+    app_version: String,
+    created_at: DateTime<Utc>,
+    includes_index: bool,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct ManifestFile {
+    /// Relative to the data directory, with "/" as separator
+    path: String,
+    size: u64,
+    /// The xxHash64 of the content, in hexadecimal
+    checksum: String,
+}
+
+/// Write the data directory into a tar archive compressed with zstd, listing the files and their
+/// checksums in a manifest at the end
+pub fn export_archive(
+    arguments: ExportArchiveArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let data_dir = data_paths.data_dir();
+    let mut files = Vec::new();
+    list_files(data_dir, &mut files)?;
+    if arguments.no_index {
+        let index_dirs = [
+            data_paths.indexes_dir(),
+            data_paths.legacy_tantivy_index_dir(),
+            data_paths.index_backups_dir(),
+        ];
+        files.retain(|path| !index_dirs.iter().any(|dir| path.starts_with(dir)));
+    }
+    files.retain(|path| !is_excluded(path, data_paths));
+    files.sort();
+
+    let mut total_size = 0;
+    for path in &files {
+        total_size += fs::metadata(path)?.len();
+    }
+    info!(
+        "Exporting {} files with {}",
+        files.len(),
+        format_size(total_size)
+    );
+
+    let archive_file = File::create(&arguments.path)
+        .with_context(|| format!("failed to create {}", arguments.path.display()))?;
+    let mut builder = Builder::new(zstd::Encoder::new(archive_file, 0)?);
+    let mut progress = Progress::new("Exported", total_size);
+    let mut manifest_files = Vec::new();
+    for path in &files {
+        let archive_path = archive_path(path, data_dir)?;
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(metadata.len());
+        header.set_mode(0o644);
+        header.set_mtime(
+            metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+
+        let mut reader = HashingReader::new(file, |read| progress.advance(read as u64));
+        builder
+            .append_data(&mut header, &archive_path, &mut reader)
+            .with_context(|| format!("failed to add {} to the archive", path.display()))?;
+        manifest_files.push(ManifestFile {
+            path: archive_path,
+            size: metadata.len(),
+            checksum: format!("{:016x}", reader.checksum()),
+        });
+    }
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now(),
+        includes_index: !arguments.no_index,
+        files: manifest_files,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, MANIFEST_NAME, manifest.as_slice())?;
+
+    let archive_file = builder.into_inner()?.finish()?;
+    archive_file.sync_all()?;
+    progress.log();
+    println!(
+        "Exported {} files into {} ({})",
+        files.len(),
+        arguments.path.display(),
+        format_size(archive_file.metadata()?.len())
+    );
+    Ok(())
+}
+
+/// Unpack an archive written by export-archive into the data directory, after verifying its
+/// content against its manifest
+pub fn import_archive(
+    arguments: ImportArchiveArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let data_dir = data_paths.data_dir();
+    let existing_entries = existing_entries(data_paths)?;
+    if !existing_entries.is_empty() && !arguments.force {
+        anyhow::bail!(
+            "the data directory {} already has data, run again with --force to replace it",
+            data_dir.display()
+        );
+    }
+
+    let import_dir = data_dir.join(IMPORT_DIR_NAME);
+    if import_dir.exists() {
+        fs::remove_dir_all(&import_dir)?;
+    }
+    let manifest = match unpack_verified(&arguments.path, &import_dir) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            let _ = fs::remove_dir_all(&import_dir);
+            return Err(error.context(format!(
+                "failed to import the archive {}",
+                arguments.path.display()
+            )));
+        }
+    };
+
+    // The archive is whole, so only now is the existing data replaced
+    for path in existing_entries {
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    for maybe_entry in fs::read_dir(&import_dir)? {
+        let entry = maybe_entry?;
+        fs::rename(entry.path(), data_dir.join(entry.file_name()))?;
+    }
+    fs::remove_dir(&import_dir)?;
+
+    println!(
+        "Imported {} files written by mind-search {} {}",
+        manifest.files.len(),
+        manifest.app_version,
+        manifest.created_at.format("on %Y-%m-%d")
+    );
+    if !manifest.includes_index {
+        println!("The archive has no index, build it with index-contents");
+    }
+    Ok(())
+}
+
+/// Unpack the archive into the directory, failing if its content doesn't match its manifest
+fn unpack_verified(path: &Path, import_dir: &Path) -> anyhow::Result<Manifest> {
+    let (manifest, unpacked_files) = unpack(path, import_dir)?;
+    let manifest = manifest.context("the archive has no manifest, it may be truncated")?;
+    if manifest.format_version > FORMAT_VERSION {
+        anyhow::bail!(
+            "the archive was written by mind-search {}, which is too new for this version",
+            manifest.app_version
+        );
+    }
+    verify_files(&manifest, unpacked_files)?;
+    Ok(manifest)
+}
+
+/// Unpack the files of the archive into the directory, returning the manifest and what each file
+/// should be listed as in it
+fn unpack(path: &Path, import_dir: &Path) -> anyhow::Result<(Option<Manifest>, Vec<ManifestFile>)> {
+    let archive_file = File::open(path)?;
+    let mut progress = Progress::new("Imported", archive_file.metadata()?.len());
+    // The progress is measured on the compressed archive, since the total size is only known for it
+    let read_bytes = Cell::new(0);
+    let counting_reader = HashingReader::new(archive_file, |read| {
+        read_bytes.set(read_bytes.get() + read as u64)
+    });
+    let mut archive = Archive::new(zstd::Decoder::new(counting_reader)?);
+
+    let mut manifest = None;
+    let mut unpacked_files = Vec::new();
+    for maybe_entry in archive.entries()? {
+        let mut entry = maybe_entry?;
+        let entry_path = entry.path()?.into_owned();
+        match entry.header().entry_type() {
+            EntryType::Regular => {}
+            EntryType::Directory => continue,
+            entry_type => anyhow::bail!(
+                "unexpected entry {} of type {:?}",
+                entry_path.display(),
+                entry_type
+            ),
+        }
+        let is_safe = entry_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_safe || is_temporary_file(&entry_path) {
+            anyhow::bail!("unexpected entry {}", entry_path.display());
+        }
+
+        if entry_path == Path::new(MANIFEST_NAME) {
+            manifest = Some(serde_json::from_reader(&mut entry).context("invalid manifest")?);
+            continue;
+        }
+        let destination = import_dir.join(&entry_path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&destination)?;
+        let mut reader = HashingReader::new(&mut entry, |_| progress.update(read_bytes.get()));
+        let size = io::copy(&mut reader, &mut file)?;
+        unpacked_files.push(ManifestFile {
+            path: archive_path(&destination, import_dir)?,
+            size,
+            checksum: format!("{:016x}", reader.checksum()),
+        });
+    }
+    progress.update(read_bytes.get());
+    progress.log();
+    Ok((manifest, unpacked_files))
+}
+
+/// Check that the unpacked files are exactly the ones of the manifest
+fn verify_files(manifest: &Manifest, unpacked_files: Vec<ManifestFile>) -> anyhow::Result<()> {
+    let mut unpacked_files: BTreeMap<String, ManifestFile> = unpacked_files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+    for expected in &manifest.files {
+        match unpacked_files.remove(&expected.path) {
+            None => anyhow::bail!("the archive is missing {}", expected.path),
+            Some(unpacked) if unpacked != *expected => {
+                anyhow::bail!(
+                    "the content of {} doesn't match its checksum",
+                    expected.path
+                )
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(unexpected) = unpacked_files.keys().next() {
+        anyhow::bail!("{} is not listed in the manifest", unexpected);
+    }
+    Ok(())
+}
+
+/// The entries of the data directory that an import would replace
+fn existing_entries(data_paths: &DataPaths) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for maybe_entry in fs::read_dir(data_paths.data_dir())? {
+        let path = maybe_entry?.path();
+        // The other workspaces are not part of the archive of the default one
+        if path != data_paths.lock_file()
+            && path != data_paths.servers_lock_file()
+            && path != data_paths.workspaces_dir()
+            && !is_temporary_file(&path)
+        {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+/// List the files in the directory and its subdirectories, skipping the hidden ones
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for maybe_entry in fs::read_dir(dir)? {
+        let entry = maybe_entry?;
+        let path = entry.path();
+        if is_temporary_file(&path) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            list_files(&path, files)?;
+        } else if entry.file_type()?.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file belongs to the machine rather than to the data: the locks, the copy of the
+/// Firefox database and the metadata database, which can be built again. The other workspaces are
+/// exported separately.
+fn is_excluded(path: &Path, data_paths: &DataPaths) -> bool {
+    path == data_paths.lock_file()
+        || path == data_paths.servers_lock_file()
+        || path.starts_with(data_paths.workspaces_dir())
+        || path == data_paths.firefox_database()
+        || metadata::is_database_file(path, data_paths)
+        || (path.starts_with(data_paths.indexes_dir())
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "lock"))
+}
+
+/// The path of the file relative to the directory, as written in the archive
+fn archive_path(path: &Path, dir: &Path) -> anyhow::Result<String> {
+    let relative_path = path.strip_prefix(dir)?;
+    let components: Option<Vec<&str>> = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect();
+    let components =
+        components.with_context(|| format!("{} is not valid UTF-8", relative_path.display()))?;
+    Ok(components.join("/"))
+}
+
+/// Compute the xxHash64 of what is read, telling how many bytes each read returned
+struct HashingReader<R, F> {
+    inner: R,
+    hasher: Xxh64,
+    on_read: F,
+}
+
+impl<R: Read, F: FnMut(usize)> HashingReader<R, F> {
+    fn new(inner: R, on_read: F) -> Self {
+        HashingReader {
+            inner,
+            hasher: Xxh64::new(0),
+            on_read,
+        }
+    }
+
+    fn checksum(&self) -> u64 {
+        self.hasher.digest()
+    }
+}
+
+impl<R: Read, F: FnMut(usize)> Read for HashingReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        (self.on_read)(read);
+        Ok(read)
+    }
+}
+
+/// Log how much of a long operation is done, every few seconds
+struct Progress {
+    verb: &'static str,
+    total: u64,
+    done: u64,
+    last_log: Instant,
+}
+
+impl Progress {
+    fn new(verb: &'static str, total: u64) -> Self {
+        Progress {
+            verb,
+            total,
+            done: 0,
+            last_log: Instant::now(),
+        }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.update(self.done + bytes);
+    }
+
+    fn update(&mut self, done: u64) {
+        self.done = done;
+        if self.last_log.elapsed() >= PROGRESS_INTERVAL {
+            self.log();
+        }
+    }
+
+    fn log(&mut self) {
+        let percent = (self.done * 100).checked_div(self.total).unwrap_or(100);
+        info!(
+            "{} {} out of {} ({}%)",
+            self.verb,
+            format_size(self.done),
+            format_size(self.total),
+            percent
+        );
+        self.last_log = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{visited_page, TestData};
+
+    /// The content of the files that an export takes, by their path in the archive
+    fn data_files(data_paths: &DataPaths) -> BTreeMap<String, Vec<u8>> {
+        let mut files = Vec::new();
+        list_files(data_paths.data_dir(), &mut files).unwrap();
+        files
+            .into_iter()
+            .filter(|path| !is_excluded(path, data_paths))
+            .map(|path| {
+                let archive_path = archive_path(&path, data_paths.data_dir()).unwrap();
+                (archive_path, fs::read(path).unwrap())
+            })
+            .collect()
+    }
+
+    fn indexed_data() -> TestData {
+        let data = TestData::new();
+        data.index_pages(
+            vec![visited_page(
+                "https://example.com/",
+                "Example",
+                "<p>Some text to export</p>",
+            )],
+            &[],
+        );
+        data
+    }
+
+    fn export(data: &TestData, path: &Path, options: &[&str]) {
+        let arguments = ExportArchiveArguments {
+            path: path.to_path_buf(),
+            no_index: options.contains(&"--no-index"),
+        };
+        export_archive(arguments, &data.data_paths).unwrap();
+    }
+
+    fn import(data: &TestData, path: &Path, options: &[&str]) -> anyhow::Result<()> {
+        let arguments = ImportArchiveArguments {
+            path: path.to_path_buf(),
+            force: options.contains(&"--force"),
+        };
+        import_archive(arguments, &data.data_paths)
+    }
+
+    /// Write an archive with the entries, given by the raw bytes of their names, and the manifest
+    fn write_archive(path: &Path, entries: &[(&[u8], &[u8])], manifest: &Manifest) {
+        let mut builder = Builder::new(zstd::Encoder::new(File::create(path).unwrap(), 0).unwrap());
+        let manifest = serde_json::to_vec(manifest).unwrap();
+        let manifest_entry = (MANIFEST_NAME.as_bytes(), manifest.as_slice());
+        for (name, content) in entries.iter().chain([&manifest_entry]) {
+            // Set the name without the checks of the tar crate, to write unsafe paths
+            let mut header = Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name);
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn manifest(files: Vec<ManifestFile>) -> Manifest {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: Utc::now(),
+            includes_index: false,
+            files,
+        }
+    }
+
+    fn manifest_file(path: &str, content: &[u8]) -> ManifestFile {
+        let mut hasher = Xxh64::new(0);
+        hasher.update(content);
+        ManifestFile {
+            path: path.to_string(),
+            size: content.len() as u64,
+            checksum: format!("{:016x}", hasher.digest()),
+        }
+    }
+
+    #[test]
+    fn imports_what_was_exported() {
+        let data = indexed_data();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tar.zst");
+        export(&data, &path, &[]);
+
+        let imported = TestData::new();
+        import(&imported, &path, &[]).unwrap();
+        let files = data_files(&data.data_paths);
+        assert!(files.contains_key("history"));
+        assert!(files.keys().any(|path| path.starts_with("indexes/")));
+        assert_eq!(data_files(&imported.data_paths), files);
+        assert!(!imported
+            .data_paths
+            .data_dir()
+            .join(IMPORT_DIR_NAME)
+            .exists());
+        assert_eq!(
+            imported.search_urls("export", &[]),
+            ["https://example.com/"]
+        );
+    }
+
+    #[test]
+    fn leaves_the_index_out() {
+        let data = indexed_data();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tar.zst");
+        export(&data, &path, &["--no-index"]);
+
+        let imported = TestData::new();
+        import(&imported, &path, &[]).unwrap();
+        let mut files = data_files(&data.data_paths);
+        files.retain(|path, _| !path.starts_with("indexes/"));
+        assert!(files.contains_key("history"));
+        assert_eq!(data_files(&imported.data_paths), files);
+        assert!(!imported.data_paths.indexes_dir().exists());
+    }
+
+    #[test]
+    fn replaces_the_data_only_with_force() {
+        let data = indexed_data();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tar.zst");
+        export(&data, &path, &["--no-index"]);
+
+        let error = import(&data, &path, &[]).unwrap_err();
+        assert!(format!("{:#}", error).contains("run again with --force"));
+        assert!(data.data_paths.indexes_dir().exists());
+
+        import(&data, &path, &["--force"]).unwrap();
+        assert!(!data.data_paths.indexes_dir().exists());
+    }
+
+    #[test]
+    fn refuses_the_corrupted_entries() {
+        let data = indexed_data();
+        let files = data_files(&data.data_paths);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tar.zst");
+        write_archive(
+            &path,
+            &[(b"history", b"corrupted")],
+            &manifest(vec![manifest_file("history", b"original")]),
+        );
+
+        let error = import(&data, &path, &["--force"]).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("the content of history doesn't match its checksum")
+        );
+        assert_eq!(data_files(&data.data_paths), files);
+        assert!(!data.data_paths.data_dir().join(IMPORT_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn refuses_the_paths_out_of_the_data_directory() {
+        let data = TestData::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tar.zst");
+        let content = b"outside";
+        write_archive(
+            &path,
+            &[(b"raw_pages/../../escaped", content)],
+            &manifest(vec![manifest_file("escaped", content)]),
+        );
+
+        let error = import(&data, &path, &[]).unwrap_err();
+        assert!(format!("{:#}", error).contains("unexpected entry raw_pages/../../escaped"));
+        assert!(!dir.path().join("escaped").exists());
+        assert!(!data.data_paths.data_dir().join("escaped").exists());
+    }
+}
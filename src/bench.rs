@@ -0,0 +1,301 @@
+use crate::index_contents::index_pages;
+use crate::search::{open_indexes, run_search, SearchArguments, SearchQuery};
+use crate::{
+    read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent, MissingStep,
+    OutputFormat, DEFAULT_INDEX_NAME,
+};
+use anyhow::Context;
+use clap::{Args, Subcommand};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+use tracing::info;
+use xxhash_rust::xxh64::xxh64;
+
+#[derive(Subcommand, Debug)]
+pub enum BenchCommand {
+    /// Index a random sample of the downloaded pages into a temporary index, reporting the
+    /// documents and megabytes indexed per second
+    Index(BenchIndexArguments),
+    /// Run the queries of a file many times, reporting the latency with and without snippets
+    Search(BenchSearchArguments),
+}
+
+#[derive(Args, Debug)]
+pub struct BenchIndexArguments {
+    /// How many pages to index
+    #[arg(long, default_value_t = 1000)]
+    sample: usize,
+    /// Another seed picks another sample, the same seed picks the same pages again
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Print the results for humans or as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchSearchArguments {
+    /// A file with one query per line. Empty lines and lines starting with "#" are skipped
+    #[arg(long)]
+    queries: PathBuf,
+    /// How many times to run each query
+    #[arg(long, default_value_t = 10)]
+    iterations: usize,
+    /// The name of the index to search
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Print the results for humans or as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+#[derive(Serialize)]
+struct IndexBenchResults {
+    sampled_pages: usize,
+    indexed_documents: usize,
+    content_bytes: u64,
+    elapsed_seconds: f64,
+    documents_per_second: f64,
+    megabytes_per_second: f64,
+}
+
+#[derive(Serialize)]
+struct SearchBenchResults {
+    queries: usize,
+    iterations: usize,
+    with_snippets: Latencies,
+    without_snippets: Latencies,
+}
+
+/// The latencies of the searches, in milliseconds
+#[derive(Serialize)]
+struct Latencies {
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+}
+
+/// A directory removed when dropped, even when the benchmark fails
+struct TemporaryDir(PathBuf);
+
+pub fn bench(command: BenchCommand, data_paths: &DataPaths) -> anyhow::Result<()> {
+    match command {
+        BenchCommand::Index(arguments) => bench_index(arguments, data_paths),
+        BenchCommand::Search(arguments) => bench_search(arguments, data_paths),
+    }
+}
+
+fn bench_index(arguments: BenchIndexArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let pages = sample_pages(arguments.sample, arguments.seed, data_paths)?;
+    let content_bytes = pages
+        .iter()
+        .map(|(_, _, page)| content_len(&page.content) as u64)
+        .sum();
+    let sampled_pages = pages.len();
+    info!("Indexing a sample of {} pages", sampled_pages);
+
+    let index_dir = TemporaryDir::create()?;
+    let start = Instant::now();
+    let indexed_documents = index_pages(pages, &index_dir.0, data_paths)?;
+    let elapsed = start.elapsed().as_secs_f64();
+    drop(index_dir);
+
+    let results = IndexBenchResults {
+        sampled_pages,
+        indexed_documents,
+        content_bytes,
+        elapsed_seconds: elapsed,
+        documents_per_second: indexed_documents as f64 / elapsed,
+        megabytes_per_second: content_bytes as f64 / 1024. / 1024. / elapsed,
+    };
+    match arguments.format {
+        OutputFormat::Human => {
+            println!(
+                "Indexed {} documents of {} sampled pages in {:.2}s",
+                results.indexed_documents, results.sampled_pages, results.elapsed_seconds
+            );
+            println!("{:<12} {:>10}", "documents/s", "MB/s");
+            println!(
+                "{:<12.1} {:>10.2}",
+                results.documents_per_second, results.megabytes_per_second
+            );
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&results)?),
+    }
+    Ok(())
+}
+
+fn bench_search(arguments: BenchSearchArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let content = fs::read_to_string(&arguments.queries)
+        .with_context(|| format!("failed to read {}", arguments.queries.display()))?;
+    let queries: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    if queries.is_empty() {
+        anyhow::bail!("no queries in {}", arguments.queries.display());
+    }
+    if arguments.iterations == 0 {
+        anyhow::bail!("--iterations must be at least 1");
+    }
+
+    let index_option = format!("--index-name={}", arguments.index_name);
+    let with_snippets = SearchArguments::parse_options([index_option.as_str()])?;
+    let without_snippets = SearchArguments::parse_options([index_option.as_str(), "--quiet"])?;
+    let indexes = open_indexes(&with_snippets, data_paths)?;
+
+    let measure = |search_arguments: &SearchArguments| -> anyhow::Result<Latencies> {
+        // A first pass, so that the index files are in the page cache
+        for query in &queries {
+            let query = SearchQuery::Text(query.to_string());
+            run_search(&indexes, &query, search_arguments, data_paths)?;
+        }
+        let mut latencies = Vec::with_capacity(queries.len() * arguments.iterations);
+        for _ in 0..arguments.iterations {
+            for query in &queries {
+                let query = SearchQuery::Text(query.to_string());
+                let start = Instant::now();
+                run_search(&indexes, &query, search_arguments, data_paths)?;
+                latencies.push(start.elapsed());
+            }
+        }
+        Ok(Latencies::of(latencies))
+    };
+    let results = SearchBenchResults {
+        queries: queries.len(),
+        iterations: arguments.iterations,
+        with_snippets: measure(&with_snippets)?,
+        without_snippets: measure(&without_snippets)?,
+    };
+
+    match arguments.format {
+        OutputFormat::Human => {
+            println!(
+                "Ran {} queries {} times each",
+                results.queries, results.iterations
+            );
+            println!("{:<18} {:>9} {:>9} {:>9}", "", "p50", "p95", "p99");
+            for (name, latencies) in [
+                ("with snippets", &results.with_snippets),
+                ("without snippets", &results.without_snippets),
+            ] {
+                println!(
+                    "{:<18} {:>7.2}ms {:>7.2}ms {:>7.2}ms",
+                    name, latencies.p50_ms, latencies.p95_ms, latencies.p99_ms
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&results)?),
+    }
+    Ok(())
+}
+
+/// The pages with the smallest hashes of their URL, which is a random sample that only depends on
+/// the seed and the downloaded pages
+fn sample_pages(
+    sample: usize,
+    seed: u64,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Vec<(PathBuf, usize, DownloadedPage)>> {
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    if bundles.is_empty() {
+        return Err(MissingStep {
+            missing: format!(
+                "no downloaded pages in {}",
+                data_paths.raw_pages_dir().display()
+            ),
+            command: "download-pages".to_string(),
+        }
+        .into());
+    }
+
+    // A first pass only keeps the hashes, to not hold all the pages in memory
+    let hashes: Vec<Vec<(u64, usize, usize)>> = bundles
+        .par_iter()
+        .enumerate()
+        .map(|(bundle_index, bundle)| -> anyhow::Result<_> {
+            let pages: Vec<DownloadedPage> = read_compressed_json(bundle)?;
+            Ok(pages
+                .iter()
+                .enumerate()
+                .filter(|(_, page)| content_len(&page.content) > 0)
+                .map(|(record, page)| (xxh64(page.url.as_bytes(), seed), bundle_index, record))
+                .collect())
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let mut hashes: Vec<_> = hashes.into_iter().flatten().collect();
+    hashes.sort_unstable();
+    hashes.truncate(sample);
+
+    let mut pages = Vec::with_capacity(hashes.len());
+    for (bundle_index, bundle) in bundles.iter().enumerate() {
+        let records: Vec<usize> = hashes
+            .iter()
+            .filter(|(_, index, _)| *index == bundle_index)
+            .map(|(_, _, record)| *record)
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        let bundle_pages: Vec<DownloadedPage> = read_compressed_json(bundle)?;
+        for (record, page) in bundle_pages.into_iter().enumerate() {
+            if records.contains(&record) {
+                pages.push((bundle.clone(), record, page));
+            }
+        }
+    }
+    Ok(pages)
+}
+
+/// The size of the downloaded content, 0 for the pages without content
+fn content_len(content: &DownloadedPageContent) -> usize {
+    match content {
+        DownloadedPageContent::Html(text)
+        | DownloadedPageContent::PlainText(text)
+        | DownloadedPageContent::Markdown(text) => text.len(),
+        DownloadedPageContent::Failure(_)
+        | DownloadedPageContent::Pruned
+        | DownloadedPageContent::SkippedEquivalent(_) => 0,
+    }
+}
+
+impl Latencies {
+    fn of(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        // The nearest rank
+        let percentile = |percent: usize| {
+            let rank = (latencies.len() * percent).div_ceil(100).max(1);
+            latencies[rank - 1].as_secs_f64() * 1000.
+        };
+        Latencies {
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            p99_ms: percentile(99),
+        }
+    }
+}
+
+impl TemporaryDir {
+    fn create() -> anyhow::Result<Self> {
+        let path = env::temp_dir().join(format!("mind-search-bench-{}", process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(TemporaryDir(path))
+    }
+}
+
+impl Drop for TemporaryDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
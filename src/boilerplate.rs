@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Lines longer than this are considered real content and never counted as boilerplate
+const MAX_BOILERPLATE_LINE_CHARS: usize = 500;
+
+/// Count, for each domain, in how many pages each line of text appears
+#[derive(Default)]
+pub struct LineFrequencies {
+    pages_by_domain: HashMap<String, usize>,
+    line_pages_by_domain: HashMap<String, HashMap<String, usize>>,
+}
+
+impl LineFrequencies {
+    pub fn add_page(&mut self, domain: &str, content: &str) {
+        *self.pages_by_domain.entry(domain.to_string()).or_default() += 1;
+
+        let line_pages = self
+            .line_pages_by_domain
+            .entry(domain.to_string())
+            .or_default();
+        let distinct_lines: HashSet<&str> = content_lines(content)
+            .filter(|line| line.chars().count() <= MAX_BOILERPLATE_LINE_CHARS)
+            .collect();
+        for line in distinct_lines {
+            *line_pages.entry(line.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: LineFrequencies) {
+        for (domain, pages) in other.pages_by_domain {
+            *self.pages_by_domain.entry(domain).or_default() += pages;
+        }
+        for (domain, other_line_pages) in other.line_pages_by_domain {
+            let line_pages = self.line_pages_by_domain.entry(domain).or_default();
+            for (line, pages) in other_line_pages {
+                *line_pages.entry(line).or_default() += pages;
+            }
+        }
+    }
+
+    /// Detect the boilerplate lines of each domain with more than `min_pages` pages: the ones that
+    /// appear in more than `min_ratio` of that domain's pages
+    pub fn boilerplate(&self, min_pages: usize, min_ratio: f64) -> Boilerplate {
+        let mut lines_by_domain = HashMap::new();
+
+        for (domain, &pages) in &self.pages_by_domain {
+            if pages <= min_pages {
+                continue;
+            }
+
+            let line_pages = &self.line_pages_by_domain[domain];
+            let lines: HashSet<String> = line_pages
+                .iter()
+                .filter(|(_, &line_pages)| line_pages as f64 > min_ratio * pages as f64)
+                .map(|(line, _)| line.clone())
+                .collect();
+            if !lines.is_empty() {
+                lines_by_domain.insert(domain.clone(), lines);
+            }
+        }
+
+        Boilerplate { lines_by_domain }
+    }
+}
+
+/// The boilerplate lines learned for each domain
+#[derive(Default)]
+pub struct Boilerplate {
+    lines_by_domain: HashMap<String, HashSet<String>>,
+}
+
+impl Boilerplate {
+    /// Read all the boilerplate files from a directory, ignoring it if it doesn't exist
+    pub fn read(dir_path: &Path) -> anyhow::Result<Self> {
+        let mut lines_by_domain = HashMap::new();
+
+        if dir_path.exists() {
+            for maybe_entry in fs::read_dir(dir_path)? {
+                let entry_path = maybe_entry?.path();
+                let domain = entry_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".txt"));
+                if let Some(domain) = domain {
+                    let lines = fs::read_to_string(&entry_path)?
+                        .lines()
+                        .map(|line| line.to_string())
+                        .collect();
+                    lines_by_domain.insert(domain.to_string(), lines);
+                }
+            }
+        }
+
+        Ok(Boilerplate { lines_by_domain })
+    }
+
+    /// Write one file per domain in the directory, named like "docs.rs.txt". Like the indexes, they
+    /// stay in clear when the data directory is encrypted.
+    pub fn write(&self, dir_path: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dir_path)?;
+
+        for (domain, lines) in &self.lines_by_domain {
+            let mut lines: Vec<_> = lines.iter().map(|line| line.as_str()).collect();
+            lines.sort_unstable();
+            let file_path = dir_path.join(format!("{}.txt", domain));
+            fs::write(file_path, lines.join("\n"))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn num_domains(&self) -> usize {
+        self.lines_by_domain.len()
+    }
+
+    /// Remove the boilerplate lines of the domain from the content
+    pub fn strip(&self, domain: &str, content: &str) -> String {
+        match self.lines_by_domain.get(domain) {
+            None => content.to_string(),
+            Some(lines) => content
+                .lines()
+                .filter(|line| !lines.contains(line.trim()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+fn content_lines(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn learn(pages: &[(&str, &str)], min_pages: usize, min_ratio: f64) -> Boilerplate {
+        let mut line_frequencies = LineFrequencies::default();
+        for (domain, content) in pages {
+            line_frequencies.add_page(domain, content);
+        }
+        line_frequencies.boilerplate(min_pages, min_ratio)
+    }
+
+    #[test]
+    fn learns_the_lines_repeated_in_most_pages_of_a_domain() {
+        let boilerplate = learn(
+            &[
+                ("blog.example", "Home | About\nFirst post\nCopyright 2023"),
+                ("blog.example", "Home | About\nSecond post\nCopyright 2023"),
+                ("blog.example", "Home | About\nThird post\nCopyright 2023"),
+                ("blog.example", "Home | About\nFourth post\nFirst post"),
+            ],
+            2,
+            0.5,
+        );
+        assert_eq!(
+            boilerplate.strip("blog.example", "Home | About\nFifth post\nCopyright 2023"),
+            "Fifth post"
+        );
+        // Exactly half of the pages is not more than half
+        assert_eq!(
+            boilerplate.strip("blog.example", "First post"),
+            "First post"
+        );
+    }
+
+    #[test]
+    fn ignores_the_domains_with_few_pages() {
+        let boilerplate = learn(
+            &[
+                ("small.example", "Menu\nOne"),
+                ("small.example", "Menu\nTwo"),
+                ("big.example", "Menu\nOne"),
+                ("big.example", "Menu\nTwo"),
+                ("big.example", "Menu\nThree"),
+            ],
+            2,
+            0.5,
+        );
+        assert_eq!(boilerplate.num_domains(), 1);
+        assert_eq!(
+            boilerplate.strip("small.example", "Menu\nFour"),
+            "Menu\nFour"
+        );
+        assert_eq!(boilerplate.strip("big.example", "Menu\nFour"), "Four");
+        assert_eq!(
+            boilerplate.strip("other.example", "Menu\nFour"),
+            "Menu\nFour"
+        );
+    }
+
+    #[test]
+    fn counts_a_line_once_per_page_and_never_long_ones() {
+        let long_line = "word ".repeat(200);
+        let pages: Vec<(&str, String)> = (0..4)
+            .map(|page| {
+                let content = if page == 0 {
+                    format!("Repeated\nRepeated\nRepeated\n{}", long_line)
+                } else {
+                    format!("Page {}\n{}", page, long_line)
+                };
+                ("site.example", content)
+            })
+            .collect();
+        let pages: Vec<(&str, &str)> = pages
+            .iter()
+            .map(|(domain, content)| (*domain, content.as_str()))
+            .collect();
+        let boilerplate = learn(&pages, 2, 0.5);
+        assert_eq!(boilerplate.strip("site.example", "Repeated"), "Repeated");
+        assert_eq!(boilerplate.strip("site.example", &long_line), long_line);
+    }
+
+    #[test]
+    fn merges_the_frequencies_of_the_bundles() {
+        let mut line_frequencies = LineFrequencies::default();
+        let mut other = LineFrequencies::default();
+        line_frequencies.add_page("site.example", "Footer\nOne");
+        line_frequencies.add_page("site.example", "Footer\nTwo");
+        other.add_page("site.example", "Footer\nThree");
+        line_frequencies.merge(other);
+        let boilerplate = line_frequencies.boilerplate(2, 0.9);
+        assert_eq!(boilerplate.strip("site.example", "Footer\nFour"), "Four");
+    }
+
+    #[test]
+    fn reads_what_it_wrote() {
+        let dir = tempfile::tempdir().unwrap();
+        let boilerplate = learn(
+            &[
+                ("site.example", "Footer\nOne"),
+                ("site.example", "Footer\nTwo"),
+                ("site.example", "Footer\nThree"),
+            ],
+            2,
+            0.5,
+        );
+        boilerplate.write(dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("site.example.txt")).unwrap(),
+            "Footer"
+        );
+
+        let read = Boilerplate::read(dir.path()).unwrap();
+        assert_eq!(read.num_domains(), 1);
+        assert_eq!(read.strip("site.example", "Footer\nFour"), "Four");
+        assert_eq!(
+            Boilerplate::read(&dir.path().join("missing"))
+                .unwrap()
+                .num_domains(),
+            0
+        );
+    }
+}
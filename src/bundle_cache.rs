@@ -0,0 +1,241 @@
+use crate::metadata::bundle_state;
+use crate::{encryption, read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// What a bundle holds without the content of its pages, so that the scans that only need the
+/// URLs don't decompress the bundle again
+#[derive(Deserialize, Serialize)]
+struct BundleSummary {
+    /// The size and the modification time of the bundle when it was summarized. The summary is
+    /// out of date when they changed, like when the bundle was rewritten by prune.
+    size: u64,
+    modified_ms: i64,
+    pages: Vec<PageSummary>,
+}
+
+/// A record of a bundle, without its content
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PageSummary {
+    pub url: String,
+    pub loaded_at: DateTime<Utc>,
+    /// Whether the content was downloaded, and not a failure or a placeholder
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_url: Option<String>,
+}
+
+impl PageSummary {
+    fn new(page: &DownloadedPage) -> Self {
+        PageSummary {
+            url: page.url.clone(),
+            loaded_at: page.loaded_at,
+            success: matches!(
+                page.content,
+                DownloadedPageContent::Html(_)
+                    | DownloadedPageContent::PlainText(_)
+                    | DownloadedPageContent::Markdown(_)
+            ),
+            final_url: page.final_url.clone(),
+            canonical_url: page.canonical_url.clone(),
+        }
+    }
+}
+
+/// Summarize the pages of a bundle that was just written, replacing its previous summary. The
+/// summaries are only a cache, so failing to write one is not an error.
+pub fn record_bundle(data_paths: &DataPaths, bundle: &Path, pages: &[DownloadedPage]) {
+    // It would keep the URLs in clear
+    if encryption::is_enabled() {
+        return;
+    }
+    let summaries: Vec<PageSummary> = pages.iter().map(PageSummary::new).collect();
+    if let Err(error) = write_summary(data_paths, bundle, summaries) {
+        warn!(
+            "Failed to cache the summary of {}: {:#}",
+            bundle.display(),
+            error
+        );
+    }
+}
+
+/// The records of the bundle without their content, from its cached summary when it's up to
+/// date, and otherwise from the bundle itself, caching its summary for the next time
+pub fn read_page_summaries(
+    data_paths: &DataPaths,
+    bundle: &Path,
+) -> anyhow::Result<Vec<PageSummary>> {
+    if let Some(summaries) = read_cached_summary(data_paths, bundle) {
+        return Ok(summaries);
+    }
+
+    debug!("Summarizing {}", bundle.display());
+    let pages: Vec<DownloadedPage> = read_compressed_json(bundle)?;
+    let summaries: Vec<PageSummary> = pages.iter().map(PageSummary::new).collect();
+    if !encryption::is_enabled() {
+        if let Err(error) = write_summary(data_paths, bundle, summaries.clone()) {
+            warn!(
+                "Failed to cache the summary of {}: {:#}",
+                bundle.display(),
+                error
+            );
+        }
+    }
+    Ok(summaries)
+}
+
+/// Summarize all the bundles again, replacing the cache
+pub fn rebuild_cache(data_paths: &DataPaths) -> anyhow::Result<()> {
+    if encryption::is_enabled() {
+        anyhow::bail!("the summaries of the bundles are not cached for encrypted data, since they would not be encrypted");
+    }
+    remove(data_paths)?;
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    bundles
+        .par_iter()
+        .try_for_each(|bundle| -> anyhow::Result<()> {
+            read_page_summaries(data_paths, bundle)
+                .with_context(|| format!("failed to read {}", bundle.display()))?;
+            Ok(())
+        })?;
+    println!(
+        "Summarized {} bundles into {}",
+        bundles.len(),
+        data_paths.bundle_cache_dir().display()
+    );
+    Ok(())
+}
+
+/// Delete the cached summaries, when the data becomes encrypted
+pub fn remove(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let cache_dir = data_paths.bundle_cache_dir();
+    if cache_dir.exists() {
+        fs::remove_dir_all(cache_dir)?;
+    }
+    Ok(())
+}
+
+fn summary_path(data_paths: &DataPaths, bundle: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = bundle.file_name().context("invalid bundle path")?;
+    Ok(data_paths
+        .bundle_cache_dir()
+        .join(format!("{}.meta.json", file_name.to_string_lossy())))
+}
+
+/// The cached summary of the bundle, unless there is none or it's out of date
+fn read_cached_summary(data_paths: &DataPaths, bundle: &Path) -> Option<Vec<PageSummary>> {
+    if encryption::is_enabled() {
+        return None;
+    }
+    let content = fs::read_to_string(summary_path(data_paths, bundle).ok()?).ok()?;
+    let summary: BundleSummary = serde_json::from_str(&content).ok()?;
+    let (_, size, modified_ms) = bundle_state(bundle).ok()?;
+    (summary.size == size && summary.modified_ms == modified_ms).then_some(summary.pages)
+}
+
+fn write_summary(
+    data_paths: &DataPaths,
+    bundle: &Path,
+    pages: Vec<PageSummary>,
+) -> anyhow::Result<()> {
+    let (_, size, modified_ms) = bundle_state(bundle)?;
+    let summary = BundleSummary {
+        size,
+        modified_ms,
+        pages,
+    };
+    let path = summary_path(data_paths, bundle)?;
+    fs::create_dir_all(data_paths.bundle_cache_dir())?;
+    // A summary written halfway would be read as invalid, and then written again
+    fs::write(path, serde_json::to_string(&summary)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{downloaded_page, TestData};
+    use std::time::{Duration, SystemTime};
+
+    fn page(url: &str) -> DownloadedPage {
+        downloaded_page(url, DownloadedPageContent::Html("<p>Text</p>".to_string()))
+    }
+
+    fn urls(summaries: Vec<PageSummary>) -> Vec<String> {
+        summaries.into_iter().map(|summary| summary.url).collect()
+    }
+
+    /// Replace the URLs of the cached summary, to tell when it is used
+    fn tamper_with_cache(data_paths: &DataPaths, bundle: &Path, url: &str) {
+        let path = summary_path(data_paths, bundle).unwrap();
+        let mut summary: BundleSummary =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        for page in &mut summary.pages {
+            page.url = url.to_string();
+        }
+        fs::write(&path, serde_json::to_string(&summary).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn uses_the_summary_while_the_bundle_is_unchanged() {
+        let data = TestData::new();
+        let bundle = data.write_bundle("1-0", &[page("https://a.example/")]);
+        let summaries = read_page_summaries(&data.data_paths, &bundle).unwrap();
+        assert_eq!(urls(summaries), ["https://a.example/"]);
+        assert!(summary_path(&data.data_paths, &bundle).unwrap().exists());
+
+        tamper_with_cache(&data.data_paths, &bundle, "https://cached.example/");
+        let summaries = read_page_summaries(&data.data_paths, &bundle).unwrap();
+        assert_eq!(urls(summaries), ["https://cached.example/"]);
+    }
+
+    #[test]
+    fn rejects_the_summary_of_a_rewritten_bundle() {
+        let data = TestData::new();
+        let bundle = data.write_bundle("1-0", &[page("https://a.example/")]);
+        read_page_summaries(&data.data_paths, &bundle).unwrap();
+
+        // Another size, like when prune rewrites it
+        data.write_bundle(
+            "1-0",
+            &[page("https://a.example/"), page("https://b.example/")],
+        );
+        let summaries = read_page_summaries(&data.data_paths, &bundle).unwrap();
+        assert_eq!(
+            urls(summaries),
+            ["https://a.example/", "https://b.example/"]
+        );
+
+        // The same size, but another modification time
+        tamper_with_cache(&data.data_paths, &bundle, "https://cached.example/");
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&bundle)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let summaries = read_page_summaries(&data.data_paths, &bundle).unwrap();
+        assert_eq!(
+            urls(summaries),
+            ["https://a.example/", "https://b.example/"]
+        );
+    }
+
+    #[test]
+    fn ignores_an_invalid_summary() {
+        let data = TestData::new();
+        let bundle = data.write_bundle("1-0", &[page("https://a.example/")]);
+        record_bundle(&data.data_paths, &bundle, &[page("https://a.example/")]);
+        fs::write(summary_path(&data.data_paths, &bundle).unwrap(), "{").unwrap();
+        let summaries = read_page_summaries(&data.data_paths, &bundle).unwrap();
+        assert_eq!(urls(summaries), ["https://a.example/"]);
+    }
+}
@@ -0,0 +1,674 @@
+use crate::annotations::AnnotateArguments;
+use crate::archive::{ExportArchiveArguments, ImportArchiveArguments};
+use crate::bench::BenchCommand;
+use crate::completions::{CompleteValueArguments, CompletionsArguments};
+use crate::config::{Config, ConfigCommand};
+use crate::daemon::DaemonArguments;
+use crate::data_lock::{DataLock, LockMode};
+use crate::doctor::DoctorArguments;
+use crate::download_pages::{download_pages, DownloadPagesArguments};
+use crate::empty_pages::ListEmptyPagesArguments;
+use crate::extract_firefox_history::extract_firefox_history;
+use crate::extract_firefox_session::{extract_firefox_session, ExtractFirefoxSessionArguments};
+use crate::extract_sqlite_history::{extract_sqlite_history, ExtractSqliteHistoryArguments};
+use crate::import_warc::ImportWarcArguments;
+use crate::index_backup::{IndexBackupArguments, IndexRestoreArguments};
+use crate::index_contents::IndexContentsArguments;
+use crate::index_stats::IndexStatsArguments;
+use crate::inspect_page::InspectPageArguments;
+use crate::logging::{init_logging, LogFormat};
+use crate::man_page::MangenArguments;
+use crate::mcp::McpServeArguments;
+use crate::metadata::QueryMetaArguments;
+use crate::prune::PruneArguments;
+use crate::relevance_test::RelevanceTestArguments;
+use crate::run_metrics::RunMetrics;
+use crate::run_report::{Outcome, ReportArguments, StageReport};
+use crate::search::SearchArguments;
+use crate::serve::ServeArguments;
+use crate::stats::StatsArguments;
+use crate::suggest::SuggestArguments;
+use crate::sync::SyncArguments;
+use crate::tui::TuiArguments;
+use crate::workspace::{workspace_paths, WorkspaceCommand};
+use crate::{
+    annotations, archive, bench, bundle_cache, completions, config, daemon, doctor, empty_pages,
+    encryption, examples, import_warc, index_backup, index_contents, index_stats, inspect_page,
+    integrity, man_page, mcp, metadata, optimize_index, prune, relevance_test, saved_searches,
+    search, serve, show_page, stats, suggest, sync, tui, workspace, DataPaths, MissingStep,
+    DEFAULT_INDEX_NAME, DEFAULT_WORKSPACE,
+};
+use anyhow::Context;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// The exit code when the command needs another one to run first, like `download-pages` before
+/// the history was extracted. It's 1 for the other errors and 2 for an invalid command line.
+const EXIT_MISSING_STEP: u8 = 3;
+/// The exit code when the command found nothing new to work on, like no page to download
+const EXIT_NOTHING_TO_DO: u8 = 4;
+/// The exit code when the command completed but skipped some of its work, like unreadable bundles
+const EXIT_WARNINGS: u8 = 5;
+
+/// How the program ends, shown at the end of its help
+const EXIT_CODES_HELP: &str = "Exit codes: 1 on errors, or when search --count or --quiet find \
+    nothing, 2 when the command line is invalid, 3 when another command must be run first, like \
+    extract-firefox-history before download-pages, 4 when download-pages, index-contents or sync \
+    found nothing new to work on and 5 when they completed with warnings, like unreadable bundles.";
+/// How the commands that add to the index end, shown at the end of their help for the scripts
+/// that run them regularly
+const PIPELINE_EXIT_CODES_HELP: &str = "Exit codes: 0 when new pages were processed, 1 on \
+    errors, 2 when the command line is invalid, 3 when another command must be run first, 4 when \
+    there was nothing new to work on and 5 when the command completed with warnings, like \
+    unreadable bundles. The \"outcome\" of the --report file tells the same.";
+
+/// Search the pages of your browser history by their content
+///
+/// The history is extracted from a Firefox profile, then the pages it lists are downloaded and
+/// indexed, so that they can be searched by their words, their site and the date of the visits.
+/// Each step is a command, and sync runs them all.
+#[derive(Parser, Debug)]
+#[command(after_help = format!("{}\n\n{}", examples::WORKFLOW, EXIT_CODES_HELP))]
+struct ProgramArguments {
+    /// The directory with all the data: the history, the downloaded pages, the indexes and the
+    /// saved searches. By default, "./data" if it exists, and otherwise the data directory of the
+    /// platform, like "~/.local/share/mind-search" on Linux
+    #[arg(long, global = true, env = "MIND_SEARCH_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Use a separate history, pages and indexes, like "work", created with `workspace create`.
+    /// The default workspace is the data directory itself
+    #[arg(
+        long,
+        global = true,
+        env = "MIND_SEARCH_WORKSPACE",
+        default_value = DEFAULT_WORKSPACE
+    )]
+    workspace: String,
+    /// Print more details of the progress, `-vv` for even more. The RUST_LOG variable, like
+    /// "mind_search::download_pages=debug", takes precedence
+    #[arg(short = 'v', action = ArgAction::Count, global = true)]
+    verbosity: u8,
+    /// Only print the errors in the logs
+    #[arg(
+        short = 'q',
+        long = "quiet-logs",
+        global = true,
+        conflicts_with = "verbosity"
+    )]
+    log_quiet: bool,
+    /// How to print the logs in the standard error
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+    /// Encrypt the history, the downloaded pages, the notes and the saved searches with a
+    /// passphrase, asked once by each command or read from the file in MIND_SEARCH_KEY_FILE. Once
+    /// given, the data directory stays encrypted. The indexes and the boilerplate lines are not
+    /// encrypted, see --indexes-dir
+    #[arg(long, global = true)]
+    encrypt: bool,
+    /// Store the indexes in this directory instead of inside the data directory, like on an
+    /// encrypted file system, since the indexes can't be encrypted with --encrypt
+    #[arg(long, global = true, env = "MIND_SEARCH_INDEXES_DIR")]
+    indexes_dir: Option<PathBuf>,
+    /// When another command is using the data directory, wait for it to finish instead of exiting
+    #[arg(long, global = true)]
+    wait: bool,
+    /// Append the duration, the counters and the resources used by the commands that change the
+    /// data as a JSON line to "runs.log" in the data directory, to follow them across runs
+    #[arg(long, global = true)]
+    record_run: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Extract your browser history information into a JSON file
+    ///
+    /// The history is read from a copy of the places.sqlite file of the profile, so Firefox can
+    /// stay open. It replaces the history extracted before, except the tabs added by
+    /// extract-firefox-session.
+    #[command(after_help = examples::EXTRACT_FIREFOX_HISTORY)]
+    ExtractFirefoxHistory {
+        /// The path to your Firefox profile. You can obtain it in the page "about:profiles" in your
+        /// Firefox
+        profile_path: PathBuf,
+    },
+    /// Add the tabs open in Firefox to the extracted history
+    ///
+    /// The tabs are read from the session file that Firefox keeps to restore them, so the pages
+    /// read in tabs kept open for weeks are downloaded even if their visit is old. They are added
+    /// as visited now.
+    #[command(after_help = examples::EXTRACT_FIREFOX_SESSION)]
+    ExtractFirefoxSession(ExtractFirefoxSessionArguments),
+    /// Add the history of another browser that keeps it in SQLite, like qutebrowser, to the
+    /// extracted history
+    #[command(after_help = examples::EXTRACT_SQLITE_HISTORY)]
+    ExtractSqliteHistory(ExtractSqliteHistoryArguments),
+    /// Download all pages that it can from your extracted history
+    ///
+    /// The pages downloaded by the previous runs, and the ones that failed, are not downloaded
+    /// again. The pages are stored compressed, in bundles of a few hundred pages.
+    ///
+    /// Only the HTML, markdown and plain text pages are kept, including the local files of the
+    /// "file:" URLs. The PDFs and the other documents are recorded as failures.
+    #[command(after_help = format!("{}\n\n{}", examples::DOWNLOAD_PAGES, PIPELINE_EXIT_CODES_HELP))]
+    DownloadPages {
+        #[command(flatten)]
+        arguments: DownloadPagesArguments,
+        #[command(flatten)]
+        report: ReportArguments,
+    },
+    /// Import the HTML pages archived in a WARC file, like by wget or ArchiveBox, as downloaded
+    /// pages
+    ///
+    /// The pages that are no longer online can be indexed this way. The pages already downloaded
+    /// successfully are skipped, unless --prefer-warc.
+    ImportWarc(ImportWarcArguments),
+    /// Read the raw pages to extract the readable text and index it for search
+    ///
+    /// The index is built again from all the downloaded pages, unless --only-new, --bundle or
+    /// --url limit it to some of them.
+    #[command(after_help = format!("{}\n\n{}", examples::INDEX_CONTENTS, PIPELINE_EXIT_CODES_HELP))]
+    IndexContents {
+        #[command(flatten)]
+        arguments: IndexContentsArguments,
+        #[command(flatten)]
+        report: ReportArguments,
+    },
+    /// Extract the history, download the new pages and index them again, in one go
+    ///
+    /// This is what to run regularly, like from cron, to keep the index up to date. The daemon
+    /// command does it too, in the background.
+    #[command(after_help = format!("{}\n\n{}", examples::SYNC, PIPELINE_EXIT_CODES_HELP))]
+    Sync {
+        #[command(flatten)]
+        arguments: SyncArguments,
+        #[command(flatten)]
+        report: ReportArguments,
+    },
+    /// Keep the index up to date in the background: extract the history, download the new pages and
+    /// index them at regular intervals, optionally answering searches over HTTP too
+    Daemon(DaemonArguments),
+    /// Merge the index segments into one, which makes the first queries faster
+    OptimizeIndex {
+        /// The name of the index to optimize
+        #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+        index_name: String,
+    },
+    /// Remove the downloaded and indexed pages of some sites or the old ones, after telling what
+    /// would be removed
+    Prune(PruneArguments),
+    /// Save a snapshot of the index, to restore it after experimenting, or list the snapshots
+    IndexBackup(IndexBackupArguments),
+    /// Replace the index with a snapshot saved by index-backup
+    IndexRestore(IndexRestoreArguments),
+    /// Measure how fast the pages are indexed and the queries answered, to compare versions or
+    /// settings
+    Bench {
+        #[command(subcommand)]
+        command: BenchCommand,
+    },
+    /// Check that the golden queries still find their pages in their top results, to catch the
+    /// changes that make the search worse
+    RelevanceTest(RelevanceTestArguments),
+    /// Report the size and composition of the index
+    IndexStats(IndexStatsArguments),
+    /// Give an overview of the history, the downloaded pages and the index, like how many pages
+    /// are not downloaded yet and when the index was last built
+    Stats(StatsArguments),
+    /// Verify the checksums of the history and the downloaded bundles, to find the corrupt ones
+    Check,
+    /// Run a read-only SQL query on the metadata database of the pages, kept up to date by
+    /// download-pages and index-contents
+    QueryMeta(QueryMetaArguments),
+    /// Build the metadata database again from the downloaded pages, the history and the default
+    /// index
+    RebuildMeta,
+    /// Summarize the bundles again into the cache of their URLs, which spares reading the whole
+    /// bundles when only their URLs are needed
+    RebuildCache,
+    /// Encrypt the history, the pages, the notes and the saved searches written before the
+    /// encryption was enabled with --encrypt, which it enables too
+    EncryptData,
+    /// Write the history, the downloaded pages and the indexes into a single file, to move them
+    /// to another computer or to back them up
+    ExportArchive(ExportArchiveArguments),
+    /// Replace the data with the content of an archive written by export-archive
+    ImportArchive(ImportArchiveArguments),
+    /// Search the indexed content
+    ///
+    /// The best matches are printed first, with the parts of the page that match. Without a
+    /// query, an interactive prompt reads one query per line.
+    #[command(after_help = examples::SEARCH)]
+    Search {
+        /// What to search for. Words in quotes match as a phrase, like "borrow of moved value",
+        /// and `"moved value"~2` also matches with up to 2 other words in between. Filters can be
+        /// written in the query too: `site:docs.rs`, `-site:reddit.com`, `after:2024-01-01`,
+        /// `before:2024-06-01` and `-word` or `-"a phrase"` to exclude pages. Without a query, an
+        /// interactive prompt reads one query per line, keeping the index open between them
+        #[arg(allow_hyphen_values = true)]
+        query: Option<String>,
+        #[command(flatten)]
+        arguments: SearchArguments,
+    },
+    /// Find the pages with the most words in common with an indexed page, like related articles
+    Similar {
+        /// The URL of the indexed page
+        url: String,
+        #[command(flatten)]
+        arguments: SearchArguments,
+    },
+    /// Save a query and its search options under a name, to run them with `search --saved NAME`
+    SaveSearch {
+        name: String,
+        query: String,
+        /// The search options, like "--site docs.rs --last 6m"
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        options: Vec<String>,
+    },
+    /// List the saved searches
+    ListSaved,
+    /// Write a note about a page, searched like its content and shown under it in the results,
+    /// or list or remove the notes
+    Annotate(AnnotateArguments),
+    /// Browse the results in the terminal, searching as you type and previewing the pages
+    Tui(TuiArguments),
+    /// Answer searches over HTTP, at `/search?q=...` with the same options as the search command,
+    /// serve a search page at `/` and an Atom feed of the last indexed pages at `/feed.xml`
+    Serve(ServeArguments),
+    /// Let assistants that speak the Model Context Protocol search the history, with requests read
+    /// from the standard input
+    McpServe(McpServeArguments),
+    /// Complete the last word of a query with the indexed words, for shell completion or fzf
+    Suggest(SuggestArguments),
+    /// Print the downloaded snapshot of an indexed page
+    ShowPage {
+        url: String,
+        /// Print the raw HTML instead of the readable text
+        #[arg(long)]
+        raw: bool,
+        /// Print the indexed text with the words of this query marked, and the lines where they
+        /// are. The words match like in the search
+        #[arg(long, conflicts_with = "raw")]
+        find: Option<String>,
+        /// How many typos the words of at least 4 chars can have with --find, like in the search
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2), requires = "find")]
+        fuzzy: u8,
+        /// The name of the index where the page is
+        #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+        index_name: String,
+    },
+    /// Print what the indexing extracts from a downloaded page, like its title, language and text
+    InspectPage(InspectPageArguments),
+    /// List the pages that extracted to no text in the last indexing runs, like the sites rendered
+    /// by JavaScript, grouped by domain
+    ListEmptyPages(ListEmptyPagesArguments),
+    /// Print the directory where the data is stored
+    WhereData,
+    /// List, create or delete the workspaces, which keep separate histories, pages and indexes
+    Workspace {
+        #[command(subcommand)]
+        command: WorkspaceCommand,
+    },
+    /// Check the usual causes of problems, like a missing Firefox profile or an empty index, and
+    /// tell what to do about them
+    Doctor(DoctorArguments),
+    /// Print the script that completes the commands and the options in a shell
+    Completions(CompletionsArguments),
+    /// Print the man page, with the examples of the commands
+    Mangen(MangenArguments),
+    /// Print the values that complete an option, for the completion scripts
+    #[command(hide = true)]
+    CompleteValue(CompleteValueArguments),
+    /// Write or print the configuration file, which gives default options to the commands, like
+    /// `parallelism = 30` in the `[download-pages]` section
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Command {
+    /// How the command uses the data directory, if it uses it for a short time. The servers run
+    /// for long and only read the index, which can change under them, so they take no lock. The
+    /// daemon takes it at each cycle.
+    fn lock_mode(&self) -> Option<LockMode> {
+        match self {
+            Command::ExtractFirefoxHistory { .. }
+            | Command::ExtractFirefoxSession(_)
+            | Command::ExtractSqliteHistory(_)
+            | Command::DownloadPages { .. }
+            | Command::ImportWarc(_)
+            | Command::IndexContents { .. }
+            | Command::Sync { .. }
+            | Command::OptimizeIndex { .. }
+            | Command::Prune(_)
+            | Command::EncryptData
+            | Command::RebuildMeta
+            | Command::RebuildCache
+            | Command::IndexRestore(_)
+            | Command::ImportArchive(_) => Some(LockMode::Exclusive),
+            Command::IndexBackup(_)
+            | Command::Bench { .. }
+            | Command::RelevanceTest(_)
+            | Command::IndexStats(_)
+            | Command::Stats(_)
+            | Command::Check
+            | Command::QueryMeta(_)
+            | Command::ExportArchive(_)
+            | Command::Search { .. }
+            | Command::Similar { .. }
+            | Command::Suggest(_)
+            | Command::ShowPage { .. }
+            | Command::InspectPage(_)
+            | Command::ListEmptyPages(_)
+            | Command::Annotate(_) => Some(LockMode::Shared),
+            Command::SaveSearch { .. }
+            | Command::ListSaved
+            | Command::Tui(_)
+            | Command::Serve(_)
+            | Command::McpServe(_)
+            | Command::Daemon(_)
+            | Command::WhereData
+            | Command::Workspace { .. }
+            | Command::Doctor(_)
+            | Command::Completions(_)
+            | Command::Mangen(_)
+            | Command::CompleteValue(_)
+            | Command::Config { .. } => None,
+        }
+    }
+
+    /// Where to write the report of the run, for the commands that support it
+    fn report_path(&self) -> Option<&PathBuf> {
+        match self {
+            Command::DownloadPages { report, .. }
+            | Command::IndexContents { report, .. }
+            | Command::Sync { report, .. } => report.report.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the command ends with a summary of what it did and what it cost. These are the
+    /// commands that change the data.
+    fn reports_metrics(&self) -> bool {
+        self.lock_mode() == Some(LockMode::Exclusive)
+    }
+}
+
+/// The exit code of the program for the error returned by [run]
+pub fn exit_code(error: &anyhow::Error) -> ExitCode {
+    if error.chain().any(|cause| cause.is::<MissingStep>()) {
+        ExitCode::from(EXIT_MISSING_STEP)
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Run the command given in the command line, returning the exit code when it didn't fail
+pub fn run() -> anyhow::Result<ExitCode> {
+    let program_command = ProgramArguments::command();
+    let arguments: Vec<OsString> = env::args_os().collect();
+    let matches = program_command.clone().get_matches_from(&arguments);
+    let args = ProgramArguments::from_arg_matches(&matches)?;
+    init_logging(args.verbosity, args.log_quiet, args.log_format)?;
+    let root_paths =
+        DataPaths::resolve(args.data_dir.clone())?.with_indexes_dir(args.indexes_dir.clone());
+    // The workspace commands manage the workspaces from the data directory itself
+    let data_paths = if matches!(args.command, Command::Workspace { .. }) {
+        root_paths.clone()
+    } else {
+        workspace_paths(&root_paths, &args.workspace)?
+    };
+
+    // The options of the configuration file are added to the command line and parsed again, so
+    // that they go through the same validation
+    let config = Config::load(&data_paths, &program_command)?;
+    let args = match &config {
+        None => args,
+        Some(config) => {
+            let merged_arguments = config.apply(&program_command, &arguments, &matches)?;
+            let merged_matches = program_command
+                .clone()
+                .try_get_matches_from(merged_arguments)
+                .map_err(|error| {
+                    // Only the first line of the error, since the rest is about the command line
+                    let message = error.to_string();
+                    let first_line = message.lines().next().unwrap_or_default();
+                    anyhow::anyhow!(
+                        "invalid configuration in {}: {}",
+                        config.path().display(),
+                        first_line.trim_start_matches("error: ")
+                    )
+                })?;
+            ProgramArguments::from_arg_matches(&merged_matches)?
+        }
+    };
+    // Only printing where the data is, listing the workspaces, diagnosing or completing doesn't
+    // count as using it
+    if !matches!(
+        args.command,
+        Command::WhereData
+            | Command::Workspace {
+                command: WorkspaceCommand::List
+            }
+            | Command::Doctor(_)
+            | Command::Completions(_)
+            | Command::Mangen(_)
+            | Command::CompleteValue(_)
+    ) {
+        data_paths.create_data_dir()?;
+    }
+    let _lock = args
+        .command
+        .lock_mode()
+        .map(|mode| DataLock::acquire(&data_paths, mode, args.wait))
+        .transpose()?;
+    // Before anything is read, and after the lock so that two commands don't enable it at once
+    let enable_encryption = args.encrypt || matches!(args.command, Command::EncryptData);
+    encryption::configure(&data_paths, enable_encryption)?;
+
+    let reports_metrics = args.command.reports_metrics();
+    let report_path = args.command.report_path().cloned();
+    let mut metrics = RunMetrics::start(matches.subcommand_name().unwrap_or_default());
+    let result = run_command(
+        args.command,
+        &args.workspace,
+        &data_paths,
+        config.as_ref(),
+        &program_command,
+        &mut metrics,
+    );
+    if let Some(report_path) = report_path {
+        metrics
+            .report(&result)
+            .write(&report_path)
+            .with_context(|| format!("failed to write the report {}", report_path.display()))?;
+    }
+    let outcome = metrics.outcome(&result);
+    if reports_metrics {
+        metrics.finish(result.is_ok(), args.record_run, &data_paths)?;
+    }
+    result?;
+
+    Ok(match outcome {
+        Outcome::Success => ExitCode::SUCCESS,
+        Outcome::Failure | Outcome::NoMatches => ExitCode::FAILURE,
+        Outcome::NothingToDo => ExitCode::from(EXIT_NOTHING_TO_DO),
+        Outcome::Warnings => ExitCode::from(EXIT_WARNINGS),
+    })
+}
+
+/// Run the subcommand, adding what it processed to the metrics
+fn run_command(
+    command: Command,
+    workspace_name: &str,
+    data_paths: &DataPaths,
+    config: Option<&Config>,
+    program_command: &clap::Command,
+    metrics: &mut RunMetrics,
+) -> anyhow::Result<()> {
+    match command {
+        Command::ExtractFirefoxHistory { profile_path } => {
+            let summary = extract_firefox_history(profile_path, false, data_paths)?;
+            metrics.processed("URLs", summary.urls);
+            metrics.count("new URLs", summary.new_urls);
+            Ok(())
+        }
+        Command::ExtractFirefoxSession(arguments) => {
+            let summary = extract_firefox_session(arguments, data_paths)?;
+            metrics.processed("URLs", summary.urls);
+            metrics.count("new URLs", summary.new_urls);
+            Ok(())
+        }
+        Command::ExtractSqliteHistory(arguments) => {
+            let summary = extract_sqlite_history(arguments, data_paths)?;
+            metrics.processed("URLs", summary.urls);
+            metrics.count("new URLs", summary.new_urls);
+            Ok(())
+        }
+        Command::DownloadPages { arguments, .. } => {
+            let summary = download_pages(&arguments, data_paths)?;
+            metrics.processed("pages fetched", summary.downloaded);
+            metrics.count("failed downloads", summary.failed);
+            metrics.count("requests avoided", summary.skipped_equivalent);
+            metrics.add_stage(StageReport::download(&summary, metrics.elapsed()));
+            Ok(())
+        }
+        Command::ImportWarc(arguments) => import_warc::import_warc(arguments, data_paths),
+        Command::IndexContents { arguments, .. } => {
+            let summary = index_contents::index_contents(arguments, data_paths)?;
+            metrics.processed("documents added", summary.indexed_pages);
+            metrics.count("unreadable bundles", summary.unreadable_bundles);
+            metrics.add_stage(StageReport::index(&summary, metrics.elapsed()));
+            Ok(())
+        }
+        Command::Sync { arguments, .. } => sync::sync(arguments, data_paths, metrics),
+        Command::Daemon(arguments) => daemon::daemon(arguments, data_paths),
+        Command::OptimizeIndex { index_name } => {
+            optimize_index::optimize_index(&index_name, data_paths)
+        }
+        Command::Prune(arguments) => prune::prune(arguments, data_paths),
+        Command::IndexBackup(arguments) => index_backup::index_backup(arguments, data_paths),
+        Command::IndexRestore(arguments) => index_backup::index_restore(arguments, data_paths),
+        Command::Bench { command } => bench::bench(command, data_paths),
+        Command::RelevanceTest(arguments) => relevance_test::relevance_test(arguments, data_paths),
+        Command::IndexStats(arguments) => index_stats::index_stats(arguments, data_paths),
+        Command::Stats(arguments) => stats::stats(arguments, data_paths),
+        Command::Check => integrity::check(data_paths),
+        Command::QueryMeta(arguments) => metadata::query_meta(arguments, data_paths),
+        Command::RebuildMeta => metadata::rebuild_meta(data_paths),
+        Command::RebuildCache => bundle_cache::rebuild_cache(data_paths),
+        Command::EncryptData => encryption::encrypt_data(data_paths),
+        Command::ExportArchive(arguments) => archive::export_archive(arguments, data_paths),
+        Command::ImportArchive(arguments) => archive::import_archive(arguments, data_paths),
+        Command::Search { query, arguments } => {
+            metrics.set_outcome(search::search(query, arguments, data_paths)?);
+            Ok(())
+        }
+        Command::Similar { url, arguments } => {
+            metrics.set_outcome(search::similar(&url, arguments, data_paths)?);
+            Ok(())
+        }
+        Command::SaveSearch {
+            name,
+            query,
+            options,
+        } => saved_searches::save_search(name, query, options, data_paths),
+        Command::ListSaved => saved_searches::list_saved(data_paths),
+        Command::Annotate(arguments) => annotations::annotate(arguments, data_paths),
+        Command::Serve(arguments) => serve::serve(arguments, data_paths),
+        Command::Tui(arguments) => tui::tui(arguments, data_paths),
+        Command::McpServe(arguments) => mcp::mcp_serve(arguments, data_paths),
+        Command::Suggest(arguments) => suggest::suggest(arguments, data_paths),
+        Command::ShowPage {
+            url,
+            raw,
+            find,
+            fuzzy,
+            index_name,
+        } => show_page::show_page(url, raw, find, fuzzy, &index_name, data_paths),
+        Command::InspectPage(arguments) => inspect_page::inspect_page(arguments, data_paths),
+        Command::ListEmptyPages(arguments) => empty_pages::list_empty_pages(arguments, data_paths),
+        Command::WhereData => {
+            // Absolute, so that scripts can use it from anywhere
+            println!("{}", std::path::absolute(data_paths.data_dir())?.display());
+            Ok(())
+        }
+        Command::Workspace { command } => workspace::workspace(command, workspace_name, data_paths),
+        Command::Doctor(arguments) => doctor::doctor(arguments, data_paths),
+        Command::Completions(arguments) => completions::completions(arguments, program_command),
+        Command::Mangen(arguments) => man_page::mangen(arguments, program_command),
+        Command::CompleteValue(arguments) => completions::complete_value(arguments, data_paths),
+        Command::Config {
+            command: ConfigCommand::Init { user },
+        } => config::init_config(user, program_command, data_paths),
+        Command::Config {
+            command: ConfigCommand::Show,
+        } => config::show_config(config, program_command, data_paths),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The arguments of the commands of the examples, without the program name nor what they are
+    /// piped into. Only single quotes are supported.
+    fn example_commands(examples: &str) -> Vec<Vec<String>> {
+        examples
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("mind-search "))
+            .map(|command| {
+                let command = command.split(" | ").next().unwrap_or_default();
+                command
+                    .split('\'')
+                    .enumerate()
+                    .flat_map(|(index, part)| {
+                        if index % 2 == 1 {
+                            vec![part.to_string()]
+                        } else {
+                            part.split_whitespace().map(str::to_string).collect()
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_the_example_commands() {
+        assert_eq!(
+            example_commands(examples::SEARCH)[4],
+            ["search", "sqlite wal", "--quiet"]
+        );
+    }
+
+    #[test]
+    fn parses_the_examples() {
+        for examples in [
+            examples::WORKFLOW,
+            examples::EXTRACT_FIREFOX_HISTORY,
+            examples::EXTRACT_FIREFOX_SESSION,
+            examples::EXTRACT_SQLITE_HISTORY,
+            examples::DOWNLOAD_PAGES,
+            examples::INDEX_CONTENTS,
+            examples::SYNC,
+            examples::SEARCH,
+        ] {
+            let commands = example_commands(examples);
+            assert!(!commands.is_empty(), "no command in {}", examples);
+            for command in commands {
+                let arguments = ["mind-search".to_string()]
+                    .into_iter()
+                    .chain(command.clone());
+                if let Err(error) = ProgramArguments::try_parse_from(arguments) {
+                    panic!("invalid example {:?}: {}", command, error);
+                }
+            }
+        }
+    }
+}
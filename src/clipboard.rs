@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+
+/// Put the URL on the system clipboard.
+///
+/// Without a clipboard, like over SSH or without X11 nor Wayland, the URL is printed alone on a
+/// line instead, so that it can still be copied by hand. The other messages go to the standard
+/// error, like the ones of `open_url()`.
+pub fn copy_url(url: &str) {
+    copy_url_with(
+        url,
+        set_clipboard_text,
+        &mut io::stdout(),
+        &mut io::stderr(),
+    );
+}
+
+/// Like [copy_url], with the clipboard set by the function and the URL printed to the output
+fn copy_url_with(
+    url: &str,
+    set_text: impl FnOnce(&str) -> anyhow::Result<()>,
+    output: &mut impl Write,
+    messages: &mut impl Write,
+) {
+    // Like `println!()`, there's nowhere to tell when the terminal is gone
+    match set_text(url) {
+        Ok(()) => {
+            let _ = writeln!(messages, "Copied {}", url);
+        }
+        Err(error) => {
+            let _ = writeln!(messages, "Failed to copy to the clipboard: {:#}", error);
+            let _ = writeln!(output, "{}", url);
+        }
+    }
+}
+
+/// Replace the content of the system clipboard with the text.
+///
+/// On X11, the text is owned by the process, so it's only kept after the process exits when a
+/// clipboard manager takes it over.
+pub fn set_clipboard_text(text: &str) -> anyhow::Result<()> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    clipboard.set_text(text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output and the messages of the copy
+    fn copy(url: &str, set_text: impl FnOnce(&str) -> anyhow::Result<()>) -> (String, String) {
+        let mut output = Vec::new();
+        let mut messages = Vec::new();
+        copy_url_with(url, set_text, &mut output, &mut messages);
+        (
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(messages).unwrap(),
+        )
+    }
+
+    #[test]
+    fn copies_the_url() {
+        let mut copied = None;
+        let (output, messages) = copy("https://example.com/", |text| {
+            copied = Some(text.to_string());
+            Ok(())
+        });
+        assert_eq!(copied.as_deref(), Some("https://example.com/"));
+        assert_eq!(output, "");
+        assert_eq!(messages, "Copied https://example.com/\n");
+    }
+
+    #[test]
+    fn prints_the_url_without_a_clipboard() {
+        let (output, messages) = copy("https://example.com/", |_| anyhow::bail!("no display"));
+        assert_eq!(output, "https://example.com/\n");
+        assert_eq!(messages, "Failed to copy to the clipboard: no display\n");
+    }
+}
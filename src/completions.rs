@@ -0,0 +1,179 @@
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use clap::{Args, ValueEnum};
+use clap_complete::Shell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use tantivy::Index;
+
+const BIN_NAME: &str = "mind-search";
+
+#[derive(Args, Debug)]
+#[command(after_help = "Install the completions with:
+  bash:       mind-search completions bash > ~/.local/share/bash-completion/completions/mind-search
+  zsh:        mind-search completions zsh > \"${fpath[1]}/_mind-search\"
+  fish:       mind-search completions fish > ~/.config/fish/completions/mind-search.fish
+  powershell: mind-search completions powershell | Out-String | Invoke-Expression
+  elvish:     mind-search completions elvish > ~/.config/elvish/lib/mind-search.elv
+
+The values of --site and --saved are completed from the index and the saved searches with bash, \
+zsh and fish.")]
+pub struct CompletionsArguments {
+    /// The shell to write the completion script for
+    shell: Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct CompleteValueArguments {
+    /// What the value is for
+    kind: ValueKind,
+    /// The beginning of the value, typed so far
+    #[arg(default_value = "", allow_hyphen_values = true)]
+    prefix: String,
+}
+
+/// The options whose values depend on the data
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ValueKind {
+    /// The sites of the default index, for --site
+    Site,
+    /// The names of the saved searches, for --saved
+    Saved,
+}
+
+/// The options whose values are completed by calling the hidden complete-value command, each with
+/// the [ValueKind] of the same name
+const DYNAMIC_OPTIONS: [&str; 2] = ["site", "saved"];
+
+/// Print the completion script of the shell.
+///
+/// The generated scripts only know the options, so the ones listed in [DYNAMIC_OPTIONS] are patched
+/// to ask the program for their values.
+pub fn completions(arguments: CompletionsArguments, command: &clap::Command) -> anyhow::Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(arguments.shell, &mut command.clone(), BIN_NAME, &mut script);
+    let script = String::from_utf8(script)?;
+    let script = match arguments.shell {
+        Shell::Bash => patch_bash(&script),
+        Shell::Zsh => patch_zsh(&script),
+        Shell::Fish => patch_fish(&script),
+        _ => script,
+    };
+    io::stdout().write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// Replace the file completion of the option values, in the branches like `--site)`
+fn patch_bash(script: &str) -> String {
+    let mut patched = String::new();
+    let mut pending_kind = None;
+    for line in script.lines() {
+        match pending_kind.take() {
+            Some(kind) if line.trim_start().starts_with("COMPREPLY=") => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                patched.push_str(&format!(
+                    "{}COMPREPLY=($(compgen -W \"$({} complete-value {} 2>/dev/null)\" -- \"${{cur}}\"))",
+                    indent, BIN_NAME, kind
+                ));
+            }
+            _ => {
+                pending_kind = DYNAMIC_OPTIONS
+                    .into_iter()
+                    .find(|option| line.trim() == format!("--{})", option));
+                patched.push_str(line);
+            }
+        }
+        patched.push('\n');
+    }
+    patched
+}
+
+/// Give the option values an action calling a helper function, defined at the top of the script
+fn patch_zsh(script: &str) -> String {
+    let mut patched = String::new();
+    for line in script.lines() {
+        let mut line = line.to_string();
+        for option in DYNAMIC_OPTIONS {
+            let value_name = option.to_uppercase();
+            if line.starts_with(&format!("'--{}=[", option)) {
+                line = line.replace(
+                    &format!("]:{}: '", value_name),
+                    &format!("]:{}:_mind-search_values {}'", value_name, option),
+                );
+            }
+        }
+        patched.push_str(&line);
+        patched.push('\n');
+        if line.starts_with("#compdef") {
+            patched.push_str(&format!(
+                "\n_mind-search_values() {{\n    \
+                local -a values\n    \
+                values=(${{(f)\"$({} complete-value $1 2>/dev/null)\"}})\n    \
+                compadd -a values\n}}\n",
+                BIN_NAME
+            ));
+        }
+    }
+    patched
+}
+
+/// Give the option values candidates from the program, instead of files
+fn patch_fish(script: &str) -> String {
+    let mut patched = String::new();
+    for line in script.lines() {
+        patched.push_str(line);
+        for option in DYNAMIC_OPTIONS {
+            if line.contains(&format!(" -l {} ", option)) && line.ends_with(" -r") {
+                patched.push_str(&format!(
+                    " -f -a \"({} complete-value {})\"",
+                    BIN_NAME, option
+                ));
+            }
+        }
+        patched.push('\n');
+    }
+    patched
+}
+
+/// Print the values that complete the prefix, one per line. Nothing is printed when there is
+/// nothing to complete from yet, since it would end up in the command line being typed.
+pub fn complete_value(
+    arguments: CompleteValueArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let values = match arguments.kind {
+        ValueKind::Site => indexed_sites(data_paths)?,
+        ValueKind::Saved => crate::saved_searches::saved_search_names(data_paths)?,
+    };
+    for value in values {
+        if value.starts_with(&arguments.prefix) {
+            println!("{}", value);
+        }
+    }
+    Ok(())
+}
+
+/// The sites of the default index, from the one with the most pages
+fn indexed_sites(data_paths: &DataPaths) -> anyhow::Result<Vec<String>> {
+    let index_dir = data_paths.tantivy_index_dir(DEFAULT_INDEX_NAME)?;
+    if !index_dir.join("meta.json").exists() {
+        return Ok(Vec::new());
+    }
+    let searcher = Index::open_in_dir(index_dir)?.reader()?.searcher();
+    let domain_field = searcher.schema().get_field("domain")?;
+
+    // Each segment has its own term dictionary, so the counts are merged
+    let mut page_counts: HashMap<String, u64> = HashMap::new();
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(domain_field)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            let domain = String::from_utf8_lossy(stream.key()).to_string();
+            *page_counts.entry(domain).or_default() += stream.value().doc_freq as u64;
+        }
+    }
+
+    let mut sites: Vec<(String, u64)> = page_counts.into_iter().collect();
+    sites.sort_by_key(|(site, count)| (Reverse(*count), site.clone()));
+    Ok(sites.into_iter().map(|(site, _)| site).collect())
+}
@@ -0,0 +1,428 @@
+use crate::domain_profiles::{DomainProfiles, DOMAINS_SECTION};
+use crate::DataPaths;
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, Subcommand};
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+use tracing::warn;
+
+/// The name of the configuration file inside the configuration directory of the user
+const USER_CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Write a configuration file with all the options commented out, to edit it
+    Init {
+        /// Write it in the configuration directory of the user, like
+        /// "~/.config/mind-search/config.toml", instead of in the data directory
+        #[arg(long)]
+        user: bool,
+    },
+    /// Print the options that each command uses when they are not given in the command line
+    Show,
+}
+
+/// The default options of the commands, read from a TOML file with one section per command, like
+/// `[download-pages]` with `parallelism = 30`.
+///
+/// The file in the data directory is used, and otherwise the one in the configuration directory of
+/// the user. The options given in the command line take precedence over the file, which takes
+/// precedence over the built-in defaults.
+pub struct Config {
+    path: PathBuf,
+    /// The known options of each command, by their long name
+    sections: Table,
+}
+
+impl Config {
+    /// Read the configuration file, if there is one. Unknown commands and options are skipped with
+    /// a warning, so that a file written for another version still works.
+    pub fn load(data_paths: &DataPaths, command: &Command) -> anyhow::Result<Option<Self>> {
+        let Some((path, table)) = read_config_file(data_paths)? else {
+            return Ok(None);
+        };
+
+        let mut sections = Table::new();
+        for (section_name, section) in table {
+            // Read by the downloads, see `DomainProfiles`
+            if section_name == DOMAINS_SECTION {
+                continue;
+            }
+            let (Some(subcommand), Value::Table(section)) =
+                (command.find_subcommand(&section_name), section)
+            else {
+                warn!(
+                    "Ignoring [{}] in {}, which is not a command",
+                    section_name,
+                    path.display()
+                );
+                continue;
+            };
+
+            let mut options = Table::new();
+            for (name, value) in section {
+                let long_name = name.replace('_', "-");
+                if find_option(subcommand, &long_name).is_some() {
+                    options.insert(long_name, value);
+                } else {
+                    warn!(
+                        "Ignoring the unknown option {:?} of [{}] in {}",
+                        name,
+                        section_name,
+                        path.display()
+                    );
+                }
+            }
+            sections.insert(section_name, Value::Table(options));
+        }
+
+        Ok(Some(Config { path, sections }))
+    }
+
+    /// Add the options of the file that the command line doesn't give, right after the command, so
+    /// that the result can be parsed again
+    pub fn apply(
+        &self,
+        command: &Command,
+        arguments: &[OsString],
+        matches: &ArgMatches,
+    ) -> anyhow::Result<Vec<OsString>> {
+        let (Some((subcommand_name, subcommand_matches)), Some(command_position)) =
+            (matches.subcommand(), command_position(command, arguments))
+        else {
+            return Ok(arguments.to_vec());
+        };
+        let Some(Value::Table(options)) = self.sections.get(subcommand_name) else {
+            return Ok(arguments.to_vec());
+        };
+        let subcommand = command
+            .find_subcommand(subcommand_name)
+            .context("unknown command")?;
+
+        let is_given = |arg: &Arg| {
+            matches!(
+                subcommand_matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let mut config_arguments = Vec::new();
+        for (name, value) in options {
+            let Some(arg) = find_option(subcommand, name) else {
+                continue;
+            };
+            // Options that can't be used with the given ones are left out too
+            let conflicts_with_given = subcommand
+                .get_arg_conflicts_with(arg)
+                .into_iter()
+                .any(is_given)
+                || subcommand.get_arguments().any(|other| {
+                    is_given(other)
+                        && subcommand
+                            .get_arg_conflicts_with(other)
+                            .iter()
+                            .any(|conflict| conflict.get_id() == arg.get_id())
+                });
+            if is_given(arg) || conflicts_with_given {
+                continue;
+            }
+            config_arguments.extend(option_arguments(arg, value).with_context(|| {
+                format!(
+                    "invalid option {:?} of [{}] in {}",
+                    name,
+                    subcommand_name,
+                    self.path.display()
+                )
+            })?);
+        }
+
+        let mut merged_arguments = arguments.to_vec();
+        merged_arguments.splice(
+            command_position + 1..command_position + 1,
+            config_arguments.into_iter().map(OsString::from),
+        );
+        Ok(merged_arguments)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Write a template of the configuration file, with the options of each command commented out
+pub fn init_config(user: bool, command: &Command, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let path = if user {
+        user_config_path().context("could not find the configuration directory of the platform")?
+    } else {
+        data_paths.config_file()
+    };
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+
+    let mut template = String::new();
+    writeln!(
+        template,
+        "# The default options of the commands, with one section per command. The options given in\n\
+        # the command line take precedence. Remove the \"#\" in front of an option to set it."
+    )?;
+    for subcommand in configurable_subcommands(command) {
+        writeln!(template, "\n[{}]", subcommand.get_name())?;
+        for arg in options(subcommand) {
+            if let Some(help) = arg.get_help() {
+                for line in wrap_comment(&help.to_string()) {
+                    writeln!(template, "# {}", line)?;
+                }
+            }
+            writeln!(
+                template,
+                "# {} = {}",
+                arg.get_long().unwrap_or_default(),
+                default_value(arg).unwrap_or_else(|| Value::from("").to_string())
+            )?;
+        }
+    }
+
+    writeln!(
+        template,
+        "\n# The downloads of some sites, and of their subdomains, can be done differently:\n\
+        # [{}.\"reddit.com\"]\n\
+        # delay-seconds = 5\n\
+        # parallelism = 1\n\
+        # timeout-seconds = 30\n\
+        # max-body-size = 5000000\n\
+        # user-agent = \"Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0\"\n\
+        # headers = {{ Cookie = \"session=...\" }}",
+        DOMAINS_SECTION
+    )?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, template)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Print the value of each option when it's not given in the command line, telling which ones come
+/// from the built-in defaults
+pub fn show_config(
+    config: Option<&Config>,
+    command: &Command,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    match config {
+        Some(config) => println!("# Read from {}", config.path.display()),
+        None => println!("# No configuration file, these are the built-in defaults"),
+    }
+
+    for subcommand in configurable_subcommands(command) {
+        let configured_options = config
+            .and_then(|config| config.sections.get(subcommand.get_name()))
+            .and_then(Value::as_table);
+        let mut lines = Vec::new();
+        for arg in options(subcommand) {
+            let long_name = arg.get_long().unwrap_or_default();
+            match configured_options.and_then(|options| options.get(long_name)) {
+                Some(value) => lines.push(format!("{} = {}", long_name, value)),
+                None => {
+                    if let Some(value) = default_value(arg) {
+                        lines.push(format!("{} = {}  # default", long_name, value));
+                    }
+                }
+            }
+        }
+        if !lines.is_empty() {
+            println!("\n[{}]", subcommand.get_name());
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+    }
+    DomainProfiles::load(data_paths)?.print();
+    Ok(())
+}
+
+/// Read the configuration file that is used, if there is one
+pub fn read_config_file(data_paths: &DataPaths) -> anyhow::Result<Option<(PathBuf, Table)>> {
+    let Some(path) = config_paths(data_paths)
+        .into_iter()
+        .find(|path| path.exists())
+    else {
+        return Ok(None);
+    };
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let table: Table =
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(Some((path, table)))
+}
+
+/// The configuration files, from the one that takes precedence
+fn config_paths(data_paths: &DataPaths) -> Vec<PathBuf> {
+    let mut paths = vec![data_paths.config_file()];
+    paths.extend(user_config_path());
+    paths
+}
+
+/// Where the platform keeps the configuration of the applications: "%APPDATA%" on Windows,
+/// "~/Library/Application Support" on macOS and "$XDG_CONFIG_HOME" or "~/.config" elsewhere
+fn user_config_path() -> Option<PathBuf> {
+    let non_empty_var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    let config_dir = if cfg!(windows) {
+        non_empty_var("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        non_empty_var("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        // Relative paths must be ignored, according to the XDG specification
+        non_empty_var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| non_empty_var("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+    Some(
+        config_dir
+            .join(crate::APP_DIR_NAME)
+            .join(USER_CONFIG_FILE_NAME),
+    )
+}
+
+/// The position of the command in the arguments, after the global options
+fn command_position(command: &Command, arguments: &[OsString]) -> Option<usize> {
+    let takes_value = |arg: &Arg| arg.get_action().takes_values();
+    let mut position = 1;
+    while let Some(argument) = arguments.get(position) {
+        let argument = argument.to_string_lossy();
+        if let Some(long_name) = argument.strip_prefix("--") {
+            let is_separate_value = !long_name.contains('=')
+                && command
+                    .get_arguments()
+                    .any(|arg| arg.get_long() == Some(long_name) && takes_value(arg));
+            position += if is_separate_value { 2 } else { 1 };
+        } else if let Some(short_names) = argument.strip_prefix('-') {
+            let is_separate_value = short_names.chars().count() == 1
+                && command.get_arguments().any(|arg| {
+                    arg.get_short().map(String::from).as_deref() == Some(short_names)
+                        && takes_value(arg)
+                });
+            position += if is_separate_value { 2 } else { 1 };
+        } else {
+            return Some(position);
+        }
+    }
+    None
+}
+
+/// The commands that have options to configure
+fn configurable_subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| subcommand.get_name() != "config")
+        .filter(|subcommand| options(subcommand).next().is_some())
+}
+
+/// The options of the command that can be configured, which excludes the positional arguments
+/// and the global options
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command.get_arguments().filter(|arg| {
+        arg.get_long().is_some()
+            && !arg.is_positional()
+            && !arg.is_global_set()
+            && !arg.is_hide_set()
+    })
+}
+
+fn find_option<'a>(command: &'a Command, long_name: &str) -> Option<&'a Arg> {
+    options(command).find(|arg| arg.get_long() == Some(long_name))
+}
+
+/// The command line arguments that give this value to the option
+fn option_arguments(arg: &Arg, value: &Value) -> anyhow::Result<Vec<String>> {
+    let long_name = arg.get_long().unwrap_or_default();
+    let scalar = |value: &Value| match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Ok(value.to_string()),
+        _ => anyhow::bail!("expected a string, a number or a boolean"),
+    };
+
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Boolean(true))
+        | (ArgAction::SetFalse, Value::Boolean(false)) => Ok(vec![format!("--{}", long_name)]),
+        (ArgAction::SetTrue | ArgAction::SetFalse, Value::Boolean(_)) => Ok(Vec::new()),
+        (ArgAction::SetTrue | ArgAction::SetFalse, _) => anyhow::bail!("expected true or false"),
+        (ArgAction::Count, Value::Integer(count)) => {
+            Ok(vec![format!("--{}", long_name); (*count).max(0) as usize])
+        }
+        (ArgAction::Count, _) => anyhow::bail!("expected a number"),
+        (ArgAction::Append, Value::Array(items)) => items
+            .iter()
+            .map(|item| Ok(format!("--{}={}", long_name, scalar(item)?)))
+            .collect(),
+        // Options like `--in title,url` take a list in a single value
+        (_, Value::Array(items)) if arg.get_value_delimiter().is_some() => {
+            let delimiter = arg.get_value_delimiter().unwrap_or(',');
+            let items = items
+                .iter()
+                .map(scalar)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(vec![format!(
+                "--{}={}",
+                long_name,
+                items.join(&delimiter.to_string())
+            )])
+        }
+        (_, value) => Ok(vec![format!("--{}={}", long_name, scalar(value)?)]),
+    }
+}
+
+/// The built-in default of the option, written in TOML
+fn default_value(arg: &Arg) -> Option<String> {
+    let to_toml = |value: &str| {
+        if value.parse::<i64>().is_ok()
+            || value.parse::<f64>().is_ok()
+            || value == "true"
+            || value == "false"
+        {
+            value.to_string()
+        } else {
+            Value::from(value).to_string()
+        }
+    };
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| to_toml(&value.to_string_lossy()))
+        .collect();
+
+    match arg.get_action() {
+        ArgAction::SetTrue => Some("false".to_string()),
+        ArgAction::SetFalse => Some("true".to_string()),
+        ArgAction::Count => Some("0".to_string()),
+        ArgAction::Append => Some(format!("[{}]", defaults.join(", "))),
+        _ => defaults.into_iter().next(),
+    }
+}
+
+/// Split the help into lines that fit in a comment
+fn wrap_comment(text: &str) -> Vec<String> {
+    const MAX_LINE_LENGTH: usize = 98;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > MAX_LINE_LENGTH {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
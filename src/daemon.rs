@@ -0,0 +1,228 @@
+use crate::data_lock::{DataLock, LockMode};
+use crate::download_pages::{download_pages, DownloadPagesArguments, DownloadSummary};
+use crate::extract_firefox_history::{extract_firefox_history, ExtractSummary};
+use crate::index_contents::{index_contents, IndexContentsArguments, IndexSummary};
+use crate::serve::{serve, ServeArguments};
+use crate::shutdown::{handle_shutdown_signals, shutdown_requested};
+use crate::sync::format_elapsed;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use clap::Args;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often a sleeping daemon checks for a shutdown request
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The sleep between cycles varies by up to this fraction of the interval, so that the downloads
+/// don't always start at the same time
+const JITTER_RATIO: f64 = 0.1;
+
+#[derive(Args, Debug)]
+pub struct DaemonArguments {
+    /// The path to your Firefox profile. You can obtain it in the page "about:profiles" in your
+    /// Firefox
+    #[arg(long)]
+    profile_path: Option<PathBuf>,
+    /// Don't extract the history, only download and index the pages of the history extracted
+    /// before
+    #[arg(long, conflicts_with = "profile_path")]
+    no_extract: bool,
+    /// How long to wait between two cycles, in seconds, minutes, hours or days, like "30m" or "6h"
+    #[arg(long, default_value = "6h", value_parser = parse_interval)]
+    interval: Duration,
+    #[command(flatten)]
+    download: DownloadPagesArguments,
+    /// The name of the index to keep up to date
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// When a page doesn't declare its publication date, use dates in the URL path like
+    /// "/2021/05/12/"
+    #[arg(long)]
+    infer_date_from_url: bool,
+    /// Also answer searches over HTTP on localhost, like the serve command
+    #[arg(long)]
+    serve: bool,
+    /// The port of the server
+    #[arg(long, default_value_t = 7700, requires = "serve")]
+    port: u16,
+}
+
+/// The stages that must run again even without new URLs, because they did not finish
+struct PendingWork {
+    download: bool,
+    index: bool,
+}
+
+/// Keep the index up to date: extract the history, download the new pages and index them, then
+/// sleep until the next cycle. Stops at the end of the current stage on SIGINT or SIGTERM.
+pub fn daemon(arguments: DaemonArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if arguments.profile_path.is_none() && !arguments.no_extract {
+        anyhow::bail!(
+            "give the --profile-path of Firefox, or --no-extract to use the history extracted \
+            before"
+        );
+    }
+    handle_shutdown_signals();
+
+    // What a previous process left is not known, so everything runs the first time
+    let mut pending_work = PendingWork {
+        download: true,
+        index: true,
+    };
+    let mut server_started = false;
+    while !shutdown_requested() {
+        // The lock is only held during the cycle, so that searches can run in between
+        match DataLock::acquire(data_paths, LockMode::Exclusive, false) {
+            Ok(_lock) => {
+                if let Err(error) = run_cycle(&arguments, data_paths, &mut pending_work) {
+                    warn!(
+                        "The cycle failed, trying again at the next one: {:#}",
+                        error
+                    );
+                }
+            }
+            Err(error) => warn!("Skipping this cycle: {:#}", error),
+        }
+
+        // The server needs an index, which the first cycle may have just created
+        if arguments.serve && !server_started && !shutdown_requested() {
+            server_started = start_server(&arguments, data_paths)?;
+        }
+
+        sleep_until_next_cycle(arguments.interval);
+    }
+
+    info!("Stopped");
+    Ok(())
+}
+
+fn run_cycle(
+    arguments: &DaemonArguments,
+    data_paths: &DataPaths,
+    pending_work: &mut PendingWork,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let mut report = Vec::new();
+
+    if let Some(profile_path) = &arguments.profile_path {
+        let ExtractSummary { new_urls, .. } =
+            extract_firefox_history(profile_path.clone(), true, data_paths)?;
+        report.push(format!("{} new URLs", new_urls));
+        pending_work.download |= new_urls > 0;
+    } else {
+        // The history may have been extracted by another command
+        pending_work.download = true;
+    }
+
+    if pending_work.download && !shutdown_requested() {
+        let DownloadSummary {
+            downloaded, failed, ..
+        } = download_pages(&arguments.download, data_paths)?;
+        report.push(format!(
+            "{} pages downloaded, {} failed",
+            downloaded, failed
+        ));
+        pending_work.download = shutdown_requested();
+        pending_work.index |= downloaded > 0;
+    }
+
+    if pending_work.index && !shutdown_requested() {
+        let mut options = vec![
+            format!("--index-name={}", arguments.index_name),
+            "--only-new".to_string(),
+        ];
+        if arguments.infer_date_from_url {
+            options.push("--infer-date-from-url".to_string());
+        }
+        let IndexSummary { indexed_pages, .. } = index_contents(
+            IndexContentsArguments::parse_options(options.iter().map(String::as_str))?,
+            data_paths,
+        )?;
+        report.push(format!("{} pages indexed", indexed_pages));
+        pending_work.index = shutdown_requested();
+    }
+
+    if report.is_empty() {
+        report.push("nothing to do".to_string());
+    }
+    info!(
+        "Cycle done in {}: {}",
+        format_elapsed(start.elapsed()),
+        report.join(", ")
+    );
+    Ok(())
+}
+
+/// Start answering searches in the background, returning whether it started. It only fails when
+/// the port can't be used.
+fn start_server(arguments: &DaemonArguments, data_paths: &DataPaths) -> anyhow::Result<bool> {
+    let index_dir = data_paths.tantivy_index_dir(&arguments.index_name)?;
+    if !index_dir.join("meta.json").exists() {
+        info!("Not serving searches yet, since there is no index");
+        return Ok(false);
+    }
+
+    let options = [
+        format!("--port={}", arguments.port),
+        format!("--index-name={}", arguments.index_name),
+    ];
+    let serve_arguments = ServeArguments::parse_options(options.iter().map(String::as_str))?;
+    // Fail now rather than in the background
+    std::net::TcpListener::bind(("127.0.0.1", arguments.port))?;
+
+    let data_paths = data_paths.clone();
+    thread::spawn(move || {
+        if let Err(error) = serve(serve_arguments, &data_paths) {
+            warn!("The server stopped: {:#}", error);
+        }
+    });
+    Ok(true)
+}
+
+/// Sleep for about the interval, waking up early when asked to shut down
+fn sleep_until_next_cycle(interval: Duration) {
+    // A random factor between -1 and 1, from the random keys of the standard library
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+    let sleep = interval.mul_f64(1.0 + random * JITTER_RATIO);
+    if !shutdown_requested() {
+        info!("Next cycle in {}", format_elapsed(sleep));
+    }
+
+    let wake_up = Instant::now() + sleep;
+    while !shutdown_requested() && Instant::now() < wake_up {
+        thread::sleep(
+            SHUTDOWN_POLL_INTERVAL.min(wake_up.saturating_duration_since(Instant::now())),
+        );
+    }
+}
+
+/// Parse "45s", "30m", "6h" or "1d"
+fn parse_interval(interval: &str) -> anyhow::Result<Duration> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid interval {:?}, expected a number followed by s, m, h or d, like \"6h\"",
+            interval
+        )
+    };
+    let unit_start = interval.len().checked_sub(1).ok_or_else(invalid)?;
+    if !interval.is_char_boundary(unit_start) {
+        return Err(invalid());
+    }
+    let (amount, unit) = interval.split_at(unit_start);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(invalid()),
+    };
+    let seconds = amount.checked_mul(unit_seconds).ok_or_else(invalid)?;
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
@@ -0,0 +1,134 @@
+use crate::DataPaths;
+// Called as `FileExt::...`, since newer versions of the standard library have methods with the same
+// names
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use tracing::info;
+
+/// How a command uses the data directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// Only reads it, at the same time as other readers
+    Shared,
+    /// Writes bundles, the history or an index, alone
+    Exclusive,
+}
+
+/// An advisory lock on the data directory, held while a command uses it, so that a download and an
+/// indexing don't run at the same time.
+///
+/// The lock is taken by the operating system on a lock file, so it is released when the process
+/// ends, even if it crashed. The lock file also stores the process ID of the exclusive holder, to
+/// tell who is holding it.
+pub struct DataLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl DataLock {
+    /// Take the lock, or fail telling who holds it. With `wait`, block until it's free instead.
+    pub fn acquire(data_paths: &DataPaths, mode: LockMode, wait: bool) -> anyhow::Result<Self> {
+        let path = data_paths.lock_file();
+        let mut file = open_lock_file(&path)?;
+
+        let try_lock = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&file),
+        };
+        if let Err(error) = try_lock {
+            if error.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+                return Err(error.into());
+            }
+
+            let holder = describe_holder(&fs::read_to_string(&path).unwrap_or_default());
+            if !wait {
+                anyhow::bail!(
+                    "{} is using the data directory, run again with --wait to wait for it to finish",
+                    holder
+                );
+            }
+            info!("Waiting for {} to finish", holder);
+            match mode {
+                LockMode::Shared => FileExt::lock_shared(&file)?,
+                LockMode::Exclusive => FileExt::lock_exclusive(&file)?,
+            }
+        }
+
+        if mode == LockMode::Exclusive {
+            file.set_len(0)?;
+            write!(file, "{}", process::id())?;
+        }
+        Ok(DataLock { file, mode })
+    }
+}
+
+impl Drop for DataLock {
+    fn drop(&mut self) {
+        // The file is kept, since removing it could let two processes lock different files
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// A shared lock held by the commands that keep the indexes open for long, like serve, so that
+/// an index is not replaced under them
+pub struct ServersLock {
+    file: File,
+}
+
+impl ServersLock {
+    /// Take the lock, as one of the commands keeping the indexes open
+    pub fn acquire_shared(data_paths: &DataPaths) -> anyhow::Result<Self> {
+        let file = open_lock_file(&data_paths.servers_lock_file())?;
+        FileExt::lock_shared(&file)?;
+        Ok(ServersLock { file })
+    }
+
+    /// Take the lock alone, or fail when a command keeps the indexes open
+    pub fn acquire_exclusive(data_paths: &DataPaths) -> anyhow::Result<Self> {
+        let file = open_lock_file(&data_paths.servers_lock_file())?;
+        if let Err(error) = FileExt::try_lock_exclusive(&file) {
+            if error.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+                return Err(error.into());
+            }
+            anyhow::bail!(
+                "a serve, mcp-serve, tui or daemon command has the indexes open, stop it first"
+            );
+        }
+        Ok(ServersLock { file })
+    }
+}
+
+impl Drop for ServersLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn open_lock_file(path: &Path) -> anyhow::Result<File> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+/// Like "the process 1234", when the process ID stored in the lock file is of a running process
+fn describe_holder(lock_file_content: &str) -> String {
+    let running_pid = lock_file_content
+        .trim()
+        .parse::<libc::pid_t>()
+        .ok()
+        // Sending no signal only checks that the process exists
+        .filter(|&pid| pid > 0 && unsafe { libc::kill(pid, 0) } == 0);
+    match running_pid {
+        Some(pid) => format!("the process {}", pid),
+        None => "another command".to_string(),
+    }
+}
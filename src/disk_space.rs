@@ -0,0 +1,151 @@
+use crate::index_stats::format_size;
+use crate::shutdown::shutdown_requested;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often the free space is measured again while the downloads are paused
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pauses the writers when the free space of the disk drops below a floor, until some space is
+/// freed or for at most a timeout. It's shared by all the threads of a run.
+pub struct DiskSpaceGuard {
+    dir: PathBuf,
+    min_free_bytes: u64,
+    timeout: Duration,
+    paused: AtomicBool,
+    /// The space stayed too low for the whole timeout, so the run should stop
+    exhausted: AtomicBool,
+}
+
+impl DiskSpaceGuard {
+    /// Watch the disk of the directory, which must exist
+    pub fn new(dir: PathBuf, min_free_bytes: u64, timeout: Duration) -> Self {
+        DiskSpaceGuard {
+            dir,
+            min_free_bytes,
+            timeout,
+            paused: AtomicBool::new(false),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a writer is waiting for some space to be freed, so that no more work should start
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the writers gave up waiting for some space, rather than stopping for a shutdown
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// The directory whose disk is watched
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The minimum free space asked for
+    pub fn min_free_bytes(&self) -> u64 {
+        self.min_free_bytes
+    }
+
+    /// Wait until the free space is above the floor, pausing the other writers meanwhile. After a
+    /// write that failed because the disk is full, wait at least once even if the floor is
+    /// reached. Return false when the space is still too low at the deadline or when a shutdown
+    /// was requested, and then the run should stop.
+    pub fn wait_for_space(&self, write_failed: bool, deadline: Instant) -> bool {
+        let mut must_wait = write_failed;
+        loop {
+            if self.is_exhausted() {
+                return false;
+            }
+            let free_bytes = free_space(&self.dir);
+            if !must_wait && free_bytes.is_none_or(|free_bytes| free_bytes >= self.min_free_bytes) {
+                if self.paused.swap(false, Ordering::Relaxed) {
+                    info!("Some disk space was freed, resuming");
+                }
+                return true;
+            }
+
+            if !self.paused.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "The disk is almost full ({} free, at least {} needed): pausing until some \
+                     space is freed, for at most {} minutes",
+                    free_bytes.map_or("no space".to_string(), format_size),
+                    format_size(self.min_free_bytes),
+                    self.timeout.as_secs() / 60
+                );
+            }
+            if shutdown_requested() {
+                return false;
+            }
+            if Instant::now() >= deadline {
+                self.exhausted.store(true, Ordering::Relaxed);
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+            must_wait = false;
+        }
+    }
+
+    /// When the writers waiting from now should give up
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+}
+
+/// The space available to this user on the file system of the path
+pub fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Whether the error comes from a write on a full disk
+pub fn is_disk_full(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|error| error.raw_os_error() == Some(libc::ENOSPC))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn tells_the_errors_of_a_full_disk() {
+        let error: std::io::Result<()> = Err(std::io::Error::from_raw_os_error(libc::ENOSPC));
+        assert!(is_disk_full(&error.context("failed to write").unwrap_err()));
+        let error: std::io::Result<()> = Err(std::io::Error::from_raw_os_error(libc::EACCES));
+        assert!(!is_disk_full(
+            &error.context("failed to write").unwrap_err()
+        ));
+        assert!(!is_disk_full(&anyhow::anyhow!("no space left on device")));
+    }
+
+    #[test]
+    fn waits_only_when_the_space_is_low() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(free_space(dir.path()).is_some());
+
+        let guard = DiskSpaceGuard::new(dir.path().to_path_buf(), 0, Duration::ZERO);
+        assert!(guard.wait_for_space(false, guard.deadline()));
+        assert!(!guard.is_paused());
+
+        let guard = DiskSpaceGuard::new(dir.path().to_path_buf(), u64::MAX, Duration::ZERO);
+        assert!(!guard.wait_for_space(false, guard.deadline()));
+        assert!(guard.is_paused());
+        assert!(guard.is_exhausted());
+    }
+}
@@ -0,0 +1,509 @@
+use crate::data_lock::{DataLock, LockMode};
+use crate::disk_space::free_space;
+use crate::index_stats::format_size;
+use crate::integrity::is_temporary_file;
+use crate::{read_compressed_json, DataPaths, FirefoxHistoryItem, DEFAULT_INDEX_NAME};
+use clap::Args;
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+use std::cmp::Reverse;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tantivy::Index;
+
+/// A rough size of each page once downloaded and indexed, to tell if the disk is large enough
+const ESTIMATED_BYTES_PER_PAGE: u64 = 40 * 1024;
+
+#[derive(Args, Debug)]
+pub struct DoctorArguments {
+    /// The Firefox profile to check, instead of the ones found in the usual places
+    #[arg(long)]
+    profile_path: Option<PathBuf>,
+    /// The name of the index to check
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        f.write_str(label)
+    }
+}
+
+/// The outcome of one check
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    message: String,
+    /// What to do about a warning or a failure
+    suggestion: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: String) -> Self {
+        Check {
+            name,
+            status: Status::Pass,
+            message,
+            suggestion: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: String, suggestion: String) -> Self {
+        Check {
+            name,
+            status: Status::Warn,
+            message,
+            suggestion: Some(suggestion),
+        }
+    }
+
+    fn fail(name: &'static str, message: String, suggestion: String) -> Self {
+        Check {
+            name,
+            status: Status::Fail,
+            message,
+            suggestion: Some(suggestion),
+        }
+    }
+}
+
+/// Check the usual causes of problems, from the Firefox profile to the index, telling what to do
+/// about each one. Fails if any check fails.
+pub fn doctor(arguments: DoctorArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let profiles = match &arguments.profile_path {
+        Some(profile_path) => vec![profile_path.clone()],
+        None => find_firefox_profiles(),
+    };
+    let checks = [
+        check_data_dir(data_paths.data_dir()),
+        check_data_lock(data_paths),
+        check_firefox_profiles(&profiles),
+        check_firefox_database(profiles.first().map(PathBuf::as_path)),
+        check_history(&data_paths.history()),
+        check_bundles(&data_paths.raw_pages_dir()),
+        match data_paths.tantivy_index_dir(&arguments.index_name) {
+            Ok(index_dir) => check_index(&index_dir),
+            Err(error) => Check::fail("Index", error.to_string(), "see --index-name".to_string()),
+        },
+        check_disk_space(data_paths),
+    ];
+
+    for check in &checks {
+        println!("{}  {}: {}", check.status, check.name, check.message);
+        if let Some(suggestion) = &check.suggestion {
+            println!("      {}", suggestion);
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} checks failed", failed);
+    }
+    Ok(())
+}
+
+fn check_data_dir(data_dir: &Path) -> Check {
+    const NAME: &str = "Data directory";
+    match fs::metadata(data_dir) {
+        Err(_) => Check::warn(
+            NAME,
+            format!("{} doesn't exist yet", data_dir.display()),
+            "it is created by the first command, see extract-firefox-history".to_string(),
+        ),
+        Ok(metadata) if !metadata.is_dir() => Check::fail(
+            NAME,
+            format!("{} is not a directory", data_dir.display()),
+            "give another directory with --data-dir".to_string(),
+        ),
+        Ok(metadata) if metadata.permissions().readonly() => Check::fail(
+            NAME,
+            format!("{} is read-only", data_dir.display()),
+            "give another directory with --data-dir".to_string(),
+        ),
+        Ok(_) => Check::pass(NAME, data_dir.display().to_string()),
+    }
+}
+
+fn check_data_lock(data_paths: &DataPaths) -> Check {
+    const NAME: &str = "Lock";
+    if !data_paths.data_dir().is_dir() {
+        return Check::pass(NAME, "no command is using the data directory".to_string());
+    }
+    match DataLock::acquire(data_paths, LockMode::Shared, false) {
+        Ok(_) => Check::pass(NAME, "no command is using the data directory".to_string()),
+        Err(error) => Check::warn(
+            NAME,
+            error.to_string(),
+            "the other commands will wait for it with --wait".to_string(),
+        ),
+    }
+}
+
+/// The Firefox profiles in the usual places, the most recently used first
+fn find_firefox_profiles() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let profile_roots: Vec<PathBuf> = if cfg!(windows) {
+        env::var_os("APPDATA")
+            .map(|app_data| PathBuf::from(app_data).join("Mozilla/Firefox/Profiles"))
+            .into_iter()
+            .collect()
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library/Application Support/Firefox/Profiles"))
+            .into_iter()
+            .collect()
+    } else {
+        // Installed from the distribution, the Snap store or Flathub
+        home.map(|home| {
+            vec![
+                home.join(".mozilla/firefox"),
+                home.join("snap/firefox/common/.mozilla/firefox"),
+                home.join(".var/app/org.mozilla.firefox/.mozilla/firefox"),
+            ]
+        })
+        .unwrap_or_default()
+    };
+
+    let mut profiles = Vec::new();
+    for profile_root in profile_roots {
+        let Ok(entries) = fs::read_dir(profile_root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let database = entry.path().join("places.sqlite");
+            if let Ok(modified) = fs::metadata(&database).and_then(|metadata| metadata.modified()) {
+                profiles.push((modified, entry.path()));
+            }
+        }
+    }
+    profiles.sort_by_key(|(modified, _)| Reverse(*modified));
+    profiles.into_iter().map(|(_, profile)| profile).collect()
+}
+
+fn check_firefox_profiles(profiles: &[PathBuf]) -> Check {
+    const NAME: &str = "Firefox profile";
+    match profiles {
+        // Not a failure, since the history may come from an archive of another computer
+        [] => Check::warn(
+            NAME,
+            "no profile was found".to_string(),
+            "give the path shown in the page \"about:profiles\" of Firefox with --profile-path"
+                .to_string(),
+        ),
+        [profile] => Check::pass(NAME, profile.display().to_string()),
+        [profile, others @ ..] => Check::pass(
+            NAME,
+            format!(
+                "{}, the most recently used of {} profiles",
+                profile.display(),
+                others.len() + 1
+            ),
+        ),
+    }
+}
+
+/// Whether the history database of the profile can be read, which fails while Firefox locks it
+fn check_firefox_database(profile: Option<&Path>) -> Check {
+    const NAME: &str = "Firefox history";
+    let Some(profile) = profile else {
+        return Check::warn(
+            NAME,
+            "not checked, without a profile".to_string(),
+            "see the Firefox profile above".to_string(),
+        );
+    };
+    let database = profile.join("places.sqlite");
+    if !database.exists() {
+        return Check::fail(
+            NAME,
+            format!("{} doesn't exist", database.display()),
+            "check the profile path in the page \"about:profiles\" of Firefox".to_string(),
+        );
+    }
+
+    let count_places = || -> rusqlite::Result<u64> {
+        let connection = Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        connection.query_row("SELECT COUNT(*) FROM moz_places", [], |row| row.get(0))
+    };
+    match count_places() {
+        Ok(places) => Check::pass(NAME, format!("{} places in {}", places, database.display())),
+        Err(rusqlite::Error::SqliteFailure(error, _))
+            if matches!(
+                error.code,
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked
+            ) =>
+        {
+            Check::warn(
+                NAME,
+                "Firefox is running and locks its history".to_string(),
+                "extract-firefox-history works on a copy, but close Firefox first to include \
+                the latest visits"
+                    .to_string(),
+            )
+        }
+        Err(error) => Check::fail(
+            NAME,
+            format!("{} can't be read: {}", database.display(), error),
+            "check the profile path in the page \"about:profiles\" of Firefox".to_string(),
+        ),
+    }
+}
+
+fn check_history(history_path: &Path) -> Check {
+    const NAME: &str = "Extracted history";
+    if !history_path.exists() {
+        return Check::fail(
+            NAME,
+            "the history was not extracted yet".to_string(),
+            "run extract-firefox-history --profile-path PATH".to_string(),
+        );
+    }
+    match read_compressed_json::<Vec<FirefoxHistoryItem>>(history_path) {
+        Ok(history) => Check::pass(NAME, format!("{} URLs", history.len())),
+        Err(error) => Check::fail(
+            NAME,
+            format!("{} can't be read: {:#}", history_path.display(), error),
+            "extract it again with extract-firefox-history".to_string(),
+        ),
+    }
+}
+
+fn check_bundles(raw_pages_dir: &Path) -> Check {
+    const NAME: &str = "Downloaded pages";
+    let mut bundles = 0;
+    let mut size_bytes = 0;
+    if let Ok(entries) = fs::read_dir(raw_pages_dir) {
+        // Not the cache nor the bundles being written
+        let entries = entries
+            .flatten()
+            .filter(|entry| !is_temporary_file(&entry.path()));
+        for metadata in entries.filter_map(|entry| entry.metadata().ok()) {
+            bundles += 1;
+            size_bytes += metadata.len();
+        }
+    }
+    if bundles == 0 {
+        return Check::warn(
+            NAME,
+            "no page was downloaded yet".to_string(),
+            "run download-pages".to_string(),
+        );
+    }
+    Check::pass(
+        NAME,
+        format!("{} bundles, {}", bundles, format_size(size_bytes)),
+    )
+}
+
+/// Whether the index can be opened and has documents. Opening it doesn't change it.
+fn check_index(index_dir: &Path) -> Check {
+    const NAME: &str = "Index";
+    if !index_dir.join("meta.json").exists() {
+        return Check::warn(
+            NAME,
+            "the index was not built yet, so searches find nothing".to_string(),
+            "run index-contents".to_string(),
+        );
+    }
+    let count_documents = || -> anyhow::Result<u64> {
+        Ok(Index::open_in_dir(index_dir)?
+            .reader()?
+            .searcher()
+            .num_docs())
+    };
+    match count_documents() {
+        Ok(0) => Check::warn(
+            NAME,
+            "the index is empty, so searches find nothing".to_string(),
+            "download pages with download-pages and run index-contents again".to_string(),
+        ),
+        Ok(documents) => Check::pass(NAME, format!("{} documents", documents)),
+        Err(error) => Check::fail(
+            NAME,
+            format!("{} can't be opened: {:#}", index_dir.display(), error),
+            "build it again with index-contents".to_string(),
+        ),
+    }
+}
+
+/// Whether the free space is enough to download and index the whole history, roughly
+fn check_disk_space(data_paths: &DataPaths) -> Check {
+    const NAME: &str = "Disk space";
+    // The data directory may not exist yet
+    let Some(free_bytes) = data_paths
+        .data_dir()
+        .ancestors()
+        .find(|path| path.exists())
+        .and_then(free_space)
+    else {
+        return Check::warn(
+            NAME,
+            "the free space could not be measured".to_string(),
+            "make sure that the disk of the data directory has some space".to_string(),
+        );
+    };
+
+    let Ok(history) = read_compressed_json::<Vec<FirefoxHistoryItem>>(&data_paths.history()) else {
+        return Check::pass(NAME, format!("{} free", format_size(free_bytes)));
+    };
+    let used_bytes = dir_size(&data_paths.raw_pages_dir()) + dir_size(&data_paths.indexes_dir());
+    let needed_bytes = (history.len() as u64 * ESTIMATED_BYTES_PER_PAGE).saturating_sub(used_bytes);
+    let message = format!(
+        "{} free, about {} more needed for the {} URLs of the history",
+        format_size(free_bytes),
+        format_size(needed_bytes),
+        history.len()
+    );
+    if free_bytes < needed_bytes {
+        Check::fail(
+            NAME,
+            message,
+            "free some space, or use --data-dir on a larger disk".to_string(),
+        )
+    } else if free_bytes < 2 * needed_bytes {
+        Check::warn(
+            NAME,
+            message,
+            "the estimate is rough, so free some space to be safe".to_string(),
+        )
+    } else {
+        Check::pass(NAME, message)
+    }
+}
+
+/// The total size of the files in the directory and its subdirectories
+pub fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{history_item, visited_page, TestData};
+
+    #[test]
+    fn checks_the_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            check_data_dir(&dir.path().join("data")).status,
+            Status::Warn
+        );
+        assert_eq!(check_data_dir(dir.path()).status, Status::Pass);
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert_eq!(check_data_dir(&file).status, Status::Fail);
+    }
+
+    #[test]
+    fn checks_the_firefox_profiles() {
+        assert_eq!(check_firefox_profiles(&[]).status, Status::Warn);
+        let check = check_firefox_profiles(&[PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.message, "a, the most recently used of 2 profiles");
+    }
+
+    #[test]
+    fn checks_the_firefox_database() {
+        assert_eq!(check_firefox_database(None).status, Status::Warn);
+
+        let profile = tempfile::tempdir().unwrap();
+        assert_eq!(
+            check_firefox_database(Some(profile.path())).status,
+            Status::Fail
+        );
+
+        let connection = Connection::open(profile.path().join("places.sqlite")).unwrap();
+        connection.execute("CREATE TABLE other (id)", []).unwrap();
+        assert_eq!(
+            check_firefox_database(Some(profile.path())).status,
+            Status::Fail
+        );
+
+        connection
+            .execute_batch("CREATE TABLE moz_places (url); INSERT INTO moz_places VALUES ('a');")
+            .unwrap();
+        let check = check_firefox_database(Some(profile.path()));
+        assert_eq!(check.status, Status::Pass);
+        assert!(check.message.starts_with("1 places in "));
+    }
+
+    #[test]
+    fn checks_the_extracted_history() {
+        let data = TestData::new();
+        let history = data.data_paths.history();
+        assert_eq!(check_history(&history).status, Status::Fail);
+
+        fs::write(&history, "not a bundle").unwrap();
+        assert_eq!(check_history(&history).status, Status::Fail);
+
+        data.write_history(&[history_item("https://a.example/", "A")]);
+        let check = check_history(&history);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.message, "1 URLs");
+    }
+
+    #[test]
+    fn checks_the_bundles_and_the_index() {
+        let data = TestData::new();
+        let index_dir = data
+            .data_paths
+            .tantivy_index_dir(DEFAULT_INDEX_NAME)
+            .unwrap();
+        assert_eq!(
+            check_bundles(&data.data_paths.raw_pages_dir()).status,
+            Status::Warn
+        );
+        assert_eq!(check_index(&index_dir).status, Status::Warn);
+
+        data.index_pages(
+            vec![visited_page("https://a.example/", "A", "<p>Some text</p>")],
+            &[],
+        );
+        let check = check_bundles(&data.data_paths.raw_pages_dir());
+        assert_eq!(check.status, Status::Pass);
+        assert!(check.message.starts_with("1 bundles, "));
+        let check = check_index(&index_dir);
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(check.message, "1 documents");
+
+        fs::write(index_dir.join("meta.json"), "{").unwrap();
+        assert_eq!(check_index(&index_dir).status, Status::Fail);
+    }
+
+    #[test]
+    fn sums_the_sizes_of_the_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a"), "123").unwrap();
+        fs::write(dir.path().join("sub/b"), "45").unwrap();
+        assert_eq!(dir_size(dir.path()), 5);
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+    }
+}
@@ -0,0 +1,32 @@
+use reqwest::Url;
+
+/// Second-level labels used under country-code TLDs, like in "bbc.co.uk" or "abc.net.au"
+const COMMON_SECOND_LEVEL_LABELS: &[&str] = &["co", "com", "org", "net", "ac", "gov", "edu", "ne"];
+
+/// Return the part of the URL's host that a person would consider "the site", like "bbc.co.uk" for
+/// "https://www.bbc.co.uk/news" or "docs.rs" for "https://docs.rs/tokio".
+///
+/// This is a heuristic and not a full implementation of the public suffix list, but it is good
+/// enough to group pages by site.
+pub fn registrable_domain(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    let host = parsed_url.host_str()?.to_lowercase();
+
+    // IP addresses don't have a registrable part
+    if host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[') {
+        return Some(host);
+    }
+
+    let labels: Vec<&str> = host.split('.').filter(|label| !label.is_empty()).collect();
+    let kept_labels = match labels.as_slice() {
+        [.., second_level, top_level]
+            if top_level.len() == 2 && COMMON_SECOND_LEVEL_LABELS.contains(second_level) =>
+        {
+            3
+        }
+        _ => 2,
+    };
+
+    let start = labels.len().saturating_sub(kept_labels);
+    Some(labels[start..].join("."))
+}
@@ -0,0 +1,189 @@
+use crate::config::read_config_file;
+use crate::DataPaths;
+use anyhow::Context;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, USER_AGENT};
+use reqwest::Url;
+use std::time::Duration;
+use toml::{Table, Value};
+use tracing::warn;
+
+/// The section of the configuration file with the download settings of some sites, like
+/// `[domains."reddit.com"]` with `delay-seconds = 5`
+pub const DOMAINS_SECTION: &str = "domains";
+
+/// How to download the pages of a site, overriding the options of download-pages
+#[derive(Debug, Default)]
+pub struct DomainProfile {
+    /// The minimum time between the start of two requests to the site
+    pub delay: Option<Duration>,
+    /// How many requests to do at once to the site, apart from the --parallelism of the others
+    pub parallelism: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// The pages with a larger body are not downloaded
+    pub max_body_size: Option<u64>,
+    /// Sent with each request, including the user agent
+    pub headers: HeaderMap,
+}
+
+/// The download profiles of the sites, from the configuration file
+#[derive(Debug, Default)]
+pub struct DomainProfiles {
+    /// The profiles by their domain, like "reddit.com", which also applies to its subdomains
+    profiles: Vec<(String, DomainProfile)>,
+}
+
+impl DomainProfiles {
+    /// Read the profiles of the configuration file, if there is one. The unknown settings are
+    /// skipped with a warning, like the unknown options of the commands.
+    pub fn load(data_paths: &DataPaths) -> anyhow::Result<Self> {
+        let Some((path, mut table)) = read_config_file(data_paths)? else {
+            return Ok(DomainProfiles::default());
+        };
+        let Some(domains) = table.remove(DOMAINS_SECTION) else {
+            return Ok(DomainProfiles::default());
+        };
+        let Value::Table(domains) = domains else {
+            anyhow::bail!(
+                "[{}] in {} must have one section per domain, like [{}.\"example.com\"]",
+                DOMAINS_SECTION,
+                path.display(),
+                DOMAINS_SECTION
+            );
+        };
+
+        let mut profiles = Vec::new();
+        for (domain, settings) in domains {
+            let section_name = format!("[{}.{:?}]", DOMAINS_SECTION, domain);
+            let Value::Table(settings) = settings else {
+                anyhow::bail!("{} in {} must be a section", section_name, path.display());
+            };
+            let profile = parse_profile(&section_name, settings).with_context(|| {
+                format!("invalid profile {} in {}", section_name, path.display())
+            })?;
+            let domain = domain.trim_start_matches('.').to_lowercase();
+            profiles.push((domain, profile));
+        }
+        Ok(DomainProfiles { profiles })
+    }
+
+    /// The index of the profile of the URL: the one of its host or of the longest domain that
+    /// the host is a subdomain of
+    pub fn resolve(&self, url: &str) -> Option<usize> {
+        let host = Url::parse(url).ok()?.host_str()?.to_lowercase();
+        self.profiles
+            .iter()
+            .enumerate()
+            .filter(|(_, (domain, _))| {
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            })
+            .max_by_key(|(_, (domain, _))| domain.len())
+            .map(|(index, _)| index)
+    }
+
+    /// The domain and the profile at the index given by [DomainProfiles::resolve]
+    pub fn get(&self, index: usize) -> (&str, &DomainProfile) {
+        let (domain, profile) = &self.profiles[index];
+        (domain, profile)
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    /// Print the profiles like in the configuration file, without the values of the headers that
+    /// hold secrets
+    pub fn print(&self) {
+        for (domain, profile) in &self.profiles {
+            println!("\n[{}.{:?}]", DOMAINS_SECTION, domain);
+            if let Some(delay) = profile.delay {
+                println!("delay-seconds = {}", delay.as_secs_f64());
+            }
+            if let Some(parallelism) = profile.parallelism {
+                println!("parallelism = {}", parallelism);
+            }
+            if let Some(timeout) = profile.timeout {
+                println!("timeout-seconds = {}", timeout.as_secs());
+            }
+            if let Some(max_body_size) = profile.max_body_size {
+                println!("max-body-size = {}", max_body_size);
+            }
+            for (name, value) in &profile.headers {
+                let value = if value.is_sensitive() {
+                    "(hidden)".to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                println!("headers.{:?} = {:?}", name.as_str(), value);
+            }
+        }
+    }
+}
+
+fn parse_profile(section_name: &str, settings: Table) -> anyhow::Result<DomainProfile> {
+    let mut profile = DomainProfile::default();
+    for (name, value) in settings {
+        let setting = name.replace('_', "-");
+        match setting.as_str() {
+            "delay-seconds" => {
+                let delay = match value {
+                    Value::Integer(seconds) => seconds as f64,
+                    Value::Float(seconds) => seconds,
+                    _ => anyhow::bail!("delay-seconds must be a number"),
+                };
+                profile.delay = Some(
+                    Duration::try_from_secs_f64(delay)
+                        .ok()
+                        .context("delay-seconds must not be negative")?,
+                );
+            }
+            "parallelism" => profile.parallelism = Some(positive_integer(&setting, &value)?),
+            "timeout-seconds" => {
+                profile.timeout = Some(Duration::from_secs(
+                    positive_integer(&setting, &value)? as u64
+                ))
+            }
+            "max-body-size" => {
+                profile.max_body_size = Some(positive_integer(&setting, &value)? as u64)
+            }
+            "user-agent" => {
+                let user_agent = value.as_str().context("user-agent must be a string")?;
+                profile
+                    .headers
+                    .insert(USER_AGENT, HeaderValue::from_str(user_agent)?);
+            }
+            "headers" => {
+                let Value::Table(headers) = value else {
+                    anyhow::bail!("headers must be a table, like {{ Cookie = \"name=value\" }}");
+                };
+                for (name, value) in headers {
+                    let name = HeaderName::try_from(name.as_str())
+                        .with_context(|| format!("invalid header name {:?}", name))?;
+                    let value = value
+                        .as_str()
+                        .with_context(|| format!("the header {} must be a string", name))?;
+                    let mut value = HeaderValue::from_str(value)
+                        .with_context(|| format!("invalid value of the header {}", name))?;
+                    // So that they are not printed
+                    value.set_sensitive(name == COOKIE || name == AUTHORIZATION);
+                    profile.headers.insert(name, value);
+                }
+            }
+            _ => warn!(
+                "Ignoring the unknown setting {:?} of {}",
+                name, section_name
+            ),
+        }
+    }
+    Ok(profile)
+}
+
+fn positive_integer(setting: &str, value: &Value) -> anyhow::Result<usize> {
+    value
+        .as_integer()
+        .filter(|value| *value > 0)
+        .and_then(|value| usize::try_from(value).ok())
+        .with_context(|| format!("{} must be a positive integer", setting))
+}
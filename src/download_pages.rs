@@ -1,151 +1,1275 @@
+use crate::bundle_cache::{self, read_page_summaries, PageSummary};
+use crate::disk_space::{free_space, is_disk_full, DiskSpaceGuard};
+use crate::domain_profiles::{DomainProfile, DomainProfiles};
+use crate::index_stats::format_size;
+use crate::normalize_url::normalize_history_url;
+use crate::shutdown::shutdown_requested;
 use crate::{
-    list_raw_pages_bundles, read_compressed_json, write_compressed_json, DownloadedPage,
-    DownloadedPageContent, FirefoxHistoryItem, HISTORY_PATH, RAW_PAGES_DIR_PATH,
+    metadata, write_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent,
+    FirefoxHistoryItem,
 };
 use chrono::Utc;
+use clap::{ArgAction, Args, ValueEnum};
+use encoding_rs::{Encoding, UTF_8};
 use rayon::prelude::*;
-use reqwest::blocking::Client;
-use std::collections::HashSet;
+use reqwest::blocking::{Client, Response};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::io::Read;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::{fmt, fs};
+use tracing::{debug, info, info_span, warn};
 
-/// Download all the pages into
-pub fn download_pages(
-    parallelism: usize,
+pub const DEFAULT_PARALLELISM: usize = 10;
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+pub const DEFAULT_BUNDLE_SIZE: usize = 500;
+pub const DEFAULT_MIN_FREE_SPACE_MB: u64 = 1024;
+pub const DEFAULT_DISK_FULL_TIMEOUT_MINUTES: u64 = 30;
+
+/// Added to the name of the bundles, after the time they were written at
+static NEXT_BUNDLE_SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// How long a thread waits before looking again for a page to download, when the limits of the
+/// domain profiles hold back all the pages left
+const WAIT_FOR_PROFILES: Duration = Duration::from_millis(100);
+
+/// How long a thread waits before looking again for a page to download, while the downloads are
+/// paused for the disk to have some space
+const WAIT_FOR_DISK_SPACE: Duration = Duration::from_secs(1);
+
+/// The kinds of failure that are likely to go away, like when the Wi-Fi drops for a moment. The
+/// pages that failed with an HTTP status or that are not text fail the same way again.
+const TRANSIENT_FAILURE_KINDS: [&str; 2] = ["connection", "timeout"];
+
+/// How many requests to do at once in the final retry pass, to be gentle with a network that just
+/// failed
+const FINAL_RETRY_PARALLELISM: usize = 2;
+
+/// The largest local file read for a "file:" URL, unless its profile says otherwise
+const MAX_LOCAL_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
+/// How many days it takes for the priority of a visit to halve
+const RECENCY_HALF_LIFE_DAYS: f64 = 30.0;
+
+/// The priority of a bookmarked page over one that is not, like a page visited a few days ago
+/// over one never visited
+const BOOKMARK_PRIORITY: f64 = 1.0;
+
+/// How many of the first URLs of the queue are shown with --verbose
+const QUEUE_PREVIEW_SIZE: usize = 10;
+
+#[derive(Args, Debug)]
+pub struct DownloadPagesArguments {
+    /// How many requests to do at once
+    #[arg(long, default_value_t = DEFAULT_PARALLELISM)]
+    pub parallelism: usize,
+    /// Time maximum time to wait for each page to answer
+    #[arg(long, default_value_t = DEFAULT_TIMEOUT_SECONDS)]
+    pub timeout_seconds: u64,
+    /// How many pages to store in each bundle
+    #[arg(long, default_value_t = DEFAULT_BUNDLE_SIZE)]
+    pub bundle_size: usize,
+    /// Try once more the pages that failed with a connection error or a timeout, at the end of the
+    /// run. Disable it with "--final-retry-pass false"
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub final_retry_pass: bool,
+    /// Which pages to download first, which matters when the run is interrupted
+    #[arg(long, value_enum, default_value_t = QueueOrder::Priority)]
+    pub order: QueueOrder,
+    /// Print the first URLs of the queue
+    #[arg(long)]
+    pub verbose: bool,
+    /// Pause the downloads when the disk has less free space than this, in MB
+    #[arg(long, default_value_t = DEFAULT_MIN_FREE_SPACE_MB)]
+    pub min_free_space_mb: u64,
+    /// How long to wait for some disk space to be freed before stopping the run, in minutes
+    #[arg(long, default_value_t = DEFAULT_DISK_FULL_TIMEOUT_MINUTES)]
+    pub disk_full_timeout_minutes: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// The pages visited recently or many times and the bookmarked ones first
+    Priority,
+    /// A different order in each run
+    Random,
+    /// The order of the extracted history
+    AsIs,
+}
+
+/// What a run of the downloader did
+#[derive(Clone, Debug, Default)]
+pub struct DownloadSummary {
+    /// The pages of the history downloaded by previous runs, which are not downloaded again
+    pub already_downloaded: usize,
+    pub downloaded: usize,
+    /// The pages that could not be downloaded or that are not text. They are not tried again.
+    pub failed: usize,
+    /// The failed pages by the kind of failure, like "timeout" or "not_text"
+    pub failures_by_kind: BTreeMap<&'static str, usize>,
+    /// How many pages were downloaded with the profile of each domain of the configuration file
+    pub pages_by_profile: BTreeMap<String, usize>,
+    /// The pages not downloaded since their content was already downloaded at another URL
+    pub skipped_equivalent: usize,
+    /// The pages that failed with a transient error and were tried again at the end of the run.
+    /// They are counted once, in `downloaded` or `failed`, with the outcome of the retry.
+    pub retried: usize,
+}
+
+/// The settings shared by all the passes over the pages
+#[derive(Clone, Copy)]
+struct DownloadContext<'a> {
     timeout: Duration,
     bundle_size: usize,
-) -> anyhow::Result<()> {
-    // Detect the pages that were already loaded
-    let bundles = list_raw_pages_bundles()?;
-    let downloaded_urls = Mutex::new(HashSet::new());
-    bundles
-        .into_par_iter()
-        .try_for_each(|path| -> anyhow::Result<()> {
-            let downloaded_pages: Vec<DownloadedPage> = read_compressed_json(&path)?;
-            let mut downloaded_urls = downloaded_urls.lock().unwrap();
-            for page in downloaded_pages {
-                downloaded_urls.insert(page.url);
-            }
-            Ok(())
-        })?;
-    let downloaded_urls = downloaded_urls.into_inner().unwrap();
-    println!(
+    profiles: &'a DomainProfiles,
+    /// The titles and visits of the pages, for the metadata database
+    history_by_url: &'a HashMap<String, FirefoxHistoryItem>,
+    disk_space: &'a DiskSpaceGuard,
+    data_paths: &'a DataPaths,
+}
+
+/// What the threads of a pass over the pages did
+#[derive(Default)]
+struct PassOutcome {
+    downloaded: usize,
+    failures_by_kind: BTreeMap<&'static str, usize>,
+    /// The pages that failed with a transient error, which were not written so that they can be
+    /// tried again
+    to_retry: Vec<(FirefoxHistoryItem, Option<usize>)>,
+}
+
+/// What the bundles tell about the URLs, read from them or from the metadata database
+#[derive(Default)]
+pub struct KnownUrls {
+    /// The URLs with a record in a bundle, whether their download succeeded or not
+    pub downloaded: HashSet<String>,
+    /// The other URLs of the successfully downloaded pages, like where their redirects ended or
+    /// their canonical URL, with the URL of the page
+    pub equivalents: HashMap<String, String>,
+}
+
+/// The pages left to download, handed to the threads so that the limits of the domain profiles
+/// are respected
+struct DownloadQueue {
+    /// The pages with the index of their profile, taken from the end
+    items: Vec<(FirefoxHistoryItem, Option<usize>)>,
+    /// How many downloads are running in each pool: one for each profile with its own
+    /// parallelism and a last one for the other pages
+    running: Vec<usize>,
+    /// When the next download of each profile can start
+    next_start: Vec<Instant>,
+    /// The number of downloads at once of the pages without their own parallelism
+    parallelism: usize,
+}
+
+/// What a thread should do next
+enum NextDownload {
+    Page(FirefoxHistoryItem, Option<usize>),
+    /// All the pages left are held back by the limits of their profile
+    Wait,
+    Done,
+}
+
+/// The page is larger than the max-body-size of its profile
+#[derive(Debug)]
+struct BodyTooLarge(u64);
+
+/// The local file of a "file:" URL could not be read, unlike the I/O errors of the responses
+#[derive(Debug)]
+struct LocalFileError(std::io::Error);
+
+/// Download all the pages into bundles, in the given order. With `final_retry_pass`, the pages that
+/// failed with a connection error or a timeout are tried once more at the end of the run, at a
+/// lower parallelism. When the disk is almost full, the downloads pause until some space is freed,
+/// and the run fails if it's not freed in time.
+pub fn download_pages(
+    arguments: &DownloadPagesArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<DownloadSummary> {
+    let DownloadPagesArguments {
+        parallelism,
+        timeout_seconds,
+        bundle_size,
+        final_retry_pass,
+        order,
+        verbose,
+        min_free_space_mb,
+        disk_full_timeout_minutes,
+    } = *arguments;
+
+    // Detect the pages that were already loaded, from the metadata database when it's up to date,
+    // and otherwise from the summaries of the bundles
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    let bundles_bytes: u64 = bundles
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let known_urls = match metadata::known_urls(data_paths, &bundles) {
+        Some(known_urls) => known_urls,
+        None => {
+            let known_urls = Mutex::new(KnownUrls::default());
+            bundles
+                .into_par_iter()
+                .try_for_each(|path| -> anyhow::Result<()> {
+                    let pages = read_page_summaries(data_paths, &path)?;
+                    let mut known_urls = known_urls.lock().unwrap();
+                    for page in pages {
+                        known_urls.add(page);
+                    }
+                    Ok(())
+                })?;
+            known_urls.into_inner().unwrap()
+        }
+    };
+    info!(
         "Detected that {} URLs were already downloaded",
-        downloaded_urls.len()
+        known_urls.downloaded.len()
     );
 
     // Detect the pages that need to be downloaded
-    let mut history: Vec<FirefoxHistoryItem> = read_compressed_json(Path::new(HISTORY_PATH))?;
-    println!("Read history with {} URLs", history.len());
-    history.retain(|item| !downloaded_urls.contains(&item.url));
-    println!("Prepare to download {} URLs", history.len());
+    let mut history = data_paths.read_history()?;
+    info!("Read history with {} URLs", history.len());
+    let history_len = history.len();
+    history.retain(|item| !known_urls.downloaded.contains(&item.url));
 
-    let history_queue = Mutex::new(history);
+    let mut summary = DownloadSummary {
+        already_downloaded: history_len - history.len(),
+        ..DownloadSummary::default()
+    };
+
+    // The titles and visits of the pages, for the metadata database
+    let history_by_url: HashMap<String, FirefoxHistoryItem> = history
+        .iter()
+        .map(|item| (item.url.clone(), item.clone()))
+        .collect();
 
-    thread::scope(|scope| -> anyhow::Result<()> {
-        // Start all the threads to do the heavy work
+    let disk_space = DiskSpaceGuard::new(
+        data_paths.data_dir().to_path_buf(),
+        min_free_space_mb * 1024 * 1024,
+        Duration::from_secs(disk_full_timeout_minutes * 60),
+    );
+    warn_if_disk_too_small(
+        history.len(),
+        bundles_bytes,
+        known_urls.downloaded.len(),
+        &disk_space,
+    );
+
+    // The pages whose content was downloaded at another URL are recorded without a request
+    let mut skipped_pages = Vec::new();
+    history.retain(|item| match known_urls.equivalents.get(&item.url) {
+        Some(representative) => {
+            debug!(
+                "Skipped {}, already downloaded as {}",
+                item.url, representative
+            );
+            skipped_pages.push(DownloadedPage {
+                url: item.url.clone(),
+                loaded_at: Utc::now(),
+                content: DownloadedPageContent::SkippedEquivalent(representative.clone()),
+                final_url: None,
+                canonical_url: None,
+            });
+            false
+        }
+        None => true,
+    });
+    summary.skipped_equivalent = skipped_pages.len();
+    if !skipped_pages.is_empty() {
+        info!(
+            "Skipped {} URLs whose content was already downloaded at another URL",
+            skipped_pages.len()
+        );
+    }
+    while !skipped_pages.is_empty() {
+        let mut bundle: Vec<_> = skipped_pages
+            .drain(..bundle_size.clamp(1, skipped_pages.len()))
+            .collect();
+        if !write_bundle(&mut bundle, &history_by_url, &disk_space, data_paths)? {
+            bail_disk_full(&disk_space)?;
+        }
+    }
+
+    info!("Prepare to download {} URLs", history.len());
+
+    // The custom settings of some sites, from the configuration file
+    let profiles = DomainProfiles::load(data_paths)?;
+    order_queue(&mut history, order);
+    if let Some(order_value) = order.to_possible_value() {
+        info!("Download the URLs in {} order", order_value.get_name());
+    }
+    if verbose {
+        for item in history.iter().take(QUEUE_PREVIEW_SIZE) {
+            info!("Queued {}", item.url);
+        }
+    }
+    let items: Vec<_> = history
+        .into_iter()
+        // The queue takes the pages from its end
+        .rev()
+        .map(|item| {
+            let profile_index = profiles.resolve(&item.url);
+            (item, profile_index)
+        })
+        .collect();
+    for (_, profile_index) in &items {
+        if let Some(profile_index) = *profile_index {
+            let (domain, _) = profiles.get(profile_index);
+            *summary
+                .pages_by_profile
+                .entry(domain.to_string())
+                .or_default() += 1;
+        }
+    }
+    for (domain, pages) in &summary.pages_by_profile {
+        info!("Using the profile of {} for {} URLs", domain, pages);
+    }
+
+    let context = DownloadContext {
+        timeout: Duration::from_secs(timeout_seconds),
+        bundle_size,
+        profiles: &profiles,
+        history_by_url: &history_by_url,
+        disk_space: &disk_space,
+        data_paths,
+    };
+    let mut outcome = download_pass(&context, items, parallelism, final_retry_pass)?;
+
+    // Transient failures come in bursts, like when the network is down for a minute. After a
+    // shutdown, they are not recorded, so that the next run tries them again.
+    let to_retry = std::mem::take(&mut outcome.to_retry);
+    if !to_retry.is_empty() && !shutdown_requested() && !disk_space.is_exhausted() {
+        info!(
+            "Retrying {} URLs that failed with a connection error or a timeout",
+            to_retry.len()
+        );
+        summary.retried = to_retry.len();
+        let retry_outcome = download_pass(
+            &context,
+            to_retry,
+            FINAL_RETRY_PARALLELISM.min(parallelism),
+            false,
+        )?;
+        info!(
+            "Downloaded {} of the retried URLs",
+            retry_outcome.downloaded
+        );
+        outcome.merge(retry_outcome);
+    }
+    if disk_space.is_exhausted() {
+        bail_disk_full(&disk_space)?;
+    }
+
+    summary.downloaded = outcome.downloaded;
+    summary.failed = outcome.failures_by_kind.values().sum();
+    summary.failures_by_kind = outcome.failures_by_kind;
+    Ok(summary)
+}
+
+/// Sort the pages in the order to download them
+fn order_queue(history: &mut [FirefoxHistoryItem], order: QueueOrder) {
+    match order {
+        QueueOrder::Priority => {
+            let now = Utc::now();
+            history.sort_by(|a, b| download_priority(b, now).total_cmp(&download_priority(a, now)));
+        }
+        QueueOrder::Random => {
+            // The random keys of the standard library change in each run
+            let random_state = RandomState::new();
+            history.sort_by_cached_key(|item| random_state.hash_one(&item.url));
+        }
+        QueueOrder::AsIs => {}
+    }
+}
+
+/// How valuable it is to download the page soon: 1 for a page visited now, halving every 30 days,
+/// plus more for the pages visited many times and for the bookmarked ones
+fn download_priority(item: &FirefoxHistoryItem, now: chrono::DateTime<Utc>) -> f64 {
+    let recency = item.last_visit.map_or(0.0, |last_visit| {
+        let days = (now - last_visit).num_seconds().max(0) as f64 / 86_400.0;
+        0.5_f64.powf(days / RECENCY_HALF_LIFE_DAYS)
+    });
+    // A page visited 50 times gains about 1
+    let frequency = item
+        .visit_count
+        .map_or(0.0, |visits| (visits as f64).ln_1p() / 4.0);
+    let bookmark = if item.bookmarked {
+        BOOKMARK_PRIORITY
+    } else {
+        0.0
+    };
+    recency + frequency + bookmark
+}
+
+/// Download the pages with threads taking them from a queue, until the queue is empty. With
+/// `retry_transient`, the pages that fail with a transient error are returned instead of being
+/// written.
+fn download_pass(
+    context: &DownloadContext,
+    items: Vec<(FirefoxHistoryItem, Option<usize>)>,
+    parallelism: usize,
+    retry_transient: bool,
+) -> anyhow::Result<PassOutcome> {
+    let profiles = context.profiles;
+    let history_queue = Mutex::new(DownloadQueue::new(items, profiles, parallelism));
+
+    thread::scope(|scope| -> anyhow::Result<PassOutcome> {
+        // Start all the threads to do the heavy work, enough for the profiles with their own
+        // parallelism to use it
         let mut threads = Vec::new();
-        for _ in 0..parallelism {
-            let thread_handle =
-                scope.spawn(|| download_pages_thread(timeout, bundle_size, &history_queue));
+        let history_queue = &history_queue;
+        let workers = parallelism
+            + (0..profiles.len())
+                .filter_map(|index| profiles.get(index).1.parallelism)
+                .sum::<usize>();
+        for worker in 0..workers {
+            let thread_handle = scope.spawn(move || {
+                let _span = info_span!("download_worker", worker).entered();
+                download_pages_thread(context, retry_transient, history_queue)
+            });
             threads.push(thread_handle);
         }
 
         // Wait for all threads and propagate errors
+        let mut outcome = PassOutcome::default();
         for thread in threads {
-            thread.join().unwrap()?;
+            outcome.merge(thread.join().unwrap()?);
         }
 
-        Ok(())
-    })?;
-
-    Ok(())
+        Ok(outcome)
+    })
 }
+
 /// Represent each thread that downloads pages
 fn download_pages_thread(
-    timeout: Duration,
-    bundle_size: usize,
-    history_queue: &Mutex<Vec<FirefoxHistoryItem>>,
-) -> anyhow::Result<()> {
+    context: &DownloadContext,
+    retry_transient: bool,
+    history_queue: &Mutex<DownloadQueue>,
+) -> anyhow::Result<PassOutcome> {
+    let DownloadContext {
+        timeout,
+        bundle_size,
+        profiles,
+        history_by_url,
+        disk_space,
+        data_paths,
+    } = *context;
     let mut downloaded_pages = Vec::new();
+    let mut outcome = PassOutcome::default();
+    // What the pages not written yet count for, added to the outcome once they are written
+    let mut unwritten = PassOutcome::default();
     let http_client = Client::builder().timeout(timeout).build()?;
 
-    /// Write the downloaded pages into the disk, cleaning the whole list
-    fn write_downloaded_pages(downloaded_pages: &mut Vec<DownloadedPage>) -> anyhow::Result<()> {
-        if !downloaded_pages.is_empty() {
-            let timestamp = Utc::now().timestamp_nanos();
-            let file_name = format!("{}/{}", RAW_PAGES_DIR_PATH, timestamp);
-            write_compressed_json(Path::new(&file_name), downloaded_pages)?;
-            downloaded_pages.clear();
-            println!("Wrote bundle to {}", file_name);
+    loop {
+        // The pages downloaded so far are still written below
+        if shutdown_requested() || disk_space.is_exhausted() {
+            break;
+        }
+        if disk_space.is_paused() {
+            thread::sleep(WAIT_FOR_DISK_SPACE);
+            continue;
         }
 
-        Ok(())
-    }
-
-    loop {
         // Obtain the next item from the queue
         let next_item;
         let remaining_items;
         {
             let mut history_queue = history_queue.lock().unwrap();
-            next_item = history_queue.pop();
-            remaining_items = history_queue.len();
+            next_item = history_queue.take(profiles);
+            remaining_items = history_queue.items.len();
         }
 
-        if remaining_items > 0 && remaining_items % 1_000 == 0 {
-            println!("{} URLs remaining", remaining_items);
+        if remaining_items > 0
+            && remaining_items % 1_000 == 0
+            && matches!(next_item, NextDownload::Page(..))
+        {
+            info!("{} URLs remaining", remaining_items);
         }
 
         // Download page
         match next_item {
-            None => break,
-            Some(next_item) => {
-                let page = download_page(&http_client, next_item.url);
+            NextDownload::Done => break,
+            NextDownload::Wait => thread::sleep(WAIT_FOR_PROFILES),
+            NextDownload::Page(next_item, profile_index) => {
+                let profile = profile_index.map(|index| profiles.get(index).1);
+                let (page, failure_kind) =
+                    download_page(&http_client, next_item.url.clone(), profile);
+                history_queue
+                    .lock()
+                    .unwrap()
+                    .finish(profiles, profile_index);
+                match failure_kind {
+                    Some(kind) if retry_transient && TRANSIENT_FAILURE_KINDS.contains(&kind) => {
+                        outcome.to_retry.push((next_item, profile_index));
+                        continue;
+                    }
+                    Some(kind) => *unwritten.failures_by_kind.entry(kind).or_default() += 1,
+                    None => unwritten.downloaded += 1,
+                }
                 downloaded_pages.push(page);
 
                 if downloaded_pages.len() >= bundle_size {
-                    write_downloaded_pages(&mut downloaded_pages)?;
+                    if !write_bundle(
+                        &mut downloaded_pages,
+                        history_by_url,
+                        disk_space,
+                        data_paths,
+                    )? {
+                        break;
+                    }
+                    outcome.merge(std::mem::take(&mut unwritten));
                 }
             }
         }
     }
 
-    write_downloaded_pages(&mut downloaded_pages)?;
+    if write_bundle(
+        &mut downloaded_pages,
+        history_by_url,
+        disk_space,
+        data_paths,
+    )? {
+        outcome.merge(unwritten);
+    } else {
+        info!(
+            "Dropped {} pages that could not be written, they will be downloaded again by the next \
+             run",
+            downloaded_pages.len()
+        );
+    }
+    Ok(outcome)
+}
+
+/// Write the pages into a new bundle like [write_downloaded_pages], waiting for some space when
+/// the disk is almost full. Return false, leaving the pages in the list, when the space was not
+/// freed in time.
+fn write_bundle(
+    downloaded_pages: &mut Vec<DownloadedPage>,
+    history_by_url: &HashMap<String, FirefoxHistoryItem>,
+    disk_space: &DiskSpaceGuard,
+    data_paths: &DataPaths,
+) -> anyhow::Result<bool> {
+    write_waiting_for_space(downloaded_pages, disk_space, |downloaded_pages| {
+        write_downloaded_pages(downloaded_pages, Some(history_by_url), data_paths)
+    })
+}
+
+/// Write the pages with the writer, which must clean the list on success, waiting for some space
+/// after each write that failed because the disk is full. Return false when the space was not
+/// freed in time.
+fn write_waiting_for_space(
+    downloaded_pages: &mut Vec<DownloadedPage>,
+    disk_space: &DiskSpaceGuard,
+    mut write: impl FnMut(&mut Vec<DownloadedPage>) -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    if downloaded_pages.is_empty() {
+        return Ok(true);
+    }
+    let deadline = disk_space.deadline();
+    let mut write_failed = false;
+    loop {
+        if !disk_space.wait_for_space(write_failed, deadline) {
+            return Ok(false);
+        }
+        match write(downloaded_pages) {
+            Ok(()) => return Ok(true),
+            Err(error) if is_disk_full(&error) => write_failed = true,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Warn when the pages left will likely not fit on the disk, from the average size of the pages
+/// downloaded before
+fn warn_if_disk_too_small(
+    pending_urls: usize,
+    bundles_bytes: u64,
+    downloaded_urls: usize,
+    disk_space: &DiskSpaceGuard,
+) {
+    if pending_urls == 0 || downloaded_urls == 0 {
+        return;
+    }
+    let Some(free_bytes) = free_space(disk_space.dir()) else {
+        return;
+    };
+    let needed_bytes = bundles_bytes / downloaded_urls as u64 * pending_urls as u64;
+    if free_bytes < needed_bytes + disk_space.min_free_bytes() {
+        warn!(
+            "The {} URLs left need about {}, but the disk only has {} free: the downloads will \
+             pause when it has less than {}",
+            pending_urls,
+            format_size(needed_bytes),
+            format_size(free_bytes),
+            format_size(disk_space.min_free_bytes())
+        );
+    } else {
+        debug!(
+            "The {} URLs left need about {}, with {} free",
+            pending_urls,
+            format_size(needed_bytes),
+            format_size(free_bytes)
+        );
+    }
+}
+
+/// Fail the run since the disk stayed almost full
+fn bail_disk_full(disk_space: &DiskSpaceGuard) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "the disk had less than {} free for too long, free some space and run again: the pages \
+         not written will be downloaded then",
+        format_size(disk_space.min_free_bytes())
+    )
+}
+
+/// Write the downloaded pages into a new bundle, cleaning the whole list. The history gives the
+/// titles and visits of the pages to the metadata database.
+pub fn write_downloaded_pages(
+    downloaded_pages: &mut Vec<DownloadedPage>,
+    history_by_url: Option<&HashMap<String, FirefoxHistoryItem>>,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    if !downloaded_pages.is_empty() {
+        // The threads can write at the same time, so the time alone is not unique
+        let sequence = NEXT_BUNDLE_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{}-{}", Utc::now().timestamp_micros(), sequence);
+        let path = data_paths.raw_pages_dir().join(name);
+        write_compressed_json(&path, downloaded_pages)?;
+        metadata::record_bundle(data_paths, &path, downloaded_pages, history_by_url);
+        bundle_cache::record_bundle(data_paths, &path, downloaded_pages);
+        downloaded_pages.clear();
+        debug!("Wrote bundle to {}", path.display());
+    }
+
     Ok(())
 }
 
-fn download_page(http_client: &Client, url: String) -> DownloadedPage {
-    let content = match try_download_page(http_client, &url) {
-        Ok(content) => content,
-        Err(error) => DownloadedPageContent::Failure(error.to_string()),
+/// Download a single page now with the settings of its profile, without writing it into a bundle
+pub fn fetch_page(
+    url: String,
+    timeout: Duration,
+    data_paths: &DataPaths,
+) -> anyhow::Result<(DownloadedPage, Option<&'static str>)> {
+    let profiles = DomainProfiles::load(data_paths)?;
+    let profile = profiles.resolve(&url).map(|index| profiles.get(index).1);
+    let http_client = Client::builder().timeout(timeout).build()?;
+    Ok(download_page(&http_client, url, profile))
+}
+
+/// Download the page with the settings of its profile, returning the kind of failure if it failed
+fn download_page(
+    http_client: &Client,
+    url: String,
+    profile: Option<&DomainProfile>,
+) -> (DownloadedPage, Option<&'static str>) {
+    // The local documents opened in the browser are read from the disk
+    let result = if url.starts_with("file:") {
+        read_local_page(&url, profile)
+    } else {
+        try_download_page(http_client, &url, profile)
     };
+    let (content, final_url, failure_kind) = match result {
+        Ok((content @ DownloadedPageContent::Failure(_), final_url)) => {
+            (content, Some(final_url), Some("not_text"))
+        }
+        Ok((content, final_url)) => (content, Some(final_url), None),
+        Err(error) => (
+            DownloadedPageContent::Failure(error.to_string()),
+            None,
+            Some(failure_kind(&error)),
+        ),
+    };
+    match &content {
+        DownloadedPageContent::Failure(error) => debug!("Failed to download {}: {}", url, error),
+        _ => debug!("Downloaded {}", url),
+    }
 
-    DownloadedPage {
+    // The other URLs of the page, so that they are not downloaded again
+    let canonical_url = match (&content, &final_url) {
+        (DownloadedPageContent::Html(source), Some(final_url)) => {
+            declared_canonical_url(source, final_url)
+        }
+        _ => None,
+    };
+    let final_url = final_url.map(|mut final_url| {
+        normalize_history_url(&mut final_url);
+        final_url.to_string()
+    });
+    let page = DownloadedPage {
+        final_url: final_url.filter(|final_url| *final_url != url),
+        canonical_url: canonical_url.filter(|canonical_url| *canonical_url != url),
         url,
         loaded_at: Utc::now(),
         content,
+    };
+    (page, failure_kind)
+}
+
+/// The canonical URL declared in the head of the page, resolved and normalized like the URLs of
+/// the history
+fn declared_canonical_url(html: &str, page_url: &Url) -> Option<String> {
+    // The head is enough, and much faster to parse than the whole page
+    let head_end = html
+        .as_bytes()
+        .windows(b"</head>".len())
+        .position(|window| window.eq_ignore_ascii_case(b"</head>"))
+        .unwrap_or(html.len());
+    let head = Html::parse_document(&html[..head_end]);
+    let selector = Selector::parse("link[rel][href]").ok()?;
+    let href = head
+        .select(&selector)
+        .find(|element| {
+            element.value().attr("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+            })
+        })?
+        .value()
+        .attr("href")?
+        .trim();
+    if href.is_empty() {
+        return None;
     }
+    let mut canonical_url = page_url.join(href).ok()?;
+    normalize_history_url(&mut canonical_url);
+    Some(canonical_url.to_string())
 }
 
-fn try_download_page(http_client: &Client, url: &str) -> anyhow::Result<DownloadedPageContent> {
-    let response = http_client.get(url).send()?.error_for_status()?;
+/// Like "timeout" or "http_status", to count the failures by kind
+fn failure_kind(error: &anyhow::Error) -> &'static str {
+    if error.is::<BodyTooLarge>() {
+        return "too_large";
+    }
+    if let Some(LocalFileError(error)) = error.downcast_ref() {
+        return match error.kind() {
+            std::io::ErrorKind::NotFound => "missing_file",
+            _ => "unreadable_file",
+        };
+    }
+    // Reading the body of a response fails with an I/O error, which may wrap the request error
+    let io_error = error.downcast_ref::<std::io::Error>();
+    let request_error = error
+        .downcast_ref::<reqwest::Error>()
+        .or_else(|| io_error?.get_ref()?.downcast_ref::<reqwest::Error>());
+    // Like a body that ends before its length, when the connection is cut
+    let connection_lost = error.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|cause| {
+            matches!(
+                cause.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            )
+        })
+    });
+    match request_error {
+        Some(error) if error.is_timeout() => "timeout",
+        Some(error) if error.is_status() => "http_status",
+        Some(error) if error.is_connect() => "connection",
+        Some(error) if error.is_redirect() => "redirect",
+        _ if connection_lost => "connection",
+        Some(error) if error.is_decode() || error.is_body() => "invalid_body",
+        None if io_error.is_some_and(|error| error.kind() == std::io::ErrorKind::TimedOut) => {
+            "timeout"
+        }
+        _ => "other",
+    }
+}
 
-    let is_html = response
+fn try_download_page(
+    http_client: &Client,
+    url: &str,
+    profile: Option<&DomainProfile>,
+) -> anyhow::Result<(DownloadedPageContent, Url)> {
+    let mut request = http_client.get(url);
+    let mut max_body_size = None;
+    if let Some(profile) = profile {
+        request = request.headers(profile.headers.clone());
+        if let Some(timeout) = profile.timeout {
+            request = request.timeout(timeout);
+        }
+        max_body_size = profile.max_body_size;
+    }
+    let response = request.send()?.error_for_status()?;
+    let final_url = response.url().clone();
+
+    let content_type = response
         .headers()
         .get("Content-Type")
         .and_then(|value| value.to_str().ok())
-        .map(|content_type| content_type.starts_with("text/html"))
-        .unwrap_or(false);
+        .unwrap_or_default()
+        .to_string();
+    // Markdown files are often served as plain text, like by raw.githubusercontent.com
+    let is_markdown_path = Path::new(response.url().path())
+        .extension()
+        .is_some_and(|extension| extension == "md" || extension == "markdown");
 
-    if is_html {
-        let content = response.text()?;
-        Ok(DownloadedPageContent::Html(content))
+    let content = if content_type.starts_with("text/html") {
+        DownloadedPageContent::Html(read_text(response, max_body_size)?)
+    } else if content_type.starts_with("text/markdown")
+        || content_type.starts_with("text/x-markdown")
+        || (content_type.starts_with("text/plain") && is_markdown_path)
+    {
+        DownloadedPageContent::Markdown(read_text(response, max_body_size)?)
+    } else if content_type.starts_with("text/plain") {
+        DownloadedPageContent::PlainText(read_text(response, max_body_size)?)
     } else {
-        Ok(DownloadedPageContent::Failure(
-            "Page is not HTML".to_string(),
-        ))
+        DownloadedPageContent::Failure("Page is not HTML, markdown or plain text".to_string())
+    };
+    Ok((content, final_url))
+}
+
+/// Read the local file of a "file:" URL, telling its kind from its extension or from its start.
+/// Symbolic links are followed.
+fn read_local_page(
+    url: &str,
+    profile: Option<&DomainProfile>,
+) -> anyhow::Result<(DownloadedPageContent, Url)> {
+    let parsed_url = Url::parse(url)?;
+    // The path is percent-decoded into bytes, so that the paths that are not UTF-8 work too
+    let path = parsed_url
+        .to_file_path()
+        .map_err(|_| anyhow::anyhow!("{} is not a path of this computer", url))?;
+    let max_size = profile
+        .and_then(|profile| profile.max_body_size)
+        .unwrap_or(MAX_LOCAL_FILE_SIZE);
+
+    let file = fs::File::open(&path).map_err(LocalFileError)?;
+    if file.metadata().map_err(LocalFileError)?.len() > max_size {
+        return Err(BodyTooLarge(max_size).into());
+    }
+    let mut body = Vec::new();
+    file.take(max_size + 1)
+        .read_to_end(&mut body)
+        .map_err(LocalFileError)?;
+    if body.len() as u64 > max_size {
+        return Err(BodyTooLarge(max_size).into());
+    }
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let start = String::from_utf8_lossy(&body[..body.len().min(512)])
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_lowercase();
+    let content = match extension.as_str() {
+        "html" | "htm" | "xhtml" => DownloadedPageContent::Html(decode_body(&body, "")),
+        "md" | "markdown" => DownloadedPageContent::Markdown(decode_body(&body, "")),
+        "txt" | "text" => DownloadedPageContent::PlainText(decode_body(&body, "")),
+        _ if body.starts_with(b"%PDF-") => {
+            DownloadedPageContent::Failure("PDF files are not supported".to_string())
+        }
+        _ if start.starts_with("<!doctype html") || start.starts_with("<html") => {
+            DownloadedPageContent::Html(decode_body(&body, ""))
+        }
+        // Text files have no null bytes, unlike most binary files
+        _ if std::str::from_utf8(&body).is_ok() && !body.contains(&0) => {
+            DownloadedPageContent::PlainText(decode_body(&body, ""))
+        }
+        _ => DownloadedPageContent::Failure("File is not HTML, markdown or plain text".to_string()),
+    };
+    Ok((content, parsed_url))
+}
+
+/// The text of the body, decoded from the charset of the response like [Response::text] does, but
+/// failing when the body is larger than the maximum size
+fn read_text(response: Response, max_body_size: Option<u64>) -> anyhow::Result<String> {
+    let Some(max_body_size) = max_body_size else {
+        return Ok(response.text()?);
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_body_size)
+    {
+        return Err(BodyTooLarge(max_body_size).into());
+    }
+
+    let content_type = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mut body = Vec::new();
+    response.take(max_body_size + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > max_body_size {
+        return Err(BodyTooLarge(max_body_size).into());
+    }
+    Ok(decode_body(&body, &content_type))
+}
+
+/// Decode the body from the charset of its content type, or from UTF-8
+pub fn decode_body(body: &[u8], content_type: &str) -> String {
+    let encoding = content_type
+        .split(';')
+        .find_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))?
+        })
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+impl PassOutcome {
+    fn merge(&mut self, other: PassOutcome) {
+        self.downloaded += other.downloaded;
+        for (kind, failed) in other.failures_by_kind {
+            *self.failures_by_kind.entry(kind).or_default() += failed;
+        }
+        self.to_retry.extend(other.to_retry);
+    }
+}
+
+impl KnownUrls {
+    /// Add the URLs of a page of a bundle
+    fn add(&mut self, page: PageSummary) {
+        if page.success {
+            for other_url in [page.final_url, page.canonical_url].into_iter().flatten() {
+                self.equivalents.insert(other_url, page.url.clone());
+            }
+        }
+        self.downloaded.insert(page.url);
+    }
+}
+
+impl DownloadQueue {
+    fn new(
+        items: Vec<(FirefoxHistoryItem, Option<usize>)>,
+        profiles: &DomainProfiles,
+        parallelism: usize,
+    ) -> Self {
+        DownloadQueue {
+            items,
+            running: vec![0; profiles.len() + 1],
+            next_start: vec![Instant::now(); profiles.len()],
+            parallelism,
+        }
+    }
+
+    /// The last page whose profile allows to download it now
+    fn take(&mut self, profiles: &DomainProfiles) -> NextDownload {
+        if self.items.is_empty() {
+            return NextDownload::Done;
+        }
+        let now = Instant::now();
+        let position = self.items.iter().rposition(|(_, profile_index)| {
+            let pool = self.pool(profiles, *profile_index);
+            self.running[pool] < self.pool_size(profiles, pool)
+                && profile_index.is_none_or(|index| self.next_start[index] <= now)
+        });
+        let Some(position) = position else {
+            return NextDownload::Wait;
+        };
+
+        let (item, profile_index) = self.items.remove(position);
+        let pool = self.pool(profiles, profile_index);
+        self.running[pool] += 1;
+        if let Some(index) = profile_index {
+            if let Some(delay) = profiles.get(index).1.delay {
+                self.next_start[index] = now + delay;
+            }
+        }
+        NextDownload::Page(item, profile_index)
+    }
+
+    /// Tell that the download of a page taken from the queue is over
+    fn finish(&mut self, profiles: &DomainProfiles, profile_index: Option<usize>) {
+        let pool = self.pool(profiles, profile_index);
+        self.running[pool] -= 1;
+    }
+
+    /// The pool of the downloads that the page counts in
+    fn pool(&self, profiles: &DomainProfiles, profile_index: Option<usize>) -> usize {
+        match profile_index {
+            Some(index) if profiles.get(index).1.parallelism.is_some() => index,
+            _ => profiles.len(),
+        }
+    }
+
+    fn pool_size(&self, profiles: &DomainProfiles, pool: usize) -> usize {
+        if pool == profiles.len() {
+            self.parallelism
+        } else {
+            profiles.get(pool).1.parallelism.unwrap_or(self.parallelism)
+        }
+    }
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the body is larger than {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+impl fmt::Display for LocalFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for LocalFileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{downloaded_page, TestData};
+    use std::sync::atomic::AtomicBool;
+
+    fn pages() -> Vec<DownloadedPage> {
+        vec![downloaded_page(
+            "https://example.com/",
+            DownloadedPageContent::Html("<p>Text</p>".to_string()),
+        )]
+    }
+
+    fn disk_full() -> anyhow::Error {
+        anyhow::Error::from(std::io::Error::from_raw_os_error(libc::ENOSPC))
+            .context("failed to write the bundle")
+    }
+
+    /// A guard that never finds the disk too low by itself, so that only the failed writes pause
+    fn disk_space(data: &TestData, timeout: Duration) -> DiskSpaceGuard {
+        DiskSpaceGuard::new(data.data_paths.raw_pages_dir(), 0, timeout)
+    }
+
+    #[test]
+    fn writes_the_bundle() {
+        let data = TestData::new();
+        fs::create_dir_all(data.data_paths.raw_pages_dir()).unwrap();
+        let disk_space = disk_space(&data, Duration::ZERO);
+        let mut pages = pages();
+        assert!(write_bundle(&mut pages, &HashMap::new(), &disk_space, &data.data_paths).unwrap());
+        assert!(pages.is_empty());
+        assert_eq!(data.data_paths.list_raw_pages_bundles().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pauses_and_resumes_after_a_full_disk() {
+        let data = TestData::new();
+        let disk_space = disk_space(&data, Duration::from_millis(200));
+        let seen_paused = AtomicBool::new(false);
+        let written = AtomicBool::new(false);
+        let mut pages = pages();
+        let mut writes = 0;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while !written.load(Ordering::Relaxed) {
+                    if disk_space.is_paused() {
+                        seen_paused.store(true, Ordering::Relaxed);
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+            });
+            let result = write_waiting_for_space(&mut pages, &disk_space, |pages| {
+                writes += 1;
+                if writes == 1 {
+                    return Err(disk_full());
+                }
+                pages.clear();
+                Ok(())
+            });
+            written.store(true, Ordering::Relaxed);
+            assert!(result.unwrap());
+        });
+        assert_eq!(writes, 2);
+        assert!(pages.is_empty());
+        assert!(seen_paused.load(Ordering::Relaxed));
+        assert!(!disk_space.is_paused());
+        assert!(!disk_space.is_exhausted());
+    }
+
+    #[test]
+    fn gives_up_when_the_disk_stays_full() {
+        let data = TestData::new();
+        let disk_space = disk_space(&data, Duration::from_millis(50));
+        let mut pages = pages();
+        let mut writes = 0;
+        let written = write_waiting_for_space(&mut pages, &disk_space, |_| {
+            writes += 1;
+            Err(disk_full())
+        });
+        assert!(!written.unwrap());
+        assert_eq!(writes, 2);
+        // Kept for the message about the pages not written
+        assert_eq!(pages.len(), 1);
+        assert!(disk_space.is_exhausted());
+        assert!(bail_disk_full(&disk_space).is_err());
+
+        // The other threads stop waiting too
+        let written = write_waiting_for_space(&mut pages, &disk_space, |_| unreachable!());
+        assert!(!written.unwrap());
+    }
+
+    #[test]
+    fn fails_on_the_other_errors() {
+        let data = TestData::new();
+        let disk_space = disk_space(&data, Duration::from_secs(60));
+        let mut pages = pages();
+        let mut writes = 0;
+        let written = write_waiting_for_space(&mut pages, &disk_space, |_| {
+            writes += 1;
+            anyhow::bail!("permission denied")
+        });
+        assert!(written.is_err());
+        assert_eq!(writes, 1);
+        assert!(!disk_space.is_paused());
+    }
+
+    /// The kind and the text of the local file, like "html: <p>Text</p>"
+    fn read_local(url: &str, profile: Option<&DomainProfile>) -> anyhow::Result<String> {
+        let (content, _) = read_local_page(url, profile)?;
+        Ok(match content {
+            DownloadedPageContent::Html(text) => format!("html: {}", text),
+            DownloadedPageContent::Markdown(text) => format!("markdown: {}", text),
+            DownloadedPageContent::PlainText(text) => format!("text: {}", text),
+            DownloadedPageContent::Failure(error) => format!("failure: {}", error),
+            _ => "other".to_string(),
+        })
+    }
+
+    fn file_url(path: &Path) -> String {
+        Url::from_file_path(path).unwrap().to_string()
+    }
+
+    #[test]
+    fn reads_the_local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            file_url(&path)
+        };
+
+        let url = file("page.html", b"<p>Text</p>");
+        assert_eq!(read_local(&url, None).unwrap(), "html: <p>Text</p>");
+        let url = file("page", b"\xef\xbb\xbf <!DOCTYPE html><p>Text</p>");
+        assert!(read_local(&url, None).unwrap().starts_with("html: "));
+        let url = file("notes.md", b"# Notes");
+        assert_eq!(read_local(&url, None).unwrap(), "markdown: # Notes");
+        let url = file("notes.txt", b"Some notes");
+        assert_eq!(read_local(&url, None).unwrap(), "text: Some notes");
+        let url = file("LICENSE", b"Some license");
+        assert_eq!(read_local(&url, None).unwrap(), "text: Some license");
+
+        let url = file("image.png", b"\x89PNG\r\n\x1a\n\0\0");
+        assert_eq!(
+            read_local(&url, None).unwrap(),
+            "failure: File is not HTML, markdown or plain text"
+        );
+        let url = file("paper.pdf", b"%PDF-1.7\n");
+        assert_eq!(
+            read_local(&url, None).unwrap(),
+            "failure: PDF files are not supported"
+        );
+    }
+
+    #[test]
+    fn fails_on_the_missing_and_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = read_local(&file_url(&dir.path().join("missing.html")), None).unwrap_err();
+        assert_eq!(failure_kind(&error), "missing_file");
+        let error = read_local(&file_url(dir.path()), None).unwrap_err();
+        assert_eq!(failure_kind(&error), "unreadable_file");
+
+        let path = dir.path().join("large.txt");
+        fs::write(&path, "0123456789").unwrap();
+        let profile = DomainProfile {
+            max_body_size: Some(9),
+            ..DomainProfile::default()
+        };
+        let error = read_local(&file_url(&path), Some(&profile)).unwrap_err();
+        assert_eq!(failure_kind(&error), "too_large");
+        let profile = DomainProfile {
+            max_body_size: Some(10),
+            ..DomainProfile::default()
+        };
+        assert_eq!(
+            read_local(&file_url(&path), Some(&profile)).unwrap(),
+            "text: 0123456789"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_the_unusual_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("real")).unwrap();
+        fs::write(dir.path().join("real/page.html"), "<p>Text</p>").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("link")).unwrap();
+        let url = file_url(&dir.path().join("link/page.html"));
+        assert_eq!(read_local(&url, None).unwrap(), "html: <p>Text</p>");
+
+        // "café.txt" in Latin-1
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(dir.path().join(name), "Some notes").unwrap();
+        let url = format!("{}/caf%E9.txt", file_url(dir.path()));
+        assert_eq!(read_local(&url, None).unwrap(), "text: Some notes");
+    }
+
+    /// Serve the start of a page and then leave the connection to the function, returning the
+    /// failures of reading the page with a limited and an unlimited body size
+    fn read_failures(after_start: fn(std::net::TcpStream)) -> Vec<&'static str> {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for maybe_stream in listener.incoming() {
+                let mut stream = maybe_stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\
+                        Content-Length: 1000\r\n\r\n<p>The start",
+                    )
+                    .unwrap();
+                thread::spawn(move || after_start(stream));
+            }
+        });
+
+        let http_client = Client::builder()
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        [Some(10_000), None]
+            .into_iter()
+            .map(|max_body_size| {
+                let response = http_client.get(&url).send().unwrap();
+                failure_kind(&read_text(response, max_body_size).unwrap_err())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retries_the_interrupted_bodies() {
+        let failures = read_failures(|stream| {
+            thread::sleep(Duration::from_secs(2));
+            drop(stream);
+        });
+        assert_eq!(failures, ["timeout", "timeout"]);
+
+        let failures = read_failures(drop);
+        assert_eq!(failures, ["connection", "connection"]);
     }
 }
@@ -0,0 +1,129 @@
+use crate::domain::registrable_domain;
+use crate::{read_compressed_json, write_compressed_json, DataPaths};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// After this many indexing runs in a row where a URL extracted to no text, the runs with
+/// --only-new stop extracting it again
+pub const MAX_CONSECUTIVE_EMPTY: u32 = 3;
+
+#[derive(Args, Debug)]
+pub struct ListEmptyPagesArguments {
+    /// Only list the pages that extracted to no text in at least this many indexing runs in a row
+    #[arg(long, default_value_t = MAX_CONSECUTIVE_EMPTY)]
+    min_consecutive: u32,
+}
+
+/// A page whose downloads extract to no text, usually because it's rendered by JavaScript or is
+/// an image
+#[derive(Deserialize, Serialize)]
+struct EmptyPage {
+    url: String,
+    /// When an indexing run last extracted it
+    last_checked: DateTime<Utc>,
+    consecutive_empty_count: u32,
+}
+
+/// The pages that extracted to no text in the last indexing runs, by URL
+#[derive(Default)]
+pub struct EmptyPages {
+    pages: BTreeMap<String, EmptyPage>,
+}
+
+impl EmptyPages {
+    /// Read the pages recorded by the previous indexing runs, none before the first one
+    pub fn read(data_paths: &DataPaths) -> anyhow::Result<Self> {
+        let path = data_paths.empty_pages();
+        if !path.exists() {
+            return Ok(EmptyPages::default());
+        }
+        let pages: Vec<EmptyPage> = read_compressed_json(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(EmptyPages {
+            pages: pages
+                .into_iter()
+                .map(|page| (page.url.clone(), page))
+                .collect(),
+        })
+    }
+
+    pub fn write(&self, data_paths: &DataPaths) -> anyhow::Result<()> {
+        let pages: Vec<&EmptyPage> = self.pages.values().collect();
+        write_compressed_json(&data_paths.empty_pages(), &pages)
+    }
+
+    /// The URLs that are not worth extracting again
+    pub fn consistently_empty(&self) -> HashSet<String> {
+        self.pages
+            .values()
+            .filter(|page| page.consecutive_empty_count >= MAX_CONSECUTIVE_EMPTY)
+            .map(|page| page.url.clone())
+            .collect()
+    }
+
+    /// Record the URLs extracted by a run: the ones with some text are forgotten, and the others
+    /// count one more empty run
+    pub fn update(
+        &mut self,
+        empty_urls: HashSet<String>,
+        non_empty_urls: &HashSet<String>,
+        checked_at: DateTime<Utc>,
+    ) {
+        for url in non_empty_urls {
+            self.pages.remove(url);
+        }
+        for url in empty_urls {
+            if non_empty_urls.contains(&url) {
+                continue;
+            }
+            let page = self.pages.entry(url.clone()).or_insert(EmptyPage {
+                url,
+                last_checked: checked_at,
+                consecutive_empty_count: 0,
+            });
+            page.last_checked = checked_at;
+            page.consecutive_empty_count += 1;
+        }
+    }
+}
+
+/// Print the pages that extract to no text, grouped by domain, from the domain with most of them
+pub fn list_empty_pages(
+    arguments: ListEmptyPagesArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let empty_pages = EmptyPages::read(data_paths)?;
+    let mut pages_by_domain: BTreeMap<String, Vec<&EmptyPage>> = BTreeMap::new();
+    for page in empty_pages.pages.values() {
+        if page.consecutive_empty_count >= arguments.min_consecutive {
+            let domain = registrable_domain(&page.url).unwrap_or_else(|| "(no domain)".to_string());
+            pages_by_domain.entry(domain).or_default().push(page);
+        }
+    }
+
+    if pages_by_domain.is_empty() {
+        println!(
+            "No pages extracted to no text in {} indexing runs in a row",
+            arguments.min_consecutive
+        );
+        return Ok(());
+    }
+
+    let mut domains: Vec<(String, Vec<&EmptyPage>)> = pages_by_domain.into_iter().collect();
+    domains.sort_by_key(|(_, pages)| std::cmp::Reverse(pages.len()));
+    for (domain, pages) in domains {
+        println!("{} ({} pages)", domain, pages.len());
+        for page in pages {
+            println!(
+                "  {} (empty in {} runs, last on {})",
+                page.url,
+                page.consecutive_empty_count,
+                page.last_checked.format("%Y-%m-%d")
+            );
+        }
+    }
+    Ok(())
+}
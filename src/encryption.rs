@@ -0,0 +1,408 @@
+use crate::integrity::{append_checksum, verify_checksum, write_atomically};
+use crate::{bundle_cache, metadata, DataPaths};
+use anyhow::Context;
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// The start of the encrypted files, which tells them apart from the zstd files that are not
+/// encrypted
+const ENCRYPTED_MAGIC: &[u8; 8] = b"MSENC\0\0\x01";
+const NONCE_LENGTH: usize = 24;
+const SALT_LENGTH: usize = 16;
+/// Encrypted with the key in the settings, to tell a wrong passphrase from a corrupt file
+const KEY_CHECK_PLAINTEXT: &[u8] = b"mind-search";
+/// The variable with the path of a file holding the passphrase, to not be asked for it
+const KEY_FILE_VARIABLE: &str = "MIND_SEARCH_KEY_FILE";
+
+/// What is needed to derive the key again, stored in the data directory
+#[derive(Serialize, Deserialize)]
+struct EncryptionSettings {
+    /// The salt of the Argon2id derivation, in hexadecimal
+    salt: String,
+    /// [KEY_CHECK_PLAINTEXT] encrypted with the key, in hexadecimal
+    key_check: String,
+}
+
+/// The encryption of the data directory in use, set by [configure]
+struct Encryption {
+    settings_path: PathBuf,
+    settings: EncryptionSettings,
+    /// Derived the first time a file is encrypted or decrypted, asking for the passphrase once
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+static ENCRYPTION: Mutex<Option<Encryption>> = Mutex::new(None);
+
+/// Read the encryption settings of the data directory, if it is encrypted. With `enable`, an
+/// unencrypted data directory becomes encrypted: the files written from now on are encrypted with
+/// a new passphrase.
+pub fn configure(data_paths: &DataPaths, enable: bool) -> anyhow::Result<()> {
+    let settings_path = data_paths.encryption_settings();
+    let (settings, cipher) = if settings_path.exists() {
+        let content = fs::read_to_string(&settings_path)?;
+        let settings: EncryptionSettings = serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse {}", settings_path.display()))?;
+        (settings, None)
+    } else if enable {
+        let passphrase = read_new_passphrase()?;
+        let (settings, cipher) = new_settings(&passphrase)?;
+        write_atomically(&settings_path, serde_json::to_string(&settings)?.as_bytes())?;
+        // They would keep the URLs and the titles in clear
+        metadata::remove(data_paths)?;
+        bundle_cache::remove(data_paths)?;
+        remove_firefox_database(data_paths)?;
+        info!(
+            "The data is now encrypted, with the settings in {}",
+            settings_path.display()
+        );
+        if data_paths.separate_indexes_dir.is_none() {
+            warn!(
+                "The indexes are not encrypted, give --indexes-dir to keep them on an \
+                encrypted file system"
+            );
+        }
+        (settings, Some(cipher))
+    } else {
+        return Ok(());
+    };
+
+    *ENCRYPTION.lock().unwrap() = Some(Encryption {
+        settings_path,
+        settings,
+        cipher,
+    });
+    Ok(())
+}
+
+/// Whether the files written must be encrypted
+pub fn is_enabled() -> bool {
+    ENCRYPTION.lock().unwrap().is_some()
+}
+
+/// Whether the content of the file is encrypted, rather than only compressed
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(ENCRYPTED_MAGIC)
+}
+
+/// Encrypt the compressed content, asking for the passphrase the first time
+pub fn encrypt(compressed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    encrypt_with(&cipher()?, compressed)
+}
+
+/// Decrypt the content written by [encrypt], asking for the passphrase the first time
+pub fn decrypt(content: &[u8]) -> anyhow::Result<Vec<u8>> {
+    decrypt_with(&cipher()?, content)
+}
+
+/// Encrypt the history, the bundles, the list of empty pages, the notes and the saved searches
+/// written before the encryption was enabled. The indexes and the boilerplate lines stay as they
+/// are.
+pub fn encrypt_data(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let (encrypted_files, total_files) = encrypt_files(&cipher()?, data_paths)?;
+    println!(
+        "Encrypted {} files, {} were already encrypted",
+        encrypted_files,
+        total_files - encrypted_files
+    );
+    println!(
+        "The indexes and the boilerplate lines are not encrypted: delete them and run \
+        index-contents with --indexes-dir on an encrypted file system to keep the indexes private \
+        too, without --strip-repeated-boilerplate"
+    );
+    Ok(())
+}
+
+/// Encrypt the files that are not encrypted yet, returning how many were out of how many files
+fn encrypt_files(
+    cipher: &XChaCha20Poly1305,
+    data_paths: &DataPaths,
+) -> anyhow::Result<(usize, usize)> {
+    remove_firefox_database(data_paths)?;
+
+    let mut paths = Vec::new();
+    if data_paths.history().exists() {
+        paths.push(data_paths.history());
+    }
+    if data_paths.empty_pages().exists() {
+        paths.push(data_paths.empty_pages());
+    }
+    paths.extend(data_paths.list_raw_pages_bundles()?);
+
+    let encrypted_files = AtomicUsize::new(0);
+    paths
+        .par_iter()
+        .try_for_each(|path| -> anyhow::Result<()> {
+            let content = fs::read(path)?;
+            let (compressed, _) = verify_checksum(&content)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if is_encrypted(compressed) {
+                return Ok(());
+            }
+            let mut encrypted = encrypt_with(cipher, compressed)?;
+            append_checksum(&mut encrypted);
+            write_atomically(path, &encrypted)?;
+            encrypted_files.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })?;
+    let mut encrypted_files = encrypted_files.into_inner();
+
+    // The JSON files that are edited by hand while they are in clear
+    let json_paths: Vec<PathBuf> = [data_paths.annotations(), data_paths.saved_searches()]
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect();
+    for path in &json_paths {
+        let content = fs::read(path)?;
+        let (json, _) = verify_checksum(&content)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if is_encrypted(json) {
+            continue;
+        }
+        let mut encrypted = encrypt_with(cipher, &zstd::encode_all(json, 0)?)?;
+        append_checksum(&mut encrypted);
+        write_atomically(path, &encrypted)?;
+        encrypted_files += 1;
+    }
+
+    Ok((encrypted_files, paths.len() + json_paths.len()))
+}
+
+/// Remove the copy of the Firefox database that older versions kept in the data directory
+fn remove_firefox_database(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let path = data_paths.firefox_database();
+    if path.exists() {
+        fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The cipher of the data directory, derived from the passphrase the first time
+fn cipher() -> anyhow::Result<XChaCha20Poly1305> {
+    let mut encryption = ENCRYPTION.lock().unwrap();
+    let Some(encryption) = encryption.as_mut() else {
+        anyhow::bail!("the file is encrypted, but the data directory has no encryption settings");
+    };
+    if let Some(cipher) = &encryption.cipher {
+        return Ok(cipher.clone());
+    }
+
+    let passphrase = read_passphrase("Passphrase of the data: ")?;
+    let Some(cipher) = unlock(&encryption.settings, &passphrase)? else {
+        anyhow::bail!(
+            "wrong passphrase for the data encrypted with {}",
+            encryption.settings_path.display()
+        );
+    };
+    encryption.cipher = Some(cipher.clone());
+    Ok(cipher)
+}
+
+/// New settings with a random salt, and the cipher derived from the passphrase with them
+fn new_settings(passphrase: &str) -> anyhow::Result<(EncryptionSettings, XChaCha20Poly1305)> {
+    let mut salt = [0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = derive_cipher(passphrase, &salt)?;
+    let key_check = encrypt_with(&cipher, KEY_CHECK_PLAINTEXT)?;
+    let settings = EncryptionSettings {
+        salt: to_hex(&salt),
+        key_check: to_hex(&key_check),
+    };
+    Ok((settings, cipher))
+}
+
+/// The cipher derived from the passphrase, `None` if it's not the passphrase of the settings
+fn unlock(
+    settings: &EncryptionSettings,
+    passphrase: &str,
+) -> anyhow::Result<Option<XChaCha20Poly1305>> {
+    let cipher = derive_cipher(passphrase, &from_hex(&settings.salt)?)?;
+    let key_check = from_hex(&settings.key_check)?;
+    let is_right_key =
+        decrypt_with(&cipher, &key_check).is_ok_and(|plaintext| plaintext == KEY_CHECK_PLAINTEXT);
+    Ok(is_right_key.then_some(cipher))
+}
+
+fn derive_cipher(passphrase: &str, salt: &[u8]) -> anyhow::Result<XChaCha20Poly1305> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|error| anyhow::anyhow!("failed to derive the key: {}", error))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// The magic, a random nonce and the encrypted content with its authentication tag
+fn encrypt_with(cipher: &XChaCha20Poly1305, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow::anyhow!("failed to encrypt"))?;
+    Ok([&ENCRYPTED_MAGIC[..], &nonce[..], &ciphertext[..]].concat())
+}
+
+fn decrypt_with(cipher: &XChaCha20Poly1305, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let content = content
+        .strip_prefix(ENCRYPTED_MAGIC)
+        .context("the content is not encrypted")?;
+    if content.len() < NONCE_LENGTH {
+        anyhow::bail!("the encrypted content is truncated");
+    }
+    let (nonce, ciphertext) = content.split_at(NONCE_LENGTH);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt, the file was modified or is corrupt"))
+}
+
+/// Read the passphrase from the file in MIND_SEARCH_KEY_FILE, or ask for it
+fn read_passphrase(prompt: &str) -> anyhow::Result<String> {
+    if let Some(key_file) = env::var_os(KEY_FILE_VARIABLE) {
+        let content = fs::read_to_string(&key_file).with_context(|| {
+            format!(
+                "failed to read the passphrase from {}",
+                PathBuf::from(key_file).display()
+            )
+        })?;
+        return Ok(content.trim_end_matches(['\n', '\r']).to_string());
+    }
+    rpassword::prompt_password(prompt).with_context(|| {
+        format!(
+            "failed to ask for the passphrase, give a file with it in {}",
+            KEY_FILE_VARIABLE
+        )
+    })
+}
+
+/// Ask for a new passphrase twice, unless it's in the key file
+fn read_new_passphrase() -> anyhow::Result<String> {
+    let passphrase = read_passphrase("New passphrase of the data: ")?;
+    if passphrase.is_empty() {
+        anyhow::bail!("the passphrase can't be empty");
+    }
+    let from_key_file = env::var_os(KEY_FILE_VARIABLE).is_some();
+    if !from_key_file && read_passphrase("Repeat the passphrase: ")? != passphrase {
+        anyhow::bail!("the passphrases are different");
+    }
+    Ok(passphrase)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        anyhow::bail!("invalid hexadecimal {:?}", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|start| Ok(u8::from_str_radix(&hex[start..start + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{downloaded_page, history_item, TestData};
+    use crate::{DownloadedPageContent, FirefoxHistoryItem};
+
+    fn test_cipher() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&[7; 32].into())
+    }
+
+    /// The decrypted and decompressed content of the file
+    fn decrypt_file(cipher: &XChaCha20Poly1305, path: &std::path::Path) -> Vec<u8> {
+        let content = fs::read(path).unwrap();
+        let (encrypted, has_checksum) = verify_checksum(&content).unwrap();
+        assert!(has_checksum);
+        assert!(is_encrypted(encrypted));
+        zstd::decode_all(&decrypt_with(cipher, encrypted).unwrap()[..]).unwrap()
+    }
+
+    #[test]
+    fn decrypts_what_was_encrypted() {
+        let cipher = test_cipher();
+        let encrypted = encrypt_with(&cipher, b"some content").unwrap();
+        assert_eq!(decrypt_with(&cipher, &encrypted).unwrap(), b"some content");
+        // Each encryption has its own nonce
+        assert_ne!(encrypt_with(&cipher, b"some content").unwrap(), encrypted);
+
+        let other_cipher = XChaCha20Poly1305::new(&[8; 32].into());
+        assert!(decrypt_with(&other_cipher, &encrypted).is_err());
+        let mut modified = encrypted.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(decrypt_with(&cipher, &modified).is_err());
+        assert!(decrypt_with(&cipher, &encrypted[..ENCRYPTED_MAGIC.len() + 4]).is_err());
+    }
+
+    #[test]
+    fn tells_a_wrong_passphrase() {
+        let (settings, cipher) = new_settings("right passphrase").unwrap();
+        let settings: EncryptionSettings =
+            serde_json::from_str(&serde_json::to_string(&settings).unwrap()).unwrap();
+        let unlocked = unlock(&settings, "right passphrase").unwrap().unwrap();
+        let encrypted = encrypt_with(&cipher, b"some content").unwrap();
+        assert_eq!(
+            decrypt_with(&unlocked, &encrypted).unwrap(),
+            b"some content"
+        );
+        assert!(unlock(&settings, "wrong passphrase").unwrap().is_none());
+    }
+
+    #[test]
+    fn tells_the_encrypted_content_by_its_magic() {
+        let compressed = zstd::encode_all(&b"[]"[..], 0).unwrap();
+        assert!(!is_encrypted(&compressed));
+        assert!(!is_encrypted(b"{\"notes\": {}}"));
+        assert!(!is_encrypted(b""));
+        assert!(is_encrypted(
+            &encrypt_with(&test_cipher(), &compressed).unwrap()
+        ));
+    }
+
+    #[test]
+    fn encrypts_the_files_only_once() {
+        let data = TestData::new();
+        let items = vec![history_item("https://example.com/", "Example")];
+        data.write_history(&items);
+        let bundle = data.write_bundle(
+            "0-0",
+            &[downloaded_page(
+                "https://example.com/",
+                DownloadedPageContent::PlainText("Some text".to_string()),
+            )],
+        );
+        let annotations = "{\n  \"notes\": {}\n}";
+        fs::write(data.data_paths.annotations(), annotations).unwrap();
+        fs::write(data.data_paths.firefox_database(), "places").unwrap();
+
+        let cipher = test_cipher();
+        assert_eq!(encrypt_files(&cipher, &data.data_paths).unwrap(), (3, 3));
+        assert!(!data.data_paths.firefox_database().exists());
+        let history: Vec<FirefoxHistoryItem> =
+            serde_json::from_slice(&decrypt_file(&cipher, &data.data_paths.history())).unwrap();
+        assert_eq!(history[0].url, "https://example.com/");
+        assert!(String::from_utf8(decrypt_file(&cipher, &bundle))
+            .unwrap()
+            .contains("Some text"));
+        assert_eq!(
+            decrypt_file(&cipher, &data.data_paths.annotations()),
+            annotations.as_bytes()
+        );
+
+        let encrypted_history = fs::read(data.data_paths.history()).unwrap();
+        assert_eq!(encrypt_files(&cipher, &data.data_paths).unwrap(), (0, 3));
+        assert_eq!(
+            fs::read(data.data_paths.history()).unwrap(),
+            encrypted_history
+        );
+    }
+}
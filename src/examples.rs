@@ -0,0 +1,69 @@
+//! The examples shown at the end of the help of the commands, kept together so that they are
+//! reviewed and updated at once when the options change
+
+/// The whole workflow, shown in the help of the program
+pub const WORKFLOW: &str = "Examples:
+  Copy the history out of a Firefox profile, found in the page about:profiles:
+    mind-search extract-firefox-history ~/.mozilla/firefox/abcd1234.default-release
+
+  Download the pages of the history, 50 at a time, then index them:
+    mind-search download-pages --parallelism 50
+    mind-search index-contents
+
+  Or do all of it again later, in one go:
+    mind-search sync --profile-path ~/.mozilla/firefox/abcd1234.default-release
+
+  Search the pages visited in the last 6 months:
+    mind-search search 'borrow checker' --last 6m";
+
+pub const EXTRACT_FIREFOX_HISTORY: &str = "Examples:
+  mind-search extract-firefox-history ~/.mozilla/firefox/abcd1234.default-release
+  mind-search extract-firefox-history ~/snap/firefox/common/.mozilla/firefox/abcd1234.default";
+
+pub const EXTRACT_FIREFOX_SESSION: &str = "Examples:
+  mind-search extract-firefox-session ~/.mozilla/firefox/abcd1234.default-release
+  mind-search extract-firefox-session --include-closed-tabs ~/.mozilla/firefox/abcd1234.default-release";
+
+pub const EXTRACT_SQLITE_HISTORY: &str = "Examples:
+  mind-search extract-sqlite-history --preset qutebrowser --db ~/.local/share/qutebrowser/history.sqlite
+  mind-search extract-sqlite-history --preset falkon --db ~/.config/falkon/profiles/default/browsedata.db
+
+  Any other browser, with a query returning the URL, the title and the visit date in seconds:
+    mind-search extract-sqlite-history --db history.db --query 'SELECT url, title, visited FROM visits'";
+
+pub const DOWNLOAD_PAGES: &str = "Examples:
+  Download with the default settings:
+    mind-search download-pages
+
+  Be gentle with a slow connection, and give up on each page after 10 seconds:
+    mind-search download-pages --parallelism 5 --timeout-seconds 10
+
+  Write a report for scripts, exiting with 4 when there was nothing new to download:
+    mind-search download-pages --report download.json";
+
+pub const INDEX_CONTENTS: &str = "Examples:
+  mind-search index-contents
+  mind-search index-contents --strip-repeated-boilerplate --infer-date-from-url
+  mind-search index-contents --only-new
+
+  Keep the pages of work apart in their own index:
+    mind-search index-contents --index-name work";
+
+pub const SYNC: &str = "Examples:
+  mind-search sync --profile-path ~/.mozilla/firefox/abcd1234.default-release
+
+  Only download and index the pages of the history extracted before:
+    mind-search sync --no-extract";
+
+pub const SEARCH: &str = "Examples:
+  mind-search search 'borrow of moved value'
+  mind-search search tokio --site docs.rs
+
+  Pages visited in 2023 only:
+    mind-search search 'async runtime' --after 2023-01-01 --before 2024-01-01
+
+  The results of the last 3 weeks as JSON, for scripts:
+    mind-search search rayon --last 3w --format json
+
+  Only the URLs, to pipe them elsewhere:
+    mind-search search 'sqlite wal' --quiet | head -3";
@@ -0,0 +1,213 @@
+use crate::search_output::{json_hits, JsonHit, JsonSnippet, SearchResults};
+use chrono::Utc;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const REPORT_STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: 2em auto; \
+    padding: 0 1em; color: #222; } .meta { color: #666; font-size: 0.85em; } \
+    mark { background: #ffe680; } li { margin-bottom: 1em; }";
+
+/// Write the results as a standalone HTML page, grouped by site with a table of contents
+pub fn export_html(results: &SearchResults, title: &str, path: &Path) -> anyhow::Result<()> {
+    let groups = group_by_domain(results);
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>{}</title>\n<style>{}</style>\n</head>\n<body>",
+        escape_html(title),
+        REPORT_STYLE
+    )?;
+    writeln!(html, "<h1>{}</h1>", escape_html(title))?;
+    writeln!(
+        html,
+        "<p class=\"meta\">{} results, exported on {}</p>",
+        results.hits.len(),
+        Utc::now().format("%Y-%m-%d")
+    )?;
+
+    writeln!(html, "<ul>")?;
+    for (index, (domain, hits)) in groups.iter().enumerate() {
+        writeln!(
+            html,
+            "<li><a href=\"#site-{}\">{}</a> ({})</li>",
+            index,
+            escape_html(domain),
+            hits.len()
+        )?;
+    }
+    writeln!(html, "</ul>")?;
+
+    for (index, (domain, hits)) in groups.iter().enumerate() {
+        writeln!(
+            html,
+            "<h2 id=\"site-{}\">{}</h2>\n<ol>",
+            index,
+            escape_html(domain)
+        )?;
+        for hit in hits {
+            writeln!(
+                html,
+                "<li value=\"{}\"><a href=\"{}\">{}</a>",
+                hit.rank,
+                escape_html(hit.url),
+                escape_html(hit.title.unwrap_or(hit.url))
+            )?;
+            writeln!(
+                html,
+                "<div class=\"meta\">{} · {}</div>",
+                escape_html(hit.url),
+                describe_dates(hit)
+            )?;
+            if !hit.snippet.text.is_empty() {
+                writeln!(
+                    html,
+                    "<div>{}</div>",
+                    mark_matches(&hit.snippet, "<mark>", "</mark>", escape_html)
+                )?;
+            }
+            writeln!(html, "</li>")?;
+        }
+        writeln!(html, "</ol>")?;
+    }
+    writeln!(html, "</body>\n</html>")?;
+
+    fs::write(path, html)?;
+    Ok(())
+}
+
+/// Write the results as Markdown, grouped by site, to paste them into notes
+pub fn export_markdown(results: &SearchResults, title: &str, path: &Path) -> anyhow::Result<()> {
+    let mut markdown = String::new();
+    writeln!(markdown, "# {}\n", escape_markdown(title))?;
+    writeln!(
+        markdown,
+        "{} results, exported on {}",
+        results.hits.len(),
+        Utc::now().format("%Y-%m-%d")
+    )?;
+
+    for (domain, hits) in group_by_domain(results) {
+        writeln!(markdown, "\n## {}\n", escape_markdown(&domain))?;
+        for hit in hits {
+            // Parentheses would end the link early
+            let url = hit.url.replace('(', "%28").replace(')', "%29");
+            writeln!(
+                markdown,
+                "{}. [{}]({}) · {}",
+                hit.rank,
+                escape_markdown(hit.title.unwrap_or(hit.url)),
+                url,
+                describe_dates(&hit)
+            )?;
+            if !hit.snippet.text.is_empty() {
+                writeln!(
+                    markdown,
+                    "   > {}",
+                    mark_matches(&hit.snippet, "**", "**", escape_markdown)
+                )?;
+            }
+        }
+    }
+
+    fs::write(path, markdown)?;
+    Ok(())
+}
+
+/// The hits of each site, with the sites in the order of their best result
+fn group_by_domain(results: &SearchResults) -> Vec<(String, Vec<JsonHit<'_>>)> {
+    let mut groups: Vec<(String, Vec<JsonHit>)> = Vec::new();
+    for hit in json_hits(results) {
+        let domain = hit.domain.clone().unwrap_or_else(|| "Other".to_string());
+        match groups
+            .iter_mut()
+            .find(|(group_domain, _)| *group_domain == domain)
+        {
+            Some((_, hits)) => hits.push(hit),
+            None => groups.push((domain, vec![hit])),
+        }
+    }
+    groups
+}
+
+fn describe_dates(hit: &JsonHit) -> String {
+    let last_visit = match hit.last_visit {
+        Some(last_visit) => format!("last visit {}", last_visit.date_naive()),
+        None => "last visit unknown".to_string(),
+    };
+    match hit.published {
+        Some(published) => format!("{}, published {}", last_visit, published.date_naive()),
+        None => last_visit,
+    }
+}
+
+/// The snippet text escaped, with the matches between the marks and the runs of whitespace
+/// collapsed
+fn mark_matches(
+    snippet: &JsonSnippet,
+    start_mark: &str,
+    end_mark: &str,
+    escape: fn(&str) -> String,
+) -> String {
+    let collapse_whitespace = |text: &str| {
+        let mut collapsed = String::with_capacity(text.len());
+        let mut pending_space = false;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                pending_space = true;
+                continue;
+            }
+            if pending_space {
+                collapsed.push(' ');
+                pending_space = false;
+            }
+            collapsed.push(c);
+        }
+        if pending_space {
+            collapsed.push(' ');
+        }
+        escape(&collapsed)
+    };
+
+    let mut marked = String::new();
+    let mut position = 0;
+    for &[start, end] in &snippet.highlights {
+        let (Some(before), Some(matched)) = (
+            snippet.text.get(position..start),
+            snippet.text.get(start..end),
+        ) else {
+            continue;
+        };
+        marked.push_str(&collapse_whitespace(before));
+        marked.push_str(start_mark);
+        marked.push_str(&collapse_whitespace(matched));
+        marked.push_str(end_mark);
+        position = end;
+    }
+    marked.push_str(&collapse_whitespace(
+        snippet.text.get(position..).unwrap_or_default(),
+    ));
+    marked.trim().to_string()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Escape the characters that Markdown would take as formatting
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -1,24 +1,113 @@
-use crate::{write_compressed_json, FirefoxHistoryItem, FIREFOX_DATABASE_PATH, HISTORY_PATH};
-use chrono::{TimeZone, Utc};
+use crate::normalize_url::normalize_history_url;
+use crate::{write_compressed_json, DataPaths, FirefoxHistoryItem};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use reqwest::Url;
 use rusqlite::{Connection, Row};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
-pub fn extract_firefox_history(profile_path: PathBuf) -> anyhow::Result<()> {
+/// What a run of the extraction did
+#[derive(Clone, Debug)]
+pub struct ExtractSummary {
+    pub urls: usize,
+    /// The URLs that were not in the history extracted before
+    pub new_urls: usize,
+}
+
+/// Convert a date of the Firefox database, in microseconds since the epoch. Some databases have
+/// garbage dates, far outside of the dates that can be represented.
+fn convert_firefox_date(micros: i64) -> Option<DateTime<Utc>> {
+    let date = NaiveDateTime::from_timestamp_micros(micros)?;
+    Some(Utc.from_utc_datetime(&date))
+}
+
+/// Extract the history of the Firefox profile and write it in the data directory. With
+/// `keep_forgotten`, the URLs of the previous history that Firefox no longer has are kept.
+pub fn extract_firefox_history(
+    profile_path: PathBuf,
+    keep_forgotten: bool,
+    data_paths: &DataPaths,
+) -> anyhow::Result<ExtractSummary> {
     // Create a temporary copy of the SQLite database file.
     // This is necessary because Firefox locks the database while it's running.
-    fs::create_dir_all("data")?;
-    fs::copy(profile_path.join("places.sqlite"), FIREFOX_DATABASE_PATH)?;
-    println!("Copied Firefox database");
+    fs::create_dir_all(data_paths.data_dir())?;
+    fs::copy(
+        profile_path.join("places.sqlite"),
+        data_paths.firefox_database(),
+    )?;
+    info!("Copied Firefox database");
+
+    // The copy has the whole history in clear, so it's only kept while it's read
+    let places = read_places(&data_paths.firefox_database());
+    fs::remove_file(data_paths.firefox_database())?;
+    let Places {
+        mut history_by_url,
+        bookmarks,
+    } = places?;
+    info!("Extracted {} bookmarks", bookmarks.len());
+    for (url, folder) in bookmarks {
+        // The bookmarks are places too, so they are always in the history
+        let Some(item) = history_by_url.get_mut(&url) else {
+            continue;
+        };
+        item.bookmarked = true;
+        if let Some(folder) = folder {
+            if !item.bookmark_folders.contains(&folder) {
+                item.bookmark_folders.push(folder);
+            }
+        }
+    }
+
+    // Everything is new when there is no previous history
+    let previous_history = data_paths.read_previous_history()?;
+    let previous_urls: HashSet<&str> = previous_history
+        .iter()
+        .map(|item| item.url.as_str())
+        .collect();
+    let new_urls = history_by_url
+        .keys()
+        .filter(|url| !previous_urls.contains(url.as_str()))
+        .count();
+    for item in previous_history {
+        // Firefox expires old visits, but their pages stay downloaded and indexed. The tabs of the
+        // sessions and the histories of the other browsers are not in the history of Firefox, so
+        // they are always kept.
+        if keep_forgotten || item.source.is_some() {
+            history_by_url.entry(item.url.clone()).or_insert(item);
+        }
+    }
 
+    let history: Vec<_> = history_by_url.into_values().collect();
+    let summary = ExtractSummary {
+        urls: history.len(),
+        new_urls,
+    };
+
+    write_compressed_json(&data_paths.history(), &history)?;
+    info!("Wrote history to disk");
+
+    Ok(summary)
+}
+
+/// What is read from the Firefox database
+struct Places {
+    /// The visited pages, by URL
+    history_by_url: HashMap<String, FirefoxHistoryItem>,
+    /// The URLs of the bookmarks, with their folder
+    bookmarks: Vec<(String, Option<String>)>,
+}
+
+fn read_places(path: &Path) -> anyhow::Result<Places> {
     // Open the SQLite database.
-    let conn = Connection::open(FIREFOX_DATABASE_PATH)?;
+    let conn = Connection::open(path)?;
 
     // Execute a query to read the browsing history.
-    let mut statement = conn.prepare("SELECT url, title, last_visit_date FROM moz_places")?;
+    let mut statement =
+        conn.prepare("SELECT url, title, last_visit_date, visit_count FROM moz_places")?;
 
     /// Convert each row for the query above into a Rust struct
     fn convert_firefox_history_row(row: &Row) -> anyhow::Result<FirefoxHistoryItem> {
@@ -27,19 +116,34 @@ pub fn extract_firefox_history(profile_path: PathBuf) -> anyhow::Result<()> {
         // "https://docs.rs/url/2.4.0/url/struct.Url.html"
         let url: String = row.get("url")?;
         let mut parsed_url = Url::parse(&url)?;
-        parsed_url.set_fragment(None);
+        normalize_history_url(&mut parsed_url);
         let url = parsed_url.to_string();
 
         let title = row.get("title")?;
 
         let last_visit_date: Option<i64> = row.get("last_visit_date")?;
-        let last_visit =
-            last_visit_date.map(|last_visit_date| Utc.timestamp_nanos(last_visit_date * 1000));
+        let last_visit = last_visit_date.and_then(|last_visit_date| {
+            let last_visit = convert_firefox_date(last_visit_date);
+            if last_visit.is_none() {
+                warn!(
+                    "Ignored the invalid last visit date {} of {}",
+                    last_visit_date, url
+                );
+            }
+            last_visit
+        });
+
+        let visit_count: Option<i64> = row.get("visit_count")?;
+        let visit_count = visit_count.map(|visit_count| visit_count.max(0) as u64);
 
         Ok(FirefoxHistoryItem {
             url,
             title,
             last_visit,
+            visit_count,
+            source: None,
+            bookmarked: false,
+            bookmark_folders: Vec::new(),
         })
     }
 
@@ -60,18 +164,173 @@ pub fn extract_firefox_history(profile_path: PathBuf) -> anyhow::Result<()> {
                     }
                     (Some(last_visit), None) | (None, Some(last_visit)) => Some(last_visit),
                     (None, None) => None,
-                }
+                };
+                // The rows are pages that only differ by their fragment, so their visits add up
+                previous.visit_count = match (previous.visit_count, item.visit_count) {
+                    (Some(previous_count), Some(new_count)) => Some(previous_count + new_count),
+                    (count, None) | (None, count) => count,
+                };
             }
             Entry::Vacant(vacant) => {
                 vacant.insert(item);
             }
         }
     }
-    let history: Vec<_> = history_by_url.into_values().collect();
-    println!("Extracted {} visited URLs", history.len());
+    info!("Extracted {} visited URLs", history_by_url.len());
+
+    let bookmarks = read_bookmarks(&conn).context("failed to read the bookmarks")?;
+    Ok(Places {
+        history_by_url,
+        bookmarks,
+    })
+}
 
-    write_compressed_json(Path::new(HISTORY_PATH), &history)?;
-    println!("Wrote history to disk");
+/// The normalized URL of each bookmark with the path of its folder, like "Rust/async". The roots,
+/// like the menu and the toolbar, are not part of the paths, and the tags are not bookmarks.
+fn read_bookmarks(conn: &Connection) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut statement = conn.prepare(
+        "WITH RECURSIVE folders(id, path) AS (
+            SELECT id, NULL FROM moz_bookmarks
+            WHERE guid IN ('menu________', 'toolbar_____', 'unfiled_____', 'mobile______')
+            UNION ALL
+            SELECT child.id, COALESCE(folders.path || '/', '') || COALESCE(child.title, '')
+            FROM moz_bookmarks child JOIN folders ON child.parent = folders.id
+            WHERE child.type = 2
+        )
+        SELECT moz_places.url, folders.path
+        FROM moz_bookmarks
+        JOIN folders ON moz_bookmarks.parent = folders.id
+        JOIN moz_places ON moz_bookmarks.fk = moz_places.id
+        WHERE moz_bookmarks.type = 1",
+    )?;
+
+    let mut bookmarks = Vec::new();
+    for maybe_row in statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+    })? {
+        let (url, folder) = maybe_row?;
+        let mut parsed_url = Url::parse(&url)?;
+        normalize_history_url(&mut parsed_url);
+        bookmarks.push((parsed_url.to_string(), folder));
+    }
+    Ok(bookmarks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{firefox_profile, history_item, TestData};
+
+    /// 275760-09-13, the last date of JavaScript, which some databases have for garbage dates
+    const YEAR_275760_MICROS: i64 = 8_640_000_000_000_000_000;
+
+    #[test]
+    fn converts_the_dates_in_range() {
+        assert_eq!(
+            convert_firefox_date(1_689_336_000_000_000)
+                .unwrap()
+                .to_rfc3339(),
+            "2023-07-14T12:00:00+00:00"
+        );
+        assert_eq!(convert_firefox_date(0).unwrap().timestamp(), 0);
+        assert!(convert_firefox_date(-1_000_000).is_some());
+        assert_eq!(convert_firefox_date(YEAR_275760_MICROS), None);
+        assert_eq!(convert_firefox_date(-YEAR_275760_MICROS), None);
+        assert_eq!(convert_firefox_date(i64::MAX), None);
+        assert_eq!(convert_firefox_date(i64::MIN), None);
+    }
 
-    Ok(())
+    #[test]
+    fn keeps_the_places_with_invalid_dates() {
+        let profile = firefox_profile(&[
+            ("https://example.com/valid", Some(1_689_336_000_000_000)),
+            ("https://example.com/far-future", Some(YEAR_275760_MICROS)),
+            ("https://example.com/max", Some(i64::MAX)),
+            ("https://example.com/min", Some(i64::MIN)),
+            ("https://example.com/never", None),
+        ]);
+        let data = TestData::new();
+        let summary =
+            extract_firefox_history(profile.path().to_path_buf(), false, &data.data_paths).unwrap();
+        assert_eq!(summary.urls, 5);
+        assert!(!data.data_paths.firefox_database().exists());
+
+        let history = data.data_paths.read_history().unwrap();
+        let last_visit = |url: &str| {
+            history
+                .iter()
+                .find(|item| item.url == url)
+                .unwrap()
+                .last_visit
+        };
+        assert_eq!(
+            last_visit("https://example.com/valid"),
+            convert_firefox_date(1_689_336_000_000_000)
+        );
+        for url in ["far-future", "max", "min", "never"] {
+            assert_eq!(last_visit(&format!("https://example.com/{}", url)), None);
+        }
+    }
+
+    #[test]
+    fn only_removes_the_fragments_of_the_urls() {
+        let profile = firefox_profile(&[
+            ("https://example.com/dir/", None),
+            ("https://example.com/dir/#section", None),
+            ("https://example.com/post?utm_source=feed", None),
+        ]);
+        let data = TestData::new();
+        extract_firefox_history(profile.path().to_path_buf(), false, &data.data_paths).unwrap();
+        let mut history = data.data_paths.read_history().unwrap();
+        history.sort_by(|a, b| a.url.cmp(&b.url));
+        let urls: Vec<_> = history.iter().map(|item| item.url.as_str()).collect();
+        // Like they are downloaded and stored in the bundles
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/dir/",
+                "https://example.com/post?utm_source=feed"
+            ]
+        );
+        assert_eq!(history[0].visit_count, Some(2));
+    }
+
+    #[test]
+    fn keeps_the_items_of_the_other_sources() {
+        let profile = firefox_profile(&[("https://example.com/firefox", None)]);
+        let data = TestData::new();
+        let mut tab = history_item("https://example.com/tab", "Tab");
+        tab.source = Some("session".to_string());
+        data.write_history(&[tab, history_item("https://example.com/forgotten", "")]);
+
+        let summary =
+            extract_firefox_history(profile.path().to_path_buf(), false, &data.data_paths).unwrap();
+        assert_eq!((summary.urls, summary.new_urls), (2, 1));
+        let mut urls: Vec<_> = data
+            .data_paths
+            .read_history()
+            .unwrap()
+            .into_iter()
+            .map(|item| item.url)
+            .collect();
+        urls.sort();
+        assert_eq!(
+            urls,
+            ["https://example.com/firefox", "https://example.com/tab"]
+        );
+    }
+
+    #[test]
+    fn keeps_a_history_that_cannot_be_read() {
+        let profile = firefox_profile(&[("https://example.com/firefox", None)]);
+        let data = TestData::new();
+        std::fs::write(data.data_paths.history(), "corrupted").unwrap();
+        let error = extract_firefox_history(profile.path().to_path_buf(), false, &data.data_paths)
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to read"));
+        assert_eq!(
+            std::fs::read(data.data_paths.history()).unwrap(),
+            b"corrupted"
+        );
+    }
 }
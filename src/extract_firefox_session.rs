@@ -0,0 +1,263 @@
+use crate::extract_firefox_history::ExtractSummary;
+use crate::normalize_url::normalize_history_url;
+use crate::{write_compressed_json, DataPaths, FirefoxHistoryItem};
+use anyhow::Context;
+use chrono::Utc;
+use clap::Args;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// The first bytes of the files compressed by Firefox with LZ4, like the session files
+const MOZLZ4_MAGIC: &[u8] = b"mozLz40\0";
+
+/// The source of the history items that come from the open tabs
+const SESSION_SOURCE: &str = "session";
+
+#[derive(Args, Debug)]
+pub struct ExtractFirefoxSessionArguments {
+    /// The path to your Firefox profile. You can obtain it in the page "about:profiles" in your
+    /// Firefox
+    profile_path: PathBuf,
+    /// Also add the tabs closed recently, which Firefox keeps to reopen them
+    #[arg(long)]
+    include_closed_tabs: bool,
+}
+
+/// The parts of the session file of Firefox that tell which pages are open
+#[derive(Deserialize)]
+struct Session {
+    #[serde(default)]
+    windows: Vec<SessionWindow>,
+}
+
+#[derive(Deserialize)]
+struct SessionWindow {
+    #[serde(default)]
+    tabs: Vec<SessionTab>,
+    #[serde(default, rename = "_closedTabs")]
+    closed_tabs: Vec<ClosedTab>,
+}
+
+#[derive(Deserialize)]
+struct ClosedTab {
+    state: SessionTab,
+}
+
+/// A tab, with the pages of its back and forward history
+#[derive(Deserialize)]
+struct SessionTab {
+    #[serde(default)]
+    entries: Vec<SessionEntry>,
+    /// The position of the current page in the entries, starting at 1
+    index: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SessionEntry {
+    url: String,
+    title: Option<String>,
+}
+
+impl SessionTab {
+    /// The page shown in the tab
+    fn current_entry(&self) -> Option<&SessionEntry> {
+        let position = self.index.unwrap_or(self.entries.len()).checked_sub(1)?;
+        self.entries.get(position).or(self.entries.last())
+    }
+}
+
+/// Add the pages open in the tabs of the Firefox profile to the extracted history, as visited now
+pub fn extract_firefox_session(
+    arguments: ExtractFirefoxSessionArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<ExtractSummary> {
+    // The recovery file is written while Firefox runs, the other one when it closes
+    let candidates = [
+        arguments
+            .profile_path
+            .join("sessionstore-backups/recovery.jsonlz4"),
+        arguments.profile_path.join("sessionstore.jsonlz4"),
+    ];
+    let session_path = candidates
+        .iter()
+        .find(|path| path.exists())
+        .with_context(|| {
+            format!(
+                "no session file in {}, is it a Firefox profile?",
+                arguments.profile_path.display()
+            )
+        })?;
+    let session = read_session(session_path)
+        .with_context(|| format!("failed to read {}", session_path.display()))?;
+
+    let mut tabs: Vec<&SessionTab> = Vec::new();
+    for window in &session.windows {
+        tabs.extend(&window.tabs);
+        if arguments.include_closed_tabs {
+            tabs.extend(
+                window
+                    .closed_tabs
+                    .iter()
+                    .map(|closed_tab| &closed_tab.state),
+            );
+        }
+    }
+
+    // Everything is new when there is no previous history
+    let previous_history = data_paths.read_previous_history()?;
+    let mut history_by_url: HashMap<String, FirefoxHistoryItem> = previous_history
+        .into_iter()
+        .map(|item| (item.url.clone(), item))
+        .collect();
+
+    let now = Utc::now();
+    let mut tab_urls = 0;
+    let mut new_urls = 0;
+    for entry in tabs.into_iter().filter_map(SessionTab::current_entry) {
+        // Like "about:newtab", which can't be downloaded
+        let Some(url) = Url::parse(&entry.url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+        else {
+            debug!("Skipped the tab of {}", entry.url);
+            continue;
+        };
+        let mut url = url;
+        normalize_history_url(&mut url);
+        let url = url.to_string();
+
+        tab_urls += 1;
+        let title = entry.title.clone().filter(|title| !title.is_empty());
+        match history_by_url.get_mut(&url) {
+            Some(item) => {
+                item.last_visit = Some(now);
+                if item.title.is_none() {
+                    item.title = title;
+                }
+            }
+            None => {
+                new_urls += 1;
+                history_by_url.insert(
+                    url.clone(),
+                    FirefoxHistoryItem {
+                        url,
+                        title,
+                        last_visit: Some(now),
+                        visit_count: None,
+                        source: Some(SESSION_SOURCE.to_string()),
+                        bookmarked: false,
+                        bookmark_folders: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+    info!(
+        "Found {} tabs, {} of them not in the history",
+        tab_urls, new_urls
+    );
+
+    let history: Vec<_> = history_by_url.into_values().collect();
+    write_compressed_json(&data_paths.history(), &history)?;
+    info!("Wrote history to disk");
+
+    Ok(ExtractSummary {
+        urls: tab_urls,
+        new_urls,
+    })
+}
+
+fn read_session(path: &Path) -> anyhow::Result<Session> {
+    let json = decode_mozlz4(&fs::read(path)?)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Decompress the content of a file in the LZ4 format of Firefox: a magic number, the size of the
+/// decompressed content as a little-endian u32, and then a single LZ4 block
+fn decode_mozlz4(content: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let block = content
+        .strip_prefix(MOZLZ4_MAGIC)
+        .context("not a mozLz4 file")?;
+    lz4_flex::block::decompress_size_prepended(block)
+        .map_err(|error| anyhow::anyhow!("invalid mozLz4 content: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::TestData;
+
+    fn encode_mozlz4(content: &[u8]) -> Vec<u8> {
+        let mut encoded = MOZLZ4_MAGIC.to_vec();
+        encoded.extend(lz4_flex::block::compress_prepend_size(content));
+        encoded
+    }
+
+    /// A profile with a session of one tab
+    fn profile_with_session() -> tempfile::TempDir {
+        let profile = tempfile::tempdir().unwrap();
+        let session = r#"{"windows": [{"tabs": [
+            {"entries": [{"url": "https://example.com/tab", "title": "Tab"}], "index": 1}
+        ]}]}"#;
+        fs::write(
+            profile.path().join("sessionstore.jsonlz4"),
+            encode_mozlz4(session.as_bytes()),
+        )
+        .unwrap();
+        profile
+    }
+
+    fn extract(profile: &Path, data: &TestData) -> anyhow::Result<ExtractSummary> {
+        let arguments = ExtractFirefoxSessionArguments {
+            profile_path: profile.to_path_buf(),
+            include_closed_tabs: false,
+        };
+        extract_firefox_session(arguments, &data.data_paths)
+    }
+
+    #[test]
+    fn decodes_mozlz4() {
+        let content = br#"{"windows": []}"#.repeat(10);
+        assert_eq!(decode_mozlz4(&encode_mozlz4(&content)).unwrap(), content);
+    }
+
+    #[test]
+    fn rejects_a_wrong_magic_number() {
+        let mut encoded = encode_mozlz4(b"{}");
+        encoded[0] = b'X';
+        let error = decode_mozlz4(&encoded).unwrap_err();
+        assert_eq!(error.to_string(), "not a mozLz4 file");
+    }
+
+    #[test]
+    fn rejects_a_truncated_block() {
+        let content = b"Some text that is long enough to be compressed".repeat(10);
+        let encoded = encode_mozlz4(&content);
+        let error = decode_mozlz4(&encoded[..encoded.len() - 5]).unwrap_err();
+        assert!(error.to_string().starts_with("invalid mozLz4 content"));
+    }
+
+    #[test]
+    fn adds_the_tabs_to_a_missing_history() {
+        let data = TestData::new();
+        let profile = profile_with_session();
+        let summary = extract(profile.path(), &data).unwrap();
+        assert_eq!((summary.urls, summary.new_urls), (1, 1));
+        let history = data.data_paths.read_history().unwrap();
+        assert_eq!(history[0].url, "https://example.com/tab");
+    }
+
+    #[test]
+    fn keeps_a_history_that_cannot_be_read() {
+        let data = TestData::new();
+        fs::write(data.data_paths.history(), "corrupted").unwrap();
+        let profile = profile_with_session();
+        let error = extract(profile.path(), &data).unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to read"));
+        assert_eq!(fs::read(data.data_paths.history()).unwrap(), b"corrupted");
+    }
+}
@@ -0,0 +1,365 @@
+use crate::extract_firefox_history::ExtractSummary;
+use crate::normalize_url::normalize_history_url;
+use crate::{write_compressed_json, DataPaths, FirefoxHistoryItem};
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use clap::builder::PossibleValuesParser;
+use clap::Args;
+use reqwest::Url;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, Row};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::{debug, info, warn};
+
+/// The history of a browser that keeps it in SQLite, read with a query returning the URL, the
+/// title and the visit date in seconds since the epoch
+struct SqlitePreset {
+    name: &'static str,
+    query: &'static str,
+}
+
+/// The browsers whose history is known. Their name is also the source of the imported URLs.
+const PRESETS: &[SqlitePreset] = &[
+    // One row per visit
+    SqlitePreset {
+        name: "qutebrowser",
+        query: "SELECT url, title, atime FROM History WHERE NOT redirect",
+    },
+    // With the dates in milliseconds
+    SqlitePreset {
+        name: "falkon",
+        query: "SELECT url, title, date / 1000 FROM history",
+    },
+];
+
+/// The source of the URLs imported with a custom query
+const CUSTOM_SOURCE: &str = "sqlite";
+
+#[derive(Args, Debug)]
+pub struct ExtractSqliteHistoryArguments {
+    /// The SQLite database of the browser, which is only read
+    #[arg(long)]
+    db: PathBuf,
+    /// The browser the database comes from, which knows how to read it
+    #[arg(long, value_parser = preset_names(), required_unless_present = "query")]
+    preset: Option<String>,
+    /// The SQL query that reads the history, returning the URL, the title and the visit date in
+    /// seconds since the epoch, in this order. Each row counts as one visit
+    #[arg(long, conflicts_with = "preset")]
+    query: Option<String>,
+}
+
+fn preset_names() -> PossibleValuesParser {
+    PossibleValuesParser::new(PRESETS.iter().map(|preset| preset.name))
+}
+
+/// Add the history of a browser that keeps it in SQLite to the extracted history. The extraction
+/// of the Firefox history keeps these URLs.
+pub fn extract_sqlite_history(
+    arguments: ExtractSqliteHistoryArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<ExtractSummary> {
+    let (query, source) = match (&arguments.preset, &arguments.query) {
+        (Some(name), _) => {
+            let preset = PRESETS
+                .iter()
+                .find(|preset| preset.name == name)
+                .context("unknown preset")?;
+            (preset.query, preset.name)
+        }
+        (None, Some(query)) => (query.as_str(), CUSTOM_SOURCE),
+        (None, None) => anyhow::bail!("either --preset or --query is needed"),
+    };
+
+    // Opening it read-only never changes the database, even while the browser uses it
+    let conn = Connection::open_with_flags(&arguments.db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("failed to open {}", arguments.db.display()))?;
+    let mut statement = conn
+        .prepare(query)
+        .with_context(|| format!("invalid query for {}", arguments.db.display()))?;
+    if statement.column_count() != 3 {
+        anyhow::bail!(
+            "the query must return 3 columns, the URL, the title and the visit date in seconds \
+             since the epoch, but it returns {}: {}",
+            statement.column_count(),
+            statement.column_names().join(", ")
+        );
+    }
+
+    let mut visits_by_url: HashMap<String, FirefoxHistoryItem> = HashMap::new();
+    let mut skipped_rows = 0;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let Some(item) = convert_row(row, source)? else {
+            skipped_rows += 1;
+            continue;
+        };
+        match visits_by_url.entry(item.url.clone()) {
+            Entry::Occupied(mut occupied) => {
+                let previous = occupied.get_mut();
+                if previous.title.is_none() {
+                    previous.title = item.title;
+                }
+                previous.last_visit = previous.last_visit.max(item.last_visit);
+                previous.visit_count = Some(previous.visit_count.unwrap_or(0) + 1);
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(item);
+            }
+        }
+    }
+    if visits_by_url.is_empty() && skipped_rows > 0 {
+        warn!(
+            "None of the {} rows has a URL that can be downloaded, is the URL the first column of \
+             the query?",
+            skipped_rows
+        );
+    } else if skipped_rows > 0 {
+        info!(
+            "Skipped {} rows whose URL can't be downloaded",
+            skipped_rows
+        );
+    }
+    info!(
+        "Extracted {} visited URLs from {}",
+        visits_by_url.len(),
+        arguments.db.display()
+    );
+
+    // Everything is new when there is no previous history
+    let previous_history = data_paths.read_previous_history()?;
+    let mut history_by_url: HashMap<String, FirefoxHistoryItem> = previous_history
+        .into_iter()
+        .map(|item| (item.url.clone(), item))
+        .collect();
+
+    let urls = visits_by_url.len();
+    let mut new_urls = 0;
+    for (url, item) in visits_by_url {
+        match history_by_url.get_mut(&url) {
+            Some(previous) => {
+                if previous.title.is_none() {
+                    previous.title = item.title;
+                }
+                previous.last_visit = previous.last_visit.max(item.last_visit);
+                // Importing the same database again must not count its visits twice
+                previous.visit_count = previous.visit_count.max(item.visit_count);
+            }
+            None => {
+                new_urls += 1;
+                history_by_url.insert(url, item);
+            }
+        }
+    }
+    info!("Found {} URLs not in the history", new_urls);
+
+    let history: Vec<_> = history_by_url.into_values().collect();
+    write_compressed_json(&data_paths.history(), &history)?;
+    info!("Wrote history to disk");
+
+    Ok(ExtractSummary { urls, new_urls })
+}
+
+/// Convert a row of the query into one visit, or `None` when its URL can't be downloaded, like
+/// the internal pages of the browser
+fn convert_row(row: &Row, source: &str) -> anyhow::Result<Option<FirefoxHistoryItem>> {
+    let url: String = row
+        .get(0)
+        .context("the first column of the query must be the URL, as text")?;
+    let Some(mut parsed_url) = Url::parse(&url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https" | "file"))
+    else {
+        debug!("Skipped {}", url);
+        return Ok(None);
+    };
+    // Like in `extract_firefox_history()`
+    normalize_history_url(&mut parsed_url);
+    let url = parsed_url.to_string();
+
+    let title: Option<String> = row
+        .get(1)
+        .context("the second column of the query must be the title, as text or NULL")?;
+    let title = title.filter(|title| !title.is_empty());
+
+    let last_visit = match row.get(2)? {
+        Value::Null => None,
+        Value::Integer(seconds) => convert_epoch_seconds(seconds as f64, &url),
+        Value::Real(seconds) => convert_epoch_seconds(seconds, &url),
+        _ => anyhow::bail!(
+            "the third column of the query must be the visit date in seconds since the epoch, as \
+             a number or NULL"
+        ),
+    };
+
+    Ok(Some(FirefoxHistoryItem {
+        url,
+        title,
+        last_visit,
+        visit_count: Some(1),
+        source: Some(source.to_string()),
+        bookmarked: false,
+        bookmark_folders: Vec::new(),
+    }))
+}
+
+/// Some databases have garbage dates, far outside of the dates that can be represented
+fn convert_epoch_seconds(seconds: f64, url: &str) -> Option<DateTime<Utc>> {
+    let date = Utc.timestamp_millis_opt((seconds * 1000.0) as i64).single();
+    if date.is_none() {
+        warn!("Ignored the invalid visit date {} of {}", seconds, url);
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{history_item, TestData};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// A database with the table of the preset, where the page was visited twice
+    fn browser_db(preset: &str) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("history.sqlite");
+        let schema = match preset {
+            "qutebrowser" => {
+                "CREATE TABLE History (url TEXT, title TEXT, atime INTEGER, redirect INTEGER);
+                INSERT INTO History VALUES
+                    ('https://example.com/page', 'Page', 1689336000, 0),
+                    ('https://example.com/page', '', 1689422400, 0),
+                    ('https://example.com/redirect', 'Redirect', 1689336000, 1),
+                    ('qute://settings', 'Settings', 1689336000, 0);"
+            }
+            "falkon" => {
+                "CREATE TABLE history (url TEXT, title TEXT, date INTEGER);
+                INSERT INTO history VALUES
+                    ('https://example.com/page', NULL, 1689336000000),
+                    ('https://example.com/page', 'Page', 1689422400000);"
+            }
+            _ => panic!("no database for {}", preset),
+        };
+        Connection::open(&db)
+            .unwrap()
+            .execute_batch(schema)
+            .unwrap();
+        (dir, db)
+    }
+
+    /// Extract with the preset, or with the query when it's one
+    fn extract(
+        db: &Path,
+        preset_or_query: &str,
+        data: &TestData,
+    ) -> anyhow::Result<ExtractSummary> {
+        let (preset, query) = match preset_or_query.strip_prefix("SELECT") {
+            Some(_) => (None, Some(preset_or_query.to_string())),
+            None => (Some(preset_or_query.to_string()), None),
+        };
+        let arguments = ExtractSqliteHistoryArguments {
+            db: db.to_path_buf(),
+            preset,
+            query,
+        };
+        extract_sqlite_history(arguments, &data.data_paths)
+    }
+
+    #[test]
+    fn reads_the_history_of_the_presets() {
+        for preset in PRESETS {
+            let (_dir, db) = browser_db(preset.name);
+            let data = TestData::new();
+            let summary = extract(&db, preset.name, &data).unwrap();
+            assert_eq!((summary.urls, summary.new_urls), (1, 1), "{}", preset.name);
+
+            let history = data.data_paths.read_history().unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].url, "https://example.com/page");
+            assert_eq!(history[0].title.as_deref(), Some("Page"));
+            assert_eq!(history[0].source.as_deref(), Some(preset.name));
+            assert_eq!(history[0].visit_count, Some(2));
+            assert_eq!(history[0].last_visit.unwrap().timestamp(), 1_689_422_400);
+        }
+    }
+
+    #[test]
+    fn counts_the_visits_once_per_import() {
+        let (_dir, db) = browser_db("qutebrowser");
+        let data = TestData::new();
+        extract(&db, "qutebrowser", &data).unwrap();
+        let summary = extract(&db, "qutebrowser", &data).unwrap();
+        assert_eq!(summary.new_urls, 0);
+
+        let history = data.data_paths.read_history().unwrap();
+        assert_eq!(history[0].visit_count, Some(2));
+    }
+
+    #[test]
+    fn validates_the_columns_of_the_query() {
+        let (_dir, db) = browser_db("qutebrowser");
+        let data = TestData::new();
+        let error = extract(&db, "SELECT url, title FROM History", &data).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("the query must return 3 columns"));
+
+        let error = extract(&db, "SELECT atime, title, atime FROM History", &data).unwrap_err();
+        assert!(format!("{:#}", error).contains("the first column of the query must be the URL"));
+
+        let error = extract(&db, "SELECT url, title, x'00' FROM History", &data).unwrap_err();
+        assert!(error.to_string().starts_with("the third column"));
+
+        let error = extract(&db, "SELECT nothing FROM nowhere", &data).unwrap_err();
+        assert!(error.to_string().starts_with("invalid query"));
+        assert!(!data.data_paths.history().exists());
+    }
+
+    #[test]
+    fn merges_into_the_previous_history() {
+        let (_dir, db) = browser_db("qutebrowser");
+        let data = TestData::new();
+        let mut untitled = history_item("https://example.com/page", "");
+        untitled.visit_count = Some(5);
+        data.write_history(&[untitled, history_item("https://example.com/other", "Other")]);
+
+        let summary = extract(&db, "SELECT url, title, atime FROM History", &data).unwrap();
+        // The redirect is a new URL
+        assert_eq!((summary.urls, summary.new_urls), (2, 1));
+
+        let history = data.data_paths.read_history().unwrap();
+        assert_eq!(history.len(), 3);
+        let page = history
+            .iter()
+            .find(|item| item.url == "https://example.com/page")
+            .unwrap();
+        assert_eq!(page.title.as_deref(), Some("Page"));
+        assert_eq!(page.visit_count, Some(5));
+    }
+
+    #[test]
+    fn keeps_a_history_that_cannot_be_read() {
+        let (_dir, db) = browser_db("falkon");
+        let data = TestData::new();
+        std::fs::write(data.data_paths.history(), "corrupted").unwrap();
+        let error = extract(&db, "falkon", &data).unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to read"));
+        assert_eq!(
+            std::fs::read(data.data_paths.history()).unwrap(),
+            b"corrupted"
+        );
+    }
+
+    #[test]
+    fn converts_the_dates_in_range() {
+        assert_eq!(
+            convert_epoch_seconds(1_689_336_000.5, "url")
+                .unwrap()
+                .timestamp_millis(),
+            1_689_336_000_500
+        );
+        assert_eq!(convert_epoch_seconds(1e20, "url"), None);
+    }
+}
@@ -0,0 +1,573 @@
+use crate::markdown::markdown_to_text;
+use crate::normalize_text::normalize_text;
+use crate::DownloadedPageContent;
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Meta tags (by their "property", "name" or "itemprop" attribute) that hold the publication date
+const PUBLISHED_META_NAMES: &[&str] = &[
+    "article:published_time",
+    "og:published_time",
+    "datepublished",
+    "date",
+    "pubdate",
+    "publishdate",
+    "publish-date",
+    "publication_date",
+    "sailthru.date",
+    "dc.date",
+    "dc.date.issued",
+    "dcterms.date",
+    "dcterms.created",
+];
+
+/// JSON-LD keys whose values are meant for humans to read. Everything else (URLs, identifiers) is
+/// ignored.
+const JSON_LD_TEXT_KEYS: &[&str] = &[
+    "name",
+    "description",
+    "headline",
+    "articleBody",
+    "recipeInstructions",
+];
+
+/// Image alt texts and titles and link titles longer than this are likely not meant for humans,
+/// like encoded data, so they are skipped
+const MAX_ATTRIBUTE_TEXT_CHARS: usize = 300;
+
+/// How many distinct anchor texts to keep for each page
+const MAX_ANCHORS: usize = 500;
+
+/// What the indexing sees of a downloaded page
+pub struct ExtractedText {
+    pub title: Option<String>,
+    pub content: String,
+    /// The language declared in the root `<html lang>` attribute
+    pub html_lang: Option<String>,
+    /// Raw values that may represent the publication date, in document order
+    pub published_candidates: Vec<String>,
+    /// The distinct texts of outgoing links
+    pub anchors: Vec<String>,
+    /// The `href` of the `<link rel="canonical">` element, as written in the page
+    pub canonical_url: Option<String>,
+    /// How many elements of each kind were left out of the content, like "script" and "style"
+    pub skipped_elements: BTreeMap<String, usize>,
+}
+
+/// Extract the text of any kind of downloaded content, or `None` if the download failed or the
+/// page was pruned
+pub fn extract_page_text(content: &DownloadedPageContent) -> Option<ExtractedText> {
+    match content {
+        DownloadedPageContent::Failure(_)
+        | DownloadedPageContent::Pruned
+        | DownloadedPageContent::SkippedEquivalent(_) => None,
+        DownloadedPageContent::Html(html_source) => Some(extract_readable_text(html_source)),
+        DownloadedPageContent::Markdown(source) => {
+            let markdown_text = markdown_to_text(source);
+            let title = markdown_text
+                .title
+                .or_else(|| first_non_empty_line(&markdown_text.content));
+            Some(extract_text(title, &markdown_text.content))
+        }
+        DownloadedPageContent::PlainText(text) => {
+            Some(extract_text(first_non_empty_line(text), text))
+        }
+    }
+}
+
+/// Build the extracted text of content that has no markup
+fn extract_text(title: Option<String>, content: &str) -> ExtractedText {
+    ExtractedText {
+        title: title.map(|title| normalize_text(&title)),
+        content: normalize_text(content),
+        html_lang: None,
+        published_candidates: Vec::new(),
+        anchors: Vec::new(),
+        canonical_url: None,
+        skipped_elements: BTreeMap::new(),
+    }
+}
+
+fn first_non_empty_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .map(|line| line.to_string())
+}
+
+pub fn extract_readable_text(html_source: &str) -> ExtractedText {
+    let document = Html::parse_document(html_source);
+    let mut extracted = ExtractedText {
+        title: None,
+        content: String::new(),
+        html_lang: document
+            .root_element()
+            .value()
+            .attr("lang")
+            .map(|lang| lang.trim().to_lowercase())
+            .filter(|lang| !lang.is_empty()),
+        published_candidates: Vec::new(),
+        anchors: Vec::new(),
+        canonical_url: None,
+        skipped_elements: BTreeMap::new(),
+    };
+
+    fn recurse_page_tree(extracted: &mut ExtractedText, node: &NodeRef<Node>) {
+        match node.value() {
+            Node::Text(text) => {
+                extracted.content.push_str(text);
+            }
+            Node::Element(element) => {
+                let element_name = element.name();
+
+                if element_name == "title" && extracted.title.is_none() {
+                    let mut title = String::new();
+                    for sub_node in node.descendants() {
+                        if let Some(text) = sub_node.value().as_text() {
+                            title.push_str(text);
+                        }
+                    }
+                    extracted.title = Some(title);
+                } else if element_name == "meta" {
+                    let meta_name = element
+                        .attr("property")
+                        .or_else(|| element.attr("name"))
+                        .or_else(|| element.attr("itemprop"));
+                    if let (Some(meta_name), Some(meta_content)) =
+                        (meta_name, element.attr("content"))
+                    {
+                        if PUBLISHED_META_NAMES.contains(&meta_name.to_lowercase().as_str()) {
+                            extracted
+                                .published_candidates
+                                .push(meta_content.to_string());
+                        }
+                    }
+                } else if element_name == "link" {
+                    let is_canonical = element.attr("rel").is_some_and(|rel| {
+                        rel.split_whitespace()
+                            .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+                    });
+                    if is_canonical && extracted.canonical_url.is_none() {
+                        extracted.canonical_url = element
+                            .attr("href")
+                            .map(|href| href.trim().to_string())
+                            .filter(|href| !href.is_empty());
+                    }
+                } else if element_name == "img" {
+                    for attribute in ["alt", "title"] {
+                        if let Some(text) = element.attr(attribute) {
+                            append_attribute_text(extracted, text);
+                        }
+                    }
+                } else if element_name == "a" {
+                    if let Some(title) = element.attr("title") {
+                        append_attribute_text(extracted, title);
+                    }
+
+                    let mut anchor = String::new();
+                    for sub_node in node.descendants() {
+                        if let Some(text) = sub_node.value().as_text() {
+                            anchor.push_str(text);
+                        }
+                    }
+                    let anchor = anchor.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !anchor.is_empty()
+                        && extracted.anchors.len() < MAX_ANCHORS
+                        && !extracted.anchors.contains(&anchor)
+                    {
+                        extracted.anchors.push(anchor);
+                    }
+
+                    for child in node.children() {
+                        recurse_page_tree(extracted, &child);
+                    }
+                } else if element_name == "time" {
+                    if let Some(date_time) = element.attr("datetime") {
+                        extracted.published_candidates.push(date_time.to_string());
+                    }
+                    for child in node.children() {
+                        recurse_page_tree(extracted, &child);
+                    }
+                } else if element_name == "script"
+                    && element.attr("type") == Some("application/ld+json")
+                {
+                    let mut json_source = String::new();
+                    for child in node.children() {
+                        if let Some(text) = child.value().as_text() {
+                            json_source.push_str(text);
+                        }
+                    }
+                    // Malformed JSON is common in the wild and is simply ignored
+                    if let Ok(json) = serde_json::from_str::<Value>(&json_source) {
+                        collect_json_ld(extracted, &json);
+                    }
+                } else if element_name == "script" || element_name == "style" {
+                    *extracted
+                        .skipped_elements
+                        .entry(element_name.to_string())
+                        .or_default() += 1;
+                } else {
+                    for child in node.children() {
+                        recurse_page_tree(extracted, &child);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Add descriptive attribute text to the content, skipping values too long to be a description
+    fn append_attribute_text(extracted: &mut ExtractedText, text: &str) {
+        let text = text.trim();
+        if !text.is_empty() && text.chars().count() <= MAX_ATTRIBUTE_TEXT_CHARS {
+            extracted.content.push(' ');
+            extracted.content.push_str(text);
+            extracted.content.push(' ');
+        }
+    }
+
+    /// Look for "datePublished" and human-readable text anywhere in a JSON-LD value, which can be
+    /// nested in "@graph" or other entities
+    fn collect_json_ld(extracted: &mut ExtractedText, json: &Value) {
+        match json {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("datePublished", Value::String(date)) => {
+                            extracted.published_candidates.push(date.clone());
+                        }
+                        (key, value) if JSON_LD_TEXT_KEYS.contains(&key) => {
+                            collect_json_ld_text(extracted, value);
+                        }
+                        _ => collect_json_ld(extracted, value),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    collect_json_ld(extracted, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Append all strings of a JSON-LD text value, like the steps in "recipeInstructions", which
+    /// may be plain strings or "HowToStep" objects with their own "text" and "name"
+    fn collect_json_ld_text(extracted: &mut ExtractedText, json: &Value) {
+        match json {
+            Value::String(text) => {
+                extracted.content.push(' ');
+                extracted.content.push_str(text);
+                extracted.content.push(' ');
+            }
+            Value::Array(values) => {
+                for value in values {
+                    collect_json_ld_text(extracted, value);
+                }
+            }
+            Value::Object(object) => {
+                for (key, value) in object {
+                    if key == "text" || JSON_LD_TEXT_KEYS.contains(&key.as_str()) {
+                        collect_json_ld_text(extracted, value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    recurse_page_tree(&mut extracted, &document.root_element());
+
+    extracted.content = normalize_text(&extracted.content);
+    extracted.title = extracted.title.map(|title| normalize_text(&title));
+    extracted.anchors = extracted
+        .anchors
+        .iter()
+        .map(|anchor| normalize_text(anchor))
+        .collect();
+
+    extracted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{visited_page, TestData};
+
+    #[test]
+    fn reads_the_declared_language() {
+        let extracted =
+            extract_readable_text(r#"<html lang=" fr-CA "><body>Bonjour</body></html>"#);
+        assert_eq!(extracted.html_lang.as_deref(), Some("fr-ca"));
+
+        let extracted = extract_readable_text("<html lang=''><body>Hello</body></html>");
+        assert_eq!(extracted.html_lang, None);
+
+        let extracted = extract_readable_text("<p>Hello</p>");
+        assert_eq!(extracted.html_lang, None);
+    }
+
+    #[test]
+    fn only_reads_the_language_of_the_root() {
+        let extracted = extract_readable_text(
+            r#"<html><body><p lang="de">Guten Tag</p><p>Hello</p></body></html>"#,
+        );
+        assert_eq!(extracted.html_lang, None);
+    }
+
+    /// Like the pages of recipe sites, with the instructions as "HowToStep" objects
+    const RECIPE_PAGE: &str = r#"<html><head>
+        <title>Lemon tart</title>
+        <script type="application/ld+json">
+        {
+          "@context": "https://schema.org/",
+          "@type": "Recipe",
+          "name": "Lemon tart",
+          "image": ["https://example.com/photos/lemon-tart.jpg"],
+          "author": {"@type": "Person", "name": "Jane Baker", "url": "https://example.com/jane"},
+          "datePublished": "2018-03-10",
+          "description": "A tangy tart with a buttery crust",
+          "recipeYield": "8 servings",
+          "recipeIngredient": ["3 lemons", "200 g sugar"],
+          "recipeInstructions": [
+            {"@type": "HowToStep", "text": "Blind bake the shortcrust"},
+            {"@type": "HowToStep", "name": "Filling", "text": "Whisk the curd until glossy"}
+          ],
+          "aggregateRating": {"@type": "AggregateRating", "ratingValue": "4.8"}
+        }
+        </script>
+        </head><body><div id="app"></div></body></html>"#;
+
+    /// Like the pages of news sites, with the entities in a "@graph"
+    const NEWS_PAGE: &str = r#"<html><head>
+        <script type="application/ld+json">
+        {"@context": "https://schema.org", "@graph": [
+          {"@type": "WebSite", "@id": "https://news.example/#website", "url": "https://news.example/"},
+          {"@type": "NewsArticle", "@id": "https://news.example/a/123#article",
+           "headline": "Council approves the new tramway",
+           "articleBody": "The vote passed with a large majority.",
+           "datePublished": "2023-02-01T09:15:00+01:00",
+           "mainEntityOfPage": "https://news.example/a/123",
+           "identifier": "article-123"}
+        ]}
+        </script>
+        </head><body><p>Subscribe to read</p></body></html>"#;
+
+    #[test]
+    fn indexes_the_text_of_recipes() {
+        let extracted = extract_readable_text(RECIPE_PAGE);
+        for text in [
+            "A tangy tart with a buttery crust",
+            "Blind bake the shortcrust",
+            "Filling",
+            "Whisk the curd until glossy",
+            "Jane Baker",
+        ] {
+            assert!(extracted.content.contains(text), "{:?}", text);
+        }
+        assert!(!extracted.content.contains("https://"));
+        assert!(!extracted.content.contains("4.8"));
+        assert_eq!(extracted.published_candidates, ["2018-03-10"]);
+    }
+
+    #[test]
+    fn indexes_the_text_of_news_articles() {
+        let extracted = extract_readable_text(NEWS_PAGE);
+        assert!(extracted
+            .content
+            .contains("Council approves the new tramway"));
+        assert!(extracted
+            .content
+            .contains("The vote passed with a large majority."));
+        assert!(extracted.content.contains("Subscribe to read"));
+        assert!(!extracted.content.contains("article-123"));
+        assert!(!extracted.content.contains("news.example"));
+        assert_eq!(
+            extracted.published_candidates,
+            ["2023-02-01T09:15:00+01:00"]
+        );
+    }
+
+    #[test]
+    fn ignores_malformed_json_ld() {
+        let extracted = extract_readable_text(
+            r#"<script type="application/ld+json">{"headline": "Broken",</script><p>Body</p>"#,
+        );
+        assert_eq!(extracted.content, "Body");
+        assert!(extracted.skipped_elements.is_empty());
+    }
+
+    /// Like a gallery of screenshots, whose descriptions are only in the images
+    const GALLERY_PAGE: &str = r#"<html><head><title>Gallery</title></head><body>
+        <h1>Holiday photos</h1>
+        <img src="1.jpg" alt="Lighthouse at sunset">
+        <img src="2.jpg" title="Harbour with fishing boats">
+        <img src="3.jpg" alt="">
+        <a href="/next" title="Older albums">Next</a>
+        </body></html>"#;
+
+    #[test]
+    fn indexes_the_descriptions_of_the_images() {
+        let extracted = extract_readable_text(GALLERY_PAGE);
+        for text in [
+            "Lighthouse at sunset",
+            "Harbour with fishing boats",
+            "Older albums",
+        ] {
+            assert!(extracted.content.contains(text), "{:?}", text);
+        }
+        // Separated from the text around them
+        assert!(extracted.content.contains("Older albums Next"));
+
+        let data = TestData::new();
+        data.index_pages(
+            vec![visited_page(
+                "https://example.com/gallery",
+                "Gallery",
+                GALLERY_PAGE,
+            )],
+            &[],
+        );
+        for query in ["lighthouse", "fishing boats", "older"] {
+            assert_eq!(
+                data.search_urls(query, &["--in=content"]),
+                ["https://example.com/gallery"],
+                "{:?}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn skips_the_long_attribute_texts() {
+        let long_text = "word ".repeat(MAX_ATTRIBUTE_TEXT_CHARS / 5 + 1);
+        let page = format!(
+            r#"<p>Body</p><img alt="{}"><img alt="{}">"#,
+            long_text,
+            "x".repeat(MAX_ATTRIBUTE_TEXT_CHARS)
+        );
+        let extracted = extract_readable_text(&page);
+        assert!(!extracted.content.contains("word"));
+        // The limit itself is kept
+        assert_eq!(
+            extracted.content,
+            format!("Body {}", "x".repeat(MAX_ATTRIBUTE_TEXT_CHARS))
+        );
+    }
+
+    /// Links whose texts name what the body doesn't
+    const LINKS_PAGE: &str = r#"<html><body>
+        <p>See the <a href="/a">borrow   checker</a> and the
+        <a href="/b"><em>Pin</em> projections</a>.</p>
+        <nav><a href="/a">Borrow checker</a><a href="/c">Lifetime elision</a></nav>
+        </body></html>"#;
+
+    #[test]
+    fn keeps_the_distinct_anchor_texts() {
+        let extracted = extract_readable_text(LINKS_PAGE);
+        assert_eq!(
+            extracted.anchors,
+            [
+                "borrow checker",
+                "Pin projections",
+                "Borrow checker",
+                "Lifetime elision"
+            ]
+        );
+
+        let data = TestData::new();
+        data.index_pages(
+            vec![visited_page(
+                "https://example.com/links",
+                "Links",
+                LINKS_PAGE,
+            )],
+            &[],
+        );
+        assert_eq!(
+            data.search_urls("projections", &["--in=anchors"]),
+            ["https://example.com/links"]
+        );
+        assert!(data.search_urls("see", &["--in=anchors"]).is_empty());
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_anchors() {
+        let mut page = String::new();
+        for link in 0..MAX_ANCHORS + 10 {
+            page.push_str(&format!(r#"<a href="/{0}">Link {0}</a>"#, link));
+        }
+        page.push_str(r#"<a href="/0">Link 0</a>"#);
+        let extracted = extract_readable_text(&page);
+        assert_eq!(extracted.anchors.len(), MAX_ANCHORS);
+        assert_eq!(extracted.anchors[0], "Link 0");
+        assert_eq!(
+            extracted.anchors.last().unwrap(),
+            &format!("Link {}", MAX_ANCHORS - 1)
+        );
+    }
+
+    /// Like the README of a project
+    const README: &str = "# mind-search
+
+A **search engine** for the pages of your *history*.
+
+## Installation
+
+Read the [docs site](https://docs.example/install) and run `cargo install`:
+
+```sh
+cargo install mind-search
+```
+
+* Fast
+* Private
+";
+
+    #[test]
+    fn indexes_the_text_of_markdown() {
+        let content = DownloadedPageContent::Markdown(README.to_string());
+        let extracted = extract_page_text(&content).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("mind-search"));
+        for text in [
+            "search engine",
+            "Installation",
+            "docs site",
+            "cargo install",
+            "Private",
+        ] {
+            assert!(extracted.content.contains(text), "{:?}", text);
+        }
+        for markup in ["#", "*", "[", "](", "docs.example", "`"] {
+            assert!(!extracted.content.contains(markup), "{:?}", markup);
+        }
+
+        let data = TestData::new();
+        let (item, mut page) = visited_page("https://example.com/README.md", "", "");
+        page.content = content;
+        data.index_pages(vec![(item, page)], &[]);
+        let results = data.search("installation docs", &["--in=content"]);
+        assert_eq!(results.hits.len(), 1);
+        assert_eq!(results.hits[0].title.as_deref(), Some("mind-search"));
+        assert!(data.search_urls("example", &["--in=content"]).is_empty());
+    }
+
+    #[test]
+    fn titles_the_text_by_its_first_line() {
+        let content =
+            DownloadedPageContent::PlainText("\n  \n  Release notes \nVersion 2\n".to_string());
+        let extracted = extract_page_text(&content).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("Release notes"));
+        assert!(extracted.content.contains("Version 2"));
+
+        // And the markdown without heading too
+        let content = DownloadedPageContent::Markdown("Some *notes*\n\nMore".to_string());
+        let extracted = extract_page_text(&content).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("Some notes"));
+
+        assert!(extract_page_text(&DownloadedPageContent::Pruned).is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::export::escape_html;
+use crate::search::{site_domain, OpenedIndex};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use std::cmp::Reverse;
+use tantivy::collector::TopDocs;
+use tantivy::query::{AllQuery, Query, TermQuery};
+use tantivy::schema::{IndexRecordOption, Schema};
+use tantivy::{DateTime, Document, Term};
+
+/// How many chars of the content to show as the summary of each entry
+const SUMMARY_CHARS: usize = 300;
+
+/// A recently indexed page
+struct FeedEntry {
+    url: String,
+    title: String,
+    summary: String,
+    indexed_at: DateTime,
+    last_visit: Option<DateTime>,
+}
+
+/// The Atom feed of the last indexed pages, optionally of a single site. The links of the feed
+/// point to `feed_url`.
+pub fn recent_pages_feed(
+    indexes: &[OpenedIndex],
+    site: Option<&str>,
+    limit: usize,
+    feed_url: &str,
+) -> anyhow::Result<String> {
+    let mut entries = Vec::new();
+    for opened_index in indexes {
+        let schema = opened_index.index.schema();
+        if schema.get_field("indexed_at").is_err() {
+            anyhow::bail!(
+                "the index {} was built before the feed existed, run index-contents to rebuild it",
+                opened_index.name
+            );
+        }
+        let query: Box<dyn Query> = match site {
+            Some(site) => Box::new(TermQuery::new(
+                Term::from_field_text(schema.get_field("domain")?, &site_domain(site)),
+                IndexRecordOption::Basic,
+            )),
+            None => Box::new(AllQuery),
+        };
+        let searcher = opened_index.reader.searcher();
+        let top_docs = searcher.search(
+            &query,
+            &TopDocs::with_limit(limit).order_by_fast_field::<DateTime>("indexed_at"),
+        )?;
+        for (indexed_at, address) in top_docs {
+            let document = searcher.doc(address)?;
+            entries.push(feed_entry(&schema, &document, indexed_at)?);
+        }
+    }
+    // The most recently indexed first, then the most recently visited, across all the indexes
+    entries.sort_by_key(|entry| Reverse((entry.indexed_at, entry.last_visit)));
+    entries.truncate(limit);
+
+    let updated = entries
+        .first()
+        .map_or_else(Utc::now, |entry| to_chrono(entry.indexed_at));
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let title = match site {
+        Some(site) => format!("Recently indexed pages of {}", site),
+        None => "Recently indexed pages".to_string(),
+    };
+    feed.push_str(&format!("  <title>{}</title>\n", escape_xml(&title)));
+    feed.push_str(&format!("  <id>{}</id>\n", escape_xml(feed_url)));
+    feed.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}\"/>\n",
+        escape_xml(feed_url)
+    ));
+    feed.push_str(&format!("  <updated>{}</updated>\n", format_date(updated)));
+    feed.push_str("  <author><name>mind-search</name></author>\n");
+    for entry in entries {
+        // The last visit tells better when the page was read, when it is known
+        let updated = to_chrono(entry.last_visit.unwrap_or(entry.indexed_at));
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!(
+            "    <title>{}</title>\n",
+            escape_xml(&entry.title)
+        ));
+        feed.push_str(&format!("    <id>{}</id>\n", escape_xml(&entry.url)));
+        feed.push_str(&format!(
+            "    <link href=\"{}\"/>\n",
+            escape_xml(&entry.url)
+        ));
+        feed.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            format_date(updated)
+        ));
+        feed.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            escape_xml(&entry.summary)
+        ));
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    Ok(feed)
+}
+
+fn feed_entry(
+    schema: &Schema,
+    document: &Document,
+    indexed_at: DateTime,
+) -> anyhow::Result<FeedEntry> {
+    let text = |field_name: &str| -> anyhow::Result<Option<String>> {
+        Ok(document
+            .get_first(schema.get_field(field_name)?)
+            .and_then(|value| value.as_text())
+            .map(str::to_string))
+    };
+    let url = text("url")?.unwrap_or_default();
+    let title = match text("title")? {
+        Some(title) => title,
+        None => text("synthetic_title")?.unwrap_or_else(|| url.clone()),
+    };
+    let content = text("content")?.unwrap_or_default();
+    let mut summary: String = content.chars().take(SUMMARY_CHARS).collect();
+    if summary.len() < content.len() {
+        summary.push('…');
+    }
+    let last_visit = document
+        .get_first(schema.get_field("last_visit")?)
+        .and_then(|value| value.as_date());
+    Ok(FeedEntry {
+        url,
+        title,
+        summary,
+        indexed_at,
+        last_visit,
+    })
+}
+
+/// Escape the text for XML, also removing the control chars that XML doesn't allow
+fn escape_xml(text: &str) -> String {
+    let allowed: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    escape_html(&allowed)
+}
+
+fn to_chrono(date: DateTime) -> chrono::DateTime<Utc> {
+    Utc.timestamp_millis_opt(date.into_timestamp_millis())
+        .single()
+        .unwrap_or_default()
+}
+
+/// Like "2024-05-12T10:00:00Z", as Atom wants
+fn format_date(date: chrono::DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,282 @@
+use crate::normalize_text::normalize_text;
+use crate::query_operators::extract_operators;
+use crate::search::MIN_FUZZY_WORD_CHARS;
+use std::ops::Range;
+use tantivy::query::Occur;
+use tantivy::query_grammar::{self, UserInputAst, UserInputLeaf};
+use tantivy::tokenizer::TextAnalyzer;
+use tantivy::Index;
+
+/// Finds the words of a query in the text of a page. The text is split into words by the analyzer
+/// of the indexed content, so that the same words match as in the search, with the same typos.
+pub struct PageMatcher {
+    analyzer: TextAnalyzer,
+    /// The analyzed words of the query, with how many typos each one can have
+    terms: Vec<(Vec<char>, usize)>,
+    /// Whether the words of the page that start with a word of the query match too
+    prefix: bool,
+}
+
+impl PageMatcher {
+    /// Match the words of the query in the content, with up to `typos` typos in the words long
+    /// enough, like the search with --fuzzy and --fuzzy-prefix. The excluded words and the words
+    /// searched in other fields, like "title:tokio", are not looked for.
+    pub fn new(index: &Index, query: &str, typos: u8, prefix: bool) -> anyhow::Result<Self> {
+        let content_field = index.schema().get_field("content")?;
+        let mut analyzer = index.tokenizer_for_field(content_field)?;
+
+        let (text, _) = extract_operators(query);
+        let text = normalize_text(&text);
+        let mut words = Vec::new();
+        match query_grammar::parse_query(&text) {
+            Ok(user_input) => query_words(user_input, &mut words),
+            // Like the search, which then looks for the plain words
+            Err(_) => words.push(text),
+        }
+
+        let mut terms: Vec<(Vec<char>, usize)> = Vec::new();
+        for word in &words {
+            let mut token_stream = analyzer.token_stream(word);
+            while token_stream.advance() {
+                let term: Vec<char> = token_stream.token().text.chars().collect();
+                let term_typos = if term.len() >= MIN_FUZZY_WORD_CHARS {
+                    typos as usize
+                } else {
+                    0
+                };
+                if !terms.iter().any(|(other, _)| *other == term) {
+                    terms.push((term, term_typos));
+                }
+            }
+        }
+
+        Ok(PageMatcher {
+            analyzer,
+            terms,
+            prefix,
+        })
+    }
+
+    /// Whether the query has no word to look for
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The byte ranges of the words of the text that match, in the order of the text
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        let mut analyzer = self.analyzer.clone();
+        let mut token_stream = analyzer.token_stream(text);
+        let mut matches = Vec::new();
+        while token_stream.advance() {
+            let token = token_stream.token();
+            let word: Vec<char> = token.text.chars().collect();
+            let matched = self
+                .terms
+                .iter()
+                .any(|(term, typos)| self.matches(term, *typos, &word));
+            if matched {
+                matches.push(token.offset_from..token.offset_to);
+            }
+        }
+        matches
+    }
+
+    fn matches(&self, term: &[char], typos: usize, word: &[char]) -> bool {
+        if !self.prefix {
+            return word.len().abs_diff(term.len()) <= typos && edit_distance(term, word) <= typos;
+        }
+        // Any start of the word can be the word of the query with its typos
+        let shortest = term.len().saturating_sub(typos).max(1);
+        let longest = (term.len() + typos).min(word.len());
+        (shortest..=longest).any(|length| edit_distance(term, &word[..length]) <= typos)
+    }
+}
+
+/// The lines of the text with a match, counted from 1
+pub fn match_lines(text: &str, matches: &[Range<usize>]) -> Vec<usize> {
+    let mut lines: Vec<usize> = Vec::new();
+    for range in matches {
+        let line = text[..range.start].matches('\n').count() + 1;
+        if lines.last() != Some(&line) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// The words and phrases of the query that must or can be in the content
+fn query_words(user_input: UserInputAst, words: &mut Vec<String>) {
+    match user_input {
+        UserInputAst::Clause(clauses) => {
+            for (occur, clause) in clauses {
+                if occur != Some(Occur::MustNot) {
+                    query_words(clause, words);
+                }
+            }
+        }
+        UserInputAst::Leaf(leaf) => {
+            if let UserInputLeaf::Literal(literal) = *leaf {
+                if literal
+                    .field_name
+                    .as_ref()
+                    .is_none_or(|field_name| field_name == "content")
+                {
+                    words.push(literal.phrase);
+                }
+            }
+        }
+        UserInputAst::Boost(user_input, _) => query_words(*user_input, words),
+    }
+}
+
+/// How many insertions, deletions, substitutions and swaps of two adjacent chars turn one word into
+/// the other, the swaps counting as one typo like in the fuzzy search
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // The distances of the prefixes of `a` to the prefixes of `b`, for the last two rows
+    let mut before_previous: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        before_previous = previous;
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::{IndexRecordOption, Schema, TextFieldIndexing, TextOptions, TEXT};
+
+    /// An index with the content analyzed like by index-contents
+    fn content_index() -> Index {
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("content", TEXT);
+        Index::create_in_ram(schema_builder.build())
+    }
+
+    /// An index whose content is analyzed with the English stemmer
+    fn stemmed_index() -> Index {
+        let mut schema_builder = Schema::builder();
+        let indexing = TextFieldIndexing::default()
+            .set_tokenizer("en_stem")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        schema_builder.add_text_field(
+            "content",
+            TextOptions::default().set_indexing_options(indexing),
+        );
+        Index::create_in_ram(schema_builder.build())
+    }
+
+    /// The matched words of the page
+    fn find(index: &Index, query: &str, typos: u8, prefix: bool) -> Vec<&'static str> {
+        let matcher = PageMatcher::new(index, query, typos, prefix).unwrap();
+        matcher
+            .find(PAGE)
+            .into_iter()
+            .map(|range| &PAGE[range])
+            .collect()
+    }
+
+    const PAGE: &str =
+        "The Tokio runtime runs the tokio tasks. Configuring the runtime, and its configuration";
+
+    #[test]
+    fn finds_the_words_like_the_search() {
+        let index = content_index();
+        assert_eq!(
+            find(&index, "tokio RUNTIME", 0, false),
+            ["Tokio", "runtime", "tokio", "runtime"]
+        );
+        // Without the excluded words and the words of the other fields
+        assert_eq!(
+            find(&index, "tokio -runtime title:tasks", 0, false),
+            ["Tokio", "tokio"]
+        );
+        assert_eq!(
+            find(&index, "\"tokio tasks\"", 0, false),
+            ["Tokio", "tokio", "tasks"]
+        );
+        // Like the search, an invalid query looks for its plain words
+        assert_eq!(find(&index, "tasks)", 0, false), ["tasks"]);
+        assert!(PageMatcher::new(&index, "-tokio", 0, false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn finds_the_stemmed_words() {
+        // The content of index-contents is not stemmed, so only the fuzzy search finds the other
+        // forms of a word
+        assert_eq!(
+            find(&content_index(), "configuring", 0, false),
+            ["Configuring"]
+        );
+        assert_eq!(
+            find(&stemmed_index(), "configuring", 0, false),
+            ["Configuring", "configuration"]
+        );
+        assert_eq!(find(&stemmed_index(), "runs", 0, false), ["runs"]);
+        assert_eq!(find(&stemmed_index(), "task", 0, false), ["tasks"]);
+    }
+
+    #[test]
+    fn finds_the_words_with_typos() {
+        let index = content_index();
+        assert!(find(&index, "runtmie", 0, false).is_empty());
+        assert_eq!(find(&index, "runtmie", 1, false), ["runtime", "runtime"]);
+        assert_eq!(find(&index, "tokiox", 1, false), ["Tokio", "tokio"]);
+        // The short words have no typo, like in the fuzzy search
+        assert_eq!(find(&index, "rum", 2, false), Vec::<&str>::new());
+        assert_eq!(find(&index, "runs", 1, false), ["runs"]);
+    }
+
+    #[test]
+    fn finds_the_words_starting_with_the_query() {
+        let index = content_index();
+        assert_eq!(find(&index, "config", 0, false), Vec::<&str>::new());
+        assert_eq!(
+            find(&index, "config", 0, true),
+            ["Configuring", "configuration"]
+        );
+        assert_eq!(
+            find(&index, "cnofig", 1, true),
+            ["Configuring", "configuration"]
+        );
+        assert_eq!(find(&index, "run", 0, true), ["runtime", "runs", "runtime"]);
+        // Longer than the word
+        assert_eq!(find(&index, "runsx", 0, true), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn counts_the_typos() {
+        let distance = |a: &str, b: &str| {
+            let a: Vec<char> = a.chars().collect();
+            let b: Vec<char> = b.chars().collect();
+            edit_distance(&a, &b)
+        };
+        assert_eq!(distance("tokio", "tokio"), 0);
+        assert_eq!(distance("", "tokio"), 5);
+        assert_eq!(distance("tokio", ""), 5);
+        assert_eq!(distance("tokio", "tokyo"), 1);
+        assert_eq!(distance("tokio", "toko"), 1);
+        assert_eq!(distance("tokio", "tokkio"), 1);
+        assert_eq!(distance("tokio", "tokoi"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn tells_the_lines_of_the_matches() {
+        let text = "tokio\nno match\ntokio and tokio\n";
+        let matcher = PageMatcher::new(&content_index(), "tokio", 0, false).unwrap();
+        assert_eq!(match_lines(text, &matcher.find(text)), [1, 3]);
+    }
+}
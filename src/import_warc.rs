@@ -0,0 +1,268 @@
+use crate::bundle_cache::read_page_summaries;
+use crate::download_pages::{decode_body, write_downloaded_pages, DEFAULT_BUNDLE_SIZE};
+use crate::normalize_url::normalize_history_url;
+use crate::{DataPaths, DownloadedPage, DownloadedPageContent};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use flate2::bufread::{GzDecoder, MultiGzDecoder, ZlibDecoder};
+use rayon::prelude::*;
+use reqwest::Url;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{debug, info};
+
+#[derive(Args, Debug)]
+#[command(after_help = "Examples:
+  mind-search import-warc ~/archives/crawl.warc.gz
+  mind-search import-warc archivebox/archive/1700000000/warc/1700000000.warc.gz --prefer-warc
+
+Then index the imported pages like the downloaded ones:
+    mind-search index-contents --only-new")]
+pub struct ImportWarcArguments {
+    /// The WARC file, compressed with gzip for each record or as a whole, or not compressed
+    path: PathBuf,
+    /// Import the pages that were already downloaded too, like when the archived version is
+    /// better than the live one
+    #[arg(long)]
+    prefer_warc: bool,
+}
+
+/// The headers of a WARC record, like "WARC-Type: response", or of the HTTP response it holds
+struct Headers(Vec<(String, String)>);
+
+/// What was done with the response records of the file
+#[derive(Default)]
+struct ImportSummary {
+    imported: usize,
+    /// Already downloaded, repeated in the file or not successful
+    skipped: usize,
+    non_html: usize,
+}
+
+/// Import the HTML pages of the WARC file as downloaded pages, into new bundles
+pub fn import_warc(arguments: ImportWarcArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let file = File::open(&arguments.path)
+        .with_context(|| format!("failed to open {}", arguments.path.display()))?;
+    let mut reader = BufReader::new(file);
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    // A gzip member for each record is read like a single one for the whole file
+    let mut reader: Box<dyn BufRead> = if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut known_urls = if arguments.prefer_warc {
+        HashSet::new()
+    } else {
+        successful_urls(data_paths)?
+    };
+    let mut summary = ImportSummary::default();
+    let mut pages = Vec::new();
+    while let Some(headers) = read_warc_headers(&mut reader)
+        .with_context(|| format!("invalid WARC file {}", arguments.path.display()))?
+    {
+        let content_length: usize = headers
+            .get("Content-Length")
+            .context("a WARC record has no Content-Length")?
+            .parse()
+            .context("a WARC record has an invalid Content-Length")?;
+        let mut block = vec![0; content_length];
+        reader
+            .read_exact(&mut block)
+            .context("the WARC file is truncated")?;
+        if headers.get("WARC-Type") != Some("response") {
+            continue;
+        }
+        let Some(page) = page_of_response(&headers, &block, &mut summary) else {
+            continue;
+        };
+        if !known_urls.insert(page.url.clone()) {
+            debug!("Skipped {}, already downloaded", page.url);
+            summary.skipped += 1;
+            continue;
+        }
+
+        pages.push(page);
+        summary.imported += 1;
+        if pages.len() >= DEFAULT_BUNDLE_SIZE {
+            write_downloaded_pages(&mut pages, None, data_paths)?;
+        }
+    }
+    write_downloaded_pages(&mut pages, None, data_paths)?;
+
+    info!("Imported {}", arguments.path.display());
+    println!(
+        "Imported {} pages, skipped {} already downloaded or unsuccessful, {} were not HTML",
+        summary.imported, summary.skipped, summary.non_html
+    );
+    if summary.imported > 0 {
+        println!("Run index-contents to search them");
+    }
+    Ok(())
+}
+
+/// The URLs of the pages downloaded successfully, which are not imported again
+fn successful_urls(data_paths: &DataPaths) -> anyhow::Result<HashSet<String>> {
+    let urls = Mutex::new(HashSet::new());
+    data_paths
+        .list_raw_pages_bundles()?
+        .into_par_iter()
+        .try_for_each(|bundle| -> anyhow::Result<()> {
+            let pages = read_page_summaries(data_paths, &bundle)?;
+            let mut urls = urls.lock().unwrap();
+            for page in pages {
+                if page.success {
+                    urls.insert(page.url);
+                }
+            }
+            Ok(())
+        })?;
+    Ok(urls.into_inner().unwrap())
+}
+
+/// Read the version line and the headers of the next record, or nothing at the end of the file
+fn read_warc_headers(reader: &mut dyn BufRead) -> anyhow::Result<Option<Headers>> {
+    let mut line = String::new();
+    // The blank lines that end the previous record
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    if !line.starts_with("WARC/") {
+        anyhow::bail!("expected a WARC record, found {:?}", line.trim());
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("the WARC file is truncated");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(Some(Headers(headers)));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
+
+/// The page of a response record, if it's a successful HTML response
+fn page_of_response(
+    headers: &Headers,
+    block: &[u8],
+    summary: &mut ImportSummary,
+) -> Option<DownloadedPage> {
+    // WARC 1.0 wrote the URI between "<>", by mistake of its specification
+    let target_uri = headers.get("WARC-Target-URI")?;
+    let mut url = Url::parse(target_uri.trim_start_matches('<').trim_end_matches('>')).ok()?;
+    normalize_history_url(&mut url);
+    let url = url.to_string();
+    let loaded_at = headers
+        .get("WARC-Date")
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map_or_else(Utc::now, |date| date.with_timezone(&Utc));
+
+    let Some((status, response_headers, body)) = parse_http_response(block) else {
+        debug!("Skipped {}, not an HTTP response", url);
+        summary.skipped += 1;
+        return None;
+    };
+    if !(200..300).contains(&status) {
+        debug!("Skipped {}, with the status {}", url, status);
+        summary.skipped += 1;
+        return None;
+    }
+    let content_type = response_headers.get("Content-Type").unwrap_or_default();
+    if !content_type.starts_with("text/html") && !content_type.starts_with("application/xhtml") {
+        summary.non_html += 1;
+        return None;
+    }
+
+    let body = if response_headers
+        .get("Transfer-Encoding")
+        .is_some_and(|value| value.contains("chunked"))
+    {
+        decode_chunked(body)
+    } else {
+        Some(body.to_vec())
+    };
+    let body = body.and_then(|body| match response_headers.get("Content-Encoding") {
+        None | Some("identity") => Some(body),
+        Some("gzip" | "x-gzip") => read_all(GzDecoder::new(&body[..])),
+        Some("deflate") => read_all(ZlibDecoder::new(&body[..])),
+        Some(_) => None,
+    });
+    let Some(body) = body else {
+        debug!("Skipped {}, with a body that could not be decoded", url);
+        summary.skipped += 1;
+        return None;
+    };
+
+    Some(DownloadedPage {
+        url,
+        loaded_at,
+        content: DownloadedPageContent::Html(decode_body(&body, content_type)),
+        final_url: None,
+        canonical_url: None,
+    })
+}
+
+/// The status, the headers and the body of a raw HTTP response
+fn parse_http_response(block: &[u8]) -> Option<(u16, Headers, &[u8])> {
+    let head_end = block.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&block[..head_end]);
+    let mut lines = head.lines();
+    // Like "HTTP/1.1 200 OK"
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Some((status, Headers(headers), &block[head_end + 4..]))
+}
+
+/// The body sent in chunks, each after its size in hexadecimal
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&body[..line_end]).ok()?;
+        // The size can be followed by extensions, like "1a;name=value"
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+fn read_all(mut reader: impl Read) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).ok()?;
+    Some(content)
+}
+
+impl Headers {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
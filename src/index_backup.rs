@@ -0,0 +1,209 @@
+use crate::data_lock::ServersLock;
+use crate::doctor::dir_size;
+use crate::index_lock::IndexLock;
+use crate::index_stats::format_size;
+use crate::integrity::is_temporary_file;
+use crate::{check_name, DataPaths, DEFAULT_INDEX_NAME};
+use anyhow::Context;
+use chrono::{DateTime, Local, Utc};
+use clap::Args;
+use std::fs;
+use std::path::Path;
+use tantivy::Index;
+use tracing::{debug, info};
+
+/// The small files that change at each commit, copied rather than shared in case they are
+/// rewritten in place
+const REWRITTEN_FILES: [&str; 2] = ["meta.json", ".managed.json"];
+
+#[derive(Args, Debug)]
+pub struct IndexBackupArguments {
+    /// The name of the snapshot, like "before-tokenizer". By default, the current date and time
+    #[arg(long)]
+    name: Option<String>,
+    /// List the snapshots instead, with their size and date
+    #[arg(long, conflicts_with = "name")]
+    list: bool,
+    /// The name of the index to snapshot
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexRestoreArguments {
+    /// The name of the snapshot, as given by `index-backup --list`
+    name: String,
+    /// The name of the index to restore
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+}
+
+/// Copy the index into a snapshot, sharing the files with the index when the file system allows it
+pub fn index_backup(arguments: IndexBackupArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if arguments.list {
+        return list_backups(data_paths);
+    }
+
+    let index_dir = data_paths.built_index_dir(&arguments.index_name)?;
+    let name = match arguments.name {
+        Some(name) => name,
+        None => Local::now().format("%Y-%m-%d_%H-%M-%S").to_string(),
+    };
+    check_name("snapshot name", &name)?;
+    let backup_dir = data_paths
+        .index_backups_dir()
+        .join(&arguments.index_name)
+        .join(&name);
+    if backup_dir.exists() {
+        anyhow::bail!(
+            "the snapshot {} of the index {} already exists",
+            name,
+            arguments.index_name
+        );
+    }
+
+    // No command writes to the index during the copy
+    let _lock = IndexLock::acquire(index_dir.clone())?;
+    let temporary_dir = backup_dir.with_file_name(format!(".{}", name));
+    if let Err(error) = link_or_copy_index(&index_dir, &temporary_dir) {
+        let _ = fs::remove_dir_all(&temporary_dir);
+        return Err(error);
+    }
+    fs::rename(&temporary_dir, &backup_dir)?;
+
+    let mut restore_command = format!("index-restore {}", name);
+    if arguments.index_name != DEFAULT_INDEX_NAME {
+        restore_command.push_str(&format!(" --index-name {}", arguments.index_name));
+    }
+    println!(
+        "Saved the index {} as the snapshot {}, restore it with `{}`",
+        arguments.index_name, name, restore_command
+    );
+    Ok(())
+}
+
+/// Replace the index with a snapshot, after checking that the snapshot opens
+pub fn index_restore(
+    arguments: IndexRestoreArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let backup_dir = data_paths
+        .index_backups_dir()
+        .join(&arguments.index_name)
+        .join(&arguments.name);
+    if !backup_dir.is_dir() {
+        anyhow::bail!(
+            "the index {} has no snapshot {}, see `index-backup --list`",
+            arguments.index_name,
+            arguments.name
+        );
+    }
+    let documents = Index::open_in_dir(&backup_dir)
+        .and_then(|index| index.reader())
+        .map(|reader| reader.searcher().num_docs())
+        .with_context(|| format!("the snapshot {} can't be opened", backup_dir.display()))?;
+
+    // The servers would keep searching the files of the replaced index
+    let _servers_lock = ServersLock::acquire_exclusive(data_paths)?;
+    let index_dir = data_paths.tantivy_index_dir(&arguments.index_name)?;
+    let _lock = IndexLock::acquire(index_dir.clone())?;
+
+    // The snapshot is copied, so that it can be restored again
+    let hidden_dir =
+        |suffix: &str| index_dir.with_file_name(format!(".{}.{}", arguments.index_name, suffix));
+    let restored_dir = hidden_dir("restored");
+    let replaced_dir = hidden_dir("replaced");
+    for dir in [&restored_dir, &replaced_dir] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    if let Err(error) = link_or_copy_index(&backup_dir, &restored_dir) {
+        let _ = fs::remove_dir_all(&restored_dir);
+        return Err(error);
+    }
+    if index_dir.exists() {
+        fs::rename(&index_dir, &replaced_dir)?;
+    }
+    fs::rename(&restored_dir, &index_dir)?;
+    if replaced_dir.exists() {
+        fs::remove_dir_all(&replaced_dir)?;
+    }
+
+    println!(
+        "Restored the index {} from the snapshot {}, with {} documents",
+        arguments.index_name, arguments.name, documents
+    );
+    Ok(())
+}
+
+fn list_backups(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let backups_dir = data_paths.index_backups_dir();
+    let mut backups = Vec::new();
+    if backups_dir.is_dir() {
+        for index_entry in fs::read_dir(&backups_dir)? {
+            let index_entry = index_entry?;
+            if !index_entry.file_type()?.is_dir() {
+                continue;
+            }
+            for backup_entry in fs::read_dir(index_entry.path())? {
+                let backup_entry = backup_entry?;
+                if is_temporary_file(&backup_entry.path()) {
+                    continue;
+                }
+                let created: DateTime<Utc> = backup_entry.metadata()?.modified()?.into();
+                backups.push((
+                    index_entry.file_name().to_string_lossy().to_string(),
+                    backup_entry.file_name().to_string_lossy().to_string(),
+                    dir_size(&backup_entry.path()),
+                    created,
+                ));
+            }
+        }
+    }
+
+    if backups.is_empty() {
+        println!("No snapshots, save one with index-backup");
+    }
+    backups.sort();
+    for (index_name, name, size, created) in backups {
+        println!(
+            "{}: {}  {}  {}",
+            index_name,
+            name,
+            format_size(size),
+            created.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        );
+    }
+    Ok(())
+}
+
+/// Hard link the files of the index into the new directory, or copy them when the file system
+/// doesn't allow it. The segment files are never modified, so the copies can share them.
+fn link_or_copy_index(source_dir: &Path, target_dir: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(target_dir)?;
+    let mut copied = 0;
+    let mut linked = 0;
+    for maybe_entry in fs::read_dir(source_dir)? {
+        let entry = maybe_entry?;
+        let file_name = entry.file_name();
+        let is_lock = file_name.to_string_lossy().ends_with(".lock");
+        if !entry.file_type()?.is_file() || is_lock {
+            continue;
+        }
+
+        let target = target_dir.join(&file_name);
+        let must_copy = REWRITTEN_FILES.iter().any(|name| file_name == *name);
+        if !must_copy && fs::hard_link(entry.path(), &target).is_ok() {
+            linked += 1;
+        } else {
+            fs::copy(entry.path(), &target)?;
+            copied += 1;
+        }
+    }
+    debug!("Linked {} files and copied {} files", linked, copied);
+    if copied > REWRITTEN_FILES.len() {
+        info!("The file system doesn't allow hard links, so the index was copied");
+    }
+    Ok(())
+}
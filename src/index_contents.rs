@@ -1,84 +1,1296 @@
+use crate::annotations;
+use crate::boilerplate::{Boilerplate, LineFrequencies};
+use crate::bundle_cache::read_page_summaries;
+use crate::domain::registrable_domain;
+use crate::empty_pages::{EmptyPages, MAX_CONSECUTIVE_EMPTY};
+use crate::extract_text::extract_page_text;
+use crate::index_lock::IndexLock;
+use crate::interstitial::is_interstitial;
+use crate::metadata::{self, Reindexed};
+use crate::normalize_url::normalize_url;
+use crate::optimize_index::merge_all_segments;
+use crate::parse_date::{infer_date_from_url, parse_date};
+use crate::shutdown::shutdown_requested;
+use crate::simhash::simhash;
+use crate::synthetic_title::synthesize_title;
 use crate::{
-    list_raw_pages_bundles, read_compressed_json, DownloadedPage, DownloadedPageContent,
-    FirefoxHistoryItem, HISTORY_PATH, TANTIVY_INDEX_DIR_PATH,
+    for_each_compressed_json_item, DataPaths, DownloadedPage, DownloadedPageContent,
+    FirefoxHistoryItem, MissingStep, DEFAULT_INDEX_NAME,
 };
-use ego_tree::NodeRef;
+use anyhow::Context;
+use chrono::Utc;
+use clap::{Args, Parser};
 use rayon::prelude::*;
-use scraper::{Html, Node};
-use std::collections::HashMap;
+use reqwest::Url;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use tantivy::collector::Count;
 use tantivy::directory::MmapDirectory;
-use tantivy::schema::{Schema, STORED, TEXT};
-use tantivy::{DateTime, Document, Index};
+use tantivy::query::TermQuery;
+use tantivy::schema::{
+    Facet, FacetOptions, Field, IndexRecordOption, Schema, FAST, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{DateTime, Document, Index, IndexWriter, Term};
+use tracing::{debug, info, info_span, warn};
+
+/// Tantivy refuses budgets outside of these bounds for each indexing thread
+const MIN_WRITER_MEMORY_MB_PER_THREAD: usize = 3;
+const MAX_WRITER_MEMORY_MB_PER_THREAD: usize = 4000;
+
+/// The indexing options alone, to read them from elsewhere than the command line
+#[derive(Parser, Debug)]
+struct IndexContentsOptions {
+    #[command(flatten)]
+    arguments: IndexContentsArguments,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexContentsArguments {
+    /// The name of the index to create, so that different corpora can be kept apart
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// When a page doesn't declare its publication date, use dates in the URL path like
+    /// "/2021/05/12/"
+    #[arg(long)]
+    infer_date_from_url: bool,
+    /// Fail when some bundles can't be read, instead of only reporting them
+    #[arg(long)]
+    strict: bool,
+    /// Do a first pass to learn the lines repeated across many pages of the same site (headers,
+    /// footers, sidebars) and remove them from the indexed content. The learned lines are saved in
+    /// the "boilerplate" directory of the data directory
+    #[arg(long)]
+    strip_repeated_boilerplate: bool,
+    /// Remove the boilerplate lines learned by a previous run with --strip-repeated-boilerplate,
+    /// without doing the first pass again
+    #[arg(long, conflicts_with = "strip_repeated_boilerplate")]
+    reuse_boilerplate: bool,
+    /// Only learn the boilerplate of sites with more than this number of pages
+    #[arg(long, default_value_t = 20)]
+    boilerplate_min_pages: usize,
+    /// A line is boilerplate when it appears in more than this fraction of the site's pages
+    #[arg(long, default_value_t = 0.5)]
+    boilerplate_min_ratio: f64,
+    /// The memory budget for the index writer, shared by all indexing threads. Defaults to 15% of
+    /// the system memory, up to 2 GB
+    #[arg(long)]
+    writer_memory_mb: Option<usize>,
+    /// How many threads the index writer uses. Defaults to the number of CPUs, up to 8
+    #[arg(long)]
+    indexing_threads: Option<usize>,
+    /// Merge all the index segments at the end, which makes the first queries faster
+    #[arg(long)]
+    optimize: bool,
+    /// Also index pages that look like login walls or cookie-consent screens, which are skipped
+    /// by default
+    #[arg(long)]
+    index_interstitials: bool,
+    /// Don't build a title from the URL for pages without a title in the history nor in the page
+    #[arg(long)]
+    no_synthetic_titles: bool,
+    /// Index every successful download of each URL as a separate version, instead of only the
+    /// newest one. The search shows the newest version of each page, unless --versions or --as-of
+    /// is given
+    #[arg(long)]
+    keep_versions: bool,
+    /// Only reindex the pages of this bundle, replacing the documents previously created from it
+    #[arg(long, conflicts_with = "url")]
+    bundle: Option<PathBuf>,
+    /// Only reindex this URL, using its newest download, or all of them with --keep-versions
+    #[arg(long)]
+    url: Option<String>,
+    /// Only index the bundles that have no documents in the index yet, keeping the others, instead
+    /// of rebuilding the whole index
+    #[arg(long, conflicts_with_all = ["bundle", "url", "strip_repeated_boilerplate"])]
+    only_new: bool,
+    /// With --only-new, also extract the pages that extracted to no text in the last runs, which
+    /// are skipped after 3 of them in a row. See list-empty-pages
+    #[arg(long)]
+    recheck_empty: bool,
+    /// How many pages read from the bundles can be extracted or wait to be indexed at once. The
+    /// bundles are read one page at a time, so this bounds the memory of the indexing besides the
+    /// writer budget, whatever the size of the bundles
+    #[arg(long, default_value_t = 128)]
+    max_in_flight_pages: usize,
+}
+
+/// What a run of the indexer did
+#[derive(Clone, Debug, Default)]
+pub struct IndexSummary {
+    pub indexed_pages: usize,
+    /// The login walls and cookie-consent pages, which are not indexed
+    pub skipped_interstitials: usize,
+    /// The older downloads of the URLs, which are not indexed without --keep-versions
+    pub skipped_versions: usize,
+    /// The new downloads of the pages that extracted to no text in the last runs
+    pub skipped_empty: usize,
+    pub unreadable_bundles: usize,
+    /// The most pages read from the bundles and not indexed yet at once, at most
+    /// --max-in-flight-pages
+    pub peak_in_flight_pages: usize,
+}
+
+impl IndexContentsArguments {
+    /// Parse the options written like in the command line, like `["--index-name=work"]`
+    pub fn parse_options<'a>(options: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+        let argv = ["index-contents"].into_iter().chain(options);
+        Ok(IndexContentsOptions::try_parse_from(argv)?.arguments)
+    }
+}
+
+/// The fields of the index
+struct IndexFields {
+    url: Field,
+    title: Field,
+    last_visit: Field,
+    content: Field,
+    published: Field,
+    word_count: Field,
+    html_lang: Field,
+    anchors: Field,
+    url_exact: Field,
+    bundle_path: Field,
+    bundle_record: Field,
+    visit_date: Field,
+    simhash: Field,
+    synthetic_title: Field,
+    domain: Field,
+    visit_count: Field,
+    canonical_url: Field,
+    indexed_at: Field,
+    notes: Field,
+    /// 1 for the bookmarked pages
+    bookmarked: Field,
+    bookmark_folder: Field,
+    /// When the page was downloaded, to tell the versions of the same URL apart
+    snapshot_at: Field,
+}
+
+impl IndexFields {
+    fn build_schema() -> (Schema, IndexFields) {
+        let mut schema_builder = Schema::builder();
+        let fields = IndexFields {
+            url: schema_builder.add_text_field("url", TEXT | STORED),
+            title: schema_builder.add_text_field("title", TEXT | STORED),
+            last_visit: schema_builder.add_date_field("last_visit", INDEXED | STORED | FAST),
+            content: schema_builder.add_text_field("content", TEXT | STORED),
+            published: schema_builder.add_date_field("published", INDEXED | STORED | FAST),
+            word_count: schema_builder.add_u64_field("word_count", STORED | FAST),
+            html_lang: schema_builder.add_text_field("html_lang", STRING | STORED),
+            anchors: schema_builder.add_text_field("anchors", TEXT),
+            url_exact: schema_builder.add_text_field("url_exact", STRING),
+            bundle_path: schema_builder.add_text_field("bundle_path", STRING | STORED),
+            bundle_record: schema_builder.add_u64_field("bundle_record", STORED),
+            visit_date: schema_builder.add_facet_field("visit_date", FacetOptions::default()),
+            simhash: schema_builder.add_u64_field("simhash", STORED | FAST),
+            synthetic_title: schema_builder.add_text_field("synthetic_title", STORED),
+            domain: schema_builder.add_text_field("domain", STRING),
+            visit_count: schema_builder.add_u64_field("visit_count", STORED | FAST),
+            canonical_url: schema_builder.add_text_field("canonical_url", STORED),
+            indexed_at: schema_builder.add_date_field("indexed_at", INDEXED | STORED | FAST),
+            notes: schema_builder.add_text_field("notes", TEXT | STORED),
+            bookmarked: schema_builder.add_u64_field("bookmarked", INDEXED | STORED | FAST),
+            bookmark_folder: schema_builder
+                .add_facet_field("bookmark_folder", FacetOptions::default().set_stored()),
+            snapshot_at: schema_builder.add_date_field("snapshot_at", INDEXED | STORED | FAST),
+        };
+        (schema_builder.build(), fields)
+    }
+}
 
-pub fn index_contents() -> anyhow::Result<()> {
-    let history: Vec<FirefoxHistoryItem> = read_compressed_json(Path::new(HISTORY_PATH))?;
+pub fn index_contents(
+    arguments: IndexContentsArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<IndexSummary> {
+    let history = data_paths.read_history()?;
     let history_by_url: HashMap<_, _> = history
         .into_iter()
         .map(|item| (item.url.clone(), item))
         .collect();
+    let notes_by_url = annotations::read_notes_by_url(data_paths)?;
+    let is_partial = arguments.bundle.is_some() || arguments.url.is_some() || arguments.only_new;
 
-    fs::create_dir_all(TANTIVY_INDEX_DIR_PATH)?;
+    // Checked before creating the index, which would be left empty otherwise
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    if bundles.is_empty() && !is_partial {
+        return Err(MissingStep {
+            missing: format!(
+                "no downloaded pages in {}",
+                data_paths.raw_pages_dir().display()
+            ),
+            command: "download-pages".to_string(),
+        }
+        .into());
+    }
 
-    let mut schema_builder = Schema::builder();
-    let url_field = schema_builder.add_text_field("url", TEXT | STORED);
-    let title_field = schema_builder.add_text_field("title", TEXT | STORED);
-    let last_visit_field = schema_builder.add_date_field("last_visit", STORED);
-    let content_field = schema_builder.add_text_field("content", TEXT | STORED);
-    let schema = schema_builder.build();
+    let index_dir_path = data_paths.tantivy_index_dir(&arguments.index_name)?;
+    let _lock = IndexLock::acquire(index_dir_path.clone())?;
+    fs::create_dir_all(&index_dir_path)?;
 
-    let index_directory = MmapDirectory::open(TANTIVY_INDEX_DIR_PATH)?;
+    let (schema, fields) = IndexFields::build_schema();
+
+    // A full run rebuilds the whole index anyway, so an index with an older schema can simply be
+    // discarded
+    let index_directory = MmapDirectory::open(&index_dir_path)?;
+    if Index::exists(&index_directory)? && Index::open(index_directory.clone())?.schema() != schema
+    {
+        if is_partial {
+            anyhow::bail!("the index schema changed, run a full index-contents first");
+        }
+        info!("Index schema changed, rebuilding it from scratch");
+        fs::remove_dir_all(&index_dir_path)?;
+        fs::create_dir_all(&index_dir_path)?;
+    }
+    let index_directory = MmapDirectory::open(&index_dir_path)?;
     let index = Index::open_or_create(index_directory, schema)?;
-    let mut index_writer = index.writer(1024 * 1024 * 1024)?;
-    index_writer.delete_all_documents()?;
+    let (writer_memory_mb, indexing_threads) =
+        decide_writer_resources(arguments.writer_memory_mb, arguments.indexing_threads)?;
+    info!(
+        "Indexing with {} threads and {} MB of writer memory",
+        indexing_threads, writer_memory_mb
+    );
+    let mut index_writer =
+        index.writer_with_num_threads(indexing_threads, writer_memory_mb * 1024 * 1024)?;
+
+    let boilerplate = if arguments.strip_repeated_boilerplate {
+        let boilerplate = learn_boilerplate(
+            &bundles,
+            arguments.boilerplate_min_pages,
+            arguments.boilerplate_min_ratio,
+        )?;
+        boilerplate.write(&data_paths.boilerplate_dir())?;
+        info!(
+            "Learned boilerplate lines for {} domains",
+            boilerplate.num_domains()
+        );
+        boilerplate
+    } else if arguments.reuse_boilerplate {
+        Boilerplate::read(&data_paths.boilerplate_dir())?
+    } else {
+        Boilerplate::default()
+    };
+
+    // The reindexed URLs look for their downloads themselves
+    let newest_records = if arguments.keep_versions || arguments.url.is_some() {
+        None
+    } else {
+        Some(newest_records(data_paths, &bundles))
+    };
+
+    // The full runs extract everything again, so that the registry stays up to date
+    let mut empty_pages = EmptyPages::read(data_paths)?;
+    let skipped_empty_urls = if arguments.only_new && !arguments.recheck_empty {
+        empty_pages.consistently_empty()
+    } else {
+        HashSet::new()
+    };
+
+    let document_builder = DocumentBuilder {
+        fields: &fields,
+        history_by_url: &history_by_url,
+        notes_by_url: &notes_by_url,
+        boilerplate: &boilerplate,
+        arguments: &arguments,
+        newest_records,
+        skipped_empty_urls,
+        skipped_interstitials: AtomicUsize::new(0),
+        skipped_versions: AtomicUsize::new(0),
+        skipped_empty: AtomicUsize::new(0),
+        indexed_at: now(),
+        indexed_records: Mutex::new(Vec::new()),
+        indexed_urls: Mutex::new(HashSet::new()),
+        empty_urls: Mutex::new(HashSet::new()),
+        non_empty_urls: Mutex::new(HashSet::new()),
+    };
+
+    let mut unreadable_bundles = Vec::new();
+    let mut peak_in_flight_pages = 0;
+    let mut indexed_pages;
+    // The URLs whose notes the index now has, `None` for all of them
+    let mut annotated_urls = Some(BTreeSet::new());
+    if let Some(bundle) = &arguments.bundle {
+        indexed_pages = reindex_bundle(
+            &index,
+            &index_writer,
+            &document_builder,
+            &data_paths.raw_pages_dir(),
+            bundle,
+        )?;
+    } else if let Some(url) = &arguments.url {
+        indexed_pages = reindex_url(
+            &index,
+            &index_writer,
+            &document_builder,
+            data_paths,
+            bundles,
+            url,
+        )?;
+        annotated_urls = Some(BTreeSet::from([url.clone()]));
+    } else if arguments.only_new {
+        let indexed_bundles = indexed_bundle_names(&index)?;
+        let new_bundles: Vec<PathBuf> = bundles
+            .iter()
+            .filter(|bundle| {
+                bundle
+                    .file_name()
+                    .is_some_and(|file_name| !indexed_bundles.contains(file_name))
+            })
+            .cloned()
+            .collect();
+        info!("Indexing {} new bundles", new_bundles.len());
+        // A new download of a URL replaces the one indexed before, unless versions are kept
+        let bundles_indexed = index_all_bundles(
+            &index_writer,
+            &document_builder,
+            new_bundles,
+            !arguments.keep_versions,
+            arguments.max_in_flight_pages,
+        )?;
+        indexed_pages = bundles_indexed.indexed_pages;
+        unreadable_bundles = bundles_indexed.unreadable_bundles;
+        peak_in_flight_pages = bundles_indexed.peak_in_flight_pages;
+
+        // The annotated pages of the new bundles may have a document with only their notes
+        let mut changed_urls = annotations::changed_urls(data_paths, &arguments.index_name)?;
+        for url in document_builder.indexed_urls.lock().unwrap().iter() {
+            if notes_by_url.contains_key(url) {
+                changed_urls.insert(url.clone());
+            }
+        }
+        if !changed_urls.is_empty() {
+            info!("Reindexing {} annotated pages", changed_urls.len());
+            indexed_pages += reindex_annotated_urls(
+                &index_writer,
+                &document_builder,
+                data_paths,
+                bundles,
+                &changed_urls,
+            )?;
+        }
+        annotated_urls = Some(changed_urls);
+    } else {
+        index_writer.delete_all_documents()?;
+        let bundles_indexed = index_all_bundles(
+            &index_writer,
+            &document_builder,
+            bundles,
+            false,
+            arguments.max_in_flight_pages,
+        )?;
+        indexed_pages = bundles_indexed.indexed_pages;
+        unreadable_bundles = bundles_indexed.unreadable_bundles;
+        peak_in_flight_pages = bundles_indexed.peak_in_flight_pages;
+        indexed_pages += index_notes_only(&index_writer, &document_builder)?;
+        annotated_urls = None;
+    }
+
+    index_writer.commit()?;
+    if arguments.optimize {
+        merge_all_segments(&index, &index_dir_path, index_writer)?;
+    }
+    annotations::mark_indexed(data_paths, &arguments.index_name, annotated_urls.as_ref())?;
+
+    let reindexed = if let Some(bundle) = &arguments.bundle {
+        Reindexed::Bundle(bundle)
+    } else if let Some(url) = &arguments.url {
+        Reindexed::Url(url)
+    } else if arguments.only_new {
+        Reindexed::NewBundles
+    } else {
+        Reindexed::All
+    };
+    metadata::record_indexed(
+        data_paths,
+        &arguments.index_name,
+        reindexed,
+        &document_builder.indexed_records.into_inner().unwrap(),
+    );
+
+    if peak_in_flight_pages > 0 {
+        debug!(
+            "At most {} pages waited to be indexed at once",
+            peak_in_flight_pages
+        );
+    }
+    let skipped_interstitials = document_builder.skipped_interstitials.into_inner();
+    info!(
+        "Skipped {} login walls and cookie-consent pages",
+        skipped_interstitials
+    );
+    let skipped_versions = document_builder.skipped_versions.into_inner();
+    if skipped_versions > 0 {
+        info!(
+            "Skipped {} older downloads of the same URLs, use --keep-versions to index them",
+            skipped_versions
+        );
+    }
+    let skipped_empty = document_builder.skipped_empty.into_inner();
+    if skipped_empty > 0 {
+        info!(
+            "Skipped {} pages that extracted to no text in the last {} runs, use --recheck-empty \
+             to extract them again",
+            skipped_empty, MAX_CONSECUTIVE_EMPTY
+        );
+    }
+
+    let non_empty_urls = document_builder.non_empty_urls.into_inner().unwrap();
+    empty_pages.update(
+        document_builder.empty_urls.into_inner().unwrap(),
+        &non_empty_urls,
+        Utc::now(),
+    );
+    empty_pages.write(data_paths)?;
+
+    if !unreadable_bundles.is_empty() {
+        let bundle_names: Vec<String> = unreadable_bundles
+            .iter()
+            .map(|bundle| bundle.display().to_string())
+            .collect();
+        warn!(
+            "Skipped {} unreadable bundles: {}",
+            unreadable_bundles.len(),
+            bundle_names.join(", ")
+        );
+
+        if arguments.strict {
+            anyhow::bail!("{} bundles could not be read", unreadable_bundles.len());
+        }
+    }
+
+    Ok(IndexSummary {
+        indexed_pages,
+        skipped_interstitials,
+        skipped_versions,
+        skipped_empty,
+        unreadable_bundles: unreadable_bundles.len(),
+        peak_in_flight_pages,
+    })
+}
+
+/// Index the pages into a new index in the directory, with the default options, returning how many
+/// were indexed. The pages come with their bundle and record, like in the real index.
+pub fn index_pages(
+    pages: Vec<(PathBuf, usize, DownloadedPage)>,
+    index_dir_path: &Path,
+    data_paths: &DataPaths,
+) -> anyhow::Result<usize> {
+    let history = data_paths.read_history()?;
+    let history_by_url: HashMap<_, _> = history
+        .into_iter()
+        .map(|item| (item.url.clone(), item))
+        .collect();
+    let notes_by_url = annotations::read_notes_by_url(data_paths)?;
+    let arguments = IndexContentsArguments::parse_options([])?;
 
-    let bundles = list_raw_pages_bundles()?;
-    bundles
+    let (schema, fields) = IndexFields::build_schema();
+    let index = Index::create_in_dir(index_dir_path, schema)?;
+    let (writer_memory_mb, indexing_threads) =
+        decide_writer_resources(arguments.writer_memory_mb, arguments.indexing_threads)?;
+    let mut index_writer =
+        index.writer_with_num_threads(indexing_threads, writer_memory_mb * 1024 * 1024)?;
+    let boilerplate = Boilerplate::default();
+    let document_builder = DocumentBuilder {
+        fields: &fields,
+        history_by_url: &history_by_url,
+        notes_by_url: &notes_by_url,
+        boilerplate: &boilerplate,
+        arguments: &arguments,
+        newest_records: None,
+        skipped_empty_urls: HashSet::new(),
+        skipped_interstitials: AtomicUsize::new(0),
+        skipped_versions: AtomicUsize::new(0),
+        skipped_empty: AtomicUsize::new(0),
+        indexed_at: now(),
+        indexed_records: Mutex::new(Vec::new()),
+        indexed_urls: Mutex::new(HashSet::new()),
+        empty_urls: Mutex::new(HashSet::new()),
+        non_empty_urls: Mutex::new(HashSet::new()),
+    };
+
+    let indexed_pages = AtomicUsize::new(0);
+    pages
         .into_par_iter()
-        .try_for_each(|bundle| -> anyhow::Result<()> {
-            let downloaded_pages: Vec<DownloadedPage> = read_compressed_json(&bundle)?;
-            let total_pages = downloaded_pages.len();
-            let mut indexed_pages = 0;
+        .try_for_each(|(bundle, record, page)| -> anyhow::Result<()> {
+            if let Some(document) = document_builder.build(&bundle, record, page) {
+                index_writer.add_document(document)?;
+                indexed_pages.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        })?;
+    index_writer.commit()?;
+    Ok(indexed_pages.into_inner())
+}
 
-            for page in downloaded_pages {
-                if let DownloadedPageContent::Html(html_source) = page.content {
-                    let extracted_text = extract_readable_text(&html_source);
+/// What indexing the pages of the bundles did
+struct BundlesIndexed {
+    indexed_pages: usize,
+    unreadable_bundles: Vec<PathBuf>,
+    /// The most pages read from the bundles and not indexed yet at once
+    peak_in_flight_pages: usize,
+}
 
-                    let mut document = Document::default();
+/// Index all the pages of all the bundles. With `replace_versions`, the documents of the same URLs
+/// already in the index are deleted.
+///
+/// The bundles are read and their pages extracted in parallel, one page at a time, and the
+/// documents go through a channel to the threads that add them to the index. At most
+/// `max_in_flight_pages` pages are between their reading and their indexing at once, so that the
+/// memory used doesn't depend on the size of the bundles.
+fn index_all_bundles(
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    bundles: Vec<PathBuf>,
+    replace_versions: bool,
+    max_in_flight_pages: usize,
+) -> anyhow::Result<BundlesIndexed> {
+    let unreadable_bundles = Mutex::new(Vec::new());
+    let in_flight_pages = InFlightPages::new(max_in_flight_pages);
+    let (document_sender, document_receiver) =
+        mpsc::sync_channel::<(Term, Document)>(in_flight_pages.max);
+    let document_receiver = Mutex::new(document_receiver);
 
-                    let history_item = history_by_url.get(&page.url);
-                    if let Some(title) = decide_title(history_item, extracted_text.title) {
-                        document.add_field_value(title_field, title);
-                    }
+    // After an error, the indexers keep emptying the channel so that the readers are not blocked
+    let indexing_failed = AtomicBool::new(false);
 
-                    if let Some(last_visit) = decide_last_visit(history_item) {
-                        document.add_field_value(last_visit_field, last_visit);
+    let indexed_pages = thread::scope(|scope| -> anyhow::Result<usize> {
+        let indexers: Vec<_> = (0..rayon::current_num_threads())
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<usize> {
+                    let mut indexed_pages = 0;
+                    let mut result = Ok(());
+                    loop {
+                        // Not locked while indexing, so that the indexers work at the same time
+                        let next = document_receiver.lock().unwrap().recv();
+                        // Ends when all the bundles were read
+                        let Ok((url_term, document)) = next else {
+                            break;
+                        };
+                        if result.is_ok() && !indexing_failed.load(Ordering::Relaxed) {
+                            match add_document(index_writer, url_term, document, replace_versions) {
+                                Ok(()) => indexed_pages += 1,
+                                Err(error) => {
+                                    indexing_failed.store(true, Ordering::Relaxed);
+                                    result = Err(error);
+                                }
+                            }
+                        }
+                        in_flight_pages.release();
                     }
+                    result.map(|()| indexed_pages)
+                })
+            })
+            .collect();
+
+        bundles
+            .into_par_iter()
+            .for_each_with(document_sender, |document_sender, bundle| {
+                // The bundles indexed so far are still committed
+                if shutdown_requested() || indexing_failed.load(Ordering::Relaxed) {
+                    return;
+                }
 
-                    document.add_field_value(url_field, page.url);
-                    document.add_field_value(content_field, extracted_text.content);
+                // The threads of rayon read several bundles at once
+                let _span = info_span!("bundle", path = %bundle.display()).entered();
 
-                    index_writer.add_document(document)?;
-                    indexed_pages += 1;
+                let mut record = 0;
+                let result = for_each_compressed_json_item(&bundle, |page: DownloadedPage| {
+                    if indexing_failed.load(Ordering::Relaxed) {
+                        anyhow::bail!("the indexing stopped");
+                    }
+                    in_flight_pages.acquire();
+                    let url_term =
+                        Term::from_field_text(document_builder.fields.url_exact, &page.url);
+                    match document_builder.build(&bundle, record, page) {
+                        Some(document) => document_sender
+                            .send((url_term, document))
+                            .context("the indexing stopped")?,
+                        None => in_flight_pages.release(),
+                    }
+                    record += 1;
+                    Ok(())
+                });
+                match result {
+                    Ok(()) => debug!("Read {} pages", record),
+                    // The error of the indexing is returned instead
+                    Err(_) if indexing_failed.load(Ordering::Relaxed) => {}
+                    // A truncated bundle (for example, left by an interrupted download run) should
+                    // not discard the work done on all the others. The pages read before the
+                    // error are indexed.
+                    Err(error) => {
+                        warn!("Failed to read the bundle: {}", error);
+                        unreadable_bundles.lock().unwrap().push(bundle);
+                    }
                 }
+            });
+
+        let mut indexed_pages = 0;
+        for indexer in indexers {
+            indexed_pages += indexer.join().expect("an indexing thread panicked")?;
+        }
+        Ok(indexed_pages)
+    })?;
+
+    Ok(BundlesIndexed {
+        indexed_pages,
+        unreadable_bundles: unreadable_bundles.into_inner().unwrap(),
+        peak_in_flight_pages: in_flight_pages.peak(),
+    })
+}
+
+/// Counts the pages read from the bundles and not indexed yet, making the readers wait while there
+/// are too many
+struct InFlightPages {
+    max: usize,
+    /// How many pages are in flight now, and the most at once
+    counts: Mutex<(usize, usize)>,
+    released: Condvar,
+}
+
+impl InFlightPages {
+    fn new(max: usize) -> Self {
+        InFlightPages {
+            max: max.max(1),
+            counts: Mutex::new((0, 0)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until one more page can be in flight, and count it
+    fn acquire(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        while counts.0 >= self.max {
+            counts = self.released.wait(counts).unwrap();
+        }
+        counts.0 += 1;
+        counts.1 = counts.1.max(counts.0);
+    }
+
+    /// Tell that a page was indexed or skipped
+    fn release(&self) {
+        self.counts.lock().unwrap().0 -= 1;
+        self.released.notify_one();
+    }
+
+    fn peak(&self) -> usize {
+        self.counts.lock().unwrap().1
+    }
+}
+
+/// The file names of the bundles that some documents come from. The bundles whose pages were all
+/// skipped are not in it.
+fn indexed_bundle_names(index: &Index) -> anyhow::Result<HashSet<OsString>> {
+    Ok(stored_bundle_paths(index)?
+        .iter()
+        .filter_map(|bundle_path| Path::new(bundle_path).file_name())
+        .map(|file_name| file_name.to_os_string())
+        .collect())
+}
+
+/// The paths of the bundles stored in the documents. They depend on how the data directory was
+/// given, like with a relative --data-dir, but not their file name.
+fn stored_bundle_paths(index: &Index) -> anyhow::Result<BTreeSet<String>> {
+    let searcher = index.reader()?.searcher();
+    let bundle_path_field = searcher.schema().get_field("bundle_path")?;
+    let mut bundle_paths = BTreeSet::new();
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(bundle_path_field)?;
+        let mut stream = inverted_index.terms().stream()?;
+        while stream.advance() {
+            bundle_paths.insert(String::from_utf8_lossy(stream.key()).to_string());
+        }
+    }
+    Ok(bundle_paths)
+}
+
+/// Replace all the documents that came from one bundle, returning how many were added
+fn reindex_bundle(
+    index: &Index,
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    raw_pages_dir: &Path,
+    bundle: &Path,
+) -> anyhow::Result<usize> {
+    // Documents refer to bundles by their path inside the raw pages directory
+    let file_name = bundle.file_name().context("invalid bundle path")?;
+    let bundle = raw_pages_dir.join(file_name);
+
+    // The documents may have been indexed with another path to the same data directory
+    let mut deleted = 0;
+    for bundle_path in stored_bundle_paths(index)? {
+        if Path::new(&bundle_path).file_name() == Some(file_name) {
+            let bundle_term =
+                Term::from_field_text(document_builder.fields.bundle_path, &bundle_path);
+            deleted += count_documents(index, &bundle_term)?;
+            index_writer.delete_term(bundle_term);
+        }
+    }
+    info!("Deleted {} documents from {}", deleted, bundle.display());
+
+    let replace_versions = !document_builder.arguments.keep_versions;
+    let mut record = 0;
+    let mut added = 0;
+    for_each_compressed_json_item(&bundle, |page: DownloadedPage| {
+        if add_page(
+            index_writer,
+            document_builder,
+            &bundle,
+            record,
+            page,
+            replace_versions,
+        )? {
+            added += 1;
+        }
+        record += 1;
+        Ok(())
+    })?;
+    info!("Added {} documents from {}", added, bundle.display());
+
+    Ok(added)
+}
+
+/// Replace the documents of one URL with its newest download, or all of them with
+/// --keep-versions, returning how many documents were added
+fn reindex_url(
+    index: &Index,
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    data_paths: &DataPaths,
+    bundles: Vec<PathBuf>,
+    url: &str,
+) -> anyhow::Result<usize> {
+    let downloads = url_downloads(
+        data_paths,
+        &bundles,
+        &BTreeSet::from([url.to_string()]),
+        document_builder.arguments.keep_versions,
+    )
+    .remove(url)
+    .unwrap_or_default();
+    // The annotated pages are indexed with their notes, even when they were not downloaded
+    if downloads.is_empty() && !document_builder.notes_by_url.contains_key(url) {
+        anyhow::bail!("{} was not found in any bundle", url);
+    }
+
+    let url_term = Term::from_field_text(document_builder.fields.url_exact, url);
+    let deleted = count_documents(index, &url_term)?;
+    info!("Deleted {} documents for {}", deleted, url);
+
+    for (bundle, record, _) in &downloads {
+        info!("Using record {} of {}", record, bundle.display());
+    }
+    let added = replace_url_documents(index_writer, document_builder, url, downloads)?;
+    if added > 0 {
+        info!("Added {} documents for {}", added, url);
+    } else {
+        info!("The downloads of {} have nothing to index", url);
+    }
+    Ok(added)
+}
+
+/// Replace the documents of the annotated URLs, so that they have their current notes, returning
+/// how many documents were added
+fn reindex_annotated_urls(
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    data_paths: &DataPaths,
+    bundles: Vec<PathBuf>,
+    urls: &BTreeSet<String>,
+) -> anyhow::Result<usize> {
+    let mut downloads = url_downloads(
+        data_paths,
+        &bundles,
+        urls,
+        document_builder.arguments.keep_versions,
+    );
+    let mut added = 0;
+    for url in urls {
+        let url_downloads = downloads.remove(url).unwrap_or_default();
+        added += replace_url_documents(index_writer, document_builder, url, url_downloads)?;
+    }
+    Ok(added)
+}
+
+/// The bundle and the record of the newest successful download of each URL, by bundle. The
+/// bundles are scanned from their summaries.
+fn newest_records(data_paths: &DataPaths, bundles: &[PathBuf]) -> HashMap<PathBuf, HashSet<usize>> {
+    let newest_records =
+        Mutex::new(HashMap::<String, (&Path, usize, chrono::DateTime<Utc>)>::new());
+    bundles.par_iter().for_each(|bundle| {
+        // Unreadable bundles are reported by the indexing pass
+        let Ok(pages) = read_page_summaries(data_paths, bundle) else {
+            return;
+        };
+
+        let mut newest_records = newest_records.lock().unwrap();
+        for (record, page) in pages.into_iter().enumerate() {
+            if !page.success {
+                continue;
             }
+            let is_newer = match newest_records.get(&page.url) {
+                None => true,
+                Some((_, _, newest_loaded_at)) => page.loaded_at > *newest_loaded_at,
+            };
+            if is_newer {
+                newest_records.insert(page.url, (bundle, record, page.loaded_at));
+            }
+        }
+    });
 
-            println!(
-                "Indexed {} out of {} pages from {}",
-                indexed_pages,
-                total_pages,
-                bundle.display()
-            );
+    let mut records_by_bundle: HashMap<PathBuf, HashSet<usize>> = HashMap::new();
+    for (bundle, record, _) in newest_records.into_inner().unwrap().into_values() {
+        records_by_bundle
+            .entry(bundle.to_path_buf())
+            .or_default()
+            .insert(record);
+    }
+    records_by_bundle
+}
+
+/// The successful downloads of each of the URLs, with their bundle and record, from the newest:
+/// only the newest one, unless `all_versions`. Only the bundles with these URLs in their summary
+/// are decompressed.
+fn url_downloads(
+    data_paths: &DataPaths,
+    bundles: &[PathBuf],
+    urls: &BTreeSet<String>,
+    all_versions: bool,
+) -> HashMap<String, Vec<(PathBuf, usize, DownloadedPage)>> {
+    let versions = Mutex::new(HashMap::<String, Vec<(&Path, usize, chrono::DateTime<Utc>)>>::new());
+    bundles.par_iter().for_each(|bundle| {
+        // Unreadable bundles are reported by full runs
+        let Ok(pages) = read_page_summaries(data_paths, bundle) else {
+            return;
+        };
 
+        for (record, page) in pages.into_iter().enumerate() {
+            if page.success && urls.contains(&page.url) {
+                let mut versions = versions.lock().unwrap();
+                versions
+                    .entry(page.url)
+                    .or_default()
+                    .push((bundle, record, page.loaded_at));
+            }
+        }
+    });
+
+    let mut records_by_bundle: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for mut url_versions in versions.into_inner().unwrap().into_values() {
+        url_versions.sort_by_key(|&(_, _, loaded_at)| Reverse(loaded_at));
+        if !all_versions {
+            url_versions.truncate(1);
+        }
+        for (bundle, record, _) in url_versions {
+            records_by_bundle.entry(bundle).or_default().push(record);
+        }
+    }
+    let mut url_downloads: HashMap<String, Vec<(PathBuf, usize, DownloadedPage)>> = HashMap::new();
+    for (bundle, records) in records_by_bundle {
+        let mut record = 0;
+        // Only the pages of the records are kept. The ones read before an error are used.
+        let _ = for_each_compressed_json_item(bundle, |page: DownloadedPage| {
+            if records.contains(&record) {
+                url_downloads.entry(page.url.clone()).or_default().push((
+                    bundle.to_path_buf(),
+                    record,
+                    page,
+                ));
+            }
+            record += 1;
             Ok(())
-        })?;
+        });
+    }
+    for downloads in url_downloads.values_mut() {
+        downloads.sort_by_key(|(_, _, page)| Reverse(page.loaded_at));
+    }
+    url_downloads
+}
 
-    index_writer.commit()?;
+/// Delete the documents of the URL and add the ones of its downloads, or the one of its notes
+/// only when the downloads have nothing to index. Returns how many documents were added.
+fn replace_url_documents(
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    url: &str,
+    downloads: Vec<(PathBuf, usize, DownloadedPage)>,
+) -> anyhow::Result<usize> {
+    index_writer.delete_term(Term::from_field_text(
+        document_builder.fields.url_exact,
+        url,
+    ));
+    let mut added = 0;
+    for (bundle, record, page) in downloads {
+        if let Some(document) = document_builder.build(&bundle, record, page) {
+            index_writer.add_document(document)?;
+            added += 1;
+        }
+    }
+    if added == 0 {
+        if let Some(document) = document_builder.build_notes_only(url) {
+            index_writer.add_document(document)?;
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+/// Add the document of a page of a bundle, first deleting the other versions of its URL when
+/// `replace_versions`. Returns whether a document was added.
+fn add_page(
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+    bundle: &Path,
+    record: usize,
+    page: DownloadedPage,
+    replace_versions: bool,
+) -> anyhow::Result<bool> {
+    let url_term = Term::from_field_text(document_builder.fields.url_exact, &page.url);
+    let Some(document) = document_builder.build(bundle, record, page) else {
+        return Ok(false);
+    };
+    add_document(index_writer, url_term, document, replace_versions)?;
+    Ok(true)
+}
 
+/// Add the document of a page. With `replace_versions`, the documents of the same URL already in
+/// the index are deleted.
+fn add_document(
+    index_writer: &IndexWriter,
+    url_term: Term,
+    document: Document,
+    replace_versions: bool,
+) -> anyhow::Result<()> {
+    if replace_versions {
+        index_writer.delete_term(url_term);
+    }
+    index_writer.add_document(document)?;
     Ok(())
 }
 
+/// Add a document for each annotated URL that got no document from the bundles, returning how
+/// many were added
+fn index_notes_only(
+    index_writer: &IndexWriter,
+    document_builder: &DocumentBuilder,
+) -> anyhow::Result<usize> {
+    let indexed_urls = document_builder.indexed_urls.lock().unwrap().clone();
+    let mut added = 0;
+    for url in document_builder.notes_by_url.keys() {
+        if indexed_urls.contains(url) {
+            continue;
+        }
+        if let Some(document) = document_builder.build_notes_only(url) {
+            index_writer.add_document(document)?;
+            added += 1;
+        }
+    }
+    if added > 0 {
+        info!(
+            "Indexed the notes of {} pages that were not downloaded",
+            added
+        );
+    }
+    Ok(added)
+}
+
+pub fn count_documents(index: &Index, term: &Term) -> anyhow::Result<usize> {
+    let searcher = index.reader()?.searcher();
+    let query = TermQuery::new(term.clone(), IndexRecordOption::Basic);
+    Ok(searcher.search(&query, &Count)?)
+}
+
+/// Everything needed to turn a downloaded page into an index document
+struct DocumentBuilder<'a> {
+    fields: &'a IndexFields,
+    history_by_url: &'a HashMap<String, FirefoxHistoryItem>,
+    /// The notes written about the pages, by URL
+    notes_by_url: &'a HashMap<String, Vec<String>>,
+    boilerplate: &'a Boilerplate,
+    arguments: &'a IndexContentsArguments,
+    /// The records of the newest download of each URL, by bundle, when only those are indexed
+    newest_records: Option<HashMap<PathBuf, HashSet<usize>>>,
+    /// The URLs that extracted to no text in the last runs, which are not extracted again
+    skipped_empty_urls: HashSet<String>,
+    skipped_interstitials: AtomicUsize,
+    /// The older downloads of the URLs, which were not indexed
+    skipped_versions: AtomicUsize,
+    /// The downloads of the URLs of `skipped_empty_urls`
+    skipped_empty: AtomicUsize,
+    /// When the run started, the same for all its documents
+    indexed_at: DateTime,
+    /// The bundle and the record of the pages that got a document
+    indexed_records: Mutex<Vec<(PathBuf, usize)>>,
+    /// The URLs of the pages that got a document
+    indexed_urls: Mutex<HashSet<String>>,
+    /// The URLs of the pages extracted to no text, and to some text
+    empty_urls: Mutex<HashSet<String>>,
+    non_empty_urls: Mutex<HashSet<String>>,
+}
+
+impl DocumentBuilder<'_> {
+    /// Build the document for a page, unless it has nothing worth indexing
+    fn build(&self, bundle: &Path, record: usize, page: DownloadedPage) -> Option<Document> {
+        let fields = self.fields;
+        if let Some(newest_records) = &self.newest_records {
+            let is_newest = newest_records
+                .get(bundle)
+                .is_some_and(|records| records.contains(&record));
+            if !is_newest {
+                if matches!(
+                    page.content,
+                    DownloadedPageContent::Html(_)
+                        | DownloadedPageContent::PlainText(_)
+                        | DownloadedPageContent::Markdown(_)
+                ) {
+                    self.skipped_versions.fetch_add(1, Ordering::Relaxed);
+                }
+                return None;
+            }
+        }
+        if self.skipped_empty_urls.contains(&page.url) {
+            self.skipped_empty.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let mut extracted_text = extract_page_text(&page.content)?;
+        let extracted_urls = if extracted_text.content.split_whitespace().next().is_none() {
+            &self.empty_urls
+        } else {
+            &self.non_empty_urls
+        };
+        extracted_urls.lock().unwrap().insert(page.url.clone());
+        let domain = registrable_domain(&page.url);
+        if let Some(domain) = &domain {
+            extracted_text.content = self.boilerplate.strip(domain, &extracted_text.content);
+        }
+
+        if !self.arguments.index_interstitials
+            && is_interstitial(extracted_text.title.as_deref(), &extracted_text.content)
+        {
+            self.skipped_interstitials.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let mut document = Document::default();
+
+        self.add_history_fields(&mut document, &page.url, extracted_text.title);
+
+        let published = decide_published(
+            &extracted_text.published_candidates,
+            &page.url,
+            self.arguments.infer_date_from_url,
+        );
+        if let Some(published) = published {
+            document.add_field_value(fields.published, published);
+        }
+
+        if let Some(html_lang) = extracted_text.html_lang {
+            document.add_field_value(fields.html_lang, html_lang);
+        }
+
+        // The canonical URL can be relative to the page
+        let canonical_url = extracted_text.canonical_url.and_then(|canonical_url| {
+            let mut canonical_url = Url::parse(&page.url).ok()?.join(&canonical_url).ok()?;
+            normalize_url(&mut canonical_url);
+            Some(canonical_url.to_string())
+        });
+        if let Some(canonical_url) = canonical_url {
+            document.add_field_value(fields.canonical_url, canonical_url);
+        }
+
+        for anchor in extracted_text.anchors {
+            document.add_field_value(fields.anchors, anchor);
+        }
+
+        let word_count = extracted_text.content.split_whitespace().count();
+        document.add_field_value(fields.word_count, word_count as u64);
+        document.add_field_value(fields.simhash, simhash(&extracted_text.content));
+
+        document.add_field_value(fields.indexed_at, self.indexed_at);
+        document.add_field_value(fields.snapshot_at, clamped_index_date(page.loaded_at));
+
+        // Allow retrieving the original HTML later
+        document.add_field_value(fields.bundle_path, bundle.display().to_string());
+        document.add_field_value(fields.bundle_record, record as u64);
+
+        if let Some(domain) = domain {
+            document.add_field_value(fields.domain, domain);
+        }
+        self.add_notes(&mut document, &page.url);
+        document.add_field_value(fields.url_exact, page.url.clone());
+        document.add_field_value(fields.content, extracted_text.content);
+
+        self.indexed_records
+            .lock()
+            .unwrap()
+            .push((bundle.to_path_buf(), record));
+        self.indexed_urls.lock().unwrap().insert(page.url.clone());
+        document.add_field_value(fields.url, page.url);
+        Some(document)
+    }
+
+    /// Build the document of an annotated page that has no downloaded content, with its notes and
+    /// what the history tells about it
+    fn build_notes_only(&self, url: &str) -> Option<Document> {
+        if !self.notes_by_url.contains_key(url) {
+            return None;
+        }
+        let fields = self.fields;
+        let mut document = Document::default();
+        self.add_history_fields(&mut document, url, None);
+        document.add_field_value(fields.indexed_at, self.indexed_at);
+        if let Some(domain) = registrable_domain(url) {
+            document.add_field_value(fields.domain, domain);
+        }
+        self.add_notes(&mut document, url);
+        document.add_field_value(fields.url_exact, url);
+        document.add_field_value(fields.url, url);
+        // The search expects every document to have a content
+        document.add_field_value(fields.content, "");
+        Some(document)
+    }
+
+    /// Add the title, or the synthetic one, and the visits of the page
+    fn add_history_fields(&self, document: &mut Document, url: &str, title: Option<String>) {
+        let fields = self.fields;
+        let history_item = self.history_by_url.get(url);
+        match decide_title(history_item, title) {
+            Some(title) => document.add_field_value(fields.title, title),
+            None if !self.arguments.no_synthetic_titles => {
+                // Kept apart from real titles, so that they can be displayed differently
+                if let Some(synthetic_title) = synthesize_title(url) {
+                    document.add_field_value(fields.synthetic_title, synthetic_title);
+                }
+            }
+            None => {}
+        }
+
+        if let Some(last_visit) = decide_last_visit(history_item) {
+            document.add_field_value(fields.last_visit, last_visit);
+        }
+        if let Some(visit_date) = decide_visit_date(history_item) {
+            document.add_facet(fields.visit_date, visit_date);
+        }
+        if let Some(visit_count) = history_item.and_then(|item| item.visit_count) {
+            document.add_field_value(fields.visit_count, visit_count);
+        }
+        if let Some(history_item) = history_item.filter(|item| item.bookmarked) {
+            document.add_field_value(fields.bookmarked, 1u64);
+            for folder in &history_item.bookmark_folders {
+                document.add_facet(fields.bookmark_folder, folder_facet(folder));
+            }
+        }
+    }
+
+    fn add_notes(&self, document: &mut Document, url: &str) {
+        for note in self.notes_by_url.get(url).into_iter().flatten() {
+            document.add_field_value(self.fields.notes, note.as_str());
+        }
+    }
+}
+
+/// Fill in the defaults for the writer memory and threads and check the values make sense
+fn decide_writer_resources(
+    writer_memory_mb: Option<usize>,
+    indexing_threads: Option<usize>,
+) -> anyhow::Result<(usize, usize)> {
+    let indexing_threads = match indexing_threads {
+        Some(indexing_threads) => indexing_threads,
+        None => thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(1)
+            .min(8),
+    };
+    if indexing_threads == 0 {
+        anyhow::bail!("--indexing-threads must be at least 1");
+    }
+
+    let writer_memory_mb = match writer_memory_mb {
+        Some(writer_memory_mb) => writer_memory_mb,
+        None => {
+            let default_memory_mb = match system_memory_mb() {
+                Some(system_memory_mb) => (system_memory_mb * 15 / 100).min(2048),
+                None => 1024,
+            };
+            // Make sure the default is usable with the chosen number of threads
+            default_memory_mb.max(MIN_WRITER_MEMORY_MB_PER_THREAD * indexing_threads)
+        }
+    };
+
+    let memory_per_thread = writer_memory_mb / indexing_threads;
+    if memory_per_thread < MIN_WRITER_MEMORY_MB_PER_THREAD {
+        anyhow::bail!(
+            "{} MB of writer memory is too little for {} threads: each thread needs at least {} MB. \
+            Increase --writer-memory-mb or decrease --indexing-threads",
+            writer_memory_mb,
+            indexing_threads,
+            MIN_WRITER_MEMORY_MB_PER_THREAD
+        );
+    }
+    if memory_per_thread > MAX_WRITER_MEMORY_MB_PER_THREAD {
+        anyhow::bail!(
+            "{} MB of writer memory is too much for {} threads: each thread can use at most {} MB. \
+            Decrease --writer-memory-mb or increase --indexing-threads",
+            writer_memory_mb,
+            indexing_threads,
+            MAX_WRITER_MEMORY_MB_PER_THREAD
+        );
+    }
+
+    Ok((writer_memory_mb, indexing_threads))
+}
+
+/// Read the total system memory. This is only implemented for Linux
+fn system_memory_mb() -> Option<usize> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let total_line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let total_kb: usize = total_line
+        .trim_start_matches("MemTotal:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(total_kb / 1024)
+}
+
+/// First pass over all bundles, counting how often each line of text repeats inside each domain
+fn learn_boilerplate(
+    bundles: &[PathBuf],
+    min_pages: usize,
+    min_ratio: f64,
+) -> anyhow::Result<Boilerplate> {
+    let line_frequencies = Mutex::new(LineFrequencies::default());
+
+    bundles.par_iter().for_each(|bundle| {
+        // Unreadable bundles are reported by the indexing pass
+        let mut bundle_line_frequencies = LineFrequencies::default();
+        let result = for_each_compressed_json_item(bundle, |page: DownloadedPage| {
+            if let Some(domain) = registrable_domain(&page.url) {
+                if let Some(extracted_text) = extract_page_text(&page.content) {
+                    bundle_line_frequencies.add_page(&domain, &extracted_text.content);
+                }
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            return;
+        }
+
+        line_frequencies
+            .lock()
+            .unwrap()
+            .merge(bundle_line_frequencies);
+    });
+
+    let line_frequencies = line_frequencies.into_inner().unwrap();
+    Ok(line_frequencies.boilerplate(min_pages, min_ratio))
+}
+
 fn decide_title(
     history_item: Option<&FirefoxHistoryItem>,
     extracted_title: Option<String>,
@@ -101,52 +1313,221 @@ fn decide_title(
     }
 }
 
+fn now() -> DateTime {
+    DateTime::from_timestamp_millis(Utc::now().timestamp_millis())
+}
+
+/// The date as stored in the index, which only holds the dates from 1677 to 2262, or `None`
+/// outside of them
+pub fn index_date(date: chrono::DateTime<Utc>) -> Option<DateTime> {
+    let nanos = date.timestamp_millis().checked_mul(1_000_000)?;
+    Some(DateTime::from_timestamp_nanos(nanos))
+}
+
+/// The date as stored in the index, clamped to the dates that it can hold
+pub fn clamped_index_date(date: chrono::DateTime<Utc>) -> DateTime {
+    index_date(date).unwrap_or(if date.timestamp() < 0 {
+        DateTime::MIN
+    } else {
+        DateTime::MAX
+    })
+}
+
 fn decide_last_visit(item: Option<&FirefoxHistoryItem>) -> Option<DateTime> {
-    let item = item?;
-    let last_visit = item.last_visit?;
-    let timestamp = last_visit.timestamp_millis();
-    Some(DateTime::from_timestamp_millis(timestamp))
+    index_date(item?.last_visit?)
 }
 
-struct ExtractedText {
-    title: Option<String>,
-    content: String,
+/// Bucket the last visit by year and month, like "/2023/07", unless it can't be indexed
+fn decide_visit_date(item: Option<&FirefoxHistoryItem>) -> Option<Facet> {
+    let last_visit = item?.last_visit?;
+    index_date(last_visit)?;
+    Some(Facet::from(
+        last_visit.format("/%Y/%m").to_string().as_str(),
+    ))
 }
 
-fn extract_readable_text(html_source: &str) -> ExtractedText {
-    let document = Html::parse_document(html_source);
-    let mut extracted = ExtractedText {
-        title: None,
-        content: String::new(),
-    };
+/// The facet of a bookmark folder, like "/Rust/async" for "Rust/async"
+pub fn folder_facet(folder: &str) -> Facet {
+    Facet::from_path(folder.split('/').filter(|part| !part.is_empty()))
+}
 
-    fn recurse_page_tree(extracted: &mut ExtractedText, node: &NodeRef<Node>) {
-        match node.value() {
-            Node::Text(text) => {
-                extracted.content.push_str(text);
+/// Use the first candidate that can be parsed. Because candidates are collected in document order,
+/// meta tags in `<head>` naturally take precedence over `<time>` elements in the body.
+fn decide_published(
+    candidates: &[String],
+    url: &str,
+    infer_date_from_url_path: bool,
+) -> Option<DateTime> {
+    let published = candidates
+        .iter()
+        .find_map(|candidate| parse_date(candidate))
+        .or_else(|| {
+            if infer_date_from_url_path {
+                infer_date_from_url(url)
+            } else {
+                None
             }
-            Node::Element(element) => {
-                let element_name = element.name();
-
-                if element_name == "title" && extracted.title.is_none() {
-                    let mut title = String::new();
-                    for sub_node in node.descendants() {
-                        if let Some(text) = sub_node.value().as_text() {
-                            title.push_str(text);
-                        }
-                    }
-                    extracted.title = Some(title);
-                } else if element_name != "script" && element_name != "style" {
-                    for child in node.children() {
-                        recurse_page_tree(extracted, &child);
-                    }
-                }
+        })?;
+    index_date(published)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{date, visited_page, TestData};
+
+    fn count_all_documents(data_paths: &DataPaths) -> u64 {
+        let index_dir = data_paths.built_index_dir(DEFAULT_INDEX_NAME).unwrap();
+        Index::open_in_dir(index_dir)
+            .unwrap()
+            .reader()
+            .unwrap()
+            .searcher()
+            .num_docs()
+    }
+
+    #[test]
+    fn reindexes_a_bundle_indexed_with_another_data_dir_path() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page("https://example.com/a", "A", "<p>First page</p>"),
+                visited_page("https://example.com/b", "B", "<p>Second page</p>"),
+            ],
+            &[],
+        );
+        assert_eq!(count_all_documents(&data.data_paths), 2);
+
+        // The same data directory, written differently
+        let data_dir = data.data_paths.data_dir();
+        let other_data_paths = DataPaths::new(data_dir.join("..").join("data"));
+        let arguments =
+            IndexContentsArguments::parse_options(["--writer-memory-mb=50", "--bundle=0-0"])
+                .unwrap();
+        let summary = index_contents(arguments, &other_data_paths).unwrap();
+        assert_eq!(summary.indexed_pages, 2);
+        assert_eq!(count_all_documents(&data.data_paths), 2);
+
+        // And once more with the original path
+        data.index(&["--bundle=0-0"]);
+        assert_eq!(count_all_documents(&data.data_paths), 2);
+    }
+
+    #[test]
+    fn bounds_the_pages_in_flight() {
+        let data = TestData::new();
+        let mut items = Vec::new();
+        for bundle in 0..3 {
+            let mut pages = Vec::new();
+            for record in 0..40 {
+                let (item, page) = visited_page(
+                    &format!("https://example.com/{}/{}", bundle, record),
+                    "Page",
+                    "<p>A page of a large bundle</p>",
+                );
+                items.push(item);
+                pages.push(page);
             }
-            _ => {}
+            data.write_bundle(&format!("{}-0", bundle), &pages);
+        }
+        data.write_history(&items);
+
+        for max_in_flight_pages in [1, 3] {
+            let summary = data.index(&[&format!("--max-in-flight-pages={}", max_in_flight_pages)]);
+            assert_eq!(summary.indexed_pages, 120);
+            assert!(summary.peak_in_flight_pages > 0);
+            assert!(
+                summary.peak_in_flight_pages <= max_in_flight_pages,
+                "{} pages in flight",
+                summary.peak_in_flight_pages
+            );
         }
     }
 
-    recurse_page_tree(&mut extracted, &document.root_element());
+    #[test]
+    fn waits_for_the_pages_in_flight() {
+        let in_flight_pages = InFlightPages::new(2);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        in_flight_pages.acquire();
+                        sender.send(()).unwrap();
+                    }
+                });
+            }
+            for _ in 0..80 {
+                receiver.recv().unwrap();
+                assert!(in_flight_pages.counts.lock().unwrap().0 <= 2);
+                in_flight_pages.release();
+            }
+        });
+        assert_eq!(in_flight_pages.peak(), 2);
+        assert_eq!(InFlightPages::new(0).max, 1);
+    }
+
+    #[test]
+    fn skips_the_dates_that_the_index_cannot_hold() {
+        let data = TestData::new();
+        let mut pages = Vec::new();
+        for (name, date) in [
+            ("recent", date(2262, 4, 11)),
+            ("max", chrono::DateTime::<Utc>::MAX_UTC),
+            ("min", chrono::DateTime::<Utc>::MIN_UTC),
+        ] {
+            let (mut item, mut page) = visited_page(
+                &format!("https://example.com/{}", name),
+                name,
+                "<p>A page with an extreme date</p>",
+            );
+            item.last_visit = Some(date);
+            page.loaded_at = date;
+            pages.push((item, page));
+        }
+        data.index_pages(pages, &[]);
 
-    extracted
+        let mut urls = data.search_urls("extreme", &[]);
+        urls.sort();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/max",
+                "https://example.com/min",
+                "https://example.com/recent"
+            ]
+        );
+        // The pages without a last visit never match a period
+        assert_eq!(
+            data.search_urls("extreme", &["--after=2023-01-01"]),
+            ["https://example.com/recent"]
+        );
+        assert_eq!(
+            data.search_urls("extreme", &["--sort=recent"])[0],
+            "https://example.com/recent"
+        );
+        assert_eq!(
+            data.search_urls("extreme", &["--as-of=2023-01-01"]),
+            ["https://example.com/min"]
+        );
+    }
+
+    #[test]
+    fn converts_the_dates_for_the_index() {
+        let date = date(2023, 7, 14);
+        assert_eq!(
+            index_date(date).unwrap().into_timestamp_secs(),
+            date.timestamp()
+        );
+        assert_eq!(index_date(chrono::DateTime::<Utc>::MAX_UTC), None);
+        assert_eq!(index_date(chrono::DateTime::<Utc>::MIN_UTC), None);
+        assert_eq!(
+            clamped_index_date(chrono::DateTime::<Utc>::MAX_UTC),
+            DateTime::MAX
+        );
+        assert_eq!(
+            clamped_index_date(chrono::DateTime::<Utc>::MIN_UTC),
+            DateTime::MIN
+        );
+    }
 }
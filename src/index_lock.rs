@@ -0,0 +1,36 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+/// A lock file next to an index, held while a command writes to it, so that two writers don't
+/// fight over the index. The file is removed when this value is dropped.
+pub struct IndexLock {
+    path: PathBuf,
+}
+
+impl IndexLock {
+    pub fn acquire(index_dir_path: PathBuf) -> anyhow::Result<Self> {
+        let path = index_dir_path.with_extension("lock");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                writeln!(file, "{}", std::process::id())?;
+                Ok(IndexLock { path })
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => anyhow::bail!(
+                "another command is writing to this index. If that is not the case, remove {}",
+                path.display()
+            ),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
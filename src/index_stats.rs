@@ -0,0 +1,198 @@
+use crate::domain::registrable_domain;
+use crate::{DataPaths, OutputFormat, DEFAULT_INDEX_NAME};
+use anyhow::Context;
+use chrono::{TimeZone, Utc};
+use clap::Args;
+use serde::Serialize;
+use std::collections::HashMap;
+use tantivy::Index;
+
+/// How many domains to list, from the one with the most documents
+const TOP_DOMAINS: usize = 20;
+
+#[derive(Args, Debug)]
+pub struct IndexStatsArguments {
+    /// The name of the index to inspect
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Print the statistics for humans or as JSON, to track them over time
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+#[derive(Serialize)]
+struct IndexStats {
+    documents: u64,
+    deleted_documents: u64,
+    segments: usize,
+    size_bytes: SizeBytes,
+    top_domains: Vec<DomainCount>,
+    documents_without_title: u64,
+    documents_with_empty_content: u64,
+    first_visit: Option<chrono::DateTime<Utc>>,
+    last_visit: Option<chrono::DateTime<Utc>>,
+}
+
+/// The space used by each component of all segments
+#[derive(Default, Serialize)]
+struct SizeBytes {
+    store: u64,
+    term_dictionary: u64,
+    postings: u64,
+    positions: u64,
+    fast_fields: u64,
+    field_norms: u64,
+    deletes: u64,
+    total: u64,
+}
+
+#[derive(Serialize)]
+struct DomainCount {
+    domain: String,
+    documents: u64,
+}
+
+pub fn index_stats(arguments: IndexStatsArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let index = Index::open_in_dir(data_paths.built_index_dir(&arguments.index_name)?)?;
+    let schema = index.schema();
+    let url_field = schema.get_field("url")?;
+    let title_field = schema.get_field("title")?;
+    let content_field = schema.get_field("content")?;
+    let last_visit_field = schema.get_field("last_visit")?;
+
+    let searcher = index.reader()?.searcher();
+
+    let mut size_bytes = SizeBytes::default();
+    for segment in searcher.space_usage()?.segments() {
+        size_bytes.store += segment.store().total().get_bytes();
+        size_bytes.term_dictionary += segment.termdict().total().get_bytes();
+        size_bytes.postings += segment.postings().total().get_bytes();
+        size_bytes.positions += segment.positions().total().get_bytes();
+        size_bytes.fast_fields += segment.fast_fields().total().get_bytes();
+        size_bytes.field_norms += segment.fieldnorms().total().get_bytes();
+        size_bytes.deletes += segment.deletes().get_bytes();
+        size_bytes.total += segment.total().get_bytes();
+    }
+
+    // The domain histogram and the other counts need a pass over the stored documents
+    let mut documents_by_domain: HashMap<String, u64> = HashMap::new();
+    let mut documents_without_title = 0;
+    let mut documents_with_empty_content = 0;
+    let mut first_visit = None;
+    let mut last_visit = None;
+    let mut deleted_documents = 0;
+    for segment_reader in searcher.segment_readers() {
+        deleted_documents += segment_reader.num_deleted_docs() as u64;
+
+        let store_reader = segment_reader.get_store_reader(10)?;
+        for document in store_reader.iter(segment_reader.alive_bitset()) {
+            let document = document?;
+
+            let url = document
+                .get_first(url_field)
+                .and_then(|url| url.as_text())
+                .context("missing url")?;
+            let domain = registrable_domain(url).unwrap_or_else(|| "(invalid URL)".to_string());
+            *documents_by_domain.entry(domain).or_default() += 1;
+
+            if document.get_first(title_field).is_none() {
+                documents_without_title += 1;
+            }
+
+            let content = document
+                .get_first(content_field)
+                .and_then(|content| content.as_text())
+                .unwrap_or_default();
+            if content.trim().is_empty() {
+                documents_with_empty_content += 1;
+            }
+
+            if let Some(visit) = document
+                .get_first(last_visit_field)
+                .and_then(|last_visit| last_visit.as_date())
+            {
+                let visit = visit.into_timestamp_millis();
+                first_visit = Some(first_visit.map_or(visit, |first: i64| first.min(visit)));
+                last_visit = Some(last_visit.map_or(visit, |last: i64| last.max(visit)));
+            }
+        }
+    }
+
+    let mut top_domains: Vec<DomainCount> = documents_by_domain
+        .into_iter()
+        .map(|(domain, documents)| DomainCount { domain, documents })
+        .collect();
+    top_domains.sort_by(|a, b| {
+        b.documents
+            .cmp(&a.documents)
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    top_domains.truncate(TOP_DOMAINS);
+
+    let stats = IndexStats {
+        documents: searcher.num_docs(),
+        deleted_documents,
+        segments: searcher.segment_readers().len(),
+        size_bytes,
+        top_domains,
+        documents_without_title,
+        documents_with_empty_content,
+        first_visit: first_visit.map(convert_millis).transpose()?,
+        last_visit: last_visit.map(convert_millis).transpose()?,
+    };
+
+    match arguments.format {
+        OutputFormat::Human => print_human(&stats),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&stats)?),
+    }
+
+    Ok(())
+}
+
+fn print_human(stats: &IndexStats) {
+    println!("Documents: {}", stats.documents);
+    println!("Deleted documents: {}", stats.deleted_documents);
+    println!("Segments: {}", stats.segments);
+
+    let size_bytes = &stats.size_bytes;
+    println!("Size on disk: {}", format_size(size_bytes.total));
+    println!("  Store: {}", format_size(size_bytes.store));
+    println!(
+        "  Term dictionary: {}",
+        format_size(size_bytes.term_dictionary)
+    );
+    println!("  Postings: {}", format_size(size_bytes.postings));
+    println!("  Positions: {}", format_size(size_bytes.positions));
+    println!("  Fast fields: {}", format_size(size_bytes.fast_fields));
+    println!("  Field norms: {}", format_size(size_bytes.field_norms));
+    println!("  Deletes: {}", format_size(size_bytes.deletes));
+
+    println!("Top domains:");
+    for domain_count in &stats.top_domains {
+        println!("  {}: {}", domain_count.domain, domain_count.documents);
+    }
+
+    println!("Documents without title: {}", stats.documents_without_title);
+    println!(
+        "Documents with empty content: {}",
+        stats.documents_with_empty_content
+    );
+
+    match (stats.first_visit, stats.last_visit) {
+        (Some(first_visit), Some(last_visit)) => {
+            println!("Visits: from {} to {}", first_visit, last_visit)
+        }
+        _ => println!("Visits: unknown"),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1024. / 1024.)
+}
+
+fn convert_millis(millis: i64) -> anyhow::Result<chrono::DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .context("failed to convert date")
+}
@@ -0,0 +1,151 @@
+use crate::boilerplate::Boilerplate;
+use crate::bundle_cache::read_page_summaries;
+use crate::domain::registrable_domain;
+use crate::download_pages::{fetch_page, DEFAULT_TIMEOUT_SECONDS};
+use crate::extract_text::extract_page_text;
+use crate::interstitial::is_interstitial;
+use crate::normalize_url::normalize_history_url;
+use crate::{read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent};
+use anyhow::Context;
+use clap::Args;
+use reqwest::Url;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Args, Debug)]
+pub struct InspectPageArguments {
+    url: String,
+    /// Download the page now instead of reading its newest download from the bundles
+    #[arg(long)]
+    live: bool,
+    /// How many lines of the extracted content to print
+    #[arg(long, default_value_t = 20)]
+    lines: usize,
+    /// Remove the boilerplate lines learned by the last index-contents with
+    /// --strip-repeated-boilerplate, like the indexing does
+    #[arg(long)]
+    strip_repeated_boilerplate: bool,
+}
+
+/// Print what the indexing extracts from a page, to debug the extraction without indexing again
+pub fn inspect_page(arguments: InspectPageArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    // The URLs are stored like in the history, see `extract_firefox_history()`
+    let mut parsed_url = Url::parse(&arguments.url)?;
+    normalize_history_url(&mut parsed_url);
+    let url = parsed_url.to_string();
+
+    let page = if arguments.live {
+        let (page, _) = fetch_page(
+            url.clone(),
+            Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+            data_paths,
+        )?;
+        println!("Source: downloaded now");
+        page
+    } else {
+        let (bundle, record, page) = newest_download(&url, data_paths)?;
+        println!(
+            "Source: record {} of the bundle {}, downloaded at {}",
+            record,
+            bundle.display(),
+            page.loaded_at.to_rfc3339()
+        );
+        page
+    };
+
+    let kind = match &page.content {
+        DownloadedPageContent::Failure(error) => anyhow::bail!("the download failed: {}", error),
+        DownloadedPageContent::Pruned => anyhow::bail!("the page was removed by prune"),
+        DownloadedPageContent::SkippedEquivalent(other_url) => {
+            anyhow::bail!(
+                "the page was not downloaded, its content is at {}",
+                other_url
+            )
+        }
+        DownloadedPageContent::Html(_) => "HTML",
+        DownloadedPageContent::PlainText(_) => "plain text",
+        DownloadedPageContent::Markdown(_) => "markdown",
+    };
+    let mut extracted_text = extract_page_text(&page.content).context("no text to extract")?;
+    println!("Kind: {}", kind);
+
+    if arguments.strip_repeated_boilerplate {
+        let boilerplate = Boilerplate::read(&data_paths.boilerplate_dir())?;
+        if let Some(domain) = registrable_domain(&page.url) {
+            let lines = extracted_text.content.lines().count();
+            extracted_text.content = boilerplate.strip(&domain, &extracted_text.content);
+            println!(
+                "Boilerplate: {} lines removed",
+                lines - extracted_text.content.lines().count()
+            );
+        }
+    }
+
+    println!(
+        "Title: {}",
+        extracted_text.title.as_deref().unwrap_or("(none)")
+    );
+    println!(
+        "Language: {}",
+        extracted_text
+            .html_lang
+            .as_deref()
+            .unwrap_or("(not declared)")
+    );
+    println!(
+        "Words: {}",
+        extracted_text.content.split_whitespace().count()
+    );
+    if !extracted_text.skipped_elements.is_empty() {
+        let skipped: Vec<String> = extracted_text
+            .skipped_elements
+            .iter()
+            .map(|(element, count)| format!("{} ({})", element, count))
+            .collect();
+        println!("Skipped elements: {}", skipped.join(", "));
+    }
+    if is_interstitial(extracted_text.title.as_deref(), &extracted_text.content) {
+        println!("Interstitial: yes, it's not indexed unless with --index-interstitials");
+    }
+
+    let lines = extracted_text.content.lines().count();
+    println!();
+    for line in extracted_text.content.lines().take(arguments.lines) {
+        println!("{}", line);
+    }
+    if lines > arguments.lines {
+        println!("... {} more lines", lines - arguments.lines);
+    }
+
+    Ok(())
+}
+
+/// The newest download of the URL in the bundles, with its bundle and its record in it
+fn newest_download(
+    url: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<(PathBuf, usize, DownloadedPage)> {
+    let mut newest = None;
+    for bundle in data_paths.list_raw_pages_bundles()? {
+        for (record, summary) in read_page_summaries(data_paths, &bundle)?
+            .into_iter()
+            .enumerate()
+        {
+            let is_newer = newest
+                .as_ref()
+                .is_none_or(|(_, _, loaded_at)| summary.loaded_at > *loaded_at);
+            if summary.url == url && is_newer {
+                newest = Some((bundle.clone(), record, summary.loaded_at));
+            }
+        }
+    }
+    let (bundle, record, _) =
+        newest.with_context(|| format!("{} was not downloaded, use --live", url))?;
+
+    let pages: Vec<DownloadedPage> = read_compressed_json(&bundle)?;
+    let page = pages
+        .into_iter()
+        .nth(record)
+        .context("the bundle changed while reading it")?;
+    Ok((bundle, record, page))
+}
@@ -0,0 +1,142 @@
+use crate::{encryption, DataPaths};
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh64::xxh64;
+
+/// The magic number of the last zstd skippable frame, which decoders ignore. The checksum is
+/// stored in such a frame so that the files stay readable by any zstd decoder.
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5E;
+/// Identifies the content of the skippable frame
+const CHECKSUM_TAG: &[u8; 8] = b"XXH64\0\0\0";
+/// The magic number, the frame size, the tag and the checksum
+const CHECKSUM_FRAME_LENGTH: usize = 4 + 4 + 8 + 8;
+
+/// Append a frame with the xxHash64 of the compressed bytes
+pub fn append_checksum(compressed: &mut Vec<u8>) {
+    let checksum = xxh64(compressed, 0);
+    compressed.extend_from_slice(&SKIPPABLE_FRAME_MAGIC.to_le_bytes());
+    compressed.extend_from_slice(&16u32.to_le_bytes());
+    compressed.extend_from_slice(CHECKSUM_TAG);
+    compressed.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Verify the checksum at the end of the file content, returning the compressed bytes and whether
+/// there was a checksum. Files written before the checksums existed have none.
+pub fn verify_checksum(content: &[u8]) -> anyhow::Result<(&[u8], bool)> {
+    let Some(frame_start) = content.len().checked_sub(CHECKSUM_FRAME_LENGTH) else {
+        return Ok((content, false));
+    };
+    let (compressed, frame) = content.split_at(frame_start);
+    let expected_header = [
+        &SKIPPABLE_FRAME_MAGIC.to_le_bytes()[..],
+        &16u32.to_le_bytes()[..],
+        &CHECKSUM_TAG[..],
+    ]
+    .concat();
+    if frame[..16] != expected_header[..] {
+        return Ok((content, false));
+    }
+
+    let expected_checksum = u64::from_le_bytes(frame[16..].try_into()?);
+    if xxh64(compressed, 0) != expected_checksum {
+        anyhow::bail!("the checksum doesn't match the content, the file is corrupt");
+    }
+    Ok((compressed, true))
+}
+
+/// Write the file under a temporary name and then move it, so that a crash never leaves a
+/// half-written file under the final name
+pub fn write_atomically(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let file_name = path.file_name().context("invalid file path")?;
+    // Hidden, so that it's not taken for a bundle
+    let temporary_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    let result = fs::write(&temporary_path, content)
+        .and_then(|_| fs::File::open(&temporary_path)?.sync_all())
+        .and_then(|_| fs::rename(&temporary_path, path));
+    if result.is_err() {
+        // Like when the disk is full, which would leave a truncated file behind
+        let _ = fs::remove_file(&temporary_path);
+    }
+    Ok(result?)
+}
+
+/// Whether the file is a temporary one left by an interrupted write
+pub fn is_temporary_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|file_name| file_name.to_string_lossy().starts_with('.'))
+}
+
+/// Verify the checksums and the compression of the history and all the bundles, failing if any
+/// is corrupt
+pub fn check(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let mut paths = Vec::new();
+    if data_paths.history().exists() {
+        paths.push(data_paths.history());
+    }
+    paths.extend(data_paths.list_raw_pages_bundles()?);
+
+    let mut with_checksum = 0;
+    let mut corrupt_files: Vec<(PathBuf, anyhow::Error)> = Vec::new();
+    for path in &paths {
+        match check_file(path) {
+            Ok(true) => with_checksum += 1,
+            Ok(false) => {}
+            Err(error) => corrupt_files.push((path.clone(), error)),
+        }
+    }
+
+    println!(
+        "Checked {} files: {} with a checksum, {} written before the checksums existed, {} corrupt",
+        paths.len(),
+        with_checksum,
+        paths.len() - with_checksum - corrupt_files.len(),
+        corrupt_files.len()
+    );
+    for (path, error) in &corrupt_files {
+        println!("  {}: {:#}", path.display(), error);
+    }
+    if !corrupt_files.is_empty() {
+        anyhow::bail!("{} files are corrupt", corrupt_files.len());
+    }
+    Ok(())
+}
+
+/// Verify one file, returning whether it had a checksum
+fn check_file(path: &Path) -> anyhow::Result<bool> {
+    let content = fs::read(path)?;
+    let (compressed, has_checksum) = verify_checksum(&content)?;
+    // The checksum covers the encrypted content, which can only be decompressed with the key
+    if has_checksum && encryption::is_encrypted(compressed) {
+        return Ok(true);
+    }
+    // Files without checksum can only be checked by decompressing them
+    io::copy(&mut zstd::Decoder::new(compressed)?, &mut io::sink())
+        .context("the compressed content is invalid")?;
+    Ok(has_checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_the_temporary_file_of_a_failed_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0-0");
+        write_atomically(&path, b"content").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"content");
+
+        // A directory in the way makes the rename fail, after the content was written
+        let blocked_path = dir.path().join("1-0");
+        fs::create_dir_all(blocked_path.join("inside")).unwrap();
+        assert!(write_atomically(&blocked_path, b"content").is_err());
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["0-0", "1-0"]);
+    }
+}
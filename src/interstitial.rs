@@ -0,0 +1,136 @@
+/// Phrases typical of login walls, paywalls and cookie-consent screens
+const INTERSTITIAL_PHRASES: &[&str] = &[
+    "sign in",
+    "log in",
+    "login",
+    "create an account",
+    "create account",
+    "forgot your password",
+    "forgot password",
+    "accept all cookies",
+    "accept cookies",
+    "reject all",
+    "manage cookies",
+    "cookie settings",
+    "cookie policy",
+    "we use cookies",
+    "your privacy choices",
+    "consent",
+    "subscribe to continue",
+    "subscribe to read",
+    "to continue reading",
+    "already a subscriber",
+    "please enable javascript",
+    "enable cookies",
+    "verify you are human",
+    "checking your browser",
+];
+
+/// Pages with more words than this are considered to have real content, even if they also have a
+/// cookie banner
+const MAX_INTERSTITIAL_WORDS: usize = 300;
+
+/// Detect whether the extracted text is a login wall, a cookie-consent screen or a similar
+/// interstitial, instead of the real page content.
+///
+/// The heuristic is: the text is short and a good part of it is made of typical interstitial
+/// phrases.
+pub fn is_interstitial(title: Option<&str>, content: &str) -> bool {
+    let num_words = content.split_whitespace().count();
+    if num_words > MAX_INTERSTITIAL_WORDS {
+        return false;
+    }
+
+    let text = format!("{} {}", title.unwrap_or(""), content).to_lowercase();
+    let num_phrases: usize = INTERSTITIAL_PHRASES
+        .iter()
+        .map(|phrase| text.matches(phrase).count())
+        .sum();
+
+    // Very short pages only need one phrase, longer ones need a higher density of them
+    match num_words {
+        0..=30 => num_phrases >= 1,
+        _ => num_phrases >= 2 && num_phrases * 100 / num_words >= 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract_text::extract_readable_text;
+
+    fn is_interstitial_page(html: &str) -> bool {
+        let extracted = extract_readable_text(html);
+        is_interstitial(extracted.title.as_deref(), &extracted.content)
+    }
+
+    /// Like the consent screen shown before the search engines and video sites in the EU
+    const CONSENT_SCREEN: &str = r#"<html><head><title>Before you continue</title></head><body>
+        <h1>Before you continue to Google</h1>
+        <p>We use cookies and data to deliver and maintain services, like tracking outages and
+        protecting against spam, fraud and abuse.</p>
+        <p>If you choose to "Accept all", we will also use cookies and data to develop and
+        improve new services.</p>
+        <button>Reject all</button><button>Accept all</button>
+        <a href="/settings">More options</a></body></html>"#;
+
+    /// Like the wall of blogging platforms after a few articles
+    const LOGIN_WALL: &str = r#"<html><head><title>Medium</title></head><body>
+        <h2>Sign in to continue reading</h2>
+        <p>Create an account to read the full story.</p>
+        <button>Sign in with Google</button><button>Sign in with email</button>
+        <p>Already have an account? <a href="/m/signin">Sign in</a></p></body></html>"#;
+
+    /// Like the paywall of newspapers
+    const PAYWALL: &str = r#"<html><head><title>Subscribe</title></head><body>
+        <p>Thank you for reading. Subscribe to continue reading this article.</p>
+        <p>Already a subscriber? <a href="/login">Log in</a>.</p></body></html>"#;
+
+    /// Like the check of content delivery networks against bots
+    const BROWSER_CHECK: &str = r#"<html><head><title>Just a moment...</title></head><body>
+        <h1>example.com</h1><h2>Checking your browser before accessing example.com.</h2>
+        <p>Please enable JavaScript and cookies to continue.</p></body></html>"#;
+
+    #[test]
+    fn detects_the_interstitials() {
+        for (name, html) in [
+            ("consent screen", CONSENT_SCREEN),
+            ("login wall", LOGIN_WALL),
+            ("paywall", PAYWALL),
+            ("browser check", BROWSER_CHECK),
+        ] {
+            assert!(is_interstitial_page(html), "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_the_articles_with_a_cookie_banner() {
+        let paragraph = "<p>The borrow checker rejects programs where a value is used after \
+            being moved, which prevents a whole class of memory bugs at compile time.</p>";
+        let html = format!(
+            "<html><head><title>Understanding ownership</title></head><body>{}\
+             <div>We use cookies. <button>Accept cookies</button></div></body></html>",
+            paragraph.repeat(20)
+        );
+        assert!(!is_interstitial_page(&html));
+    }
+
+    #[test]
+    fn keeps_the_short_pages_without_interstitial_phrases() {
+        assert!(!is_interstitial_page(
+            "<html><head><title>Tokio</title></head><body><p>An asynchronous runtime for \
+             Rust.</p></body></html>"
+        ));
+        assert!(!is_interstitial(None, ""));
+    }
+
+    #[test]
+    fn needs_a_density_of_phrases_in_longer_pages() {
+        let words = "word ".repeat(200);
+        assert!(!is_interstitial(None, &format!("{} sign in", words)));
+        assert!(is_interstitial(
+            None,
+            &format!("{} sign in log in accept cookies reject all consent", words)
+        ));
+    }
+}
@@ -0,0 +1,595 @@
+//! Search the pages of your browser history by their content.
+//!
+//! The history is first extracted from the browser with [`History::extract`], then its pages are
+//! downloaded with [`Downloader::run`] and indexed with [`Indexer::run`]. The index can then be
+//! searched with [`Searcher::query`], or all its matches iterated with [`Searcher::stream`].
+//!
+//! The progress is logged with the `tracing` crate, so it is only printed when a subscriber is
+//! installed.
+
+mod annotations;
+mod api;
+mod archive;
+mod bench;
+mod boilerplate;
+mod bundle_cache;
+pub mod cli;
+mod clipboard;
+mod completions;
+mod config;
+mod daemon;
+mod data_lock;
+mod disk_space;
+mod doctor;
+mod domain;
+mod domain_profiles;
+mod download_pages;
+mod empty_pages;
+mod encryption;
+mod examples;
+mod export;
+mod extract_firefox_history;
+mod extract_firefox_session;
+mod extract_sqlite_history;
+mod extract_text;
+mod feed;
+mod find_in_page;
+mod import_warc;
+mod index_backup;
+mod index_contents;
+mod index_lock;
+mod index_stats;
+mod inspect_page;
+mod integrity;
+mod interstitial;
+pub mod logging;
+mod man_page;
+mod markdown;
+mod mcp;
+mod metadata;
+mod normalize_text;
+mod normalize_url;
+mod open_url;
+mod optimize_index;
+mod parse_date;
+mod prune;
+mod query_operators;
+mod relative_date;
+mod relevance_test;
+mod repl;
+mod run_metrics;
+mod run_report;
+mod saved_searches;
+mod search;
+mod search_output;
+mod serve;
+mod show_page;
+mod shutdown;
+mod simhash;
+mod similar;
+mod snippets;
+mod spelling;
+mod stats;
+mod suggest;
+mod sync;
+mod synonyms;
+mod synthetic_title;
+#[cfg(test)]
+mod test_fixtures;
+mod timeline;
+mod tui;
+mod workspace;
+
+pub use crate::api::{
+    Downloader, DownloaderConfig, History, Indexer, IndexerConfig, SearchHit, SearchOptions,
+    Searcher,
+};
+pub use crate::download_pages::{DownloadSummary, QueueOrder};
+pub use crate::index_contents::IndexSummary;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::de::{DeserializeOwned, Deserializer as _, Error as _, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// How to print the output of commands that support more than plain text
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Human,
+    /// A single JSON document
+    Json,
+    /// One compact JSON document per line
+    Jsonl,
+}
+
+const DEFAULT_INDEX_NAME: &str = "default";
+/// The workspace kept directly in the data directory, as before workspaces existed
+const DEFAULT_WORKSPACE: &str = "default";
+/// The data directory used before it could be configured, kept when it exists
+const LEGACY_DATA_DIR_PATH: &str = "data";
+/// The name of the data directory inside the data directory of the platform
+const APP_DIR_NAME: &str = "mind-search";
+
+/// Where each kind of data is stored, inside the data directory
+#[derive(Clone, Debug)]
+struct DataPaths {
+    data_dir: PathBuf,
+    /// Where the indexes are stored instead of inside the data directory, like on an encrypted
+    /// file system
+    separate_indexes_dir: Option<PathBuf>,
+}
+
+impl DataPaths {
+    fn new(data_dir: PathBuf) -> Self {
+        DataPaths {
+            data_dir,
+            separate_indexes_dir: None,
+        }
+    }
+
+    /// Store the indexes in this directory instead of inside the data directory
+    fn with_indexes_dir(mut self, indexes_dir: Option<PathBuf>) -> Self {
+        self.separate_indexes_dir = indexes_dir;
+        self
+    }
+
+    /// Use the given directory, or decide the default one
+    fn resolve(data_dir: Option<PathBuf>) -> anyhow::Result<Self> {
+        let data_dir = match data_dir {
+            Some(data_dir) => data_dir,
+            None if Path::new(LEGACY_DATA_DIR_PATH).exists() => PathBuf::from(LEGACY_DATA_DIR_PATH),
+            None => platform_data_dir()
+                .context("could not find the data directory of the platform, use --data-dir")?
+                .join(APP_DIR_NAME),
+        };
+        Ok(DataPaths::new(data_dir))
+    }
+
+    /// Create the data directory the first time it is used, telling where it is
+    fn create_data_dir(&self) -> anyhow::Result<()> {
+        if !self.data_dir.exists() {
+            fs::create_dir_all(&self.data_dir)?;
+            info!("Storing the data in {}", self.data_dir.display());
+        }
+        Ok(())
+    }
+
+    fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    fn firefox_database(&self) -> PathBuf {
+        self.data_dir.join("places.sqlite")
+    }
+
+    /// The database of the pages, to query them with SQL. It can be built again from the bundles.
+    fn metadata_database(&self) -> PathBuf {
+        self.data_dir.join("meta.sqlite")
+    }
+
+    fn history(&self) -> PathBuf {
+        self.data_dir.join("history")
+    }
+
+    fn raw_pages_dir(&self) -> PathBuf {
+        self.data_dir.join("raw_pages")
+    }
+
+    /// The summaries of the bundles, without the content of their pages
+    fn bundle_cache_dir(&self) -> PathBuf {
+        self.raw_pages_dir().join(".cache")
+    }
+
+    fn indexes_dir(&self) -> PathBuf {
+        match &self.separate_indexes_dir {
+            Some(indexes_dir) => indexes_dir.clone(),
+            None => self.data_dir.join("indexes"),
+        }
+    }
+
+    /// The snapshots of the indexes, next to them so that they can share their files
+    fn index_backups_dir(&self) -> PathBuf {
+        match &self.separate_indexes_dir {
+            Some(indexes_dir) => indexes_dir.join(".backups"),
+            None => self.data_dir.join("index_backups"),
+        }
+    }
+
+    /// Where the only index was stored, before named indexes existed
+    fn legacy_tantivy_index_dir(&self) -> PathBuf {
+        self.data_dir.join("tantivy_index")
+    }
+
+    fn boilerplate_dir(&self) -> PathBuf {
+        self.data_dir.join("boilerplate")
+    }
+
+    /// The notes written about the pages, with the annotate command
+    fn annotations(&self) -> PathBuf {
+        self.data_dir.join("annotations.json")
+    }
+
+    fn saved_searches(&self) -> PathBuf {
+        self.data_dir.join("saved_searches.json")
+    }
+
+    /// The queries and the pages they must find, checked by relevance-test
+    fn golden_queries(&self) -> PathBuf {
+        self.data_dir.join("golden_queries.toml")
+    }
+
+    /// The pages that extracted to no text in the last indexing runs
+    fn empty_pages(&self) -> PathBuf {
+        self.data_dir.join("empty_pages")
+    }
+
+    fn synonyms(&self) -> PathBuf {
+        self.data_dir.join("synonyms.txt")
+    }
+
+    /// The other workspaces, each with its own history, pages and indexes
+    fn workspaces_dir(&self) -> PathBuf {
+        self.data_dir.join("workspaces")
+    }
+
+    /// The paths of a workspace of this data directory, which may not exist yet
+    fn workspace(&self, name: &str) -> anyhow::Result<DataPaths> {
+        check_name("workspace name", name)?;
+        if name == DEFAULT_WORKSPACE {
+            Ok(self.clone())
+        } else {
+            Ok(DataPaths {
+                data_dir: self.workspaces_dir().join(name),
+                separate_indexes_dir: self
+                    .separate_indexes_dir
+                    .as_ref()
+                    .map(|indexes_dir| indexes_dir.join(".workspaces").join(name)),
+            })
+        }
+    }
+
+    fn lock_file(&self) -> PathBuf {
+        self.data_dir.join("lock")
+    }
+
+    /// Locked by the commands that keep the indexes open, like serve
+    fn servers_lock_file(&self) -> PathBuf {
+        self.data_dir.join("servers.lock")
+    }
+
+    /// The token generated for the requests to the server, with `serve --auth-token`
+    fn serve_token(&self) -> PathBuf {
+        self.data_dir.join("serve_token")
+    }
+
+    fn config_file(&self) -> PathBuf {
+        self.data_dir.join("mind-search.toml")
+    }
+
+    /// The salt and the key check of an encrypted data directory
+    fn encryption_settings(&self) -> PathBuf {
+        self.data_dir.join("encryption.json")
+    }
+
+    /// The metrics of the runs recorded with `--record-run`, one JSON line each
+    fn runs_log(&self) -> PathBuf {
+        self.data_dir.join("runs.log")
+    }
+
+    fn list_raw_pages_bundles(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let raw_pages_dir = self.raw_pages_dir();
+        fs::create_dir_all(&raw_pages_dir)?;
+
+        let mut bundles = Vec::new();
+        for maybe_entry in fs::read_dir(raw_pages_dir)? {
+            let entry_path = maybe_entry?.path();
+            if !integrity::is_temporary_file(&entry_path) {
+                bundles.push(entry_path);
+            }
+        }
+        Ok(bundles)
+    }
+
+    /// Return the directory of the named index.
+    ///
+    /// The legacy index directory is moved into place the first time the default index is used.
+    fn tantivy_index_dir(&self, index_name: &str) -> anyhow::Result<PathBuf> {
+        check_name("index name", index_name)?;
+
+        let index_dir_path = self.indexes_dir().join(index_name);
+        let legacy_path = self.legacy_tantivy_index_dir();
+        if index_name == DEFAULT_INDEX_NAME && !index_dir_path.exists() && legacy_path.exists() {
+            fs::create_dir_all(self.indexes_dir())?;
+            fs::rename(&legacy_path, &index_dir_path)?;
+            info!(
+                "Moved index from {} to {}",
+                legacy_path.display(),
+                index_dir_path.display()
+            );
+        }
+
+        Ok(index_dir_path)
+    }
+
+    fn list_index_names(&self) -> anyhow::Result<Vec<String>> {
+        // Make sure the legacy index is detected too
+        self.tantivy_index_dir(DEFAULT_INDEX_NAME)?;
+        fs::create_dir_all(self.indexes_dir())?;
+
+        let mut index_names = Vec::new();
+        for maybe_entry in fs::read_dir(self.indexes_dir())? {
+            let entry = maybe_entry?;
+            // Not the indexes being restored, nor the workspaces of a separate indexes directory
+            if entry.file_type()?.is_dir() && !integrity::is_temporary_file(&entry.path()) {
+                index_names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        index_names.sort();
+        Ok(index_names)
+    }
+
+    /// Read the extracted history, failing with a [MissingStep] before the first extraction
+    fn read_history(&self) -> anyhow::Result<Vec<FirefoxHistoryItem>> {
+        let path = self.history();
+        if !path.exists() {
+            return Err(MissingStep {
+                missing: format!("{} not found", path.display()),
+                command: "extract-firefox-history --profile-path PROFILE".to_string(),
+            }
+            .into());
+        }
+        read_compressed_json(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    /// Read the extracted history to update it, which is empty before the first extraction
+    fn read_previous_history(&self) -> anyhow::Result<Vec<FirefoxHistoryItem>> {
+        let path = self.history();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        read_compressed_json(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    /// Return the directory of an index that was built, failing with a [MissingStep] otherwise
+    fn built_index_dir(&self, index_name: &str) -> anyhow::Result<PathBuf> {
+        let index_dir = self.tantivy_index_dir(index_name)?;
+        if !index_dir.join("meta.json").exists() {
+            let command = if index_name == DEFAULT_INDEX_NAME {
+                "index-contents".to_string()
+            } else {
+                format!("index-contents --index-name {}", index_name)
+            };
+            return Err(MissingStep {
+                missing: format!("the index {} not found", index_dir.display()),
+                command,
+            }
+            .into());
+        }
+        Ok(index_dir)
+    }
+}
+
+/// Fail unless the name of an index or a workspace can be used as a directory name
+fn check_name(kind: &str, name: &str) -> anyhow::Result<()> {
+    let is_valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !is_valid_name {
+        anyhow::bail!(
+            "invalid {} {:?}: use only letters, digits, '-' and '_'",
+            kind,
+            name
+        );
+    }
+    Ok(())
+}
+
+/// The error of a command that needs what another command creates, when that command was not run
+/// yet. The program exits with a distinct code for it.
+#[derive(Debug)]
+pub struct MissingStep {
+    /// Like "data/history not found"
+    missing: String,
+    /// The command to run first, without the program name
+    command: String,
+}
+
+impl fmt::Display for MissingStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: run `mind-search {}` first",
+            self.missing, self.command
+        )
+    }
+}
+
+impl std::error::Error for MissingStep {}
+
+/// A page of the browser history
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FirefoxHistoryItem {
+    pub url: String,
+    /// The page title, if this information is available
+    pub title: Option<String>,
+    /// When this page was last visited
+    pub last_visit: Option<DateTime<Utc>>,
+    /// How many times this page was visited, unknown in histories extracted by older versions
+    pub visit_count: Option<u64>,
+    /// Where the URL comes from when it's not the Firefox history, like "session" for the tabs
+    /// that were open or "qutebrowser" for the history of another browser
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Whether the page is bookmarked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bookmarked: bool,
+    /// The folders of its bookmarks, like "Rust/async", without the bookmarks directly in the menu
+    /// or the toolbar
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmark_folders: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DownloadedPage {
+    url: String,
+    loaded_at: DateTime<Utc>,
+    content: DownloadedPageContent,
+    /// Where the redirects ended, when it's another URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    final_url: Option<String>,
+    /// The canonical URL declared by the page, when it's another URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    canonical_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+enum DownloadedPageContent {
+    Failure(String),
+    Html(String),
+    PlainText(String),
+    Markdown(String),
+    /// Removed by the prune command. The record is kept so that the page is not downloaded again.
+    Pruned,
+    /// Not downloaded, since the content was already downloaded at this other URL. The record is
+    /// kept so that the page is not checked again.
+    SkippedEquivalent(String),
+}
+
+/// Write the content with a checksum, replacing the file only once it's fully written. It is
+/// encrypted when the data directory is.
+fn write_compressed_json<T: Serialize>(path: &Path, content: &T) -> anyhow::Result<()> {
+    let mut compressor_writer = zstd::Encoder::new(Vec::new(), 0)?;
+    serde_json::to_writer(&mut compressor_writer, content)?;
+    let mut compressed = compressor_writer.finish()?;
+    if encryption::is_enabled() {
+        compressed = encryption::encrypt(&compressed)?;
+    }
+    integrity::append_checksum(&mut compressed);
+    integrity::write_atomically(path, &compressed)
+}
+
+/// Read the content, failing if it doesn't match its checksum. Files without checksum are read as
+/// they are, and encrypted files are decrypted.
+fn read_compressed_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let file_content = fs::read(path)?;
+    let (compressed, _) = integrity::verify_checksum(&file_content)?;
+    let decrypted;
+    let compressed = if encryption::is_encrypted(compressed) {
+        decrypted = encryption::decrypt(compressed)?;
+        &decrypted[..]
+    } else {
+        compressed
+    };
+    let compressor_reader = zstd::Decoder::new(compressed)?;
+    let content = serde_json::from_reader(compressor_reader)?;
+    Ok(content)
+}
+
+/// Write the content as pretty JSON, to be read and edited by hand, unless the data directory is
+/// encrypted: then it is written like [write_compressed_json]
+fn write_readable_json<T: Serialize>(path: &Path, content: &T) -> anyhow::Result<()> {
+    if encryption::is_enabled() {
+        return write_compressed_json(path, content);
+    }
+    fs::write(path, serde_json::to_string_pretty(content)?)?;
+    Ok(())
+}
+
+/// Read the content written by [write_readable_json], in clear or encrypted
+fn read_readable_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let file_content = fs::read(path)?;
+    let (content, _) = integrity::verify_checksum(&file_content)?;
+    if encryption::is_encrypted(content) {
+        return read_compressed_json(path);
+    }
+    Ok(serde_json::from_slice(&file_content)?)
+}
+
+/// Read the items of an array written by [write_compressed_json] one at a time, so that only one
+/// of them is in memory at once besides the compressed content. The checksum is verified before
+/// the first item, but an error in the middle comes after the items before it.
+fn for_each_compressed_json_item<T, F>(path: &Path, handle_item: F) -> anyhow::Result<()>
+where
+    T: DeserializeOwned,
+    F: FnMut(T) -> anyhow::Result<()>,
+{
+    let file_content = fs::read(path)?;
+    let (compressed, _) = integrity::verify_checksum(&file_content)?;
+    let decrypted;
+    let compressed = if encryption::is_encrypted(compressed) {
+        decrypted = encryption::decrypt(compressed)?;
+        &decrypted[..]
+    } else {
+        compressed
+    };
+    // The deserializer reads a few bytes at a time
+    let compressor_reader = io::BufReader::new(zstd::Decoder::new(compressed)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(compressor_reader);
+    let mut item_error = None;
+    let visitor = ItemVisitor {
+        handle_item,
+        item_error: &mut item_error,
+        item: PhantomData,
+    };
+    let result = (&mut deserializer).deserialize_seq(visitor);
+    if let Some(error) = item_error {
+        return Err(error);
+    }
+    result?;
+    deserializer.end()?;
+    Ok(())
+}
+
+/// Hands each item of an array to a function, keeping its error apart from the ones of the JSON
+struct ItemVisitor<'a, T, F> {
+    handle_item: F,
+    item_error: &'a mut Option<anyhow::Error>,
+    item: PhantomData<T>,
+}
+
+impl<'de, T, F> Visitor<'de> for ItemVisitor<'_, T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(T) -> anyhow::Result<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(item) = seq.next_element()? {
+            if let Err(error) = (self.handle_item)(item) {
+                *self.item_error = Some(error);
+                return Err(A::Error::custom("stopped reading the items"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the platform keeps the data of the applications: "%LOCALAPPDATA%" on Windows,
+/// "~/Library/Application Support" on macOS and "$XDG_DATA_HOME" or "~/.local/share" elsewhere
+fn platform_data_dir() -> Option<PathBuf> {
+    let non_empty_var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        non_empty_var("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        non_empty_var("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        // Relative paths must be ignored, according to the XDG specification
+        non_empty_var("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| non_empty_var("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    }
+}
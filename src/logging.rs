@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+use std::env;
+use std::io::{self, IsTerminal};
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+/// The target of the events of this crate
+const CRATE_TARGET: &str = "mind_search";
+
+/// How to print the logs in the standard error
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    /// One short line per event, colored in a terminal
+    Human,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+/// Print the logs of this crate in the standard error, at the info level unless more or fewer
+/// details are asked. The RUST_LOG variable, like "warn,mind_search::download_pages=debug",
+/// takes precedence over the verbosity.
+pub fn init_logging(verbosity: u8, quiet: bool, format: LogFormat) -> anyhow::Result<()> {
+    let rust_log = env::var("RUST_LOG").ok();
+    let filter = level_filter(rust_log.as_deref(), verbosity, quiet);
+    let subscriber = build_subscriber(filter, format, io::stderr().is_terminal(), io::stderr);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// The RUST_LOG directives when they are set, otherwise the level of this crate from the
+/// verbosity. Invalid directives are ignored with a message.
+fn level_filter(rust_log: Option<&str>, verbosity: u8, quiet: bool) -> EnvFilter {
+    let directives = match rust_log {
+        Some(directives) if !directives.trim().is_empty() => directives.to_string(),
+        _ => {
+            let crate_level = match (quiet, verbosity) {
+                (true, _) => "error",
+                (false, 0) => "info",
+                (false, 1) => "debug",
+                (false, _) => "trace",
+            };
+            // The other crates only tell when something goes wrong
+            format!("warn,{}={}", CRATE_TARGET, crate_level)
+        }
+    };
+    EnvFilter::builder().parse_lossy(directives)
+}
+
+fn build_subscriber<W>(
+    filter: EnvFilter,
+    format: LogFormat,
+    use_colors: bool,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    match format {
+        // Like `INFO bundle{path=data/raw_pages/3}: Indexed 497 out of 500 pages`
+        LogFormat::Human => Box::new(
+            builder
+                .compact()
+                .without_time()
+                .with_target(false)
+                .with_ansi(use_colors)
+                .finish(),
+        ),
+        LogFormat::Json => Box::new(builder.json().with_current_span(false).finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects the logs in memory
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The lines logged by the function
+    fn log(filter: EnvFilter, format: LogFormat, f: impl FnOnce()) -> Vec<String> {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = build_subscriber(filter, format, false, move || writer.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    fn log_all_levels() {
+        tracing::error!(target: "mind_search::search", "error");
+        tracing::info!(target: "mind_search::search", "info");
+        tracing::debug!(target: "mind_search::search", "debug");
+        tracing::trace!(target: "mind_search::search", "trace");
+        tracing::info!(target: "mind_search::download_pages", "downloads");
+        tracing::info!(target: "reqwest", "other crate");
+    }
+
+    fn messages(lines: &[String]) -> Vec<&str> {
+        lines
+            .iter()
+            .map(|line| line.rsplit(' ').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_verbosity() {
+        let lines = log(
+            level_filter(None, 0, false),
+            LogFormat::Human,
+            log_all_levels,
+        );
+        assert_eq!(messages(&lines), ["error", "info", "downloads"]);
+
+        let lines = log(
+            level_filter(None, 2, false),
+            LogFormat::Human,
+            log_all_levels,
+        );
+        assert_eq!(
+            messages(&lines),
+            ["error", "info", "debug", "trace", "downloads"]
+        );
+
+        let lines = log(
+            level_filter(None, 2, true),
+            LogFormat::Human,
+            log_all_levels,
+        );
+        assert_eq!(messages(&lines), ["error"]);
+    }
+
+    #[test]
+    fn follows_the_rust_log_directives() {
+        let filter = level_filter(
+            Some("warn,mind_search=debug,mind_search::download_pages=off"),
+            0,
+            true,
+        );
+        let lines = log(filter, LogFormat::Human, log_all_levels);
+        assert_eq!(messages(&lines), ["error", "info", "debug"]);
+
+        let filter = level_filter(Some("info,mind_search=invalid"), 0, false);
+        let lines = log(filter, LogFormat::Human, log_all_levels);
+        assert_eq!(messages(&lines), ["error", "info", "downloads", "crate"]);
+    }
+
+    #[test]
+    fn prints_the_spans_and_the_fields() {
+        let lines = log(level_filter(None, 0, false), LogFormat::Human, || {
+            let _span = tracing::info_span!("bundle", path = "data/raw_pages/3").entered();
+            tracing::info!(indexed = 497, "Indexed pages");
+        });
+        assert_eq!(
+            lines,
+            [" INFO bundle: Indexed pages indexed=497 path=\"data/raw_pages/3\""]
+        );
+    }
+
+    #[test]
+    fn prints_one_json_object_per_line() {
+        let lines = log(level_filter(None, 0, false), LogFormat::Json, || {
+            let _span = tracing::info_span!("bundle", path = "data/raw_pages/3").entered();
+            tracing::info!(indexed = 497, "Indexed pages");
+            tracing::warn!("Skipped a page");
+        });
+        assert_eq!(lines.len(), 2);
+
+        let event: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert!(event["timestamp"].is_string());
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["target"], "mind_search::logging::tests");
+        assert_eq!(event["fields"]["message"], "Indexed pages");
+        assert_eq!(event["fields"]["indexed"], 497);
+        assert_eq!(event["spans"][0]["name"], "bundle");
+        assert_eq!(event["spans"][0]["path"], "data/raw_pages/3");
+
+        let event: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["fields"]["message"], "Skipped a page");
+    }
+}
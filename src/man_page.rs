@@ -0,0 +1,35 @@
+use clap::Args;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[command(after_help = "Examples:
+  Read the manual without installing it:
+    mind-search mangen | man -l -
+
+  Install the pages of the program and of each command:
+    mind-search mangen --out-dir ~/.local/share/man/man1")]
+pub struct MangenArguments {
+    /// Write a page for the program and one for each command, like "mind-search-search.1", into
+    /// this directory instead of printing the page of the program
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+}
+
+/// Print the man page of the program, or write the pages of all the commands
+pub fn mangen(arguments: MangenArguments, command: &clap::Command) -> anyhow::Result<()> {
+    match arguments.out_dir {
+        None => {
+            let mut page = Vec::new();
+            clap_mangen::Man::new(command.clone()).render(&mut page)?;
+            io::stdout().write_all(&page)?;
+        }
+        Some(out_dir) => {
+            fs::create_dir_all(&out_dir)?;
+            clap_mangen::generate_to(command.clone(), &out_dir)?;
+            println!("Wrote the man pages into {}", out_dir.display());
+        }
+    }
+    Ok(())
+}
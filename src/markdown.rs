@@ -0,0 +1,76 @@
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// The readable text of a markdown document, without its markup
+pub struct MarkdownText {
+    /// The text of the first heading
+    pub title: Option<String>,
+    pub content: String,
+}
+
+/// Render markdown as plain text: headings, emphasis and links keep only their text and each block
+/// goes into its own line
+pub fn markdown_to_text(source: &str) -> MarkdownText {
+    let mut title: Option<String> = None;
+    let mut content = String::new();
+    let mut in_first_heading = false;
+
+    // The tables of GitHub, which are common in the READMEs
+    for event in Parser::new_ext(source, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::Heading(..)) if title.is_none() => {
+                in_first_heading = true;
+                title = Some(String::new());
+            }
+            Event::End(Tag::Heading(..)) if in_first_heading => {
+                in_first_heading = false;
+                content.push('\n');
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if in_first_heading {
+                    title.get_or_insert_with(String::new).push_str(&text);
+                }
+                content.push_str(&text);
+            }
+            Event::SoftBreak | Event::HardBreak | Event::Rule => content.push('\n'),
+            Event::End(
+                Tag::Paragraph
+                | Tag::Heading(..)
+                | Tag::Item
+                | Tag::CodeBlock(_)
+                | Tag::TableHead
+                | Tag::TableRow,
+            ) => content.push('\n'),
+            Event::End(Tag::TableCell) => content.push(' '),
+            _ => {}
+        }
+    }
+
+    MarkdownText {
+        title: title.filter(|title| !title.trim().is_empty()),
+        content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_text() {
+        let text = markdown_to_text(
+            "Intro with a [link](https://example.com)\n\n# First *heading*\n\n## Second\n\n\
+             | a | b |\n|---|---|\n| c | d |\n",
+        );
+        assert_eq!(text.title.as_deref(), Some("First heading"));
+        assert_eq!(
+            text.content,
+            "Intro with a link\nFirst heading\nSecond\na b \nc d \n"
+        );
+    }
+
+    #[test]
+    fn has_no_title_without_heading() {
+        assert_eq!(markdown_to_text("Some text").title, None);
+        assert_eq!(markdown_to_text("#\n\nSome text").title, None);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::data_lock::ServersLock;
+use crate::search::{open_indexes, run_search, OpenedIndex, SearchArguments, SearchQuery};
+use crate::DataPaths;
+use crate::DEFAULT_INDEX_NAME;
+use anyhow::Context;
+use clap::Args;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::IndexRecordOption;
+use tantivy::Term;
+
+/// The protocol versions that this server speaks, from the newest
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+#[derive(Args, Debug)]
+pub struct McpServeArguments {
+    /// The name of the index to search in
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Search in all the indexes and merge their results by score
+    #[arg(long, conflicts_with = "index_name")]
+    all_indexes: bool,
+}
+
+/// Answer Model Context Protocol requests from the standard input, one JSON-RPC message per line,
+/// until it is closed
+pub fn mcp_serve(arguments: McpServeArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let index_options = if arguments.all_indexes {
+        vec!["--all-indexes".to_string()]
+    } else {
+        vec![format!("--index-name={}", arguments.index_name)]
+    };
+    let _servers_lock = ServersLock::acquire_shared(data_paths)?;
+    let indexes = open_indexes(
+        &SearchArguments::parse_options(index_options.iter().map(String::as_str))?,
+        data_paths,
+    )?;
+
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Value>(&line) {
+            Err(error) => Some(error_response(Value::Null, -32700, &error.to_string())),
+            // Notifications have no id and get no response
+            Ok(message) => message.get("id").cloned().map(|id| {
+                let method = message["method"].as_str().unwrap_or_default();
+                match answer(
+                    &indexes,
+                    &index_options,
+                    data_paths,
+                    method,
+                    &message["params"],
+                ) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err(McpError::UnknownMethod) => {
+                        error_response(id, -32601, &format!("unknown method {:?}", method))
+                    }
+                    Err(McpError::InvalidParams(error)) => {
+                        error_response(id, -32602, &error.to_string())
+                    }
+                }
+            }),
+        };
+        if let Some(response) = response {
+            writeln!(stdout, "{}", response)?;
+            stdout.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+enum McpError {
+    UnknownMethod,
+    InvalidParams(anyhow::Error),
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn answer(
+    indexes: &[OpenedIndex],
+    index_options: &[String],
+    data_paths: &DataPaths,
+    method: &str,
+    params: &Value,
+) -> Result<Value, McpError> {
+    match method {
+        "initialize" => {
+            let requested_version = params["protocolVersion"].as_str().unwrap_or_default();
+            let version = PROTOCOL_VERSIONS
+                .iter()
+                .find(|&&version| version == requested_version)
+                .unwrap_or(&PROTOCOL_VERSIONS[0]);
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "mind-search", "version": env!("CARGO_PKG_VERSION") },
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let arguments = &params["arguments"];
+            let result = match params["name"].as_str().unwrap_or_default() {
+                "search_history" => search_history(indexes, index_options, data_paths, arguments),
+                "get_page_text" => get_page_text(indexes, arguments),
+                name => {
+                    return Err(McpError::InvalidParams(anyhow::anyhow!(
+                        "unknown tool {:?}",
+                        name
+                    )))
+                }
+            };
+            // Errors of the tools are shown to the model, so that it can fix its call
+            Ok(match result {
+                Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+                Err(error) => json!({
+                    "content": [{ "type": "text", "text": error.to_string() }],
+                    "isError": true,
+                }),
+            })
+        }
+        _ => Err(McpError::UnknownMethod),
+    }
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search_history",
+            "description": "Search the pages of the user's browser history by their content. \
+                Returns the best matches with their URL, title, a snippet and the last visit date.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "The words to search for. Words in quotes match as a phrase",
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "description": "How many results to return, 10 by default",
+                    },
+                    "site": {
+                        "type": "string",
+                        "description": "Only return pages of this site, like \"docs.rs\"",
+                    },
+                    "after": {
+                        "type": "string",
+                        "description": "Only return pages last visited on or after this date, \
+                            like \"2021-05-12\"",
+                    },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "get_page_text",
+            "description": "Get the readable text of a page of the browser history, as it was \
+                when it was downloaded.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "url": {
+                        "type": "string",
+                        "description": "The URL of the page, as returned by search_history",
+                    },
+                },
+                "required": ["url"],
+            },
+        },
+    ])
+}
+
+fn search_history(
+    indexes: &[OpenedIndex],
+    index_options: &[String],
+    data_paths: &DataPaths,
+    arguments: &Value,
+) -> anyhow::Result<String> {
+    let query = arguments["query"]
+        .as_str()
+        .context("missing the argument \"query\"")?;
+
+    let mut options = index_options.to_vec();
+    if let Some(limit) = arguments["limit"].as_u64() {
+        options.push(format!("--limit={}", limit));
+    }
+    for name in ["site", "after"] {
+        if let Some(value) = arguments[name].as_str() {
+            options.push(format!("--{}={}", name, value));
+        }
+    }
+    let search_arguments = SearchArguments::parse_options(options.iter().map(String::as_str))?;
+
+    let results = run_search(
+        indexes,
+        &SearchQuery::Text(query.to_string()),
+        &search_arguments,
+        data_paths,
+    )?;
+    let hits: Vec<Value> = results
+        .hits
+        .iter()
+        .map(|hit| {
+            json!({
+                "url": hit.url,
+                "title": hit.title.as_ref().or(hit.synthetic_title.as_ref()),
+                "snippet": hit.snippet.text,
+                "last_visit": hit.last_visit,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&hits)?)
+}
+
+fn get_page_text(indexes: &[OpenedIndex], arguments: &Value) -> anyhow::Result<String> {
+    let url = arguments["url"]
+        .as_str()
+        .context("missing the argument \"url\"")?;
+    // Indexed URLs don't have fragments, see `extract_firefox_history()`
+    let mut parsed_url = Url::parse(url)?;
+    parsed_url.set_fragment(None);
+    let url = parsed_url.to_string();
+
+    for opened_index in indexes {
+        let schema = opened_index.index.schema();
+        let url_exact_field = schema.get_field("url_exact")?;
+        let title_field = schema.get_field("title")?;
+        let content_field = schema.get_field("content")?;
+
+        let searcher = opened_index.reader.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(url_exact_field, &url),
+            IndexRecordOption::Basic,
+        );
+        if let Some((_score, hit_id)) = searcher
+            .search(&query, &TopDocs::with_limit(1))?
+            .into_iter()
+            .next()
+        {
+            let document = searcher.doc(hit_id)?;
+            let title = document
+                .get_first(title_field)
+                .and_then(|title| title.as_text());
+            let content = document
+                .get_first(content_field)
+                .and_then(|content| content.as_text())
+                .unwrap_or_default();
+            return Ok(match title {
+                Some(title) => format!("{}\n\n{}", title, content),
+                None => content.to_string(),
+            });
+        }
+    }
+
+    anyhow::bail!("{} is not in the browser history", url)
+}
@@ -0,0 +1,475 @@
+use crate::domain::registrable_domain;
+use crate::download_pages::KnownUrls;
+use crate::{
+    encryption, read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent,
+    FirefoxHistoryItem, OutputFormat, DEFAULT_INDEX_NAME,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use rayon::prelude::*;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tantivy::Index;
+use tracing::{info, warn};
+
+/// The tables of the metadata database. The dates are in UTC, like "2024-05-12 10:00:00", which
+/// the date functions of SQLite understand.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS bundles (
+    name TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    modified_ms INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pages (
+    -- The file name of the bundle in the raw pages directory
+    bundle_path TEXT NOT NULL,
+    record INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    domain TEXT,
+    title TEXT,
+    last_visit TEXT,
+    loaded_at TEXT NOT NULL,
+    -- html, plain_text, markdown, failure, pruned or skipped_equivalent
+    status TEXT NOT NULL,
+    -- Whether the default index has a document for the page
+    indexed INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (bundle_path, record)
+);
+CREATE INDEX IF NOT EXISTS pages_url ON pages (url);
+CREATE INDEX IF NOT EXISTS pages_domain ON pages (domain);
+CREATE INDEX IF NOT EXISTS pages_last_visit ON pages (last_visit);
+-- The other URLs of the pages: where their redirects ended and their canonical URL
+CREATE TABLE IF NOT EXISTS aliases (
+    bundle_path TEXT NOT NULL,
+    record INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (bundle_path, record, url)
+);
+";
+
+/// Why there is no metadata database for encrypted data
+const ENCRYPTED_DATA: &str =
+    "the metadata database is not kept for encrypted data, since it would not be encrypted";
+/// How long to wait for the other threads writing into the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Args, Debug)]
+#[command(after_help = "Examples:
+  The sites with the most pages:
+    mind-search query-meta \"SELECT domain, count(*) AS pages FROM pages GROUP BY domain ORDER BY pages DESC LIMIT 10\"
+
+  The pages visited yesterday:
+    mind-search query-meta \"SELECT last_visit, url FROM pages WHERE date(last_visit) = date('now', '-1 day')\"
+
+The tables are described by: mind-search query-meta \"SELECT sql FROM sqlite_schema\"")]
+pub struct QueryMetaArguments {
+    /// The SQL query, run on a read-only connection
+    query: String,
+    /// Print the rows as tab-separated values with a header, or as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+/// Which documents of the default index an indexing run replaced
+pub enum Reindexed<'a> {
+    All,
+    Bundle(&'a Path),
+    Url(&'a str),
+    /// Only documents were added
+    NewBundles,
+}
+
+/// Record the pages of a bundle that was just written, replacing what was known of it. The
+/// database is only a cache, so failing to update it is not an error.
+pub fn record_bundle(
+    data_paths: &DataPaths,
+    bundle: &Path,
+    pages: &[DownloadedPage],
+    history_by_url: Option<&HashMap<String, FirefoxHistoryItem>>,
+) {
+    if encryption::is_enabled() {
+        return;
+    }
+    let result = open(data_paths)
+        .and_then(|connection| write_bundle(&connection, bundle, pages, history_by_url));
+    if let Err(error) = result {
+        warn!(
+            "Failed to update the metadata database, run rebuild-meta to fix it: {:#}",
+            error
+        );
+    }
+}
+
+/// Record which pages of the default index have a document, after an indexing run
+pub fn record_indexed(
+    data_paths: &DataPaths,
+    index_name: &str,
+    reindexed: Reindexed,
+    indexed_records: &[(PathBuf, usize)],
+) {
+    if index_name != DEFAULT_INDEX_NAME
+        || encryption::is_enabled()
+        || !data_paths.metadata_database().exists()
+    {
+        return;
+    }
+    let result = open(data_paths)
+        .and_then(|connection| write_indexed(&connection, reindexed, indexed_records));
+    if let Err(error) = result {
+        warn!(
+            "Failed to update the metadata database, run rebuild-meta to fix it: {:#}",
+            error
+        );
+    }
+}
+
+/// The URLs of all the bundles, if the database knows all the bundles as they are now
+pub fn known_urls(data_paths: &DataPaths, bundles: &[PathBuf]) -> Option<KnownUrls> {
+    if encryption::is_enabled() || !data_paths.metadata_database().exists() {
+        return None;
+    }
+    let result = (|| -> anyhow::Result<_> {
+        let connection = open(data_paths)?;
+        let mut statement = connection.prepare("SELECT name, size, modified_ms FROM bundles")?;
+        let known_bundles: HashSet<(String, u64, i64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        let current_bundles: HashSet<(String, u64, i64)> = bundles
+            .iter()
+            .map(|bundle| bundle_state(bundle))
+            .collect::<anyhow::Result<_>>()?;
+        if known_bundles != current_bundles {
+            info!(
+                "The metadata database is out of date, run rebuild-meta to skip reading the \
+                bundles"
+            );
+            return Ok(None);
+        }
+
+        let mut statement = connection.prepare("SELECT DISTINCT url FROM pages")?;
+        let downloaded = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let mut statement = connection.prepare(
+            "SELECT aliases.url, pages.url FROM aliases
+            JOIN pages USING (bundle_path, record)
+            WHERE pages.status IN ('html', 'plain_text', 'markdown')",
+        )?;
+        let equivalents = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(Some(KnownUrls {
+            downloaded,
+            equivalents,
+        }))
+    })();
+    result.unwrap_or_else(|error| {
+        warn!("Failed to read the metadata database: {:#}", error);
+        None
+    })
+}
+
+/// Run a query on a read-only connection to the metadata database and print the rows
+pub fn query_meta(arguments: QueryMetaArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    check_available(data_paths)?;
+    let connection = Connection::open_with_flags(
+        data_paths.metadata_database(),
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?;
+    let mut statement = connection.prepare(&arguments.query)?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    let mut rows = statement.query([])?;
+    let mut json_rows = Vec::new();
+    if matches!(arguments.format, OutputFormat::Human) {
+        println!("{}", columns.join("\t"));
+    }
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(columns.len());
+        for column in 0..columns.len() {
+            values.push(to_json(row.get_ref(column)?));
+        }
+        match arguments.format {
+            OutputFormat::Human => {
+                let texts: Vec<String> = values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::Null => String::new(),
+                        Value::String(text) => text.replace(['\t', '\n'], " "),
+                        value => value.to_string(),
+                    })
+                    .collect();
+                println!("{}", texts.join("\t"));
+            }
+            OutputFormat::Json | OutputFormat::Jsonl => {
+                let object: Map<String, Value> = columns.iter().cloned().zip(values).collect();
+                if matches!(arguments.format, OutputFormat::Jsonl) {
+                    println!("{}", Value::Object(object));
+                } else {
+                    json_rows.push(Value::Object(object));
+                }
+            }
+        }
+    }
+    if matches!(arguments.format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&json_rows)?);
+    }
+    Ok(())
+}
+
+/// Build the metadata database again from the bundles, the history and the default index
+pub fn rebuild_meta(data_paths: &DataPaths) -> anyhow::Result<()> {
+    if encryption::is_enabled() {
+        anyhow::bail!(ENCRYPTED_DATA);
+    }
+    let database = data_paths.metadata_database();
+    for path in [database.clone(), journal_path(&database)] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let history_by_url: HashMap<_, _> = match data_paths.read_history() {
+        Ok(history) => history
+            .into_iter()
+            .map(|item| (item.url.clone(), item))
+            .collect(),
+        Err(error) => {
+            warn!("The titles and visits will be missing: {:#}", error);
+            HashMap::new()
+        }
+    };
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    open(data_paths)?;
+    bundles
+        .par_iter()
+        .try_for_each(|bundle| -> anyhow::Result<()> {
+            let pages: Vec<DownloadedPage> = read_compressed_json(bundle)
+                .with_context(|| format!("failed to read {}", bundle.display()))?;
+            write_bundle(&open(data_paths)?, bundle, &pages, Some(&history_by_url))
+        })?;
+
+    let index_dir = data_paths.tantivy_index_dir(DEFAULT_INDEX_NAME)?;
+    let mut indexed_records = Vec::new();
+    if index_dir.join("meta.json").exists() {
+        let index = Index::open_in_dir(&index_dir)?;
+        let schema = index.schema();
+        let bundle_path_field = schema.get_field("bundle_path")?;
+        let bundle_record_field = schema.get_field("bundle_record")?;
+        let searcher = index.reader()?.searcher();
+        for segment_reader in searcher.segment_readers() {
+            let store_reader = segment_reader.get_store_reader(10)?;
+            for document in store_reader.iter(segment_reader.alive_bitset()) {
+                let document = document?;
+                let bundle_path = document
+                    .get_first(bundle_path_field)
+                    .and_then(|value| value.as_text());
+                let bundle_record = document
+                    .get_first(bundle_record_field)
+                    .and_then(|value| value.as_u64());
+                if let (Some(bundle_path), Some(bundle_record)) = (bundle_path, bundle_record) {
+                    indexed_records.push((PathBuf::from(bundle_path), bundle_record as usize));
+                }
+            }
+        }
+    }
+    write_indexed(&open(data_paths)?, Reindexed::All, &indexed_records)?;
+
+    println!(
+        "Rebuilt {} from {} bundles, with {} indexed pages",
+        database.display(),
+        bundles.len(),
+        indexed_records.len()
+    );
+    Ok(())
+}
+
+/// Delete the database, when the data becomes encrypted
+pub fn remove(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let database = data_paths.metadata_database();
+    for path in [database.clone(), journal_path(&database)] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file is the database or its journal, which are not part of the data
+pub fn is_database_file(path: &Path, data_paths: &DataPaths) -> bool {
+    let database = data_paths.metadata_database();
+    path == database || path == journal_path(&database)
+}
+
+fn check_available(data_paths: &DataPaths) -> anyhow::Result<()> {
+    if encryption::is_enabled() {
+        anyhow::bail!(ENCRYPTED_DATA);
+    }
+    if !data_paths.metadata_database().exists() {
+        anyhow::bail!(
+            "there is no metadata database yet, build it with `rebuild-meta` or run download-pages"
+        );
+    }
+    Ok(())
+}
+
+fn open(data_paths: &DataPaths) -> anyhow::Result<Connection> {
+    let connection = Connection::open(data_paths.metadata_database())?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+fn write_bundle(
+    connection: &Connection,
+    bundle: &Path,
+    pages: &[DownloadedPage],
+    history_by_url: Option<&HashMap<String, FirefoxHistoryItem>>,
+) -> anyhow::Result<()> {
+    let (name, size, modified_ms) = bundle_state(bundle)?;
+    let transaction = connection.unchecked_transaction()?;
+    {
+        // The titles and visits are kept when the history is not given, like by prune, which
+        // also removes the documents of the pruned pages
+        let mut upsert = transaction.prepare(
+            "INSERT INTO pages (bundle_path, record, url, domain, title, last_visit, loaded_at,
+                status)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT (bundle_path, record) DO UPDATE SET
+                url = excluded.url,
+                domain = excluded.domain,
+                title = coalesce(excluded.title, title),
+                last_visit = coalesce(excluded.last_visit, last_visit),
+                loaded_at = excluded.loaded_at,
+                status = excluded.status,
+                indexed = indexed AND excluded.status != 'pruned'",
+        )?;
+        for (record, page) in pages.iter().enumerate() {
+            let history_item = history_by_url.and_then(|history| history.get(&page.url));
+            upsert.execute(params![
+                name,
+                record,
+                page.url,
+                registrable_domain(&page.url),
+                history_item.and_then(|item| item.title.as_deref()),
+                history_item
+                    .and_then(|item| item.last_visit)
+                    .map(format_date),
+                format_date(page.loaded_at),
+                status(&page.content),
+            ])?;
+        }
+    }
+    transaction.execute(
+        "DELETE FROM pages WHERE bundle_path = ?1 AND record >= ?2",
+        params![name, pages.len()],
+    )?;
+    transaction.execute("DELETE FROM aliases WHERE bundle_path = ?1", [&name])?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT OR IGNORE INTO aliases (bundle_path, record, url) VALUES (?1, ?2, ?3)",
+        )?;
+        for (record, page) in pages.iter().enumerate() {
+            for alias in [&page.final_url, &page.canonical_url].into_iter().flatten() {
+                insert.execute(params![name, record, alias])?;
+            }
+        }
+    }
+    transaction.execute(
+        "INSERT OR REPLACE INTO bundles (name, size, modified_ms) VALUES (?1, ?2, ?3)",
+        params![name, size, modified_ms],
+    )?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn write_indexed(
+    connection: &Connection,
+    reindexed: Reindexed,
+    indexed_records: &[(PathBuf, usize)],
+) -> anyhow::Result<()> {
+    let transaction = connection.unchecked_transaction()?;
+    match reindexed {
+        Reindexed::All => {
+            transaction.execute("UPDATE pages SET indexed = 0", [])?;
+        }
+        Reindexed::Bundle(bundle) => {
+            transaction.execute(
+                "UPDATE pages SET indexed = 0 WHERE bundle_path = ?1",
+                [file_name(bundle)?],
+            )?;
+        }
+        Reindexed::Url(url) => {
+            transaction.execute("UPDATE pages SET indexed = 0 WHERE url = ?1", [url])?;
+        }
+        Reindexed::NewBundles => {}
+    }
+    {
+        let mut update = transaction
+            .prepare("UPDATE pages SET indexed = 1 WHERE bundle_path = ?1 AND record = ?2")?;
+        for (bundle, record) in indexed_records {
+            update.execute(params![file_name(bundle)?, record])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The name, size and modification time of the bundle, which change when it is rewritten
+pub fn bundle_state(bundle: &Path) -> anyhow::Result<(String, u64, i64)> {
+    let metadata = fs::metadata(bundle)?;
+    let modified_ms = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64);
+    Ok((file_name(bundle)?, metadata.len(), modified_ms))
+}
+
+fn file_name(bundle: &Path) -> anyhow::Result<String> {
+    Ok(bundle
+        .file_name()
+        .context("invalid bundle path")?
+        .to_string_lossy()
+        .to_string())
+}
+
+fn journal_path(database: &Path) -> PathBuf {
+    let mut journal = database.as_os_str().to_owned();
+    journal.push("-journal");
+    PathBuf::from(journal)
+}
+
+fn status(content: &DownloadedPageContent) -> &'static str {
+    match content {
+        DownloadedPageContent::Failure(_) => "failure",
+        DownloadedPageContent::Html(_) => "html",
+        DownloadedPageContent::PlainText(_) => "plain_text",
+        DownloadedPageContent::Markdown(_) => "markdown",
+        DownloadedPageContent::Pruned => "pruned",
+        DownloadedPageContent::SkippedEquivalent(_) => "skipped_equivalent",
+    }
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(integer) => Value::from(integer),
+        ValueRef::Real(real) => Value::from(real),
+        ValueRef::Text(text) => Value::from(String::from_utf8_lossy(text).to_string()),
+        ValueRef::Blob(blob) => Value::from(format!("<{} bytes>", blob.len())),
+    }
+}
@@ -0,0 +1,101 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Entities that survive in the extracted text when a page escaped its HTML twice
+const LEFTOVER_ENTITIES: &[(&str, &str)] = &[
+    ("&nbsp;", " "),
+    ("&quot;", "\""),
+    ("&#39;", "'"),
+    ("&apos;", "'"),
+    ("&amp;", "&"),
+];
+
+/// Normalize text so that the same words are always represented the same way, both when indexing
+/// and when searching. For example, "café" can be written with a single "é" code point or with an
+/// "e" followed by a combining accent.
+///
+/// Besides the NFC normalization, this replaces the exotic spaces (like NBSP) with regular spaces,
+/// removes invisible characters (zero-width spaces and joiners, soft hyphens) and collapses
+/// whitespace, keeping at most one line break between lines.
+pub fn normalize_text(text: &str) -> String {
+    let mut text = text.to_string();
+    for (entity, replacement) in LEFTOVER_ENTITIES {
+        if text.contains(entity) {
+            text = text.replace(entity, replacement);
+        }
+    }
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut pending_space = false;
+    let mut pending_line_break = false;
+    for c in text.nfc() {
+        match c {
+            '\u{ad}' | '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}' => {}
+            '\n' | '\r' | '\u{2028}' | '\u{2029}' => pending_line_break = true,
+            c if c.is_whitespace() => pending_space = true,
+            c => {
+                if !normalized.is_empty() {
+                    if pending_line_break {
+                        normalized.push('\n');
+                    } else if pending_space {
+                        normalized.push(' ');
+                    }
+                }
+                pending_space = false;
+                pending_line_break = false;
+                normalized.push(c);
+            }
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn composes_the_decomposed_accents() {
+        let decomposed = "cafe\u{301} cre\u{300}me bru\u{302}le\u{301}e";
+        assert_eq!(normalize_text(decomposed), "café crème brûlée");
+        assert_eq!(
+            normalize_text(decomposed),
+            normalize_text("café crème brûlée")
+        );
+    }
+
+    #[test]
+    fn replaces_the_exotic_spaces() {
+        assert_eq!(normalize_text("1\u{a0}000\u{a0}000 €"), "1 000 000 €");
+        assert_eq!(normalize_text("10\u{202f}km\u{2009}h"), "10 km h");
+        assert_eq!(normalize_text("1&nbsp;000"), "1 000");
+    }
+
+    #[test]
+    fn removes_the_invisible_chars() {
+        assert_eq!(normalize_text("hy\u{ad}phen\u{ad}ated"), "hyphenated");
+        assert_eq!(
+            normalize_text("zero\u{200b}width\u{200d}joined"),
+            "zerowidthjoined"
+        );
+        assert_eq!(normalize_text("\u{feff}bom"), "bom");
+    }
+
+    #[test]
+    fn collapses_the_whitespace() {
+        assert_eq!(
+            normalize_text("  first \t line \r\n\n\n   second  line\n "),
+            "first line\nsecond line"
+        );
+        assert_eq!(normalize_text("para\u{2029}graph"), "para\ngraph");
+        assert_eq!(normalize_text(" \n\t"), "");
+    }
+
+    #[test]
+    fn decodes_the_leftover_entities() {
+        assert_eq!(
+            normalize_text("Tom &amp; Jerry&#39;s &quot;show&quot;"),
+            "Tom & Jerry's \"show\""
+        );
+    }
+}
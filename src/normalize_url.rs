@@ -0,0 +1,139 @@
+use reqwest::Url;
+
+/// Query parameters that only track where the visit came from, besides the "utm_" ones
+const TRACKING_PARAMETERS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga", "_gl",
+];
+
+/// Remove the fragment of a URL of the history, or of a URL looked up in it. For example:
+/// "https://docs.rs/url/2.4.0/url/struct.Url.html#impl-Serialize-for-Url" becomes
+/// "https://docs.rs/url/2.4.0/url/struct.Url.html"
+///
+/// The rest is kept, since it's the URL that is downloaded: some servers answer "/dir" and "/dir/"
+/// differently.
+pub fn normalize_history_url(url: &mut Url) {
+    url.set_fragment(None);
+}
+
+/// Remove the parts of the URL that don't change the page it points to, so that the snapshots of a
+/// page under different URLs are seen as the same page. For example:
+/// "https://example.com/post/?utm_source=twitter#comments" becomes "https://example.com/post"
+///
+/// This removes the fragment, the tracking query parameters and the trailing slash of the path.
+/// The URLs are only compared this way, they are stored like [normalize_history_url] does.
+pub fn normalize_url(url: &mut Url) {
+    url.set_fragment(None);
+
+    // The query is only rewritten when needed, since rewriting it can change how it is encoded
+    if url
+        .query_pairs()
+        .any(|(name, _)| is_tracking_parameter(&name))
+    {
+        let kept_pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| !is_tracking_parameter(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        if kept_pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(kept_pairs);
+        }
+    }
+
+    // The root path can't lose its slash
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+}
+
+fn is_tracking_parameter(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(url: &str) -> String {
+        let mut url = Url::parse(url).unwrap();
+        normalize_url(&mut url);
+        url.to_string()
+    }
+
+    fn history_url(url: &str) -> String {
+        let mut url = Url::parse(url).unwrap();
+        normalize_history_url(&mut url);
+        url.to_string()
+    }
+
+    #[test]
+    fn removes_the_fragment() {
+        assert_eq!(
+            normalized("https://example.com/post#comments"),
+            "https://example.com/post"
+        );
+        assert_eq!(
+            normalized("https://example.com/#top"),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn removes_the_tracking_parameters() {
+        assert_eq!(
+            normalized("https://example.com/post?utm_source=twitter&UTM_Medium=social&fbclid=1"),
+            "https://example.com/post"
+        );
+        // The other parameters are kept, in their order
+        assert_eq!(
+            normalized("https://example.com/search?q=rust&gclid=2&page=2"),
+            "https://example.com/search?q=rust&page=2"
+        );
+        // And as they were encoded without tracking parameters
+        assert_eq!(
+            normalized("https://example.com/search?q=a%20b"),
+            "https://example.com/search?q=a%20b"
+        );
+        assert_eq!(
+            normalized("https://example.com/search?utmost=1"),
+            "https://example.com/search?utmost=1"
+        );
+    }
+
+    #[test]
+    fn removes_the_trailing_slash() {
+        assert_eq!(
+            normalized("https://example.com/dir/"),
+            "https://example.com/dir"
+        );
+        assert_eq!(
+            normalized("https://example.com/dir//"),
+            "https://example.com/dir"
+        );
+        assert_eq!(
+            normalized("https://example.com/dir/?q=1"),
+            "https://example.com/dir?q=1"
+        );
+    }
+
+    #[test]
+    fn keeps_the_slash_of_the_root() {
+        assert_eq!(normalized("https://example.com"), "https://example.com/");
+        assert_eq!(normalized("https://example.com/"), "https://example.com/");
+        assert_eq!(
+            normalized("https://example.com/?utm_source=feed"),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn only_removes_the_fragment_of_the_history() {
+        assert_eq!(
+            history_url("https://example.com/dir/?utm_source=feed#top"),
+            "https://example.com/dir/?utm_source=feed"
+        );
+    }
+}
@@ -0,0 +1,10 @@
+/// Open the URL in the default browser, honoring `$BROWSER`.
+///
+/// Failures are printed along with the URL, so that it can still be copied by hand. Messages go to
+/// the standard error, to keep the standard output clean for the JSON formats.
+pub fn open_url(url: &str) {
+    match webbrowser::open(url) {
+        Ok(()) => eprintln!("Opened {}", url),
+        Err(error) => eprintln!("Failed to open {} in the browser: {}", url, error),
+    }
+}
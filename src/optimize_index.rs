@@ -0,0 +1,59 @@
+use crate::index_lock::IndexLock;
+use crate::DataPaths;
+use std::fs;
+use std::path::Path;
+use tantivy::{Index, IndexWriter};
+use tracing::info;
+
+/// Merge all segments of the index into one, which makes the first queries faster
+pub fn optimize_index(index_name: &str, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let index_dir_path = data_paths.built_index_dir(index_name)?;
+    let _lock = IndexLock::acquire(index_dir_path.clone())?;
+
+    let index = Index::open_in_dir(&index_dir_path)?;
+    let index_writer = index.writer_with_num_threads(1, 50 * 1024 * 1024)?;
+    merge_all_segments(&index, &index_dir_path, index_writer)
+}
+
+/// Wait for the background merges to finish and then merge whatever segments are left
+pub fn merge_all_segments(
+    index: &Index,
+    index_dir_path: &Path,
+    mut index_writer: IndexWriter,
+) -> anyhow::Result<()> {
+    let segment_ids = index.searchable_segment_ids()?;
+    info!(
+        "Before optimizing: {} segments, {:.1} MB",
+        segment_ids.len(),
+        dir_size(index_dir_path)? as f64 / 1024. / 1024.
+    );
+
+    if segment_ids.len() > 1 {
+        index_writer.merge(&segment_ids).wait()?;
+    }
+    index_writer.wait_merging_threads()?;
+
+    // Deleted segment files are only removed by the garbage collection
+    let index_writer: IndexWriter = index.writer_with_num_threads(1, 50 * 1024 * 1024)?;
+    index_writer.garbage_collect_files().wait()?;
+    drop(index_writer);
+
+    info!(
+        "After optimizing: {} segments, {:.1} MB",
+        index.searchable_segment_ids()?.len(),
+        dir_size(index_dir_path)? as f64 / 1024. / 1024.
+    );
+
+    Ok(())
+}
+
+fn dir_size(dir_path: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for maybe_entry in fs::read_dir(dir_path)? {
+        let metadata = maybe_entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
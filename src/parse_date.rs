@@ -0,0 +1,197 @@
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+/// Formats with an explicit timezone offset, tried in order
+const OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M%z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S %z",
+    "%a, %d %b %Y %H:%M:%S %z",
+    "%d %b %Y %H:%M:%S %z",
+];
+
+/// Formats without timezone information, which are assumed to be in UTC
+const NAIVE_DATE_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%a, %d %b %Y %H:%M:%S",
+    "%B %d, %Y %H:%M",
+];
+
+/// Formats that only contain a day
+const NAIVE_DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%Y.%m.%d",
+    "%Y%m%d",
+    "%B %d, %Y",
+    "%b %d, %Y",
+    "%d %B %Y",
+    "%d %b %Y",
+    "%B %d %Y",
+    "%b %d %Y",
+    "%d.%m.%Y",
+];
+
+/// Parse a date as found in the wild: in HTML meta tags, `<time>` elements, JSON-LD blocks or
+/// typed by the user in the command line.
+///
+/// This is intentionally lenient: it accepts RFC 3339, RFC 2822 and a list of common variations.
+/// Dates that are clearly wrong (before 1970 or after 2100) are rejected.
+pub fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    // Some sites spell out the timezone, which is always UTC in practice
+    let text = text.trim_end_matches(" UTC");
+
+    let parsed = DateTime::parse_from_rfc3339(text)
+        .or_else(|_| DateTime::parse_from_rfc2822(text))
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|| {
+            OFFSET_FORMATS
+                .iter()
+                .find_map(|format| DateTime::parse_from_str(text, format).ok())
+                .map(|date| date.with_timezone(&Utc))
+        })
+        .or_else(|| {
+            // Drop a trailing "Z" that wasn't accepted by the RFC 3339 parser (like "2021-05-12Z")
+            let text = text.trim_end_matches('Z');
+            NAIVE_DATE_TIME_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+                .or_else(|| {
+                    NAIVE_DATE_FORMATS
+                        .iter()
+                        .find_map(|format| NaiveDate::parse_from_str(text, format).ok())
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+                .map(|date| Utc.from_utc_datetime(&date))
+        })?;
+
+    if (1970..=2100).contains(&parsed.year()) {
+        Some(parsed)
+    } else {
+        None
+    }
+}
+
+/// Recognize dates encoded in URL paths, like "https://blog.example/2021/05/12/some-post"
+pub fn infer_date_from_url(url: &str) -> Option<DateTime<Utc>> {
+    let path = url.split("://").nth(1).unwrap_or(url);
+    let segments: Vec<&str> = path.split(['/', '?', '#']).collect();
+
+    segments.windows(3).find_map(|window| {
+        let is_numeric = |segment: &str, len: usize| {
+            segment.len() == len && segment.bytes().all(|byte| byte.is_ascii_digit())
+        };
+        if is_numeric(window[0], 4) && is_numeric(window[1], 2) && is_numeric(window[2], 2) {
+            parse_date(&format!("{}-{}-{}", window[0], window[1], window[2]))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_the_formats_found_in_pages() {
+        let corpus = [
+            // article:published_time and JSON-LD datePublished
+            ("2021-05-12T08:30:00Z", "2021-05-12T08:30:00Z"),
+            ("2021-05-12T08:30:00+02:00", "2021-05-12T06:30:00Z"),
+            ("2021-05-12T08:30:00.123456Z", "2021-05-12T08:30:00.123456Z"),
+            ("2021-05-12T08:30:00+0200", "2021-05-12T06:30:00Z"),
+            ("2021-05-12T08:30+02:00", "2021-05-12T06:30:00Z"),
+            ("2021-05-12T08:30:00", "2021-05-12T08:30:00Z"),
+            ("2021-05-12T08:30", "2021-05-12T08:30:00Z"),
+            ("2021-05-12 08:30:00", "2021-05-12T08:30:00Z"),
+            ("2021-05-12 08:30:00 +0200", "2021-05-12T06:30:00Z"),
+            ("2021-05-12 08:30:00 UTC", "2021-05-12T08:30:00Z"),
+            ("2021-05-12 08:30", "2021-05-12T08:30:00Z"),
+            ("2021/05/12 08:30:00", "2021-05-12T08:30:00Z"),
+            // RSS and HTTP headers
+            ("Wed, 12 May 2021 08:30:00 GMT", "2021-05-12T08:30:00Z"),
+            ("Wed, 12 May 2021 08:30:00 +0000", "2021-05-12T08:30:00Z"),
+            ("Wed, 12 May 2021 08:30:00", "2021-05-12T08:30:00Z"),
+            ("12 May 2021 08:30:00 +0100", "2021-05-12T07:30:00Z"),
+            // <time datetime> and the dates written for humans
+            ("2021-05-12", "2021-05-12T00:00:00Z"),
+            ("2021-05-12Z", "2021-05-12T00:00:00Z"),
+            ("2021/05/12", "2021-05-12T00:00:00Z"),
+            ("2021.05.12", "2021-05-12T00:00:00Z"),
+            ("20210512", "2021-05-12T00:00:00Z"),
+            ("May 12, 2021", "2021-05-12T00:00:00Z"),
+            ("May 12, 2021 08:30", "2021-05-12T08:30:00Z"),
+            ("12 May 2021", "2021-05-12T00:00:00Z"),
+            ("12 September 2021", "2021-09-12T00:00:00Z"),
+            ("Sep 12 2021", "2021-09-12T00:00:00Z"),
+            ("September 12 2021", "2021-09-12T00:00:00Z"),
+            ("12.05.2021", "2021-05-12T00:00:00Z"),
+            ("  2021-05-12\n", "2021-05-12T00:00:00Z"),
+        ];
+        for (text, expected) in corpus {
+            assert_eq!(parse_date(text), Some(utc(expected)), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_plausible_date() {
+        let corpus = [
+            "",
+            "   ",
+            "yesterday",
+            "2 hours ago",
+            "12/05/2021",
+            "2021-13-12",
+            "2021-02-30",
+            "1969-12-31",
+            "2101-01-01",
+            "0001-01-01T00:00:00Z",
+            "{{ page.date }}",
+        ];
+        for text in corpus {
+            assert_eq!(parse_date(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn infers_the_date_of_the_url_path() {
+        assert_eq!(
+            infer_date_from_url("https://blog.example/2021/05/12/some-post"),
+            Some(utc("2021-05-12T00:00:00Z"))
+        );
+        assert_eq!(
+            infer_date_from_url("https://blog.example/posts/2021/05/12"),
+            Some(utc("2021-05-12T00:00:00Z"))
+        );
+        assert_eq!(
+            infer_date_from_url("https://blog.example/2021/05/some-post"),
+            None
+        );
+        assert_eq!(
+            infer_date_from_url("https://blog.example/2021/13/40/some-post"),
+            None
+        );
+        assert_eq!(infer_date_from_url("https://example.com/1234/56/78"), None);
+        assert_eq!(
+            infer_date_from_url("https://example.com/search?q=2021-05-12"),
+            None
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::bundle_cache;
+use crate::domain::registrable_domain;
+use crate::index_contents::count_documents;
+use crate::index_lock::IndexLock;
+use crate::search::{parse_last, site_domain};
+use crate::{
+    metadata, read_compressed_json, write_compressed_json, DataPaths, DownloadedPage,
+    DownloadedPageContent, FirefoxHistoryItem,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tantivy::{Index, IndexWriter, Term};
+use tracing::info;
+
+#[derive(Args, Debug)]
+pub struct PruneArguments {
+    /// Remove the pages of this site, like "reddit.com". Can be given several times
+    #[arg(long = "domain")]
+    domains: Vec<String>,
+    /// Remove the pages last visited before this period, like "6m" or "3y"
+    #[arg(long)]
+    older_than: Option<String>,
+    /// Also remove the pages from the extracted history
+    #[arg(long)]
+    also_history: bool,
+    /// Delete the data. Without it, only tell what would be deleted
+    #[arg(long)]
+    yes: bool,
+}
+
+/// Which pages to remove
+struct PruneFilter {
+    domains: HashSet<String>,
+    visited_before: Option<DateTime<Utc>>,
+}
+
+impl PruneFilter {
+    fn matches(&self, url: &str, last_visit: Option<DateTime<Utc>>) -> bool {
+        let matches_domain =
+            registrable_domain(url).is_some_and(|domain| self.domains.contains(&domain));
+        // Pages with an unknown last visit are kept
+        let is_old = match (self.visited_before, last_visit) {
+            (Some(visited_before), Some(last_visit)) => last_visit < visited_before,
+            _ => false,
+        };
+        matches_domain || is_old
+    }
+}
+
+/// Remove the pages of some sites or the old pages from the bundles, the indexes and optionally the
+/// history.
+///
+/// The records of the bundles are not removed but emptied, so that the other records keep the
+/// position that the indexes refer to, and so that the pages are not downloaded again.
+pub fn prune(arguments: PruneArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if arguments.domains.is_empty() && arguments.older_than.is_none() {
+        anyhow::bail!("give the pages to remove with --domain or --older-than");
+    }
+    let filter = PruneFilter {
+        domains: arguments
+            .domains
+            .iter()
+            .map(|domain| site_domain(domain))
+            .collect(),
+        visited_before: arguments
+            .older_than
+            .as_deref()
+            .map(|older_than| parse_last(older_than, Utc::now()))
+            .transpose()?,
+    };
+
+    let history = data_paths.read_history()?;
+    let last_visits: HashMap<&str, Option<DateTime<Utc>>> = history
+        .iter()
+        .map(|item| (item.url.as_str(), item.last_visit))
+        .collect();
+    let pruned_history_items = history
+        .iter()
+        .filter(|item| filter.matches(&item.url, item.last_visit))
+        .count();
+
+    // The pages that were already pruned are skipped, so that running it again only finishes
+    // the work
+    let pruned_urls = Mutex::new(HashSet::new());
+    let affected_bundles = Mutex::new(Vec::new());
+    data_paths
+        .list_raw_pages_bundles()?
+        .into_par_iter()
+        .try_for_each(|bundle| -> anyhow::Result<()> {
+            let downloaded_pages: Vec<DownloadedPage> = read_compressed_json(&bundle)
+                .with_context(|| format!("failed to read {}", bundle.display()))?;
+            let mut matching_urls = Vec::new();
+            for page in downloaded_pages {
+                let last_visit = last_visits.get(page.url.as_str()).copied().flatten();
+                if !matches!(page.content, DownloadedPageContent::Pruned)
+                    && filter.matches(&page.url, last_visit)
+                {
+                    matching_urls.push(page.url);
+                }
+            }
+            if !matching_urls.is_empty() {
+                pruned_urls.lock().unwrap().extend(matching_urls);
+                affected_bundles.lock().unwrap().push(bundle);
+            }
+            Ok(())
+        })?;
+    let pruned_urls = pruned_urls.into_inner().unwrap();
+    let affected_bundles = affected_bundles.into_inner().unwrap();
+
+    let mut indexes = Vec::new();
+    for index_name in data_paths.list_index_names()? {
+        let index_dir_path = data_paths.tantivy_index_dir(&index_name)?;
+        if index_dir_path.join("meta.json").exists() {
+            indexes.push((index_name, index_dir_path));
+        }
+    }
+    let mut indexed_documents = 0;
+    for (_, index_dir_path) in &indexes {
+        let index = Index::open_in_dir(index_dir_path)?;
+        let url_exact_field = index.schema().get_field("url_exact")?;
+        for url in &pruned_urls {
+            indexed_documents +=
+                count_documents(&index, &Term::from_field_text(url_exact_field, url))?;
+        }
+    }
+
+    println!("To delete:");
+    println!(
+        "  Downloaded pages: {} in {} bundles",
+        pruned_urls.len(),
+        affected_bundles.len()
+    );
+    println!(
+        "  Indexed documents: {} in {} indexes",
+        indexed_documents,
+        indexes.len()
+    );
+    if arguments.also_history {
+        println!("  URLs of the history: {}", pruned_history_items);
+    }
+    if !arguments.yes {
+        println!("Nothing was deleted, run again with --yes to delete them");
+        return Ok(());
+    }
+
+    // The indexes go first, so that no search result points to a pruned page. Each step can be
+    // done again, so a failure only requires running the same command again.
+    for (index_name, index_dir_path) in &indexes {
+        let _lock = IndexLock::acquire(index_dir_path.clone())?;
+        let index = Index::open_in_dir(index_dir_path)?;
+        let url_exact_field = index.schema().get_field("url_exact")?;
+        let mut index_writer: IndexWriter = index.writer_with_num_threads(1, 50 * 1024 * 1024)?;
+        for url in &pruned_urls {
+            index_writer.delete_term(Term::from_field_text(url_exact_field, url));
+        }
+        index_writer.commit()?;
+        info!("Deleted the pruned pages from the index {:?}", index_name);
+    }
+
+    prune_bundles(&affected_bundles, &pruned_urls, data_paths).context(
+        "the indexes were updated, but rewriting the bundles failed: run the same command again \
+        to finish",
+    )?;
+
+    if arguments.also_history {
+        let kept_history: Vec<&FirefoxHistoryItem> = history
+            .iter()
+            .filter(|item| !filter.matches(&item.url, item.last_visit))
+            .collect();
+        write_compressed_json(&data_paths.history(), &kept_history).context(
+            "the indexes and the bundles were updated, but rewriting the history failed: run the \
+            same command again to finish",
+        )?;
+        info!("Removed {} URLs from the history", pruned_history_items);
+    }
+
+    println!("Deleted");
+    Ok(())
+}
+
+/// Empty the records of the pruned pages in each bundle
+fn prune_bundles(
+    bundles: &[PathBuf],
+    pruned_urls: &HashSet<String>,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    for bundle in bundles {
+        let mut downloaded_pages: Vec<DownloadedPage> = read_compressed_json(bundle)?;
+        for page in &mut downloaded_pages {
+            if pruned_urls.contains(&page.url) {
+                page.content = DownloadedPageContent::Pruned;
+            }
+        }
+        write_compressed_json(bundle, &downloaded_pages)?;
+        metadata::record_bundle(data_paths, bundle, &downloaded_pages, None);
+        bundle_cache::record_bundle(data_paths, bundle, &downloaded_pages);
+        info!("Rewrote {}", bundle.display());
+    }
+    Ok(())
+}
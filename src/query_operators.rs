@@ -0,0 +1,154 @@
+/// The filters written in the query itself, like "site:docs.rs" or "-forum"
+#[derive(Default, Debug)]
+pub struct QueryOperators {
+    /// Sites from "site:", of which the results must be in one
+    pub sites: Vec<String>,
+    /// Sites from "-site:"
+    pub excluded_sites: Vec<String>,
+    /// The date from "after:", as written
+    pub after: Option<String>,
+    /// The date from "before:", as written
+    pub before: Option<String>,
+    /// Words or phrases after a minus, like "forum" in "-forum"
+    pub excluded: Vec<String>,
+}
+
+impl QueryOperators {
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+            && self.excluded_sites.is_empty()
+            && self.after.is_none()
+            && self.before.is_none()
+            && self.excluded.is_empty()
+    }
+}
+
+/// Split the operators from the rest of the query, which is returned without them.
+///
+/// Only whole tokens outside of quoted phrases are operators, so "foo-bar" and "\"a -b\"" are
+/// kept as they are.
+pub fn extract_operators(query: &str) -> (String, QueryOperators) {
+    let mut operators = QueryOperators::default();
+    let mut remaining_tokens = Vec::new();
+    for token in split_tokens(query) {
+        if !is_operator(token) {
+            remaining_tokens.push(token);
+            continue;
+        }
+
+        let (negated, operator) = match token.strip_prefix('-') {
+            Some(operator) => (true, operator),
+            None => (false, token),
+        };
+        match operator.split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("site") && negated => {
+                operators.excluded_sites.push(value.to_string())
+            }
+            Some((name, value)) if name.eq_ignore_ascii_case("site") => {
+                operators.sites.push(value.to_string())
+            }
+            Some((name, value)) if name.eq_ignore_ascii_case("after") => {
+                operators.after = Some(value.to_string())
+            }
+            Some((name, value)) if name.eq_ignore_ascii_case("before") => {
+                operators.before = Some(value.to_string())
+            }
+            _ => operators.excluded.push(operator.to_string()),
+        }
+    }
+
+    (remaining_tokens.join(" "), operators)
+}
+
+/// Whether the token is one of the operators, as opposed to words or the syntax of the parser
+pub fn is_operator(token: &str) -> bool {
+    match token.strip_prefix('-') {
+        // Any exclusion, including other fields like "-title:tokio", but not a lone minus
+        Some(excluded) => !excluded.is_empty(),
+        None => match token.split_once(':') {
+            Some((name, value)) => {
+                !value.is_empty()
+                    && ["site", "after", "before"]
+                        .iter()
+                        .any(|operator| name.eq_ignore_ascii_case(operator))
+            }
+            None => false,
+        },
+    }
+}
+
+/// Split the query at the whitespace outside of quoted phrases
+pub fn split_tokens(query: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut token_start = None;
+    let mut in_quotes = false;
+    for (index, c) in query.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if c.is_whitespace() && !in_quotes {
+            if let Some(start) = token_start.take() {
+                tokens.push(&query[start..index]);
+            }
+        } else if token_start.is_none() {
+            token_start = Some(index);
+        }
+    }
+    if let Some(start) = token_start {
+        tokens.push(&query[start..]);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_the_filters() {
+        let (query, operators) = extract_operators(
+            "tokio site:docs.rs SITE:tokio.rs -site:reddit.com after:2021-05 before:2023 -forum",
+        );
+        assert_eq!(query, "tokio");
+        assert_eq!(operators.sites, ["docs.rs", "tokio.rs"]);
+        assert_eq!(operators.excluded_sites, ["reddit.com"]);
+        assert_eq!(operators.after.as_deref(), Some("2021-05"));
+        assert_eq!(operators.before.as_deref(), Some("2023"));
+        assert_eq!(operators.excluded, ["forum"]);
+    }
+
+    #[test]
+    fn excludes_phrases_and_other_fields() {
+        let (query, operators) = extract_operators(r#"async -"green threads" -title:tokio"#);
+        assert_eq!(query, "async");
+        assert_eq!(operators.excluded, [r#""green threads""#, "title:tokio"]);
+    }
+
+    #[test]
+    fn keeps_the_minus_signs_inside_words_and_phrases() {
+        let (query, operators) = extract_operators(r#"foo-bar "a -b site:docs.rs" - c"#);
+        assert_eq!(query, r#"foo-bar "a -b site:docs.rs" - c"#);
+        assert!(operators.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_fields_that_are_not_operators() {
+        assert!(!is_operator("title:tokio"));
+        assert!(!is_operator("site:"));
+        assert!(!is_operator("-"));
+        assert!(is_operator("-title:tokio"));
+        assert!(is_operator("After:2021"));
+    }
+
+    #[test]
+    fn splits_outside_of_the_phrases() {
+        assert_eq!(
+            split_tokens(r#"  rust "borrow  checker"  -"moved value" "#),
+            ["rust", r#""borrow  checker""#, r#"-"moved value""#]
+        );
+        assert_eq!(
+            split_tokens(r#"unbalanced "quote here"#),
+            ["unbalanced", r#""quote here"#]
+        );
+    }
+}
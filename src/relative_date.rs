@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+
+/// Describe how long ago the date was, like "3 weeks ago", rounding down to the largest unit
+pub fn relative_date(date: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let elapsed = now.signed_duration_since(date);
+    let plural = |count: i64, unit: &str| {
+        if count == 1 {
+            format!("1 {} ago", unit)
+        } else {
+            format!("{} {}s ago", count, unit)
+        }
+    };
+
+    if elapsed.num_seconds() < -60 {
+        "in the future".to_string()
+    } else if elapsed.num_minutes() < 1 {
+        "just now".to_string()
+    } else if elapsed.num_hours() < 1 {
+        plural(elapsed.num_minutes(), "minute")
+    } else if elapsed.num_days() < 1 {
+        plural(elapsed.num_hours(), "hour")
+    } else if elapsed.num_days() < 2 {
+        "yesterday".to_string()
+    } else if elapsed.num_days() < 7 {
+        plural(elapsed.num_days(), "day")
+    } else if elapsed.num_days() < 30 {
+        plural(elapsed.num_weeks(), "week")
+    } else if elapsed.num_days() < 365 {
+        plural(elapsed.num_days() / 30, "month")
+    } else {
+        plural(elapsed.num_days() / 365, "year")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn ago(elapsed: Duration) -> String {
+        let now = crate::test_fixtures::date(2023, 7, 14);
+        relative_date(now - elapsed, now)
+    }
+
+    #[test]
+    fn rounds_down_to_the_largest_unit() {
+        assert_eq!(ago(Duration::seconds(59)), "just now");
+        assert_eq!(ago(Duration::minutes(1)), "1 minute ago");
+        assert_eq!(ago(Duration::minutes(59)), "59 minutes ago");
+        assert_eq!(ago(Duration::minutes(61)), "1 hour ago");
+        assert_eq!(ago(Duration::hours(23)), "23 hours ago");
+        assert_eq!(ago(Duration::hours(24)), "yesterday");
+        assert_eq!(ago(Duration::hours(47)), "yesterday");
+        assert_eq!(ago(Duration::days(2)), "2 days ago");
+        assert_eq!(ago(Duration::days(6)), "6 days ago");
+        assert_eq!(ago(Duration::days(7)), "1 week ago");
+        assert_eq!(ago(Duration::days(29)), "4 weeks ago");
+        assert_eq!(ago(Duration::days(30)), "1 month ago");
+        assert_eq!(ago(Duration::days(364)), "12 months ago");
+        assert_eq!(ago(Duration::days(365)), "1 year ago");
+        assert_eq!(ago(Duration::days(3 * 365 + 100)), "3 years ago");
+    }
+
+    #[test]
+    fn tolerates_a_clock_skew() {
+        assert_eq!(ago(Duration::seconds(-30)), "just now");
+        assert_eq!(ago(Duration::seconds(-61)), "in the future");
+    }
+}
@@ -0,0 +1,175 @@
+use crate::search::{open_indexes, run_search, SearchArguments, SearchQuery};
+use crate::search_output::SearchResults;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use anyhow::Context;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// How many results are checked for the queries that don't tell
+const DEFAULT_TOP: usize = 10;
+
+#[derive(Args, Debug)]
+#[command(
+    after_help = "The golden queries are read from \"golden_queries.toml\" in the data \
+directory, like:
+
+  [[queries]]
+  query = \"borrow checker\"
+  # The URLs must be in the first 5 results, in any order
+  top = 5
+  urls = [\"https://doc.rust-lang.org/book/ch04-02-references-and-borrowing.html\"]
+  # Optional, like in the command line of search
+  options = [\"--site=doc.rust-lang.org\"]
+
+Examples:
+  Write the queries without URLs, then record their current results:
+    mind-search relevance-test --record
+
+  Check that a change of the tokenizers or the boosts didn't lose a result:
+    mind-search index-contents && mind-search relevance-test"
+)]
+pub struct RelevanceTestArguments {
+    /// Replace the URLs of each golden query with its current top results, instead of checking
+    /// them
+    #[arg(long)]
+    record: bool,
+    /// The name of the index to search, unless the options of a query tell otherwise
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct GoldenQueries {
+    #[serde(default)]
+    queries: Vec<GoldenQuery>,
+}
+
+/// A query and the pages it must find
+#[derive(Deserialize, Serialize)]
+struct GoldenQuery {
+    query: String,
+    /// How many of the first results the URLs must be in
+    #[serde(default = "default_top")]
+    top: usize,
+    #[serde(default)]
+    urls: Vec<String>,
+    /// The search options as they are written in the command line, like "--site=docs.rs"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    options: Vec<String>,
+}
+
+/// Run the golden queries and tell which ones lost a result, or record their current results
+pub fn relevance_test(
+    arguments: RelevanceTestArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let path = data_paths.golden_queries();
+    if !path.exists() {
+        anyhow::bail!(
+            "there are no golden queries, write them in {} (see --help)",
+            path.display()
+        );
+    }
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut golden_queries: GoldenQueries =
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))?;
+    if golden_queries.queries.is_empty() {
+        anyhow::bail!("there are no golden queries in {}", path.display());
+    }
+
+    let mut failed = 0;
+    for golden_query in &mut golden_queries.queries {
+        let results = search_golden_query(golden_query, &arguments.index_name, data_paths)
+            .with_context(|| format!("failed to search for {:?}", golden_query.query))?;
+        let found: Vec<String> = results.hits.into_iter().map(|hit| hit.url).collect();
+
+        if arguments.record {
+            golden_query.urls = found;
+            continue;
+        }
+        let missing = golden_query
+            .urls
+            .iter()
+            .filter(|url| !found.contains(url))
+            .count();
+        if missing == 0 {
+            println!("PASS {:?}", golden_query.query);
+            continue;
+        }
+        failed += 1;
+        println!(
+            "FAIL {:?}: {} of {} URLs are not in the top {}",
+            golden_query.query,
+            missing,
+            golden_query.urls.len(),
+            golden_query.top
+        );
+        print_ranking_diff(&golden_query.urls, &found);
+    }
+
+    if arguments.record {
+        fs::write(&path, toml::to_string(&golden_queries)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        println!(
+            "Recorded the results of {} queries in {}",
+            golden_queries.queries.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} golden queries failed",
+            failed,
+            golden_queries.queries.len()
+        );
+    }
+    println!("All {} golden queries passed", golden_queries.queries.len());
+    Ok(())
+}
+
+/// Search like the search command does, with the same parsing of the options and the same code,
+/// so that the results are the ones a search would show
+fn search_golden_query(
+    golden_query: &GoldenQuery,
+    index_name: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<SearchResults> {
+    let index_option = format!("--index-name={}", index_name);
+    let limit_option = format!("--limit={}", golden_query.top);
+    // The options of the query win over the index name, and the top of the query over their
+    // limit. The snippets are not needed.
+    let options = [index_option.as_str(), "--quiet"]
+        .into_iter()
+        .chain(golden_query.options.iter().map(String::as_str))
+        .chain([limit_option.as_str()]);
+    let arguments = SearchArguments::parse_options(options)?;
+    let indexes = open_indexes(&arguments, data_paths)?;
+    run_search(
+        &indexes,
+        &SearchQuery::Text(golden_query.query.clone()),
+        &arguments,
+        data_paths,
+    )
+}
+
+/// Print the expected URLs with their rank now, then the results that were not expected
+fn print_ranking_diff(expected: &[String], found: &[String]) {
+    for url in expected {
+        match found.iter().position(|found_url| found_url == url) {
+            Some(position) => println!("    {:>3}  {}", position + 1, url),
+            None => println!("  - ---  {}", url),
+        }
+    }
+    for (position, url) in found.iter().enumerate() {
+        if !expected.contains(url) {
+            println!("  + {:>3}  {}", position + 1, url);
+        }
+    }
+}
+
+fn default_top() -> usize {
+    DEFAULT_TOP
+}
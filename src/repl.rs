@@ -0,0 +1,83 @@
+use crate::clipboard::copy_url;
+use crate::open_url::open_url;
+use crate::search::{run_search, OpenedIndex, SearchArguments, SearchQuery};
+use crate::search_output::SearchResults;
+use crate::DataPaths;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HELP: &str = "Type a query to search, or one of the commands:
+  :limit N    show N results per query
+  :site SITE  only show pages of this site, or of all sites without SITE
+  :open N     open the result N of the last query in the browser
+  :copy N     copy the URL of the result N of the last query
+  :quit       leave";
+
+/// Read queries from the prompt until the user leaves, keeping the indexes open between them
+pub fn run_repl(
+    indexes: &[OpenedIndex],
+    mut arguments: SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let formatter = arguments.formatter();
+    let mut editor = DefaultEditor::new()?;
+    let mut last_results: Option<SearchResults> = None;
+
+    println!("{}", HELP);
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // Ctrl+C discards the current line, like in a shell
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        match line.strip_prefix(':') {
+            Some(command) => {
+                let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+                let argument = argument.trim();
+                match name {
+                    "limit" => match argument.parse() {
+                        Ok(limit) => arguments.limit = limit,
+                        Err(_) => println!("Invalid limit {:?}", argument),
+                    },
+                    "site" if argument.is_empty() => arguments.site = None,
+                    "site" => arguments.site = Some(argument.to_string()),
+                    "open" | "copy" => {
+                        let hit = last_results
+                            .as_ref()
+                            .and_then(|results| results.hit_by_rank(argument.parse().ok()?));
+                        match hit {
+                            Some(hit) if name == "open" => open_url(&hit.url),
+                            Some(hit) => copy_url(&hit.url),
+                            None => println!("No result {:?} in the last query", argument),
+                        }
+                    }
+                    "quit" | "q" => break,
+                    _ => println!("{}", HELP),
+                }
+            }
+            None => match run_search(
+                indexes,
+                &SearchQuery::Text(line.to_string()),
+                &arguments,
+                data_paths,
+            ) {
+                Ok(results) => {
+                    formatter.print(&results)?;
+                    last_results = Some(results);
+                }
+                // A typo in the query should not end the session
+                Err(error) => println!("Error: {}", error),
+            },
+        }
+    }
+
+    Ok(())
+}
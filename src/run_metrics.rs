@@ -0,0 +1,208 @@
+use crate::index_stats::format_size;
+use crate::run_report::{Outcome, RunReport, StageReport, REPORT_VERSION};
+use crate::sync::format_elapsed;
+use crate::DataPaths;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// What a command did and what it cost, printed at its end and optionally kept in `runs.log`
+#[derive(Debug)]
+pub struct RunMetrics {
+    command: String,
+    started_at: DateTime<Utc>,
+    start: Instant,
+    /// What the command mainly processes, like "pages fetched", and how many
+    items: Option<(&'static str, usize)>,
+    /// The other counters, in the order they were added
+    counters: Vec<(&'static str, usize)>,
+    /// What the stages did, for the commands that run some
+    stages: Vec<StageReport>,
+    /// How the command ended, when the command decides it rather than its stages
+    outcome: Option<Outcome>,
+}
+
+/// One line of `runs.log`
+#[derive(Serialize)]
+struct RunRecord<'a> {
+    command: &'a str,
+    started_at: DateTime<Utc>,
+    succeeded: bool,
+    elapsed_seconds: f64,
+    items: Option<&'static str>,
+    items_per_second: Option<f64>,
+    counters: BTreeMap<&'static str, usize>,
+    bytes_read: Option<u64>,
+    bytes_written: Option<u64>,
+    peak_memory_bytes: Option<u64>,
+}
+
+/// The resources used by the process so far, only known on Linux
+#[derive(Default)]
+struct ResourceUsage {
+    bytes_read: Option<u64>,
+    bytes_written: Option<u64>,
+    peak_memory_bytes: Option<u64>,
+}
+
+impl RunMetrics {
+    pub fn start(command: &str) -> Self {
+        RunMetrics {
+            command: command.to_string(),
+            started_at: Utc::now(),
+            start: Instant::now(),
+            items: None,
+            counters: Vec::new(),
+            stages: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    /// Set what the command mainly processes, which also gives the rate
+    pub fn processed(&mut self, items: &'static str, count: usize) {
+        self.items = Some((items, count));
+    }
+
+    /// Add a counter specific to a stage, like the pages that failed to download
+    pub fn count(&mut self, counter: &'static str, count: usize) {
+        self.counters.push((counter, count));
+    }
+
+    /// Add what a stage did, which decides the outcome of the command
+    pub fn add_stage(&mut self, stage: StageReport) {
+        self.stages.push(stage);
+    }
+
+    /// Set how the command ended, for the commands without stages
+    pub fn set_outcome(&mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+    }
+
+    /// How long the command has been running
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// How the command ended, given its result
+    pub fn outcome(&self, result: &anyhow::Result<()>) -> Outcome {
+        match result {
+            Ok(()) => self.outcome.unwrap_or_else(|| Outcome::of(&self.stages)),
+            Err(_) => Outcome::Failure,
+        }
+    }
+
+    /// The report written with `--report`
+    pub fn report(&self, result: &anyhow::Result<()>) -> RunReport {
+        RunReport {
+            version: REPORT_VERSION,
+            command: self.command.clone(),
+            started_at: self.started_at,
+            elapsed_seconds: self.elapsed().as_secs_f64(),
+            outcome: self.outcome(result),
+            stages: self.stages.clone(),
+            error: result.as_ref().err().map(|error| format!("{:#}", error)),
+        }
+    }
+
+    /// Print the summary and, with `record`, append it to `runs.log` in the data directory
+    pub fn finish(
+        self,
+        succeeded: bool,
+        record: bool,
+        data_paths: &DataPaths,
+    ) -> anyhow::Result<()> {
+        let elapsed = self.start.elapsed();
+        let usage = ResourceUsage::read();
+        let items_per_second = self
+            .items
+            .filter(|_| !elapsed.is_zero())
+            .map(|(_, count)| count as f64 / elapsed.as_secs_f64());
+
+        let mut parts = Vec::new();
+        if let Some((items, count)) = self.items {
+            let mut part = format!("{} {}", count, items);
+            if let Some(rate) = items_per_second {
+                part.push_str(&format!(" ({:.1}/s)", rate));
+            }
+            parts.push(part);
+        }
+        for (counter, count) in &self.counters {
+            parts.push(format!("{} {}", count, counter));
+        }
+        if let Some(bytes) = usage.bytes_read {
+            parts.push(format!("read {}", format_size(bytes)));
+        }
+        if let Some(bytes) = usage.bytes_written {
+            parts.push(format!("wrote {}", format_size(bytes)));
+        }
+        if let Some(bytes) = usage.peak_memory_bytes {
+            parts.push(format!("peak memory {}", format_size(bytes)));
+        }
+        info!(
+            "{} {} in {}{}{}",
+            self.command,
+            if succeeded { "finished" } else { "failed" },
+            format_elapsed(elapsed),
+            if parts.is_empty() { "" } else { ": " },
+            parts.join(", ")
+        );
+
+        if record {
+            let mut counters: BTreeMap<_, _> = self.counters.iter().copied().collect();
+            counters.extend(self.items);
+            let run_record = RunRecord {
+                command: &self.command,
+                started_at: self.started_at,
+                succeeded,
+                elapsed_seconds: elapsed.as_secs_f64(),
+                items: self.items.map(|(items, _)| items),
+                items_per_second,
+                counters,
+                bytes_read: usage.bytes_read,
+                bytes_written: usage.bytes_written,
+                peak_memory_bytes: usage.peak_memory_bytes,
+            };
+            let mut line = serde_json::to_string(&run_record)?;
+            line.push('\n');
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(data_paths.runs_log())?;
+            // A single write, so that the lines of concurrent runs don't mix
+            file.write_all(line.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ResourceUsage {
+    /// The bytes are the ones given to the read and write calls, so they include the network and
+    /// the reads served by the page cache
+    fn read() -> Self {
+        let mut usage = ResourceUsage::default();
+        if let Ok(io) = fs::read_to_string("/proc/self/io") {
+            usage.bytes_read = find_value(&io, "rchar:");
+            usage.bytes_written = find_value(&io, "wchar:");
+        }
+        if let Ok(status) = fs::read_to_string("/proc/self/status") {
+            usage.peak_memory_bytes = find_value(&status, "VmHWM:").map(|kb| kb * 1024);
+        }
+        usage
+    }
+}
+
+/// Read the number after the label in a file of /proc, like "VmHWM:    1234 kB"
+fn find_value(contents: &str, label: &str) -> Option<u64> {
+    let line = contents.lines().find(|line| line.starts_with(label))?;
+    line[label.len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
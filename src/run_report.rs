@@ -0,0 +1,239 @@
+use crate::download_pages::DownloadSummary;
+use crate::extract_firefox_history::ExtractSummary;
+use crate::index_contents::IndexSummary;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The version of the report schema. It changes when a field is removed or changes meaning, not
+/// when one is added.
+pub const REPORT_VERSION: u32 = 1;
+
+#[derive(Args, Debug)]
+pub struct ReportArguments {
+    /// Write what the command did as a JSON document into this file, for scripts
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+/// How a command ended, which also decides the exit code
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    /// The command found nothing new to work on
+    NothingToDo,
+    /// The command completed, but skipped some of its work, like unreadable bundles
+    Warnings,
+    /// The search found nothing, with --count or --quiet
+    NoMatches,
+    Failure,
+}
+
+/// What a command did, written with `--report`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RunReport {
+    pub version: u32,
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_seconds: f64,
+    pub outcome: Outcome,
+    pub stages: Vec<StageReport>,
+    /// The error that stopped the command
+    pub error: Option<String>,
+}
+
+/// What one stage did, like the download of the pages
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StageReport {
+    /// "extract", "download" or "index"
+    pub name: String,
+    pub elapsed_seconds: f64,
+    /// Whether the stage brought data that was not there before
+    pub new_data: bool,
+    pub counts: BTreeMap<String, usize>,
+    pub warnings: Vec<String>,
+    /// The items that failed, by the kind of failure
+    pub failures: BTreeMap<String, usize>,
+    /// The pages downloaded with the profile of each domain of the configuration file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, usize>,
+}
+
+impl Outcome {
+    /// A failure is an error rather than an outcome of the stages
+    pub fn of(stages: &[StageReport]) -> Self {
+        if stages.iter().any(|stage| !stage.warnings.is_empty()) {
+            Outcome::Warnings
+        } else if !stages.is_empty() && !stages.iter().any(|stage| stage.new_data) {
+            Outcome::NothingToDo
+        } else {
+            Outcome::Success
+        }
+    }
+}
+
+impl RunReport {
+    /// Write the report, replacing the file
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+impl StageReport {
+    pub fn extract(summary: &ExtractSummary, elapsed: Duration) -> Self {
+        let mut report = StageReport::new("extract", elapsed, summary.new_urls > 0);
+        report.count("urls", summary.urls);
+        report.count("new_urls", summary.new_urls);
+        report
+    }
+
+    pub fn download(summary: &DownloadSummary, elapsed: Duration) -> Self {
+        let attempted = summary.downloaded + summary.failed;
+        let mut report = StageReport::new("download", elapsed, attempted > 0);
+        report.count("downloaded", summary.downloaded);
+        report.count("failed", summary.failed);
+        report.count("already_downloaded", summary.already_downloaded);
+        report.count("skipped_equivalent", summary.skipped_equivalent);
+        report.count("retried", summary.retried);
+        for (kind, failed) in &summary.failures_by_kind {
+            report.failures.insert(kind.to_string(), *failed);
+        }
+        report.profiles = summary.pages_by_profile.clone();
+        report
+    }
+
+    pub fn index(summary: &IndexSummary, elapsed: Duration) -> Self {
+        let mut report = StageReport::new("index", elapsed, summary.indexed_pages > 0);
+        report.count("indexed_pages", summary.indexed_pages);
+        report.count("skipped_interstitials", summary.skipped_interstitials);
+        report.count("skipped_versions", summary.skipped_versions);
+        report.count("skipped_empty", summary.skipped_empty);
+        report.count("unreadable_bundles", summary.unreadable_bundles);
+        report.count("peak_in_flight_pages", summary.peak_in_flight_pages);
+        if summary.unreadable_bundles > 0 {
+            report.warnings.push(format!(
+                "skipped {} unreadable bundles",
+                summary.unreadable_bundles
+            ));
+        }
+        report
+    }
+
+    fn new(name: &str, elapsed: Duration, new_data: bool) -> Self {
+        StageReport {
+            name: name.to_string(),
+            elapsed_seconds: elapsed.as_secs_f64(),
+            new_data,
+            counts: BTreeMap::new(),
+            warnings: Vec::new(),
+            failures: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    fn count(&mut self, name: &str, count: usize) {
+        self.counts.insert(name.to_string(), count);
+    }
+
+    /// Like "12 downloaded, 3 failed", to print it
+    pub fn describe_counts(&self) -> String {
+        let counts: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(name, count)| format!("{} {}", count, name.replace('_', " ")))
+            .collect();
+        counts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::date;
+
+    fn index_stage(indexed_pages: usize, unreadable_bundles: usize) -> StageReport {
+        let summary = IndexSummary {
+            indexed_pages,
+            unreadable_bundles,
+            ..IndexSummary::default()
+        };
+        StageReport::index(&summary, Duration::from_millis(1500))
+    }
+
+    #[test]
+    fn reads_what_it_wrote() {
+        let mut download = StageReport::new("download", Duration::from_secs(3), true);
+        download.count("downloaded", 12);
+        download.failures.insert("timeout".to_string(), 2);
+        download.profiles.insert("docs.rs".to_string(), 4);
+        let report = RunReport {
+            version: REPORT_VERSION,
+            command: "sync".to_string(),
+            started_at: date(2023, 7, 14),
+            elapsed_seconds: 4.5,
+            outcome: Outcome::Warnings,
+            stages: vec![download, index_stage(10, 1)],
+            error: None,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        report.write(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains(r#""outcome": "warnings""#));
+        assert_eq!(serde_json::from_str::<RunReport>(&content).unwrap(), report);
+    }
+
+    #[test]
+    fn omits_the_empty_profiles() {
+        let json = serde_json::to_value(index_stage(1, 0)).unwrap();
+        assert!(json.get("profiles").is_none());
+        let stage: StageReport = serde_json::from_value(json).unwrap();
+        assert!(stage.profiles.is_empty());
+    }
+
+    #[test]
+    fn decides_the_outcome_of_the_stages() {
+        let extract = |new_urls| {
+            let summary = ExtractSummary { urls: 5, new_urls };
+            StageReport::extract(&summary, Duration::ZERO)
+        };
+
+        assert_eq!(Outcome::of(&[]), Outcome::Success);
+        assert_eq!(Outcome::of(&[index_stage(3, 0)]), Outcome::Success);
+        assert_eq!(Outcome::of(&[index_stage(0, 0)]), Outcome::NothingToDo);
+        assert_eq!(
+            Outcome::of(&[extract(0), index_stage(0, 0)]),
+            Outcome::NothingToDo
+        );
+        // Some new data is enough
+        assert_eq!(
+            Outcome::of(&[extract(2), index_stage(0, 0)]),
+            Outcome::Success
+        );
+        // Warnings win, even without new data
+        assert_eq!(Outcome::of(&[index_stage(3, 1)]), Outcome::Warnings);
+        assert_eq!(
+            Outcome::of(&[extract(0), index_stage(0, 2)]),
+            Outcome::Warnings
+        );
+    }
+
+    #[test]
+    fn describes_the_counts() {
+        let mut stage = StageReport::new("download", Duration::ZERO, true);
+        stage.count("downloaded", 12);
+        stage.count("already_downloaded", 3);
+        assert_eq!(
+            stage.describe_counts(),
+            "3 already downloaded, 12 downloaded"
+        );
+    }
+}
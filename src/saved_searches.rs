@@ -0,0 +1,159 @@
+use crate::search::SearchArguments;
+use crate::{read_readable_json, write_readable_json, DataPaths};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+
+/// A query and the search options to run it with
+#[derive(Deserialize, Serialize)]
+struct SavedSearch {
+    query: String,
+    /// The options as they are written in the command line, like "--site=docs.rs"
+    options: Vec<String>,
+    last_used: Option<DateTime<Utc>>,
+}
+
+/// Save the query and the options under the name, asking before replacing another search
+pub fn save_search(
+    name: String,
+    query: String,
+    options: Vec<String>,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    // Fail now instead of when the search is replayed
+    SearchArguments::parse_options(options.iter().map(String::as_str))?;
+    if options
+        .iter()
+        .any(|option| option.starts_with("--saved") || option == "--stdin")
+    {
+        anyhow::bail!("--saved and --stdin can't be saved");
+    }
+
+    let mut saved_searches = read_saved_searches(data_paths)?;
+    if let Some(saved_search) = saved_searches.get(&name) {
+        print!(
+            "The search {:?} already exists, for {:?} {}. Replace it? [y/N] ",
+            name,
+            saved_search.query,
+            saved_search.options.join(" ")
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Kept the existing search");
+            return Ok(());
+        }
+    }
+
+    saved_searches.insert(
+        name.clone(),
+        SavedSearch {
+            query,
+            options,
+            last_used: None,
+        },
+    );
+    write_saved_searches(&saved_searches, data_paths)?;
+    println!("Saved, run it with: mind-search search --saved {}", name);
+    Ok(())
+}
+
+pub fn list_saved(data_paths: &DataPaths) -> anyhow::Result<()> {
+    let saved_searches = read_saved_searches(data_paths)?;
+    if saved_searches.is_empty() {
+        println!("No saved searches, add one with save-search");
+    }
+    for (name, saved_search) in saved_searches {
+        println!(
+            "{}: {:?} {}",
+            name,
+            saved_search.query,
+            saved_search.options.join(" ")
+        );
+        match saved_search.last_used {
+            None => println!("  Last used: never"),
+            Some(last_used) => println!("  Last used: {}", last_used),
+        }
+    }
+    Ok(())
+}
+
+/// Return the query and the arguments of the saved search, with the other options of the command
+/// line applied on top of the saved ones
+pub fn replay_saved_search(
+    name: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<(String, SearchArguments)> {
+    let mut saved_searches = read_saved_searches(data_paths)?;
+    let saved_search = saved_searches.get_mut(name).with_context(|| {
+        format!(
+            "there is no saved search named {:?}, see them with list-saved",
+            name
+        )
+    })?;
+
+    let mut options = saved_search.options.clone();
+    options.extend(command_line_options());
+    let arguments = SearchArguments::parse_options(options.iter().map(String::as_str))?;
+    let query = saved_search.query.clone();
+
+    saved_search.last_used = Some(Utc::now());
+    write_saved_searches(&saved_searches, data_paths)?;
+
+    Ok((query, arguments))
+}
+
+/// The options given to the search command, without the one that chose the saved search and the
+/// global ones
+fn command_line_options() -> Vec<String> {
+    let mut arguments = std::env::args().skip(1);
+    let mut options = Vec::new();
+    let mut found_command = false;
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--saved" | "--data-dir" | "--log-format" => {
+                arguments.next();
+            }
+            _ if ["--saved=", "--data-dir=", "--log-format="]
+                .iter()
+                .any(|prefix| argument.starts_with(prefix)) => {}
+            // The verbosity of the logs, like "-vv"
+            "--quiet-logs" => {}
+            _ if argument.len() > 1
+                && argument.starts_with('-')
+                && argument[1..].chars().all(|c| c == 'v' || c == 'q') => {}
+            // Only global options can come before the "search" command
+            _ if !found_command => found_command = true,
+            _ => options.push(argument),
+        }
+    }
+    options
+}
+
+/// The names of the saved searches, in alphabetical order
+pub fn saved_search_names(data_paths: &DataPaths) -> anyhow::Result<Vec<String>> {
+    Ok(read_saved_searches(data_paths)?.into_keys().collect())
+}
+
+fn read_saved_searches(data_paths: &DataPaths) -> anyhow::Result<BTreeMap<String, SavedSearch>> {
+    let path = data_paths.saved_searches();
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    read_readable_json(&path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn write_saved_searches(
+    saved_searches: &BTreeMap<String, SavedSearch>,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let path = data_paths.saved_searches();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_readable_json(&path, saved_searches)
+}
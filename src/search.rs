@@ -1,66 +1,2798 @@
-use crate::TANTIVY_INDEX_DIR_PATH;
+use crate::clipboard::copy_url;
+use crate::domain::registrable_domain;
+use crate::export::{export_html, export_markdown};
+use crate::index_contents::{clamped_index_date, folder_facet};
+use crate::normalize_text::normalize_text;
+use crate::normalize_url::{normalize_history_url, normalize_url};
+use crate::open_url::open_url;
+use crate::parse_date::parse_date;
+use crate::query_operators::{extract_operators, QueryOperators};
+use crate::repl::run_repl;
+use crate::run_report::Outcome;
+use crate::saved_searches::replay_saved_search;
+use crate::search_output::{
+    json_results, search_formatter, Correction, CountFormatter, DisplayOptions, SearchFormat,
+    SearchFormatter, SearchResults, UrlsFormatter,
+};
+use crate::simhash::{collapse_near_duplicates, is_near_duplicate};
+use crate::similar::{similar_page, SimilarPage};
+use crate::snippets::{highlight_whole_text, HitSnippet, HitSnippetGenerator};
+use crate::spelling::correct_query;
+use crate::synonyms::Synonyms;
+use crate::timeline::{fill_months, Month, TimelineCollector, TimelineHit, TimelineMonth};
+use crate::{DataPaths, MissingStep, DEFAULT_INDEX_NAME};
 use anyhow::Context;
-use chrono::{TimeZone, Utc};
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::{Index, SnippetGenerator};
+use chrono::{Duration, Months, TimeZone, Utc};
+use clap::{Args, Parser, ValueEnum};
+use reqwest::Url;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, BufRead};
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::time::Instant;
+use tantivy::collector::{Count, CustomScorer, CustomSegmentScorer, FacetCollector, TopDocs};
+use tantivy::columnar::Column;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, EmptyQuery, Occur, Query, QueryParser, RangeQuery,
+    TermQuery,
+};
+use tantivy::query_grammar::{self, UserInputAst, UserInputLeaf};
+use tantivy::schema::{Facet, Field, IndexRecordOption, Schema};
+use tantivy::{
+    DateTime, DocAddress, DocId, DocSet, Index, IndexReader, ReloadPolicy, Score, Searcher,
+    SegmentReader, Term,
+};
 
-pub fn search(query: String) -> anyhow::Result<()> {
-    let index = Index::open_in_dir(TANTIVY_INDEX_DIR_PATH)?;
+#[derive(Args, Debug)]
+pub struct SearchArguments {
+    /// Only show pages published on or after this date, like "2021-05-12"
+    #[arg(long)]
+    published_after: Option<String>,
+    /// Only show pages last visited on or after this date, like "2021-05-12"
+    #[arg(long)]
+    after: Option<String>,
+    /// Only show pages last visited before this date, like "2021-05-12"
+    #[arg(long)]
+    before: Option<String>,
+    /// Only show pages last visited in this recent period, in days, weeks, months or years, like
+    /// "7d", "3w", "6m" or "2y"
+    #[arg(long)]
+    last: Option<String>,
+    /// Hide pages with fewer words than this, like stubs and redirect pages
+    #[arg(long)]
+    min_words: Option<u64>,
+    /// The name of the index to search in
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Search in all the indexes and merge their results by score
+    #[arg(long, conflicts_with = "index_name")]
+    all_indexes: bool,
+    /// Also print how many matches were last visited in each year and month
+    #[arg(long)]
+    facet_counts: bool,
+    /// Also print how many matches were last visited in each month, as a bar chart with the best
+    /// matches of each month
+    #[arg(long)]
+    timeline: bool,
+    /// Only show pages last visited in this year ("2023") or month ("2023-07")
+    #[arg(long)]
+    period: Option<String>,
+    /// Hide results that are nearly identical to a better ranked one, like the same article with
+    /// different ads
+    #[arg(long)]
+    collapse_near_duplicates: bool,
+    /// Show the snapshots of the same page under different URLs as separate results, like with and
+    /// without tracking parameters, and the older versions kept by index-contents --keep-versions.
+    /// By default, only the most recently visited and downloaded one is shown
+    #[arg(long)]
+    show_duplicates: bool,
+    /// Only show the versions of this URL kept by index-contents --keep-versions, from the newest
+    /// download. Without a query, all of them are shown
+    #[arg(long)]
+    versions: Option<String>,
+    /// Search the pages as they were downloaded by this date, like "2022-06-01": only the versions
+    /// downloaded before it are searched, showing the newest of each page
+    #[arg(long)]
+    as_of: Option<String>,
+    /// Only show pages of this site, like "docs.rs"
+    #[arg(long)]
+    pub site: Option<String>,
+    /// Only show pages whose URL starts with this, like "docs.rs/tokio/". Without a query, all
+    /// the pages under it are shown, from the most recently visited
+    #[arg(long)]
+    under: Option<String>,
+    /// Only show bookmarked pages. Without a query, all the bookmarked pages are shown, from the
+    /// most recently visited
+    #[arg(long)]
+    bookmarked: bool,
+    /// Only show pages bookmarked in this folder or in its subfolders, like "Rust/async". Without
+    /// a query, all the pages of the folder are shown
+    #[arg(long)]
+    folder: Option<String>,
+    /// Keep only the best results of each site, 2 by default, filling the page with results from
+    /// other sites. Ignored with --site
+    #[arg(long, num_args = 0..=1, default_missing_value = "2")]
+    collapse_domains: Option<usize>,
+    /// Group the results by site, the sites ordered by their best result, with their 3 best
+    /// results each. The limit is then the number of groups
+    #[arg(long, value_enum, conflicts_with_all = ["collapse_domains", "offset"])]
+    group_by: Option<GroupBy>,
+    /// How many results to show
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+    /// How many of the best results to skip, to see the next page of results
+    #[arg(long, default_value_t = 0)]
+    pub offset: usize,
+    /// How many chars to show around the matches of each result
+    #[arg(long, default_value_t = 150)]
+    snippet_chars: usize,
+    /// Show up to this many separate parts of the content with matches for each result
+    #[arg(long, default_value_t = 1)]
+    snippet_fragments: usize,
+    /// Print the results for humans, for humans with HTML snippets, as one JSON document or as one
+    /// JSON line per result
+    #[arg(long, value_enum, default_value_t = SearchFormat::Human)]
+    pub format: SearchFormat,
+    /// Only print how many documents match, exiting with code 1 when none does
+    #[arg(long, conflicts_with_all = ["quiet", "format", "facet_counts", "timeline", "open", "open_first", "copy"])]
+    count: bool,
+    /// Only print the URLs of the results, one per line, exiting with code 1 when none matches
+    #[arg(long, conflicts_with = "format")]
+    quiet: bool,
+    /// Only search in these fields, unless the query names others, like "title:tokio"
+    #[arg(long = "in", value_enum, value_delimiter = ',')]
+    search_in: Vec<SearchField>,
+    /// How many typos the words of at least 4 chars can have in the fuzzy fields, 0 to only match
+    /// the exact words
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+    fuzzy: u8,
+    /// The fields where the words also match with typos, when they are searched
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "content")]
+    fuzzy_fields: Vec<SearchField>,
+    /// With typos, also match the words that start with the word of the query, like "tokio" for
+    /// "toki"
+    #[arg(long)]
+    fuzzy_prefix: bool,
+    /// Print which fields are searched, and how many documents matched in how long
+    #[arg(long)]
+    verbose: bool,
+    /// Print the dates in UTC, instead of in the local time zone and relative to now
+    #[arg(long)]
+    utc: bool,
+    /// Print the relevance score of each result
+    #[arg(long)]
+    scores: bool,
+    /// Print how the score of the result with this rank was computed
+    #[arg(long)]
+    explain: Option<usize>,
+    /// Let phrases in quotes also match with up to this many words moved or in between, unless the
+    /// phrase sets its own, like `"moved value"~2`
+    #[arg(long)]
+    phrase_slop: Option<u32>,
+    /// When nothing matches, search for the query with its misspelled words corrected, instead of
+    /// only suggesting it
+    #[arg(long)]
+    auto_correct: bool,
+    /// Don't search for the synonyms of the words of the query. Synonyms are read from
+    /// "synonyms.txt" in the data directory, with one group per line like
+    /// "js, javascript, ecmascript"
+    #[arg(long)]
+    no_synonyms: bool,
+    /// Fail on invalid query syntax, instead of searching for the words of the query
+    #[arg(long)]
+    strict_syntax: bool,
+    /// Hide the results with a relevance score below this
+    #[arg(long, conflicts_with = "count")]
+    min_score: Option<f32>,
+    /// Hide the results with a relevance score below this fraction of the best score, like 0.2
+    #[arg(long, conflicts_with = "count")]
+    min_score_ratio: Option<f32>,
+    /// In which order to show the results
+    #[arg(long, value_enum, default_value_t = SortOrder::Relevance)]
+    sort: SortOrder,
+    /// How to score the results when sorting by relevance
+    #[arg(long, value_enum, default_value_t = Rank::Text)]
+    rank: Rank,
+    /// Run the saved search with this name, see save-search. Other options are applied on top of
+    /// the saved ones
+    #[arg(long)]
+    saved: Option<String>,
+    /// Read the queries from the standard input, one per line, skipping blank lines and lines
+    /// starting with "#". With --format jsonl, one JSON line is printed per query
+    #[arg(long, conflicts_with_all = ["open", "open_first", "copy"])]
+    stdin: bool,
+    /// Write the results to this standalone HTML file, grouped by site, instead of printing them
+    #[arg(long, conflicts_with_all = ["count", "quiet", "format", "stdin"])]
+    export: Option<PathBuf>,
+    /// Write the results to this Markdown file, grouped by site, instead of printing them
+    #[arg(long, conflicts_with_all = ["count", "quiet", "format", "stdin", "export"])]
+    export_md: Option<PathBuf>,
+    /// Open the result with this rank in the browser, after printing the results
+    #[arg(long)]
+    open: Option<usize>,
+    /// Open the best result in the browser, after printing the results
+    #[arg(long, conflicts_with = "open")]
+    open_first: bool,
+    /// Copy the URL of the result with this rank to the clipboard, after printing the results.
+    /// Without a clipboard, the URL is printed alone on a line
+    #[arg(long)]
+    copy: Option<usize>,
+}
+
+/// The search options alone, to read them from elsewhere than the command line. The last value of
+/// an option wins, so that saved options can be replaced.
+#[derive(Parser, Debug)]
+#[command(args_override_self = true)]
+struct SearchOptions {
+    #[command(flatten)]
+    arguments: SearchArguments,
+}
+
+/// What to look for
+pub enum SearchQuery {
+    /// Words in the query language
+    Text(String),
+    /// Pages with the same vocabulary as an indexed page
+    Similar(SimilarPage),
+    /// All the pages, to be narrowed down by the filters
+    All,
+}
+
+impl SearchQuery {
+    /// A title for the results of the query
+    fn describe(&self) -> String {
+        match self {
+            SearchQuery::Text(text) => text.clone(),
+            SearchQuery::Similar(similar_page) => format!("Pages similar to {}", similar_page.url),
+            SearchQuery::All => "All pages".to_string(),
+        }
+    }
+}
+
+/// What was found in one index
+struct IndexSearchResults {
+    hits: Vec<SearchHit>,
+    /// How many documents match the query in total
+    total_matches: usize,
+    /// How many matches were last visited in each period, like "2023" or "2023-07"
+    facet_counts: BTreeMap<String, u64>,
+    /// The matches by the month of their last visit
+    timeline: BTreeMap<Month, TimelineMonth>,
+    /// Whether the query syntax was invalid and the query was searched as plain words
+    syntax_ignored: bool,
+}
+
+/// A search result, with all the information needed to display it
+pub struct SearchHit {
+    pub index_name: String,
+    /// The relevance score, unknown when sorting by date
+    pub score: Option<f32>,
+    pub url: String,
+    pub title: Option<String>,
+    /// A title built from the URL, when the page has no real one
+    pub synthetic_title: Option<String>,
+    pub last_visit: Option<chrono::DateTime<Utc>>,
+    pub published: Option<chrono::DateTime<Utc>>,
+    pub word_count: Option<u64>,
+    simhash: Option<u64>,
+    canonical_url: Option<String>,
+    pub snippet: HitSnippet,
+    /// When the shown version of the page was downloaded, unknown for older indexes
+    pub snapshot_at: Option<chrono::DateTime<Utc>>,
+    /// The fields where the page has words of the query, like "title" and "content"
+    pub matched_fields: Vec<String>,
+    /// Whether the page only matched the words of the query with typos
+    pub fuzzy_only: bool,
+    /// The notes written about the page with the annotate command
+    pub notes: Vec<String>,
+    pub bookmarked: bool,
+    /// The folders of the bookmarks of the page, like "Rust/async"
+    pub bookmark_folders: Vec<String>,
+    /// How many more results of the same site were hidden after this one
+    pub more_from_domain: usize,
+    /// How many snapshots of the page were found under different URLs or as older versions,
+    /// including this one
+    pub snapshots: usize,
+    doc_address: DocAddress,
+}
+
+/// Limits on when the results were last visited
+#[derive(Default)]
+struct VisitRange {
+    after: Option<chrono::DateTime<Utc>>,
+    before: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SortOrder {
+    /// The best matches first
+    Relevance,
+    /// The most recently visited pages first
+    Recent,
+    /// The least recently visited pages first
+    Oldest,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GroupBy {
+    /// The registrable domain of the URL, like "docs.rs" for "https://docs.rs/tokio"
+    Domain,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Rank {
+    /// Only by how well the text matches
+    Text,
+    /// By how well the text matches, boosted for the pages that were visited many times
+    Frequent,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchField {
+    Url,
+    Title,
+    Content,
+    /// The texts of the links in the page
+    Anchors,
+    /// The notes written about the page with the annotate command
+    Notes,
+}
+
+impl SearchField {
+    pub fn field_name(self) -> &'static str {
+        match self {
+            SearchField::Url => "url",
+            SearchField::Title => "title",
+            SearchField::Content => "content",
+            SearchField::Anchors => "anchors",
+            SearchField::Notes => "notes",
+        }
+    }
+
+    /// The field in the index, which the indexes built before the notes existed don't have
+    pub fn index_field(self, schema: &Schema) -> anyhow::Result<Option<Field>> {
+        match schema.get_field(self.field_name()) {
+            Ok(field) => Ok(Some(field)),
+            Err(_) if matches!(self, SearchField::Notes) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Rank documents by a date, like their last visit, with the documents without one always last
+struct DateOrder {
+    field: &'static str,
+    newest_first: bool,
+}
+
+struct DateOrderSegmentScorer {
+    newest_first: bool,
+    dates: Column<tantivy::DateTime>,
+}
+
+impl CustomScorer<i64> for DateOrder {
+    type Child = DateOrderSegmentScorer;
+
+    fn segment_scorer(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        Ok(DateOrderSegmentScorer {
+            newest_first: self.newest_first,
+            dates: segment_reader.fast_fields().date(self.field)?,
+        })
+    }
+}
+
+impl CustomSegmentScorer<i64> for DateOrderSegmentScorer {
+    fn score(&mut self, doc: DocId) -> i64 {
+        match self.dates.first(doc) {
+            None => i64::MIN,
+            Some(date) if self.newest_first => date.into_timestamp_micros(),
+            Some(date) => -date.into_timestamp_micros(),
+        }
+    }
+}
+
+/// Multiply the scores by `1 + log2(visit_count)`, leaving the pages without a visit count as they
+/// are
+fn frequency_boost(segment_reader: &SegmentReader) -> impl FnMut(DocId, Score) -> Score {
+    let visit_counts = segment_reader.fast_fields().u64("visit_count").ok();
+    move |doc, score| {
+        let visit_count = visit_counts
+            .as_ref()
+            .and_then(|visit_counts| visit_counts.first(doc))
+            .unwrap_or(1)
+            .max(1);
+        score * (1. + (visit_count as f32).log2())
+    }
+}
+
+/// How many more candidates to fetch when the other snapshots of the same pages are going to be
+/// dropped
+const SNAPSHOT_CANDIDATES_FACTOR: usize = 2;
+/// How many more candidates to fetch when near-duplicates are going to be dropped
+const NEAR_DUPLICATE_CANDIDATES_FACTOR: usize = 5;
+/// How many more candidates to fetch when results of the same site are going to be dropped
+const DOMAIN_CANDIDATES_FACTOR: usize = 5;
+/// How many results of each group are shown, with --group-by
+const HITS_PER_GROUP: usize = 3;
+/// How much more the words of the notes count than the others, since they were written about the
+/// page on purpose
+const NOTES_BOOST: Score = 3.;
+/// The words shorter than this never match with typos
+pub const MIN_FUZZY_WORD_CHARS: usize = 4;
+
+impl SearchArguments {
+    /// Parse the options written like in the command line, like `["--site=docs.rs", "--limit=5"]`
+    pub fn parse_options<'a>(options: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+        let argv = ["search"].into_iter().chain(options);
+        // Only the first line of the error, since the rest is about the command line
+        let search_options = SearchOptions::try_parse_from(argv).map_err(|error| {
+            let message = error.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            anyhow::anyhow!("{}", first_line.trim_start_matches("error: "))
+        })?;
+        Ok(search_options.arguments)
+    }
+
+    pub fn formatter(&self) -> Box<dyn SearchFormatter> {
+        if self.count {
+            Box::new(CountFormatter)
+        } else if self.quiet {
+            Box::new(UrlsFormatter)
+        } else {
+            search_formatter(
+                self.format,
+                DisplayOptions {
+                    scores: self.scores,
+                    verbose: self.verbose,
+                    utc: self.utc,
+                },
+            )
+        }
+    }
+
+    /// Whether the filters select pages to list even without a query
+    fn lists_pages(&self) -> bool {
+        self.under.is_some() || self.bookmarked || self.folder.is_some() || self.versions.is_some()
+    }
+
+    /// Whether only one snapshot of each page is shown
+    fn collapses_snapshots(&self) -> bool {
+        !self.show_duplicates && self.versions.is_none()
+    }
+
+    /// The date that orders the results when they are not sorted by relevance: the download date
+    /// of the versions of a URL, and otherwise the last visit
+    fn order_date_field(&self) -> &'static str {
+        if self.versions.is_some() {
+            "snapshot_at"
+        } else {
+            "last_visit"
+        }
+    }
+
+    /// The fields searched when the query doesn't name one
+    pub fn search_fields(&self) -> Vec<SearchField> {
+        if self.search_in.is_empty() {
+            vec![
+                SearchField::Url,
+                SearchField::Title,
+                SearchField::Content,
+                SearchField::Anchors,
+                SearchField::Notes,
+            ]
+        } else {
+            self.search_in.clone()
+        }
+    }
+
+    /// The searched fields where words also match with typos
+    fn fuzzy_search_fields(&self) -> Vec<SearchField> {
+        if self.fuzzy == 0 {
+            return Vec::new();
+        }
+        let search_fields = self.search_fields();
+        self.fuzzy_fields
+            .iter()
+            .copied()
+            .filter(|fuzzy_field| search_fields.contains(fuzzy_field))
+            .collect()
+    }
+
+    /// How many typos the words of the content can have, and whether they match as prefixes, to
+    /// find the words of the query in a page like the search does
+    pub fn content_typos(&self) -> (u8, bool) {
+        if self.fuzzy_search_fields().contains(&SearchField::Content) {
+            (self.fuzzy, self.fuzzy_prefix)
+        } else {
+            (0, false)
+        }
+    }
+
+    /// How many results to keep of each site, if results should be collapsed by site
+    fn domains_to_collapse(&self) -> Option<usize> {
+        // Collapsing makes no sense when only one site was asked for
+        self.collapse_domains.filter(|_| self.site.is_none())
+    }
+
+    /// Whether the results are written to a file instead of printed
+    fn exports(&self) -> bool {
+        self.export.is_some() || self.export_md.is_some()
+    }
+
+    /// Whether the output shows snippets, which are the slowest part of the search to compute
+    fn needs_snippets(&self) -> bool {
+        !self.count && !self.quiet
+    }
+}
+
+/// An index opened once and then searched many times
+pub struct OpenedIndex {
+    pub name: String,
+    pub index: Index,
+    pub reader: IndexReader,
+}
+
+/// Run the search command. With --count and --quiet, finding nothing is the
+/// [Outcome::NoMatches], so that shell conditionals can use the exit code.
+pub fn search(
+    query: Option<String>,
+    mut arguments: SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Outcome> {
+    if let Some(name) = &arguments.saved {
+        if query.is_some() {
+            anyhow::bail!("--saved runs the query of the saved search, don't give one");
+        }
+        // The saved options go through the same parsing as the command line
+        let (query, arguments) = replay_saved_search(name, data_paths)?;
+        return search(Some(query), arguments, data_paths);
+    }
+
+    let indexes = open_indexes(&arguments, data_paths)?;
+    match query {
+        None if arguments.lists_pages() && !arguments.stdin => {
+            // There is no relevance without a query
+            if matches!(arguments.sort, SortOrder::Relevance) {
+                arguments.sort = SortOrder::Recent;
+            }
+            print_search(&indexes, &SearchQuery::All, &arguments, data_paths)
+        }
+        Some(_) if arguments.stdin => anyhow::bail!("--stdin reads the queries, don't give one"),
+        None if arguments.stdin => {
+            run_batch(&indexes, &arguments, data_paths)?;
+            Ok(Outcome::Success)
+        }
+        None if arguments.open.is_some() || arguments.open_first || arguments.copy.is_some() => {
+            anyhow::bail!("--open, --open-first and --copy need a query")
+        }
+        None => {
+            run_repl(&indexes, arguments, data_paths)?;
+            Ok(Outcome::Success)
+        }
+        Some(query) => print_search(&indexes, &SearchQuery::Text(query), &arguments, data_paths),
+    }
+}
+
+/// The results of one query of the standard input, or its error
+#[derive(Serialize)]
+struct BatchLine<'a, T: Serialize> {
+    query: &'a str,
+    #[serde(flatten)]
+    results: T,
+}
+
+/// Search for each query of the standard input, with the indexes opened only once. A query that
+/// fails doesn't stop the others.
+fn run_batch(
+    indexes: &[OpenedIndex],
+    arguments: &SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    let formatter = arguments.formatter();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let query = line.trim();
+        if query.is_empty() || query.starts_with('#') {
+            continue;
+        }
+
+        let results = run_search(
+            indexes,
+            &SearchQuery::Text(query.to_string()),
+            arguments,
+            data_paths,
+        );
+        match arguments.format {
+            SearchFormat::Jsonl if !arguments.count && !arguments.quiet => {
+                let line = match results {
+                    Ok(results) => serde_json::to_string(&BatchLine {
+                        query,
+                        results: json_results(&results),
+                    })?,
+                    Err(error) => serde_json::to_string(&BatchLine {
+                        query,
+                        results: serde_json::json!({ "error": error.to_string() }),
+                    })?,
+                };
+                println!("{}", line);
+            }
+            _ => {
+                println!("Query: {}", query);
+                match results {
+                    Ok(results) => formatter.print(&results)?,
+                    Err(error) => println!("Error: {}\n", error),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Search for the pages most similar to the indexed page with this URL
+pub fn similar(
+    url: &str,
+    arguments: SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Outcome> {
+    let indexes = open_indexes(&arguments, data_paths)?;
+    let similar_page = similar_page(&indexes, url, &arguments.search_fields())?;
+    if similar_page.terms.is_empty() {
+        anyhow::bail!(
+            "{} has no words in common with other pages",
+            similar_page.url
+        );
+    }
+    if arguments.verbose {
+        let terms: Vec<String> = similar_page
+            .terms
+            .iter()
+            .map(|(field_name, text)| format!("{}:{}", field_name, text))
+            .collect();
+        eprintln!("Searching for {}", terms.join(" "));
+    }
+
+    print_search(
+        &indexes,
+        &SearchQuery::Similar(similar_page),
+        &arguments,
+        data_paths,
+    )
+}
+
+pub fn open_indexes(
+    arguments: &SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Vec<OpenedIndex>> {
+    if arguments.facet_counts && matches!(arguments.format, SearchFormat::Jsonl) {
+        anyhow::bail!("--facet-counts is not available with --format jsonl, use --format json");
+    }
+    if arguments.timeline && matches!(arguments.format, SearchFormat::Jsonl) {
+        anyhow::bail!("--timeline is not available with --format jsonl, use --format json");
+    }
+
+    let index_names = if arguments.all_indexes {
+        let index_names = data_paths.list_index_names()?;
+        if index_names.is_empty() {
+            return Err(MissingStep {
+                missing: format!("no index in {}", data_paths.indexes_dir().display()),
+                command: "index-contents".to_string(),
+            }
+            .into());
+        }
+        index_names
+    } else {
+        vec![arguments.index_name.clone()]
+    };
+    let mut indexes = Vec::new();
+    for name in index_names {
+        let index = Index::open_in_dir(data_paths.built_index_dir(&name)?)?;
+        // New commits are picked up, for the indexes that stay open like in `serve`
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()?;
+        indexes.push(OpenedIndex {
+            name,
+            index,
+            reader,
+        });
+    }
+    Ok(indexes)
+}
+
+/// Search once, print the results and open the one asked for
+fn print_search(
+    indexes: &[OpenedIndex],
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Outcome> {
+    let results = run_search(indexes, query, arguments, data_paths)?;
+    if let Some(path) = &arguments.export {
+        export_html(&results, &query.describe(), path)?;
+        println!(
+            "Exported {} results to {}",
+            results.hits.len(),
+            path.display()
+        );
+    } else if let Some(path) = &arguments.export_md {
+        export_markdown(&results, &query.describe(), path)?;
+        println!(
+            "Exported {} results to {}",
+            results.hits.len(),
+            path.display()
+        );
+    } else {
+        arguments.formatter().print(&results)?;
+    }
+
+    let open_rank = match arguments.open {
+        Some(rank) => Some(rank),
+        None if arguments.open_first => Some(arguments.offset + 1),
+        None => None,
+    };
+    if let Some(rank) = open_rank {
+        match results.hit_by_rank(rank) {
+            Some(hit) => open_url(&hit.url),
+            None => anyhow::bail!("there is no result {} to open", rank),
+        }
+    }
+    if let Some(rank) = arguments.copy {
+        match results.hit_by_rank(rank) {
+            Some(hit) => copy_url(&hit.url),
+            None => anyhow::bail!("there is no result {} to copy", rank),
+        }
+    }
+
+    // Allow shell conditionals like `if mind-search search --count ...`
+    if (arguments.count || arguments.quiet) && results.total_matches == 0 {
+        Ok(Outcome::NoMatches)
+    } else {
+        Ok(Outcome::Success)
+    }
+}
+
+/// Search for the query in all the indexes and merge their results. When nothing matches, the
+/// words that are in no document are corrected, to suggest or search for the corrected query.
+pub fn run_search(
+    indexes: &[OpenedIndex],
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<SearchResults> {
+    let SearchQuery::Text(text) = query else {
+        return search_indexes(indexes, query, arguments);
+    };
+
+    let synonyms = read_synonyms(arguments, data_paths)?;
+    let search_text = |text: &str| {
+        let expanded_text = match &synonyms {
+            Some(synonyms) => synonyms.expand(text),
+            None => text.to_string(),
+        };
+        if arguments.verbose && expanded_text != text {
+            eprintln!("Searching for {}", expanded_text);
+        }
+        search_indexes(indexes, &SearchQuery::Text(expanded_text), arguments)
+    };
+
+    let mut results = search_text(text)?;
+    if results.total_matches > 0 {
+        return Ok(results);
+    }
+
+    if let Some(corrected_query) = correct_query(indexes, text, &arguments.search_fields())? {
+        if arguments.auto_correct {
+            results = search_text(&corrected_query)?;
+        }
+        results.correction = Some(Correction {
+            query: corrected_query,
+            applied: arguments.auto_correct,
+        });
+    }
+    Ok(results)
+}
+
+/// The synonyms of the data directory, unless they are disabled
+pub fn read_synonyms(
+    arguments: &SearchArguments,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Option<Synonyms>> {
+    if arguments.no_synonyms {
+        Ok(None)
+    } else {
+        Synonyms::read(&data_paths.synonyms())
+    }
+}
+
+fn search_indexes(
+    indexes: &[OpenedIndex],
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+) -> anyhow::Result<SearchResults> {
+    let start = Instant::now();
+    let visit_range = decide_visit_range(arguments, query)?;
+    check_ranking(arguments)?;
+    if arguments.verbose {
+        let field_names: Vec<&str> = arguments
+            .search_fields()
+            .iter()
+            .map(|search_field| search_field.field_name())
+            .collect();
+        let fuzzy_field_names: Vec<&str> = arguments
+            .fuzzy_search_fields()
+            .iter()
+            .map(|search_field| search_field.field_name())
+            .collect();
+        eprintln!(
+            "Searching in {}{}",
+            field_names.join(", "),
+            if !fuzzy_field_names.is_empty() && matches!(query, SearchQuery::Text(_)) {
+                format!(
+                    ", with up to {} {} allowed in the {}",
+                    arguments.fuzzy,
+                    if arguments.fuzzy == 1 {
+                        "typo"
+                    } else {
+                        "typos"
+                    },
+                    fuzzy_field_names.join(", ")
+                )
+            } else {
+                String::new()
+            }
+        );
+    }
+
+    let mut hits = Vec::new();
+    let mut total_matches = 0;
+    let mut syntax_ignored = false;
+    let mut facet_counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut timeline: BTreeMap<Month, TimelineMonth> = BTreeMap::new();
+    if arguments.count || arguments.exports() {
+        // Neither needs the facet counts nor the timeline, and the stream reads only as many
+        // matches as needed to fill the page
+        let stream = stream_search(indexes, query, arguments)?;
+        total_matches = stream.total_matches()?;
+        syntax_ignored = stream.syntax_ignored();
+        if !arguments.count {
+            let mut candidates = arguments.offset + arguments.limit;
+            if arguments.domains_to_collapse().is_some() || arguments.group_by.is_some() {
+                candidates *= DOMAIN_CANDIDATES_FACTOR;
+            }
+            hits = stream.take(candidates).collect::<anyhow::Result<_>>()?;
+        }
+    } else {
+        for index in indexes {
+            let results = search_index(index, query, arguments, &visit_range)?;
+            hits.extend(results.hits);
+            syntax_ignored |= results.syntax_ignored;
+            total_matches += results.total_matches;
+            for (period, count) in results.facet_counts {
+                *facet_counts.entry(period).or_default() += count;
+            }
+            for (month, timeline_month) in results.timeline {
+                timeline.entry(month).or_default().merge(timeline_month);
+            }
+        }
+    }
+    match arguments.sort {
+        // Scores of different indexes are not strictly comparable, but close enough to be merged
+        SortOrder::Relevance => hits.sort_by(|a, b| {
+            let score = |hit: &SearchHit| hit.score.unwrap_or_default();
+            score(b).total_cmp(&score(a))
+        }),
+        // Pages without a date go last in both orders
+        SortOrder::Recent | SortOrder::Oldest => {
+            let order_date = |hit: &SearchHit| {
+                if arguments.versions.is_some() {
+                    hit.snapshot_at
+                } else {
+                    hit.last_visit
+                }
+            };
+            if matches!(arguments.sort, SortOrder::Recent) {
+                hits.sort_by_key(|hit| (order_date(hit).is_none(), Reverse(order_date(hit))))
+            } else {
+                hits.sort_by_key(|hit| (order_date(hit).is_none(), order_date(hit)))
+            }
+        }
+    }
+    let mut below_min_score = 0;
+    if arguments.min_score.is_some() || arguments.min_score_ratio.is_some() {
+        let best_score = hits.first().and_then(|hit| hit.score).unwrap_or_default();
+        let min_score = arguments
+            .min_score
+            .unwrap_or(f32::MIN)
+            .max(best_score * arguments.min_score_ratio.unwrap_or(0.));
+        let hit_count = hits.len();
+        hits.retain(|hit| hit.score.unwrap_or_default() >= min_score);
+        below_min_score = hit_count - hits.len();
+    }
+    if arguments.collapses_snapshots() {
+        hits = collapse_snapshots(hits);
+    }
+    if arguments.collapse_near_duplicates {
+        hits = collapse_near_duplicates(hits, |hit| hit.simhash);
+    }
+    if let Some(max_per_domain) = arguments.domains_to_collapse() {
+        hits = collapse_domains(hits, max_per_domain);
+    }
+    let hits: Vec<SearchHit> = match arguments.group_by {
+        Some(GroupBy::Domain) => group_domains(hits, arguments.limit, HITS_PER_GROUP),
+        None => hits
+            .into_iter()
+            .skip(arguments.offset)
+            .take(arguments.limit)
+            .collect(),
+    };
+
+    let format_date = |date: chrono::DateTime<Utc>| date.format("%Y-%m-%d %H:%M UTC").to_string();
+    let visit_filter = match (visit_range.after, visit_range.before) {
+        (None, None) => None,
+        (Some(after), None) => Some(format!("visited on or after {}", format_date(after))),
+        (None, Some(before)) => Some(format!("visited before {}", format_date(before))),
+        (Some(after), Some(before)) => Some(format!(
+            "visited on or after {} and before {}",
+            format_date(after),
+            format_date(before)
+        )),
+    };
+
+    let explanation = match arguments.explain {
+        None => None,
+        Some(rank) => {
+            let hit = rank
+                .checked_sub(arguments.offset + 1)
+                .and_then(|position| hits.get(position))
+                .with_context(|| format!("there is no result {} to explain", rank))?;
+            let opened_index = indexes
+                .iter()
+                .find(|opened_index| opened_index.name == hit.index_name)
+                .context("missing index")?;
+            let parsed_query = parse_query(&opened_index.index, query, arguments, &visit_range)?;
+            let explanation = parsed_query
+                .query
+                .explain(&opened_index.reader.searcher(), hit.doc_address)?;
+            let explanation = name_fields(&opened_index.index, &explanation.to_pretty_json());
+            Some(serde_json::from_str(&explanation)?)
+        }
+    };
+
+    // Show how the text of the query was understood when nothing is shown
+    let parsed_query = match (indexes.first(), query) {
+        (Some(opened_index), SearchQuery::Text(text)) if hits.is_empty() => {
+            let parsed_query = parse_text_query(&opened_index.index, text, arguments)?;
+            Some(name_fields(
+                &opened_index.index,
+                &format!("{:?}", parsed_query.query),
+            ))
+        }
+        _ => None,
+    };
+
+    Ok(SearchResults {
+        elapsed: start.elapsed(),
+        explanation,
+        syntax_ignored,
+        correction: None,
+        parsed_query,
+        below_min_score,
+        visit_filter,
+        offset: arguments.offset,
+        total_matches,
+        hits,
+        facet_counts: arguments.facet_counts.then_some(facet_counts),
+        timeline: arguments.timeline.then(|| fill_months(timeline)),
+        all_indexes: arguments.all_indexes,
+        grouped: arguments.group_by.is_some(),
+        versions: arguments.versions.is_some(),
+    })
+}
+
+/// Fail when the ranking options don't apply to the sort order
+fn check_ranking(arguments: &SearchArguments) -> anyhow::Result<()> {
+    let by_relevance = matches!(arguments.sort, SortOrder::Relevance);
+    if matches!(arguments.rank, Rank::Frequent) && !by_relevance {
+        anyhow::bail!("--rank frequent needs --sort relevance");
+    }
+    if (arguments.min_score.is_some() || arguments.min_score_ratio.is_some()) && !by_relevance {
+        anyhow::bail!("--min-score and --min-score-ratio need --sort relevance");
+    }
+    Ok(())
+}
+
+/// Match the URLs that start with the prefix. Without a scheme, both "http://" and "https://" are
+/// matched.
+fn url_prefix_query(prefix: &str) -> anyhow::Result<Box<dyn Query>> {
+    let prefixes = if prefix.contains("://") {
+        // Normalized like the indexed URLs, see `extract_firefox_history()`
+        let mut parsed_prefix = Url::parse(prefix)?;
+        parsed_prefix.set_fragment(None);
+        let mut prefix = parsed_prefix.to_string();
+        // The parsing adds a slash after a bare host, which must not exclude "https://docs.rs"
+        if parsed_prefix.path() == "/" && parsed_prefix.query().is_none() {
+            prefix.pop();
+        }
+        vec![prefix]
+    } else {
+        vec![format!("https://{}", prefix), format!("http://{}", prefix)]
+    };
+
+    // The term dictionary is sorted, so the URLs with the prefix are the ones between the prefix
+    // and the prefix followed by the last char
+    let clauses = prefixes
+        .into_iter()
+        .map(|prefix| {
+            let range: Box<dyn Query> = Box::new(RangeQuery::new_str_bounds(
+                "url_exact".to_string(),
+                Bound::Included(&prefix),
+                Bound::Included(&format!("{}{}", prefix, char::MAX)),
+            ));
+            (Occur::Should, range)
+        })
+        .collect();
+    Ok(Box::new(BooleanQuery::new(clauses)))
+}
+
+/// Replace the field numbers in the description of terms with the field names
+fn name_fields(index: &Index, description: &str) -> String {
+    let mut description = description.to_string();
+    for (field, field_entry) in index.schema().fields() {
+        description = description.replace(
+            &format!("field={},", field.field_id()),
+            &format!("field={},", field_entry.name()),
+        );
+    }
+    description
+}
+
+/// The query of one index, with all the filters
+struct ParsedQuery {
+    query: Box<dyn Query>,
+    /// The text query without the fuzzy matching, to build the snippets
+    snippet_query: Box<dyn Query>,
+    /// Whether the query syntax was invalid and the query was searched as plain words
+    syntax_ignored: bool,
+}
+
+fn parse_query(
+    index: &Index,
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+    visit_range: &VisitRange,
+) -> anyhow::Result<ParsedQuery> {
     let schema = index.schema();
-    let url_field = schema.get_field("url")?;
-    let title_field = schema.get_field("title")?;
-    let last_visit_field = schema.get_field("last_visit")?;
-    let content_field = schema.get_field("content")?;
+    let visit_date_field = schema.get_field("visit_date")?;
+    let domain_field = schema.get_field("domain")?;
+
+    let ParsedQuery {
+        mut query,
+        snippet_query,
+        syntax_ignored,
+    } = match query {
+        SearchQuery::Text(text) => parse_text_query(index, text, arguments)?,
+        SearchQuery::Similar(similar_page) => {
+            let query = similar_query(index, similar_page)?;
+            ParsedQuery {
+                snippet_query: query.box_clone(),
+                query,
+                syntax_ignored: false,
+            }
+        }
+        SearchQuery::All => ParsedQuery {
+            query: Box::new(AllQuery),
+            snippet_query: Box::new(AllQuery),
+            syntax_ignored: false,
+        },
+    };
+
+    // Filters that all results must match, on top of the text query
+    let mut filters: Vec<Box<dyn Query>> = Vec::new();
+    if let Some(published_after) = &arguments.published_after {
+        let published_after = parse_date(published_after)
+            .with_context(|| format!("failed to parse date {:?}", published_after))?;
+        filters.push(Box::new(RangeQuery::new_date_bounds(
+            "published".to_string(),
+            Bound::Included(clamped_index_date(published_after)),
+            Bound::Unbounded,
+        )));
+    }
+    if visit_range.after.is_some() || visit_range.before.is_some() {
+        // Pages without a last visit don't match any range, so they are excluded too
+        let to_bound = |date: Option<chrono::DateTime<Utc>>, inclusive: bool| match date {
+            None => Bound::Unbounded,
+            Some(date) => {
+                let date = clamped_index_date(date);
+                if inclusive {
+                    Bound::Included(date)
+                } else {
+                    Bound::Excluded(date)
+                }
+            }
+        };
+        filters.push(Box::new(RangeQuery::new_date_bounds(
+            "last_visit".to_string(),
+            to_bound(visit_range.after, true),
+            to_bound(visit_range.before, false),
+        )));
+    }
+    if let Some(min_words) = arguments.min_words {
+        filters.push(Box::new(RangeQuery::new_u64_bounds(
+            "word_count".to_string(),
+            Bound::Included(min_words),
+            Bound::Unbounded,
+        )));
+    }
+    if let Some(period) = &arguments.period {
+        filters.push(Box::new(TermQuery::new(
+            Term::from_facet(visit_date_field, &parse_period(period)?),
+            IndexRecordOption::Basic,
+        )));
+    }
+    if let Some(site) = &arguments.site {
+        filters.push(Box::new(TermQuery::new(
+            Term::from_field_text(domain_field, &site_domain(site)),
+            IndexRecordOption::Basic,
+        )));
+    }
+    if let Some(under) = &arguments.under {
+        filters.push(url_prefix_query(under)?);
+    }
+    if arguments.versions.is_some() || arguments.as_of.is_some() {
+        schema
+            .get_field("snapshot_at")
+            .context("the index has no download dates, run index-contents --keep-versions again")?;
+    }
+    if let Some(url) = &arguments.versions {
+        // Normalized like the indexed URLs, see `extract_firefox_history()`
+        let mut parsed_url =
+            Url::parse(url).with_context(|| format!("invalid URL {:?} for --versions", url))?;
+        normalize_history_url(&mut parsed_url);
+        filters.push(Box::new(TermQuery::new(
+            Term::from_field_text(schema.get_field("url_exact")?, parsed_url.as_str()),
+            IndexRecordOption::Basic,
+        )));
+    }
+    if let Some(as_of) = &arguments.as_of {
+        let as_of =
+            parse_date(as_of).with_context(|| format!("failed to parse date {:?}", as_of))?;
+        // The pages only known from their notes have no download date, so they are excluded too
+        filters.push(Box::new(RangeQuery::new_date_bounds(
+            "snapshot_at".to_string(),
+            Bound::Unbounded,
+            Bound::Excluded(clamped_index_date(as_of)),
+        )));
+    }
+    if arguments.bookmarked || arguments.folder.is_some() {
+        let missing_bookmarks =
+            "the index has no bookmarks, run extract-firefox-history and index-contents again";
+        let bookmarked_field = schema.get_field("bookmarked").context(missing_bookmarks)?;
+        filters.push(Box::new(TermQuery::new(
+            Term::from_field_u64(bookmarked_field, 1),
+            IndexRecordOption::Basic,
+        )));
+        if let Some(folder) = &arguments.folder {
+            let folder_facet = folder_facet(folder);
+            if folder_facet.is_root() {
+                anyhow::bail!("the folder is empty, use --bookmarked for all the bookmarks");
+            }
+            let bookmark_folder_field = schema
+                .get_field("bookmark_folder")
+                .context(missing_bookmarks)?;
+            // The facet of a folder also matches its subfolders
+            filters.push(Box::new(TermQuery::new(
+                Term::from_facet(bookmark_folder_field, &folder_facet),
+                IndexRecordOption::Basic,
+            )));
+        }
+    }
+    if !filters.is_empty() {
+        let mut clauses = vec![(Occur::Must, query)];
+        clauses.extend(filters.into_iter().map(|filter| (Occur::Must, filter)));
+        query = Box::new(BooleanQuery::new(clauses));
+    }
+
+    Ok(ParsedQuery {
+        query,
+        snippet_query,
+        syntax_ignored,
+    })
+}
+
+/// Parse the text of the query, without the filters of the options. The operators written in the
+/// text are applied, except for the dates, which are part of the `VisitRange`.
+fn parse_text_query(
+    index: &Index,
+    text: &str,
+    arguments: &SearchArguments,
+) -> anyhow::Result<ParsedQuery> {
+    let schema = index.schema();
+    let domain_field = schema.get_field("domain")?;
+
+    // Fields can still be chosen in the query itself, like "title:tokio"
+    let mut default_fields = Vec::new();
+    for search_field in arguments.search_fields() {
+        default_fields.extend(search_field.index_field(&schema)?);
+    }
+    let mut query_parser = QueryParser::for_index(index, default_fields);
+    if let Some(notes_field) = SearchField::Notes.index_field(&schema)? {
+        query_parser.set_field_boost(notes_field, NOTES_BOOST);
+    }
+    let parse_leniently = |query_parser: &QueryParser, query_text: &mut String| {
+        match query_parser.parse_query(query_text) {
+            Ok(query) => Ok((query, false)),
+            Err(error) if arguments.strict_syntax => Err(anyhow::Error::from(error)),
+            Err(_) => {
+                // Pasted error messages and URLs are full of characters of the query language
+                *query_text = plain_terms(query_text);
+                if query_text.trim().is_empty() {
+                    anyhow::bail!("the query has no words to search for");
+                }
+                Ok((query_parser.parse_query(query_text)?, true))
+            }
+        }
+    };
+
+    let (text, operators) = extract_operators(text);
+    // Normalize the query the same way as the indexed content
+    let mut query_text = normalize_text(&text);
+    if let Some(phrase_slop) = arguments.phrase_slop {
+        query_text = add_phrase_slop(&query_text, phrase_slop);
+    }
+    // Only operators, like "-site:reddit.com", filter all the pages
+    let only_operators = query_text.is_empty() && !operators.is_empty();
+
+    // Fuzzy queries don't report their terms, so the snippets are built from the exact query
+    let (snippet_query, mut syntax_ignored): (Box<dyn Query>, _) = if only_operators {
+        (Box::new(AllQuery), false)
+    } else {
+        parse_leniently(&query_parser, &mut query_text)?
+    };
+    let mut filters: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for excluded in &operators.excluded {
+        let (excluded_query, excluded_syntax_ignored) =
+            parse_leniently(&query_parser, &mut normalize_text(excluded))?;
+        syntax_ignored |= excluded_syntax_ignored;
+        filters.push((Occur::MustNot, excluded_query));
+    }
+    if !operators.sites.is_empty() {
+        // The pages can be in any of the sites
+        let sites = operators
+            .sites
+            .iter()
+            .map(|site| {
+                let term = Term::from_field_text(domain_field, &site_domain(site));
+                let query: Box<dyn Query> =
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+                (Occur::Should, query)
+            })
+            .collect();
+        filters.push((Occur::Must, Box::new(BooleanQuery::new(sites))));
+    }
+    for site in &operators.excluded_sites {
+        let term = Term::from_field_text(domain_field, &site_domain(site));
+        filters.push((
+            Occur::MustNot,
+            Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+        ));
+    }
+
+    let fuzzy_fields = arguments.fuzzy_search_fields();
+    let mut query = if only_operators {
+        Box::new(AllQuery)
+    } else if fuzzy_fields.is_empty() {
+        query_parser.parse_query(&query_text)?
+    } else {
+        let mut fuzzy_parser = query_parser.clone();
+        for fuzzy_field in fuzzy_fields {
+            if let Some(field) = fuzzy_field.index_field(&schema)? {
+                fuzzy_parser.set_field_fuzzy(field, arguments.fuzzy_prefix, arguments.fuzzy, true);
+            }
+        }
+        let user_input = query_grammar::parse_query(&query_text)
+            .map_err(|_| anyhow::anyhow!("invalid query syntax: {}", query_text))?;
+        fuzzy_query(&query_parser, &fuzzy_parser, user_input)?
+    };
+    if !filters.is_empty() {
+        let mut clauses = vec![(Occur::Must, query)];
+        clauses.extend(filters);
+        query = Box::new(BooleanQuery::new(clauses));
+    }
+
+    Ok(ParsedQuery {
+        query,
+        snippet_query,
+        syntax_ignored,
+    })
+}
+
+/// Build the query with typos allowed in the words long enough, since short words with a typo are
+/// other common words, like "cat" and "car"
+fn fuzzy_query(
+    exact_parser: &QueryParser,
+    fuzzy_parser: &QueryParser,
+    user_input: UserInputAst,
+) -> anyhow::Result<Box<dyn Query>> {
+    match user_input {
+        UserInputAst::Clause(clauses) => {
+            let mut subqueries = Vec::new();
+            for (occur, clause) in clauses {
+                let subquery = fuzzy_query(exact_parser, fuzzy_parser, clause)?;
+                // Like the words without any indexed token, which the parser drops too
+                if subquery.downcast_ref::<EmptyQuery>().is_none() {
+                    subqueries.push((occur.unwrap_or(Occur::Should), subquery));
+                }
+            }
+            if subqueries.is_empty() {
+                Ok(Box::new(EmptyQuery))
+            } else {
+                Ok(Box::new(BooleanQuery::new(subqueries)))
+            }
+        }
+        UserInputAst::Leaf(leaf) => {
+            let parser = match &*leaf {
+                UserInputLeaf::Literal(literal)
+                    if literal.phrase.chars().count() >= MIN_FUZZY_WORD_CHARS =>
+                {
+                    fuzzy_parser
+                }
+                _ => exact_parser,
+            };
+            Ok(parser.build_query_from_user_input_ast(UserInputAst::Leaf(leaf))?)
+        }
+        UserInputAst::Boost(user_input, boost) => Ok(Box::new(BoostQuery::new(
+            fuzzy_query(exact_parser, fuzzy_parser, *user_input)?,
+            boost as Score,
+        ))),
+    }
+}
+
+/// The names of the fields where the document has any of the terms, in the order of the schema
+fn matched_fields(
+    searcher: &Searcher,
+    terms: &[Term],
+    doc_address: DocAddress,
+) -> anyhow::Result<Vec<String>> {
+    let segment_reader = searcher.segment_reader(doc_address.segment_ord);
+    let mut fields = Vec::new();
+    for term in terms {
+        if fields.contains(&term.field()) {
+            continue;
+        }
+        let inverted_index = segment_reader.inverted_index(term.field())?;
+        if let Some(mut postings) = inverted_index.read_postings(term, IndexRecordOption::Basic)? {
+            // Seeking can't go backwards, and the postings start at their first document
+            if postings.doc() <= doc_address.doc_id
+                && postings.seek(doc_address.doc_id) == doc_address.doc_id
+            {
+                fields.push(term.field());
+            }
+        }
+    }
+    fields.sort();
+
+    let schema = searcher.schema();
+    Ok(fields
+        .into_iter()
+        .map(|field| schema.get_field_name(field).to_string())
+        .collect())
+}
+
+/// The whole stored content of a hit, with the matches of the query marked
+pub fn hit_text(
+    indexes: &[OpenedIndex],
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+    hit: &SearchHit,
+) -> anyhow::Result<HitSnippet> {
+    let opened_index = indexes
+        .iter()
+        .find(|opened_index| opened_index.name == hit.index_name)
+        .context("missing index")?;
+    let content_field = opened_index.index.schema().get_field("content")?;
+    // Only the text query is needed, the filters don't change what matches in the content
+    let parsed_query = parse_query(
+        &opened_index.index,
+        query,
+        arguments,
+        &VisitRange::default(),
+    )?;
+
+    let searcher = opened_index.reader.searcher();
+    let document = searcher.doc(hit.doc_address)?;
+    let content = document
+        .get_first(content_field)
+        .and_then(|content| content.as_text())
+        .context("missing content")?;
+    highlight_whole_text(
+        &searcher,
+        parsed_query.snippet_query.as_ref(),
+        content_field,
+        content,
+    )
+}
+
+/// Match any of the terms of the page, but not the page itself
+fn similar_query(index: &Index, similar_page: &SimilarPage) -> anyhow::Result<Box<dyn Query>> {
+    let schema = index.schema();
+    let url_exact_field = schema.get_field("url_exact")?;
 
-    let reader = index.reader()?;
-    let searcher = reader.searcher();
-    let mut query_parser =
-        QueryParser::for_index(&index, vec![url_field, title_field, content_field]);
-    query_parser.set_field_fuzzy(content_field, false, 1, true);
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+    for (field_name, text) in &similar_page.terms {
+        let term = Term::from_field_text(schema.get_field(field_name)?, text);
+        clauses.push((
+            Occur::Should,
+            Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)),
+        ));
+    }
+    clauses.push((
+        Occur::MustNot,
+        Box::new(TermQuery::new(
+            Term::from_field_text(url_exact_field, &similar_page.url),
+            IndexRecordOption::Basic,
+        )),
+    ));
+
+    Ok(Box::new(BooleanQuery::new(clauses)))
+}
+
+fn search_index(
+    opened_index: &OpenedIndex,
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+    visit_range: &VisitRange,
+) -> anyhow::Result<IndexSearchResults> {
+    let searcher = opened_index.reader.searcher();
+    let ParsedQuery {
+        query,
+        snippet_query,
+        syntax_ignored,
+    } = parse_query(&opened_index.index, query, arguments, visit_range)?;
+
+    let facet_counts = if arguments.facet_counts {
+        count_visit_dates(&searcher, &query)?
+    } else {
+        BTreeMap::new()
+    };
+
+    let timeline = if arguments.timeline {
+        collect_timeline(&searcher, &query)?
+    } else {
+        BTreeMap::new()
+    };
+
+    // Each index must return enough hits to fill the requested page after merging
+    let mut limit = arguments.offset + arguments.limit;
+    if arguments.collapses_snapshots() {
+        limit *= SNAPSHOT_CANDIDATES_FACTOR;
+    }
+    if arguments.collapse_near_duplicates {
+        limit *= NEAR_DUPLICATE_CANDIDATES_FACTOR;
+    }
+    if arguments.domains_to_collapse().is_some() || arguments.group_by.is_some() {
+        limit *= DOMAIN_CANDIDATES_FACTOR;
+    }
+    let (top_hits, total_matches) = top_matches(
+        &searcher,
+        query.as_ref(),
+        arguments.sort,
+        arguments.rank,
+        arguments.order_date_field(),
+        limit.max(1),
+        0,
+    )?;
 
-    let query = query_parser.parse_query(&query)?;
-    let top_hits = searcher.search(&query, &TopDocs::with_limit(10))?;
+    let hit_reader = HitReader::create(opened_index, &searcher, snippet_query.as_ref(), arguments)?;
+    let mut hits = Vec::new();
+    for (rank_key, hit_id) in top_hits {
+        hits.push(hit_reader.read(&searcher, rank_key.score(), hit_id)?);
+    }
+    if arguments.collapses_snapshots() {
+        hits = newest_versions(&searcher, query.as_ref(), &hit_reader, hits)?;
+    }
+
+    Ok(IndexSearchResults {
+        hits,
+        total_matches,
+        facet_counts,
+        timeline,
+        syntax_ignored,
+    })
+}
 
-    let snippet_generator = SnippetGenerator::create(&searcher, &query, content_field)?;
+/// Replace the hits by the newest version of their URL that matches the query, at the rank of the
+/// best one, counting the versions that match. The URLs have several versions when they were
+/// indexed with --keep-versions.
+fn newest_versions(
+    searcher: &Searcher,
+    query: &dyn Query,
+    hit_reader: &HitReader,
+    hits: Vec<SearchHit>,
+) -> anyhow::Result<Vec<SearchHit>> {
+    let Ok(url_exact_field) = searcher.schema().get_field("url_exact") else {
+        return Ok(hits);
+    };
 
-    for (index, (_score, hit_id)) in top_hits.into_iter().enumerate() {
+    let mut seen_urls = HashSet::new();
+    let mut newest_hits = Vec::new();
+    for hit in hits {
+        // The other versions were counted with the best one
+        if !seen_urls.insert(hit.url.clone()) {
+            continue;
+        }
+        let url_term = Term::from_field_text(url_exact_field, &hit.url);
+        // The document frequency also counts the deleted documents, so it's only a hint
+        if hit.snapshot_at.is_none() || searcher.doc_freq(&url_term)? <= 1 {
+            newest_hits.push(hit);
+            continue;
+        }
+
+        let url_query = BooleanQuery::new(vec![
+            (Occur::Must, query.box_clone()),
+            (
+                Occur::Must,
+                Box::new(TermQuery::new(url_term, IndexRecordOption::Basic)),
+            ),
+        ]);
+        let date_order = DateOrder {
+            field: "snapshot_at",
+            newest_first: true,
+        };
+        let (newest, versions) = searcher.search(
+            &url_query,
+            &(TopDocs::with_limit(1).custom_score(date_order), Count),
+        )?;
+        let mut newest_hit = match newest.first() {
+            Some(&(_, doc_address)) if doc_address != hit.doc_address => {
+                hit_reader.read(searcher, hit.score, doc_address)?
+            }
+            _ => hit,
+        };
+        newest_hit.snapshots = versions.max(1);
+        newest_hits.push(newest_hit);
+    }
+    Ok(newest_hits)
+}
+
+/// Where a match goes in the order of the results, the greatest first
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum RankKey {
+    Score(Score),
+    /// A date, as ranked by [DateOrder]
+    Date(i64),
+}
+
+impl RankKey {
+    /// The relevance score, unknown when sorting by date
+    fn score(self) -> Option<Score> {
+        match self {
+            RankKey::Score(score) => Some(score),
+            RankKey::Date(_) => None,
+        }
+    }
+}
+
+/// The `limit` matches of the query after the first `offset` ones, in the order of the results,
+/// and how many documents match in total. Matches with the same rank are ordered by their address,
+/// so that the pages of the same searcher follow each other.
+fn top_matches(
+    searcher: &Searcher,
+    query: &dyn Query,
+    sort: SortOrder,
+    rank: Rank,
+    date_field: &'static str,
+    limit: usize,
+    offset: usize,
+) -> anyhow::Result<(Vec<(RankKey, DocAddress)>, usize)> {
+    let top_docs = TopDocs::with_limit(limit).and_offset(offset);
+    match sort {
+        SortOrder::Relevance => {
+            let (top_hits, total_matches) = match rank {
+                Rank::Text => searcher.search(query, &(top_docs, Count))?,
+                Rank::Frequent => {
+                    searcher.schema().get_field("visit_count").context(
+                        "the index has no visit counts, run extract-firefox-history and \
+                        index-contents again",
+                    )?;
+                    searcher.search(query, &(top_docs.tweak_score(frequency_boost), Count))?
+                }
+            };
+            let top_hits = top_hits
+                .into_iter()
+                .map(|(score, address)| (RankKey::Score(score), address))
+                .collect();
+            Ok((top_hits, total_matches))
+        }
+        SortOrder::Recent | SortOrder::Oldest => {
+            // The text query still selects the documents, but the order comes from the dates
+            let date_order = DateOrder {
+                field: date_field,
+                newest_first: matches!(sort, SortOrder::Recent),
+            };
+            let (top_hits, total_matches) =
+                searcher.search(query, &(top_docs.custom_score(date_order), Count))?;
+            let top_hits = top_hits
+                .into_iter()
+                .map(|(date, address)| (RankKey::Date(date), address))
+                .collect();
+            Ok((top_hits, total_matches))
+        }
+    }
+}
+
+/// Reads the matches of one index into hits, with their snippets when the output shows them
+struct HitReader {
+    index_name: String,
+    url_field: Field,
+    title_field: Field,
+    synthetic_title_field: Field,
+    last_visit_field: Field,
+    content_field: Field,
+    published_field: Field,
+    word_count_field: Field,
+    simhash_field: Field,
+    // Older indexes don't have them
+    canonical_url_field: Option<Field>,
+    notes_field: Option<Field>,
+    bookmarked_field: Option<Field>,
+    bookmark_folder_field: Option<Field>,
+    snapshot_at_field: Option<Field>,
+    snippet_generator: Option<HitSnippetGenerator>,
+    /// The terms of the text query, to tell in which fields each hit matched
+    query_terms: Vec<Term>,
+    /// Whether the words of the query also match with typos
+    fuzzy: bool,
+}
+
+impl HitReader {
+    fn create(
+        opened_index: &OpenedIndex,
+        searcher: &Searcher,
+        snippet_query: &dyn Query,
+        arguments: &SearchArguments,
+    ) -> anyhow::Result<Self> {
+        let schema = opened_index.index.schema();
+        let title_field = schema.get_field("title")?;
+        let content_field = schema.get_field("content")?;
+
+        let snippet_generator = if arguments.needs_snippets() {
+            Some(HitSnippetGenerator::create(
+                searcher,
+                snippet_query,
+                content_field,
+                title_field,
+                arguments.snippet_chars,
+                arguments.snippet_fragments,
+            )?)
+        } else {
+            None
+        };
+
+        let mut query_terms = Vec::new();
+        if arguments.needs_snippets() {
+            snippet_query.query_terms(&mut |term, _| query_terms.push(term.clone()));
+        }
+
+        Ok(HitReader {
+            index_name: opened_index.name.clone(),
+            url_field: schema.get_field("url")?,
+            title_field,
+            synthetic_title_field: schema.get_field("synthetic_title")?,
+            last_visit_field: schema.get_field("last_visit")?,
+            content_field,
+            published_field: schema.get_field("published")?,
+            word_count_field: schema.get_field("word_count")?,
+            simhash_field: schema.get_field("simhash")?,
+            canonical_url_field: schema.get_field("canonical_url").ok(),
+            notes_field: SearchField::Notes.index_field(&schema)?,
+            bookmarked_field: schema.get_field("bookmarked").ok(),
+            bookmark_folder_field: schema.get_field("bookmark_folder").ok(),
+            snapshot_at_field: schema.get_field("snapshot_at").ok(),
+            snippet_generator,
+            query_terms,
+            fuzzy: !arguments.fuzzy_search_fields().is_empty(),
+        })
+    }
+
+    /// Read the match from the searcher that found it
+    fn read(
+        &self,
+        searcher: &Searcher,
+        score: Option<Score>,
+        hit_id: DocAddress,
+    ) -> anyhow::Result<SearchHit> {
         let document = searcher.doc(hit_id)?;
 
         let url = document
-            .get_first(url_field)
+            .get_first(self.url_field)
             .and_then(|url| url.as_text())
             .context("missing url")?;
         let title = document
-            .get_first(title_field)
+            .get_first(self.title_field)
             .and_then(|title| title.as_text());
+        let synthetic_title = document
+            .get_first(self.synthetic_title_field)
+            .and_then(|synthetic_title| synthetic_title.as_text());
         let last_visit = document
-            .get_first(last_visit_field)
+            .get_first(self.last_visit_field)
             .and_then(|last_visit| last_visit.as_date());
+        let published = document
+            .get_first(self.published_field)
+            .and_then(|published| published.as_date());
+        let word_count = document
+            .get_first(self.word_count_field)
+            .and_then(|word_count| word_count.as_u64());
+        let simhash = document
+            .get_first(self.simhash_field)
+            .and_then(|simhash| simhash.as_u64());
+        let canonical_url = self
+            .canonical_url_field
+            .and_then(|canonical_url_field| document.get_first(canonical_url_field))
+            .and_then(|canonical_url| canonical_url.as_text());
+        let notes = match self.notes_field {
+            Some(notes_field) => document
+                .get_all(notes_field)
+                .filter_map(|note| note.as_text())
+                .map(|note| note.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        let bookmarked = self
+            .bookmarked_field
+            .and_then(|bookmarked_field| document.get_first(bookmarked_field))
+            .and_then(|bookmarked| bookmarked.as_u64())
+            == Some(1);
+        let bookmark_folders = match self.bookmark_folder_field {
+            Some(bookmark_folder_field) => document
+                .get_all(bookmark_folder_field)
+                .filter_map(|folder| folder.as_facet())
+                .map(|folder| folder.to_path().join("/"))
+                .collect(),
+            None => Vec::new(),
+        };
+        let snapshot_at = self
+            .snapshot_at_field
+            .and_then(|snapshot_at_field| document.get_first(snapshot_at_field))
+            .and_then(|snapshot_at| snapshot_at.as_date());
         let content = document
-            .get_first(content_field)
+            .get_first(self.content_field)
             .and_then(|content| content.as_text())
             .context("missing content")?;
 
-        let snippet = snippet_generator.snippet(content);
+        let snippet = match &self.snippet_generator {
+            Some(snippet_generator) => snippet_generator.snippet(content, title),
+            None => HitSnippet::default(),
+        };
 
-        println!("{}. {}", index + 1, url);
-        if let Some(title) = title {
-            println!("  Title: {}", title);
+        let matched_fields = matched_fields(searcher, &self.query_terms, hit_id)?;
+        // The fuzzy queries don't report their terms, so a hit without any exact term matched
+        // only with typos
+        let fuzzy_only = self.fuzzy && !self.query_terms.is_empty() && matched_fields.is_empty();
+        Ok(SearchHit {
+            index_name: self.index_name.clone(),
+            score,
+            url: url.to_string(),
+            title: title.map(|title| title.to_string()),
+            synthetic_title: synthetic_title.map(|synthetic_title| synthetic_title.to_string()),
+            last_visit: last_visit.map(convert_date).transpose()?,
+            published: published.map(convert_date).transpose()?,
+            word_count,
+            simhash,
+            canonical_url: canonical_url.map(|canonical_url| canonical_url.to_string()),
+            snippet,
+            snapshot_at: snapshot_at.map(convert_date).transpose()?,
+            matched_fields,
+            fuzzy_only,
+            notes,
+            bookmarked,
+            bookmark_folders,
+            more_from_domain: 0,
+            snapshots: 1,
+            doc_address: hit_id,
+        })
+    }
+}
+
+/// How many matches each index reads at once, when streaming them
+const STREAM_PAGE_SIZE: usize = 500;
+
+/// The matches of one index, read one page at a time
+struct IndexStream {
+    /// Kept for the whole stream, so that all the pages come from the same generation of the
+    /// index, even if it's reloaded meanwhile
+    searcher: Searcher,
+    query: Box<dyn Query>,
+    hit_reader: HitReader,
+    /// How many matches were read into pages
+    read: usize,
+    page: VecDeque<(RankKey, DocAddress)>,
+    exhausted: bool,
+}
+
+impl IndexStream {
+    /// The rank of the next match, reading the next page when the current one is over
+    fn peek(
+        &mut self,
+        sort: SortOrder,
+        rank: Rank,
+        date_field: &'static str,
+    ) -> anyhow::Result<Option<RankKey>> {
+        if self.page.is_empty() && !self.exhausted {
+            let (matches, _) = top_matches(
+                &self.searcher,
+                self.query.as_ref(),
+                sort,
+                rank,
+                date_field,
+                STREAM_PAGE_SIZE,
+                self.read,
+            )?;
+            self.exhausted = matches.len() < STREAM_PAGE_SIZE;
+            self.read += matches.len();
+            self.page.extend(matches);
+        }
+        Ok(self.page.front().map(|&(rank_key, _)| rank_key))
+    }
+}
+
+/// All the matches of a query, in the order of the results, read lazily from the indexes. The
+/// snapshots of the same page and the near-duplicates are dropped like in the search, except that
+/// the best ranked snapshot is kept instead of the most recently visited one. The options that
+/// need all the results at once, like the limit or --collapse-domains, are not applied.
+pub struct HitStream {
+    indexes: Vec<IndexStream>,
+    sort: SortOrder,
+    rank: Rank,
+    date_field: &'static str,
+    min_score: Option<Score>,
+    min_score_ratio: Option<Score>,
+    /// The score of the first match, for the minimum score ratio
+    best_score: Option<Score>,
+    /// The pages already streamed, unless all their snapshots are streamed
+    streamed_pages: Option<HashSet<String>>,
+    /// The SimHashes of the hits streamed, when their near-duplicates are dropped
+    streamed_simhashes: Option<Vec<u64>>,
+    syntax_ignored: bool,
+    /// Whether the stream ended early, after an error or below the minimum score
+    ended: bool,
+}
+
+impl HitStream {
+    /// How many documents match the query in total, including the ones the stream drops
+    pub fn total_matches(&self) -> anyhow::Result<usize> {
+        let mut total_matches = 0;
+        for index in &self.indexes {
+            total_matches += index.searcher.search(index.query.as_ref(), &Count)?;
         }
-        match last_visit {
-            None => println!("  Last visit: unknown"),
-            Some(last_visit) => {
-                let timestamp = last_visit.into_timestamp_millis();
-                let date = Utc
-                    .timestamp_millis_opt(timestamp)
-                    .single()
-                    .context("failed to convert date")?;
-                println!("  Last visit: {}", date)
+        Ok(total_matches)
+    }
+
+    /// Whether the query syntax was invalid and the query was searched as plain words
+    pub fn syntax_ignored(&self) -> bool {
+        self.syntax_ignored
+    }
+
+    /// The best next match among all the indexes, before the filters of the stream
+    fn next_match(&mut self) -> anyhow::Result<Option<SearchHit>> {
+        let mut best: Option<(usize, RankKey)> = None;
+        for (position, index) in self.indexes.iter_mut().enumerate() {
+            if let Some(rank_key) = index.peek(self.sort, self.rank, self.date_field)? {
+                // On ties, the first index wins
+                if best.is_none_or(|(_, best_key)| rank_key > best_key) {
+                    best = Some((position, rank_key));
+                }
             }
         }
-        println!("{}\n", snippet.to_html());
+        let Some((position, _)) = best else {
+            return Ok(None);
+        };
+
+        let index = &mut self.indexes[position];
+        let (rank_key, doc_address) = index.page.pop_front().context("missing match")?;
+        let hit = index
+            .hit_reader
+            .read(&index.searcher, rank_key.score(), doc_address)?;
+        Ok(Some(hit))
     }
 
-    Ok(())
+    /// Whether the hit is kept by the filters of the stream, remembering it if so
+    fn keep(&mut self, hit: &SearchHit) -> bool {
+        if let Some(streamed_pages) = &mut self.streamed_pages {
+            if !streamed_pages.insert(snapshot_page(hit)) {
+                return false;
+            }
+        }
+        if let (Some(streamed_simhashes), Some(simhash)) =
+            (&mut self.streamed_simhashes, hit.simhash)
+        {
+            if streamed_simhashes
+                .iter()
+                .any(|&streamed_simhash| is_near_duplicate(streamed_simhash, simhash))
+            {
+                return false;
+            }
+            streamed_simhashes.push(simhash);
+        }
+        true
+    }
+}
+
+impl Iterator for HitStream {
+    type Item = anyhow::Result<SearchHit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.ended {
+            let hit = match self.next_match() {
+                Ok(Some(hit)) => hit,
+                Ok(None) => return None,
+                Err(error) => {
+                    self.ended = true;
+                    return Some(Err(error));
+                }
+            };
+
+            // The matches come from the best score down, so the ones after are below it too
+            if let Some(score) = hit.score {
+                let best_score = *self.best_score.get_or_insert(score);
+                let min_score = self
+                    .min_score
+                    .unwrap_or(f32::MIN)
+                    .max(best_score * self.min_score_ratio.unwrap_or(0.));
+                if score < min_score {
+                    self.ended = true;
+                    return None;
+                }
+            }
+
+            if self.keep(&hit) {
+                return Some(Ok(hit));
+            }
+        }
+        None
+    }
+}
+
+/// Stream all the matches of the query in the indexes, in the order of the results, see
+/// [HitStream]
+pub fn stream_search(
+    indexes: &[OpenedIndex],
+    query: &SearchQuery,
+    arguments: &SearchArguments,
+) -> anyhow::Result<HitStream> {
+    check_ranking(arguments)?;
+    let visit_range = decide_visit_range(arguments, query)?;
+
+    let mut index_streams = Vec::new();
+    let mut syntax_ignored = false;
+    for opened_index in indexes {
+        let searcher = opened_index.reader.searcher();
+        let parsed_query = parse_query(&opened_index.index, query, arguments, &visit_range)?;
+        syntax_ignored |= parsed_query.syntax_ignored;
+        let hit_reader = HitReader::create(
+            opened_index,
+            &searcher,
+            parsed_query.snippet_query.as_ref(),
+            arguments,
+        )?;
+        index_streams.push(IndexStream {
+            searcher,
+            query: parsed_query.query,
+            hit_reader,
+            read: 0,
+            page: VecDeque::new(),
+            exhausted: false,
+        });
+    }
+
+    Ok(HitStream {
+        indexes: index_streams,
+        sort: arguments.sort,
+        rank: arguments.rank,
+        date_field: arguments.order_date_field(),
+        min_score: arguments.min_score,
+        min_score_ratio: arguments.min_score_ratio,
+        best_score: None,
+        streamed_pages: arguments.collapses_snapshots().then(HashSet::new),
+        streamed_simhashes: arguments.collapse_near_duplicates.then(Vec::new),
+        syntax_ignored,
+        ended: false,
+    })
+}
+
+/// The page of the hit, where its snapshots have the same canonical or normalized URL
+fn snapshot_page(hit: &SearchHit) -> String {
+    let page = hit.canonical_url.as_deref().unwrap_or(&hit.url);
+    match Url::parse(page) {
+        Ok(mut parsed_page) => {
+            normalize_url(&mut parsed_page);
+            parsed_page.to_string()
+        }
+        Err(_) => page.to_string(),
+    }
+}
+
+/// Keep one hit of each page, where the snapshots of the same page have the same canonical or
+/// normalized URL. The most recently visited snapshot is kept, at the rank of the best one, and
+/// the newest version among the ones of the same URL.
+fn collapse_snapshots(hits: Vec<SearchHit>) -> Vec<SearchHit> {
+    let mut kept_hits: Vec<SearchHit> = Vec::new();
+    let mut position_by_page: HashMap<String, usize> = HashMap::new();
+    for hit in hits {
+        let page = snapshot_page(&hit);
+        match position_by_page.get(&page) {
+            Some(&position) => {
+                let kept_hit = &mut kept_hits[position];
+                let snapshots = kept_hit.snapshots + hit.snapshots;
+                if (hit.last_visit, hit.snapshot_at) > (kept_hit.last_visit, kept_hit.snapshot_at) {
+                    let score = kept_hit.score;
+                    *kept_hit = hit;
+                    kept_hit.score = score;
+                }
+                kept_hit.snapshots = snapshots;
+            }
+            None => {
+                position_by_page.insert(page, kept_hits.len());
+                kept_hits.push(hit);
+            }
+        }
+    }
+    kept_hits
+}
+
+/// Keep the first `max_per_domain` hits of each domain, counting the hidden ones in the last hit
+/// kept of each domain
+fn collapse_domains(hits: Vec<SearchHit>, max_per_domain: usize) -> Vec<SearchHit> {
+    let mut kept_hits: Vec<SearchHit> = Vec::new();
+    // The number of kept hits and the position of the last one, by domain
+    let mut kept_by_domain: HashMap<String, (usize, usize)> = HashMap::new();
+    for hit in hits {
+        let domain = registrable_domain(&hit.url).unwrap_or_else(|| hit.url.clone());
+        match kept_by_domain.get_mut(&domain) {
+            Some((kept, last_position)) if *kept >= max_per_domain => {
+                kept_hits[*last_position].more_from_domain += 1;
+            }
+            Some((kept, last_position)) => {
+                *kept += 1;
+                *last_position = kept_hits.len();
+                kept_hits.push(hit);
+            }
+            None => {
+                kept_by_domain.insert(domain, (1, kept_hits.len()));
+                kept_hits.push(hit);
+            }
+        }
+    }
+    kept_hits
+}
+
+/// Group the hits by domain, the groups in the order of their first hit, and keep the first
+/// `hits_per_group` hits of the first `max_groups` groups. The hits of each group follow each
+/// other, and the hidden ones are counted in the last hit kept of each group.
+fn group_domains(hits: Vec<SearchHit>, max_groups: usize, hits_per_group: usize) -> Vec<SearchHit> {
+    let mut groups: Vec<Vec<SearchHit>> = Vec::new();
+    let mut position_by_domain: HashMap<String, usize> = HashMap::new();
+    for hit in hits {
+        let domain = group_domain(&hit.url);
+        match position_by_domain.get(&domain) {
+            Some(&position) => groups[position].push(hit),
+            None if groups.len() < max_groups => {
+                position_by_domain.insert(domain, groups.len());
+                groups.push(vec![hit]);
+            }
+            None => {}
+        }
+    }
+    groups
+        .into_iter()
+        .flat_map(|mut group| {
+            let hidden = group.len().saturating_sub(hits_per_group);
+            group.truncate(hits_per_group);
+            if let Some(last_hit) = group.last_mut() {
+                last_hit.more_from_domain = hidden;
+            }
+            group
+        })
+        .collect()
+}
+
+/// The domain that groups the results of the URL, or the URL itself when it has none
+pub fn group_domain(url: &str) -> String {
+    registrable_domain(url).unwrap_or_else(|| url.to_string())
+}
+
+/// Add the slop to the phrases of the query that don't have one
+fn add_phrase_slop(query: &str, phrase_slop: u32) -> String {
+    let mut rewritten = String::new();
+    let mut in_phrase = false;
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        rewritten.push(c);
+        if c == '"' {
+            if in_phrase && chars.peek() != Some(&'~') {
+                rewritten.push_str(&format!("~{}", phrase_slop));
+            }
+            in_phrase = !in_phrase;
+        }
+    }
+    rewritten
+}
+
+/// Remove everything but the words from a query, lowercasing them so that they are not taken as
+/// operators like "AND"
+fn plain_terms(query: &str) -> String {
+    query
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_lowercase().to_string()
+            } else {
+                " ".to_string()
+            }
+        })
+        .collect()
+}
+
+/// The domain of a site given like "docs.rs", "www.docs.rs" or "https://docs.rs/tokio"
+pub fn site_domain(site: &str) -> String {
+    let url = if site.contains("://") {
+        site.to_string()
+    } else {
+        format!("https://{}", site)
+    };
+    registrable_domain(&url).unwrap_or_else(|| site.to_lowercase())
+}
+
+fn decide_visit_range(
+    arguments: &SearchArguments,
+    query: &SearchQuery,
+) -> anyhow::Result<VisitRange> {
+    let parse =
+        |date: &str| parse_date(date).with_context(|| format!("failed to parse date {:?}", date));
+    let mut visit_range = VisitRange {
+        after: arguments.after.as_deref().map(parse).transpose()?,
+        before: arguments.before.as_deref().map(parse).transpose()?,
+    };
+
+    if let Some(last) = &arguments.last {
+        let start = parse_last(last, Utc::now())?;
+        // Both limits apply, so the latest one wins
+        visit_range.after = Some(visit_range.after.map_or(start, |after| after.max(start)));
+    }
+
+    // The dates written in the query, like "after:2024-01-01", narrow the range further
+    let operators = match query {
+        SearchQuery::Text(text) => extract_operators(text).1,
+        _ => QueryOperators::default(),
+    };
+    if let Some(after) = operators.after.as_deref().map(parse).transpose()? {
+        visit_range.after = Some(visit_range.after.map_or(after, |other| other.max(after)));
+    }
+    if let Some(before) = operators.before.as_deref().map(parse).transpose()? {
+        visit_range.before = Some(visit_range.before.map_or(before, |other| other.min(before)));
+    }
+
+    Ok(visit_range)
+}
+
+/// Parse a recent period like "7d", "3w", "6m" or "2y" into when it started
+pub fn parse_last(last: &str, now: chrono::DateTime<Utc>) -> anyhow::Result<chrono::DateTime<Utc>> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid period {:?}, expected a number followed by d, w, m or y, like \"7d\"",
+            last
+        )
+    };
+    let unit_start = last.len().checked_sub(1).ok_or_else(invalid)?;
+    if !last.is_char_boundary(unit_start) {
+        return Err(invalid());
+    }
+    let (amount, unit) = last.split_at(unit_start);
+    let amount: u32 = amount.parse().map_err(|_| invalid())?;
+
+    let start = match unit {
+        "d" => now.checked_sub_signed(Duration::days(amount.into())),
+        "w" => now.checked_sub_signed(Duration::weeks(amount.into())),
+        "m" => now.checked_sub_months(Months::new(amount)),
+        "y" => amount
+            .checked_mul(12)
+            .and_then(|months| now.checked_sub_months(Months::new(months))),
+        _ => return Err(invalid()),
+    };
+    start.ok_or_else(invalid)
+}
+
+/// Parse "2023" or "2023-07" into the corresponding visit date facet
+fn parse_period(period: &str) -> anyhow::Result<Facet> {
+    let parts: Vec<&str> = period.split('-').collect();
+    let is_valid = match parts.as_slice() {
+        [year] => year.len() == 4 && year.parse::<u32>().is_ok(),
+        [year, month] => {
+            year.len() == 4
+                && year.parse::<u32>().is_ok()
+                && month.len() == 2
+                && matches!(month.parse::<u32>(), Ok(1..=12))
+        }
+        _ => false,
+    };
+    if !is_valid {
+        anyhow::bail!(
+            "invalid period {:?}, expected a year like \"2023\" or a month like \"2023-07\"",
+            period
+        );
+    }
+
+    Ok(Facet::from_path(parts))
+}
+
+/// Count the matches by year and then by month of each year with matches
+fn count_visit_dates(
+    searcher: &Searcher,
+    query: &dyn Query,
+) -> anyhow::Result<BTreeMap<String, u64>> {
+    let mut year_collector = FacetCollector::for_field("visit_date");
+    year_collector.add_facet("/");
+    let year_counts = searcher.search(query, &year_collector)?;
+
+    let mut counts = BTreeMap::new();
+    let mut month_collector = FacetCollector::for_field("visit_date");
+    for (year_facet, count) in year_counts.get("/") {
+        counts.insert(year_facet.to_path().join("-"), count);
+        month_collector.add_facet(year_facet.clone());
+    }
+
+    let month_counts = searcher.search(query, &month_collector)?;
+    let years: Vec<String> = counts.keys().cloned().collect();
+    for year in years {
+        for (month_facet, count) in month_counts.get(Facet::from_path([year])) {
+            counts.insert(month_facet.to_path().join("-"), count);
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Count the matches by month, with the URL and title of the best ones
+fn collect_timeline(
+    searcher: &Searcher,
+    query: &dyn Query,
+) -> anyhow::Result<BTreeMap<Month, TimelineMonth>> {
+    let schema = searcher.schema();
+    let url_field = schema.get_field("url")?;
+    let title_field = schema.get_field("title")?;
+
+    let mut timeline = BTreeMap::new();
+    for ((year, month), matches) in searcher.search(query, &TimelineCollector)? {
+        let mut hits = Vec::new();
+        for (score, doc_address) in matches.best {
+            let document = searcher.doc(doc_address)?;
+            let text = |field| {
+                document
+                    .get_first(field)
+                    .and_then(|value| value.as_text())
+                    .map(|text| text.to_string())
+            };
+            hits.push(TimelineHit {
+                url: text(url_field).context("missing url")?,
+                title: text(title_field),
+                score,
+            });
+        }
+        timeline.insert(
+            (year, month),
+            TimelineMonth {
+                month: format!("{}-{:02}", year, month),
+                count: matches.count,
+                hits,
+            },
+        );
+    }
+    Ok(timeline)
+}
+
+fn convert_date(date: DateTime) -> anyhow::Result<chrono::DateTime<Utc>> {
+    Utc.timestamp_millis_opt(date.into_timestamp_millis())
+        .single()
+        .context("failed to convert date")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{date, visited_page, TestData};
+
+    #[test]
+    fn counts_the_words_and_filters_the_stubs() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://example.com/stub",
+                    "Stub",
+                    "<html lang='en'><p>Moved to the tokio docs</p></html>",
+                ),
+                visited_page(
+                    "https://example.com/article",
+                    "Article",
+                    "<html><p>The tokio runtime schedules the tasks of the program on a pool \
+                     of worker threads</p></html>",
+                ),
+            ],
+            &[],
+        );
+
+        let results = data.search("tokio", &[]);
+        let mut word_counts: Vec<(String, Option<u64>)> = results
+            .hits
+            .into_iter()
+            .map(|hit| (hit.url, hit.word_count))
+            .collect();
+        word_counts.sort();
+        assert_eq!(
+            word_counts,
+            [
+                ("https://example.com/article".to_string(), Some(15)),
+                ("https://example.com/stub".to_string(), Some(5)),
+            ]
+        );
+
+        assert_eq!(
+            data.search_urls("tokio", &["--min-words=10"]),
+            ["https://example.com/article"]
+        );
+        assert!(data.search_urls("tokio", &["--min-words=100"]).is_empty());
+    }
+
+    #[test]
+    fn normalizes_the_query_like_the_content() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![visited_page(
+                "https://example.com/menu",
+                "Menu",
+                "<p>Cafe\u{301} and cre\u{300}me bru\u{302}le\u{301}e</p>",
+            )],
+            &[],
+        );
+        for query in ["café", "cafe\u{301}", "crème brûlée"] {
+            assert_eq!(
+                data.search_urls(query, &["--fuzzy=0"]),
+                ["https://example.com/menu"],
+                "{:?}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn counts_and_filters_the_visits_by_period() {
+        let data = TestData::new();
+        let visits = [
+            ("https://example.com/a", date(2022, 12, 31)),
+            ("https://example.com/b", date(2023, 7, 1)),
+            ("https://example.com/c", date(2023, 7, 30)),
+            ("https://example.com/d", date(2023, 9, 2)),
+        ];
+        let pages = visits
+            .iter()
+            .map(|(url, last_visit)| {
+                let (mut item, page) = visited_page(url, "", "<p>Tokio tasks</p>");
+                item.last_visit = Some(*last_visit);
+                (item, page)
+            })
+            .collect();
+        data.index_pages(pages, &[]);
+
+        let facet_counts = data.search("tokio", &["--facet-counts"]).facet_counts;
+        let expected = [
+            ("2022", 1),
+            ("2022-12", 1),
+            ("2023", 3),
+            ("2023-07", 2),
+            ("2023-09", 1),
+        ];
+        assert_eq!(
+            facet_counts,
+            Some(
+                expected
+                    .into_iter()
+                    .map(|(period, count)| (period.to_string(), count))
+                    .collect()
+            )
+        );
+
+        let mut urls = data.search_urls("tokio", &["--period=2023-07"]);
+        urls.sort();
+        assert_eq!(urls, ["https://example.com/b", "https://example.com/c"]);
+        assert_eq!(data.search_urls("tokio", &["--period=2023"]).len(), 3);
+        assert!(data.search_urls("tokio", &["--period=2023-08"]).is_empty());
+    }
+
+    #[test]
+    fn parses_the_periods() {
+        assert_eq!(parse_period("2023").unwrap(), Facet::from("/2023"));
+        assert_eq!(parse_period("2023-07").unwrap(), Facet::from("/2023/07"));
+        for invalid in ["23", "2023-7", "2023-13", "2023-07-01", "july", ""] {
+            assert!(parse_period(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn tells_when_count_and_quiet_find_nothing() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![visited_page("https://example.com/", "", "<p>Tokio</p>")],
+            &[],
+        );
+        let outcome = |query: &str, option: &str| {
+            let arguments = SearchArguments::parse_options([option, "--fuzzy=0"]).unwrap();
+            search(Some(query.to_string()), arguments, &data.data_paths).unwrap()
+        };
+        assert_eq!(outcome("tokio", "--count"), Outcome::Success);
+        assert_eq!(outcome("tokio", "--quiet"), Outcome::Success);
+        assert_eq!(outcome("async", "--count"), Outcome::NoMatches);
+        assert_eq!(outcome("async", "--quiet"), Outcome::NoMatches);
+        assert_eq!(outcome("async", "--limit=5"), Outcome::Success);
+    }
+
+    /// The results and whether the syntax was ignored, or the error
+    fn try_search(
+        data: &TestData,
+        query: &str,
+        options: &[&str],
+    ) -> anyhow::Result<(Vec<String>, bool)> {
+        let arguments = SearchArguments::parse_options(options.iter().copied())?;
+        let indexes = open_indexes(&arguments, &data.data_paths)?;
+        let query = SearchQuery::Text(query.to_string());
+        let results = run_search(&indexes, &query, &arguments, &data.data_paths)?;
+        let urls = results.hits.into_iter().map(|hit| hit.url).collect();
+        Ok((urls, results.syntax_ignored))
+    }
+
+    #[test]
+    fn searches_the_words_of_pasted_text() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://example.com/moved",
+                    "Moved values",
+                    "<p>error[E0382]: borrow of moved value: `config`</p>",
+                ),
+                visited_page(
+                    "https://example.com/bookmarks",
+                    "Links",
+                    "<p>See https://docs.rs/tokio/latest for the runtime</p>",
+                ),
+            ],
+            &[],
+        );
+
+        let pasted_error = r#"error[E0382]: borrow of moved value: "config"#;
+        assert_eq!(
+            try_search(&data, pasted_error, &[]).unwrap(),
+            (vec!["https://example.com/moved".to_string()], true)
+        );
+        assert!(try_search(&data, pasted_error, &["--strict-syntax"]).is_err());
+
+        let pasted_url = "https://docs.rs/tokio/latest";
+        let (urls, syntax_ignored) = try_search(&data, pasted_url, &[]).unwrap();
+        assert_eq!(urls[0], "https://example.com/bookmarks");
+        assert!(syntax_ignored);
+        assert!(try_search(&data, pasted_url, &["--strict-syntax"]).is_err());
+
+        assert_eq!(
+            try_search(&data, r#""moved value""#, &["--strict-syntax"]).unwrap(),
+            (vec!["https://example.com/moved".to_string()], false)
+        );
+        assert!(try_search(&data, "(:\"", &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_only_the_words_of_invalid_queries() {
+        assert_eq!(
+            plain_terms("Error: \"Can't (open) URL")
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            ["error", "can", "t", "open", "url"]
+        );
+    }
+
+    #[test]
+    fn adds_the_slop_to_the_phrases_without_one() {
+        assert_eq!(
+            add_phrase_slop(r#"rust "moved value" "borrow check"~1"#, 2),
+            r#"rust "moved value"~2 "borrow check"~1"#
+        );
+        assert_eq!(add_phrase_slop("moved value", 2), "moved value");
+    }
+
+    #[test]
+    fn matches_the_phrases_with_the_slop() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://example.com/moved",
+                    "Errors",
+                    "<p>error: borrow of moved value</p>",
+                ),
+                visited_page(
+                    "https://example.com/other",
+                    "Notes",
+                    "<p>the value was moved before the borrow</p>",
+                ),
+            ],
+            &[],
+        );
+
+        let moved = vec!["https://example.com/moved".to_string()];
+        assert_eq!(data.search_urls(r#""moved value""#, &[]), moved);
+        assert_eq!(
+            data.search_urls(r#""borrow value""#, &[]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            data.search_urls(r#""borrow value""#, &["--phrase-slop=2"]),
+            moved
+        );
+        assert_eq!(
+            data.search_urls(r#""borrow value"~1"#, &["--phrase-slop=2"]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            data.search_urls(r#""value moved""#, &[]),
+            Vec::<String>::new()
+        );
+        // Swapping two words takes a slop of 2
+        let mut swapped = data.search_urls(r#""value moved""#, &["--phrase-slop=2"]);
+        swapped.sort();
+        assert_eq!(
+            swapped,
+            ["https://example.com/moved", "https://example.com/other"]
+        );
+    }
+
+    #[test]
+    fn boosts_the_pages_visited_often() {
+        let data = TestData::new();
+        let mut often_visited = visited_page(
+            "https://example.com/often",
+            "Notes",
+            "<p>The tokio runtime, with a few words about other things</p>",
+        );
+        often_visited.0.visit_count = Some(16);
+        let mut never_counted = visited_page(
+            "https://example.com/uncounted",
+            "Notes",
+            "<p>The tokio runtime runs tokio tasks</p>",
+        );
+        never_counted.0.visit_count = None;
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://example.com/once",
+                    "Notes",
+                    "<p>The tokio runtime and the tokio tasks</p>",
+                ),
+                often_visited,
+                never_counted,
+            ],
+            &[],
+        );
+
+        let by_text = data.search_urls("tokio", &[]);
+        assert_ne!(by_text[0], "https://example.com/often");
+        // 16 visits multiply the score by 5, and no visit count counts as a single visit
+        let by_frequency = data.search_urls("tokio", &["--rank=frequent"]);
+        assert_eq!(by_frequency[0], "https://example.com/often");
+        let others = |urls: Vec<String>| {
+            urls.into_iter()
+                .filter(|url| url != "https://example.com/often")
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(others(by_frequency), others(by_text));
+
+        let arguments =
+            SearchArguments::parse_options(["--rank=frequent", "--sort=recent"]).unwrap();
+        assert!(check_ranking(&arguments).is_err());
+    }
+
+    #[test]
+    fn collapses_the_snapshots_of_the_same_page() {
+        let data = TestData::new();
+        let mut older = visited_page(
+            "https://example.com/post/?utm_source=twitter",
+            "Post",
+            "<p>The tokio runtime</p>",
+        );
+        older.0.last_visit = Some(date(2023, 1, 1));
+        data.index_pages(
+            vec![
+                older,
+                visited_page(
+                    "https://example.com/post",
+                    "Post",
+                    "<p>The tokio runtime</p>",
+                ),
+                visited_page(
+                    "https://example.com/other",
+                    "Other",
+                    "<p>Another tokio page</p>",
+                ),
+            ],
+            &[],
+        );
+
+        let mut urls = data.search_urls("tokio", &[]);
+        urls.sort();
+        // The most recently visited snapshot is kept, with its own URL
+        assert_eq!(
+            urls,
+            ["https://example.com/other", "https://example.com/post"]
+        );
+        assert_eq!(data.search_urls("tokio", &["--show-duplicates"]).len(), 3);
+    }
+
+    #[test]
+    fn shows_only_the_bookmarked_pages() {
+        let data = TestData::new();
+        let mut bookmarked = visited_page(
+            "https://example.com/bookmarked",
+            "Tokio",
+            "<p>The tokio runtime</p>",
+        );
+        bookmarked.0.bookmarked = true;
+        bookmarked.0.bookmark_folders = vec!["Rust/async".to_string()];
+        data.index_pages(
+            vec![
+                bookmarked,
+                visited_page(
+                    "https://example.com/visited",
+                    "Tokio",
+                    "<p>The tokio runtime and the tokio tasks</p>",
+                ),
+            ],
+            &[],
+        );
+
+        assert_eq!(data.search_urls("tokio", &[]).len(), 2);
+        for (query, options) in [
+            ("tokio", &["--bookmarked"][..]),
+            ("", &["--bookmarked"]),
+            ("tokio", &["--folder=Rust"]),
+            ("", &["--folder=Rust/async"]),
+        ] {
+            assert_eq!(
+                data.search_urls(query, options),
+                ["https://example.com/bookmarked"],
+                "{:?} {:?}",
+                query,
+                options
+            );
+        }
+        assert!(data.search_urls("", &["--folder=Python"]).is_empty());
+    }
+
+    fn hit(url: &str) -> SearchHit {
+        SearchHit {
+            index_name: DEFAULT_INDEX_NAME.to_string(),
+            score: Some(1.),
+            url: url.to_string(),
+            title: None,
+            synthetic_title: None,
+            last_visit: None,
+            published: None,
+            word_count: None,
+            simhash: None,
+            canonical_url: None,
+            snippet: HitSnippet::default(),
+            snapshot_at: None,
+            matched_fields: Vec::new(),
+            fuzzy_only: false,
+            notes: Vec::new(),
+            bookmarked: false,
+            bookmark_folders: Vec::new(),
+            more_from_domain: 0,
+            snapshots: 1,
+            doc_address: DocAddress::new(0, 0),
+        }
+    }
+
+    /// The URLs with the count of hidden results after them, like "a.com/1 +2"
+    fn urls_and_more(hits: &[SearchHit]) -> Vec<String> {
+        hits.iter()
+            .map(|hit| {
+                let url = hit.url.trim_start_matches("https://");
+                match hit.more_from_domain {
+                    0 => url.to_string(),
+                    more => format!("{} +{}", url, more),
+                }
+            })
+            .collect()
+    }
+
+    /// From the best one
+    const HITS: &[&str] = &[
+        "https://a.com/1",
+        "https://b.org/1",
+        "https://www.a.com/2",
+        "https://c.net/1",
+        "https://docs.a.com/3",
+        "https://a.com/4",
+        "https://b.org/2",
+        "https://d.io/1",
+    ];
+
+    #[test]
+    fn groups_the_hits_by_domain() {
+        let hits = HITS.iter().map(|url| hit(url)).collect();
+        assert_eq!(
+            urls_and_more(&group_domains(hits, 3, 2)),
+            ["a.com/1", "www.a.com/2 +2", "b.org/1", "b.org/2", "c.net/1"]
+        );
+
+        let hits = HITS.iter().map(|url| hit(url)).collect();
+        assert_eq!(
+            urls_and_more(&group_domains(hits, 10, 1)),
+            ["a.com/1 +3", "b.org/1 +1", "c.net/1", "d.io/1"]
+        );
+    }
+
+    #[test]
+    fn collapses_the_hits_by_domain() {
+        let hits = HITS.iter().map(|url| hit(url)).collect();
+        assert_eq!(
+            urls_and_more(&collapse_domains(hits, 2)),
+            [
+                "a.com/1",
+                "b.org/1",
+                "www.a.com/2 +2",
+                "c.net/1",
+                "b.org/2",
+                "d.io/1"
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_the_groups_by_their_best_hit() {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://docs.example.com/one",
+                    "Tokio",
+                    "<p>tokio tokio tokio runtime</p>",
+                ),
+                visited_page(
+                    "https://blog.example.org/one",
+                    "Runtime",
+                    "<p>tokio runtime and other things</p>",
+                ),
+                visited_page(
+                    "https://docs.example.com/two",
+                    "Notes",
+                    "<p>tokio and many other words about something else entirely</p>",
+                ),
+            ],
+            &[],
+        );
+
+        let results = data.search("tokio", &["--group-by=domain"]);
+        let domains: Vec<String> = results
+            .hits
+            .iter()
+            .map(|hit| group_domain(&hit.url))
+            .collect();
+        assert_eq!(domains, ["example.com", "example.com", "example.org"]);
+
+        let results = data.search("tokio", &["--group-by=domain", "--limit=1"]);
+        assert_eq!(
+            urls_and_more(&results.hits),
+            ["docs.example.com/one", "docs.example.com/two"]
+        );
+    }
 }
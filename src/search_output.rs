@@ -0,0 +1,598 @@
+use crate::domain::registrable_domain;
+use crate::relative_date::relative_date;
+use crate::search::{group_domain, SearchHit};
+use crate::snippets::HitSnippet;
+use crate::timeline::TimelineMonth;
+use chrono::{Local, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+/// How many chars the bar of the busiest month takes in the timeline
+const TIMELINE_BAR_WIDTH: u64 = 40;
+
+/// Everything that a search displays
+pub struct SearchResults {
+    /// How long the search took
+    pub elapsed: Duration,
+    /// How the score of one result was computed, if it was asked for
+    pub explanation: Option<Value>,
+    /// Whether the query syntax was invalid and the query was searched as plain words
+    pub syntax_ignored: bool,
+    /// The query with its misspelled words corrected, when nothing matched
+    pub correction: Option<Correction>,
+    /// How the query was parsed, when there are no hits to show
+    pub parsed_query: Option<String>,
+    /// How many results were hidden for scoring below the minimum
+    pub below_min_score: usize,
+    /// A description of the active filter on the last visit, if any
+    pub visit_filter: Option<String>,
+    /// How many of the best results were skipped
+    pub offset: usize,
+    /// Approximately how many documents match the query in total
+    pub total_matches: usize,
+    pub hits: Vec<SearchHit>,
+    /// How many matches were last visited in each period, if they were requested
+    pub facet_counts: Option<BTreeMap<String, u64>>,
+    /// The matches by the month of their last visit, if they were requested
+    pub timeline: Option<Vec<TimelineMonth>>,
+    /// Whether the hits come from several indexes
+    pub all_indexes: bool,
+    /// Whether the hits are grouped by domain, the hits of each group following each other
+    pub grouped: bool,
+    /// Whether the hits are the versions of one URL
+    pub versions: bool,
+}
+
+#[derive(Serialize)]
+pub struct Correction {
+    pub query: String,
+    /// Whether the results are of the corrected query, instead of the query itself
+    pub applied: bool,
+}
+
+impl SearchResults {
+    /// Find a hit by the rank that was displayed next to it
+    pub fn hit_by_rank(&self, rank: usize) -> Option<&SearchHit> {
+        self.hits.get(rank.checked_sub(self.offset + 1)?)
+    }
+
+    /// The domain and the hits of each group, when the hits are grouped
+    fn groups(&self) -> Vec<(String, &[SearchHit])> {
+        self.hits
+            .chunk_by(|a, b| group_domain(&a.url) == group_domain(&b.url))
+            .map(|group| (group_domain(&group[0].url), group))
+            .collect()
+    }
+}
+
+/// Prints search results in one output format
+pub trait SearchFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()>;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SearchFormat {
+    Human,
+    /// Like human, but with the matches in snippets marked by `<b>` tags
+    Html,
+    /// A single JSON document
+    Json,
+    /// One compact JSON document per result
+    Jsonl,
+}
+
+/// What else the human formats show
+pub struct DisplayOptions {
+    /// Show the relevance score of each result
+    pub scores: bool,
+    /// Show how many documents matched and how long it took
+    pub verbose: bool,
+    /// Show the dates in UTC, instead of in the local time zone and relative to now
+    pub utc: bool,
+}
+
+pub fn search_formatter(format: SearchFormat, options: DisplayOptions) -> Box<dyn SearchFormatter> {
+    let is_terminal = io::stdout().is_terminal();
+    match format {
+        SearchFormat::Human => Box::new(HumanFormatter {
+            is_terminal,
+            highlight: if is_terminal {
+                Highlight::Ansi
+            } else {
+                Highlight::None
+            },
+            options,
+        }),
+        SearchFormat::Html => Box::new(HumanFormatter {
+            is_terminal,
+            highlight: Highlight::Html,
+            options,
+        }),
+        SearchFormat::Json => Box::new(JsonFormatter),
+        SearchFormat::Jsonl => Box::new(JsonlFormatter),
+    }
+}
+
+struct HumanFormatter {
+    /// Whether escape codes can be used to style the output
+    is_terminal: bool,
+    highlight: Highlight,
+    options: DisplayOptions,
+}
+
+/// How to mark the matches in snippets
+#[derive(Clone, Copy)]
+enum Highlight {
+    /// No marks, for when the output is piped
+    None,
+    /// Bold and colored with terminal escape codes
+    Ansi,
+    /// Inside `<b>` tags, with the rest of the text escaped
+    Html,
+}
+
+impl SearchFormatter for HumanFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()> {
+        if results.syntax_ignored {
+            println!("Invalid query syntax, searching for the words only");
+        }
+        if let Some(visit_filter) = &results.visit_filter {
+            println!("Only pages {}", visit_filter);
+        }
+        if let Some(correction) = results.correction.as_ref().filter(|c| c.applied) {
+            println!(
+                "Nothing matched, showing results for {:?}",
+                correction.query
+            );
+        }
+
+        // The total is approximate because near-duplicates and results from several indexes are
+        // not accounted for exactly
+        if results.hits.is_empty() {
+            if results.total_matches == 0 {
+                println!("No results");
+                if let Some(correction) = results.correction.as_ref().filter(|c| !c.applied) {
+                    println!("Did you mean: {:?}?", correction.query);
+                }
+            } else if results.below_min_score > 0 {
+                println!(
+                    "No results with a high enough score, {} scored below the minimum",
+                    results.below_min_score
+                );
+            } else {
+                println!(
+                    "No more results, there are approximately {} in total",
+                    results.total_matches
+                );
+            }
+            if let Some(parsed_query) = &results.parsed_query {
+                println!("The query was understood as: {}", parsed_query);
+            }
+        } else if results.grouped {
+            println!(
+                "Showing the best results of {} sites, of approximately {} results\n",
+                results.groups().len(),
+                results.total_matches
+            );
+        } else {
+            println!(
+                "Showing results {}..{} of approximately {}\n",
+                results.offset + 1,
+                results.offset + results.hits.len(),
+                results.total_matches
+            );
+        }
+
+        if results.grouped {
+            let mut rank = results.offset;
+            for (domain, hits) in results.groups() {
+                let more = hits.last().map_or(0, |hit| hit.more_from_domain);
+                println!("{} ({} results)\n", domain, hits.len() + more);
+                for hit in hits {
+                    rank += 1;
+                    self.print_hit(rank, hit, results);
+                }
+            }
+        } else {
+            for (index, hit) in results.hits.iter().enumerate() {
+                self.print_hit(results.offset + index + 1, hit, results);
+            }
+        }
+
+        if let Some(facet_counts) = &results.facet_counts {
+            println!("Matches by last visit:");
+            for (period, count) in facet_counts {
+                // Months are indented below their year
+                let indentation = if period.contains('-') { "    " } else { "  " };
+                println!("{}{}: {}", indentation, period, count);
+            }
+        }
+
+        if let Some(timeline) = &results.timeline {
+            print_timeline(timeline);
+        }
+
+        if let Some(explanation) = &results.explanation {
+            println!("Score explanation:");
+            print_explanation(explanation, 1);
+        }
+
+        if self.options.verbose {
+            println!(
+                "{} matching documents found in {} ms",
+                results.total_matches,
+                results.elapsed.as_millis()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl HumanFormatter {
+    fn print_hit(&self, rank: usize, hit: &SearchHit, results: &SearchResults) {
+        let now = Utc::now();
+        let badges: String = hit
+            .matched_fields
+            .iter()
+            .map(|field| format!(" [{}]", field))
+            .collect();
+        let snapshots = if hit.snapshots > 1 {
+            format!(" ({} snapshots)", hit.snapshots)
+        } else {
+            String::new()
+        };
+        println!("{}. {}{}{}", rank, hit.url, badges, snapshots);
+        if self.options.scores {
+            match hit.score {
+                None => println!("  Score: unknown when sorting by date"),
+                Some(score) => println!("  Score: {:.3}", score),
+            }
+        }
+        if results.all_indexes {
+            println!("  Index: {}", hit.index_name);
+        }
+        if self.options.verbose && hit.fuzzy_only {
+            println!("  Matched only with typos");
+        }
+        if let Some(title) = &hit.title {
+            println!("  Title: {}", title);
+        } else if let Some(synthetic_title) = &hit.synthetic_title {
+            // Dimmed, to distinguish it from real titles
+            if self.is_terminal {
+                println!("  Title: \x1b[2;3m{}\x1b[0m", synthetic_title);
+            } else {
+                println!("  Title: {} (from URL)", synthetic_title);
+            }
+        }
+        match hit.last_visit {
+            None => println!("  Last visit: unknown"),
+            Some(last_visit) if self.options.utc => println!("  Last visit: {}", last_visit),
+            Some(last_visit) => println!(
+                "  Last visit: {} ({})",
+                last_visit.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                relative_date(last_visit, now)
+            ),
+        }
+        // The download date tells the versions of the same URL apart
+        match hit.snapshot_at {
+            Some(snapshot_at) if results.versions || hit.snapshots > 1 => {
+                if self.options.utc {
+                    println!("  Downloaded: {}", snapshot_at);
+                } else {
+                    println!(
+                        "  Downloaded: {} ({})",
+                        snapshot_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                        relative_date(snapshot_at, now)
+                    );
+                }
+            }
+            _ => {}
+        }
+        // Publication dates are usually given without a time, so they stay as they are
+        if let Some(published) = hit.published {
+            println!("  Published: {}", published.date_naive());
+        }
+        if let Some(word_count) = hit.word_count {
+            println!("  Words: {}", word_count);
+        }
+        if !hit.snippet.is_empty() {
+            println!("{}", render_snippet(&hit.snippet, self.highlight));
+        }
+        if hit.bookmarked {
+            if hit.bookmark_folders.is_empty() {
+                println!("  ★ Bookmarked");
+            } else {
+                println!("  ★ Bookmarked in {}", hit.bookmark_folders.join(", "));
+            }
+        }
+        for note in &hit.notes {
+            println!("  Note: {}", note);
+        }
+        if hit.more_from_domain > 0 {
+            let domain = registrable_domain(&hit.url).unwrap_or_default();
+            println!("  +{} more from {}", hit.more_from_domain, domain);
+        }
+        println!();
+    }
+}
+
+/// Print a bar for each month, scaled to the busiest one, followed by its best matches
+fn print_timeline(timeline: &[TimelineMonth]) {
+    println!("Matches by month of last visit:");
+    let max_count = timeline.iter().map(|month| month.count).max().unwrap_or(1);
+    for month in timeline {
+        // Any match gets at least a sliver of a bar
+        let bar_length = (month.count * TIMELINE_BAR_WIDTH).div_ceil(max_count.max(1));
+        let line = format!(
+            "  {} {:>5} {}",
+            month.month,
+            month.count,
+            "█".repeat(bar_length as usize)
+        );
+        println!("{}", line.trim_end());
+        for hit in &month.hits {
+            println!(
+                "            {}",
+                hit.title
+                    .as_deref()
+                    .map_or(hit.url.clone(), |title| format!("{} ({})", title, hit.url))
+            );
+        }
+    }
+}
+
+/// Print the explanation tree with one node per line, children indented below their parent
+fn print_explanation(explanation: &Value, depth: usize) {
+    let indentation = "  ".repeat(depth);
+    let value = explanation["value"].as_f64().unwrap_or_default();
+    let description = explanation["description"].as_str().unwrap_or_default();
+    println!("{}{:.3}  {}", indentation, value, description);
+
+    for context in explanation["context"].as_array().into_iter().flatten() {
+        if let Some(context) = context.as_str() {
+            println!("{}       ({})", indentation, context);
+        }
+    }
+    for detail in explanation["details"].as_array().into_iter().flatten() {
+        print_explanation(detail, depth + 1);
+    }
+}
+
+/// Render the snippet fragment with its matches marked, collapsing the runs of whitespace left by
+/// the text extraction
+fn render_snippet(snippet: &HitSnippet, highlight: Highlight) -> String {
+    let (start_mark, end_mark) = match highlight {
+        Highlight::None => ("", ""),
+        Highlight::Ansi => ("\x1b[1;33m", "\x1b[0m"),
+        Highlight::Html => ("<b>", "</b>"),
+    };
+
+    let mut rendered = String::new();
+    let mut ranges = snippet.highlights.iter().peekable();
+    let mut in_match = false;
+    let mut pending_space = false;
+    for (index, c) in snippet.text.char_indices() {
+        // Ranges are byte offsets, compared with each char start so that an offset that is not at
+        // a char boundary can never split a char
+        if in_match && ranges.peek().is_none_or(|range| index >= range.end) {
+            rendered.push_str(end_mark);
+            in_match = false;
+            ranges.next();
+        }
+        if c.is_whitespace() {
+            pending_space = !rendered.is_empty();
+            continue;
+        }
+        if pending_space {
+            rendered.push(' ');
+            pending_space = false;
+        }
+        if !in_match && ranges.peek().is_some_and(|range| index >= range.start) {
+            rendered.push_str(start_mark);
+            in_match = true;
+        }
+        match (highlight, c) {
+            (Highlight::Html, '&') => rendered.push_str("&amp;"),
+            (Highlight::Html, '<') => rendered.push_str("&lt;"),
+            (Highlight::Html, '>') => rendered.push_str("&gt;"),
+            (Highlight::Html, '"') => rendered.push_str("&quot;"),
+            _ => rendered.push(c),
+        }
+    }
+    if in_match {
+        rendered.push_str(end_mark);
+    }
+
+    rendered
+}
+
+/// Only the number of matches
+pub struct CountFormatter;
+
+impl SearchFormatter for CountFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()> {
+        println!("{}", results.total_matches);
+        Ok(())
+    }
+}
+
+/// Only the URLs, one per line, to be piped into other commands
+pub struct UrlsFormatter;
+
+impl SearchFormatter for UrlsFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()> {
+        for hit in &results.hits {
+            println!("{}", hit.url);
+        }
+        Ok(())
+    }
+}
+
+/// One JSON document with all the results
+struct JsonFormatter;
+
+#[derive(Serialize)]
+struct JsonResults<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<&'a Value>,
+    syntax_ignored: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correction: Option<&'a Correction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parsed_query: Option<&'a str>,
+    #[serde(skip_serializing_if = "is_zero")]
+    below_min_score: usize,
+    offset: usize,
+    total_matches: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hits: Option<Vec<JsonHit<'a>>>,
+    /// Instead of the hits, when they are grouped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<JsonGroup<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    facet_counts: Option<&'a BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeline: Option<&'a [TimelineMonth]>,
+}
+
+impl SearchFormatter for JsonFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()> {
+        println!("{}", serde_json::to_string_pretty(&json_results(results))?);
+        Ok(())
+    }
+}
+
+/// The results in the structure of the JSON format
+pub fn json_results(results: &SearchResults) -> impl Serialize + '_ {
+    JsonResults {
+        explanation: results.explanation.as_ref(),
+        syntax_ignored: results.syntax_ignored,
+        correction: results.correction.as_ref(),
+        parsed_query: results.parsed_query.as_deref(),
+        below_min_score: results.below_min_score,
+        offset: results.offset,
+        total_matches: results.total_matches,
+        hits: (!results.grouped).then(|| json_hits(results).collect()),
+        groups: results.grouped.then(|| json_groups(results)),
+        facet_counts: results.facet_counts.as_ref(),
+        timeline: results.timeline.as_deref(),
+    }
+}
+
+/// One JSON line per hit, for tools that read line by line
+struct JsonlFormatter;
+
+impl SearchFormatter for JsonlFormatter {
+    fn print(&self, results: &SearchResults) -> anyhow::Result<()> {
+        for json_hit in json_hits(results) {
+            println!("{}", serde_json::to_string(&json_hit)?);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct JsonHit<'a> {
+    pub rank: usize,
+    pub score: Option<f32>,
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    /// Whether the title was built from the URL
+    pub synthetic_title: bool,
+    pub domain: Option<String>,
+    pub index: &'a str,
+    pub last_visit: Option<chrono::DateTime<Utc>>,
+    pub published: Option<chrono::DateTime<Utc>>,
+    pub word_count: Option<u64>,
+    pub snippet: JsonSnippet<'a>,
+    pub matched_fields: &'a [String],
+    /// Whether the page only matched the words of the query with typos
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fuzzy_only: bool,
+    /// The notes written about the page with the annotate command
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub notes: &'a [String],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bookmarked: bool,
+    /// The folders of the bookmarks of the page, like "Rust/async"
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub bookmark_folders: &'a [String],
+    /// How many more results of the same site were hidden after this one
+    #[serde(skip_serializing_if = "is_zero")]
+    pub more_from_domain: usize,
+    /// When the shown version of the page was downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_at: Option<chrono::DateTime<Utc>>,
+    /// How many snapshots of the page were found under different URLs or as older versions,
+    /// including this one
+    pub snapshots: usize,
+}
+
+#[derive(Serialize)]
+struct JsonGroup<'a> {
+    domain: String,
+    hits: Vec<JsonHit<'a>>,
+    /// How many more results of the group were hidden
+    more: usize,
+}
+
+fn json_groups(results: &SearchResults) -> Vec<JsonGroup<'_>> {
+    let mut json_hits = json_hits(results);
+    results
+        .groups()
+        .into_iter()
+        .map(|(domain, hits)| JsonGroup {
+            domain,
+            hits: json_hits.by_ref().take(hits.len()).collect(),
+            more: hits.last().map_or(0, |hit| hit.more_from_domain),
+        })
+        .collect()
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+#[derive(Serialize)]
+pub struct JsonSnippet<'a> {
+    pub text: &'a str,
+    /// The byte ranges of the matches in the text, as `[start, end]` pairs
+    pub highlights: Vec<[usize; 2]>,
+}
+
+pub fn json_hits(results: &SearchResults) -> impl Iterator<Item = JsonHit<'_>> {
+    results.hits.iter().enumerate().map(|(index, hit)| JsonHit {
+        rank: results.offset + index + 1,
+        score: hit.score,
+        url: &hit.url,
+        title: hit.title.as_deref().or(hit.synthetic_title.as_deref()),
+        synthetic_title: hit.title.is_none() && hit.synthetic_title.is_some(),
+        domain: registrable_domain(&hit.url),
+        index: &hit.index_name,
+        last_visit: hit.last_visit,
+        published: hit.published,
+        word_count: hit.word_count,
+        snippet: JsonSnippet {
+            text: &hit.snippet.text,
+            highlights: hit
+                .snippet
+                .highlights
+                .iter()
+                .map(|range| [range.start, range.end])
+                .collect(),
+        },
+        matched_fields: &hit.matched_fields,
+        fuzzy_only: hit.fuzzy_only,
+        notes: &hit.notes,
+        bookmarked: hit.bookmarked,
+        bookmark_folders: &hit.bookmark_folders,
+        more_from_domain: hit.more_from_domain,
+        snapshot_at: hit.snapshot_at,
+        snapshots: hit.snapshots,
+    })
+}
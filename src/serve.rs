@@ -0,0 +1,641 @@
+use crate::data_lock::ServersLock;
+use crate::feed::recent_pages_feed;
+use crate::search::{
+    open_indexes, run_search, OpenedIndex, SearchArguments, SearchField, SearchQuery,
+};
+use crate::search_output::json_results;
+use crate::suggest::complete_last_word;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use anyhow::Context;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use clap::{Args, Parser};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Url;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tantivy::Searcher;
+use tracing::warn;
+
+/// The search page, which calls `/search`
+const WEB_UI: &str = include_str!("web_ui.html");
+/// Lets browsers add the server as a search engine
+const OPENSEARCH_DESCRIPTION: &str = include_str!("opensearch.xml");
+/// How many completions to suggest to the browser
+const SUGGESTIONS: usize = 8;
+/// How many pages the feed has, unless the "limit" parameter says otherwise
+const DEFAULT_FEED_ENTRIES: usize = 50;
+
+/// How long to wait for a client to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// The most bytes read from the request line and the headers, so that a client can't make the
+/// server grow its memory without bound
+const MAX_REQUEST_HEAD_BYTES: u64 = 16 * 1024;
+/// How many connections are answered at once. The others wait in a queue of the same size, and the
+/// ones beyond it are refused.
+const CONNECTION_THREADS: usize = 16;
+
+/// Search options that only make sense in the command line
+const FORBIDDEN_PARAMETERS: &[&str] = &[
+    "format",
+    "count",
+    "quiet",
+    "verbose",
+    "open",
+    "open-first",
+    "index-name",
+    "all-indexes",
+    "saved",
+    "stdin",
+    "export",
+    "export-md",
+];
+
+#[derive(Args, Debug)]
+pub struct ServeArguments {
+    /// The port to listen on
+    #[arg(long, default_value_t = 7700)]
+    port: u16,
+    /// The address to listen on
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    bind: IpAddr,
+    /// Allow binding to an address other than localhost, which exposes your history to the network.
+    /// It needs --auth-token
+    #[arg(long)]
+    allow_remote: bool,
+    /// Require this token in every request, as an "Authorization: Bearer" header or as a "token"
+    /// parameter. Without a value, the token stored in the data directory is used, generated the
+    /// first time
+    #[arg(long, num_args = 0..=1, default_missing_value = "")]
+    auth_token: Option<String>,
+    /// The name of the index to search in
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Search in all the indexes and merge their results by score
+    #[arg(long, conflicts_with = "index_name")]
+    all_indexes: bool,
+}
+
+/// The server options alone, to read them from elsewhere than the command line
+#[derive(Parser, Debug)]
+struct ServeOptions {
+    #[command(flatten)]
+    arguments: ServeArguments,
+}
+
+impl ServeArguments {
+    /// Parse the options written like in the command line, like `["--port=8080"]`
+    pub fn parse_options<'a>(options: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+        let argv = ["serve"].into_iter().chain(options);
+        Ok(ServeOptions::try_parse_from(argv)?.arguments)
+    }
+}
+
+/// Answer searches over HTTP until killed, with the indexes opened once
+pub fn serve(serve_arguments: ServeArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if !serve_arguments.bind.is_loopback() && !serve_arguments.allow_remote {
+        anyhow::bail!(
+            "binding to {} exposes your history to the network, pass --allow-remote to do it anyway",
+            serve_arguments.bind
+        );
+    }
+    let auth_token = match serve_arguments.auth_token.as_deref() {
+        None if !serve_arguments.bind.is_loopback() => anyhow::bail!(
+            "anyone on the network could read your history from {}, pass --auth-token to require \
+             a token",
+            serve_arguments.bind
+        ),
+        None => None,
+        Some("") => Some(stored_auth_token(data_paths)?),
+        Some(auth_token) => Some(auth_token.to_string()),
+    };
+
+    let index_options = if serve_arguments.all_indexes {
+        vec!["--all-indexes".to_string()]
+    } else {
+        vec![format!("--index-name={}", serve_arguments.index_name)]
+    };
+    let _servers_lock = ServersLock::acquire_shared(data_paths)?;
+    let indexes = open_indexes(
+        &SearchArguments::parse_options(index_options.iter().map(String::as_str))?,
+        data_paths,
+    )?;
+
+    let listener = TcpListener::bind((serve_arguments.bind, serve_arguments.port))?;
+    let local_address = listener.local_addr()?;
+    println!("Listening on http://{}", local_address);
+    if let Some(auth_token) = &auth_token {
+        println!(
+            "Requests need the token {}, open http://{}/?token={} to search with it",
+            auth_token,
+            local_address,
+            utf8_percent_encode(auth_token, NON_ALPHANUMERIC)
+        );
+    }
+
+    let server = Server {
+        indexes,
+        index_options,
+        data_paths,
+        local_address,
+        auth_token,
+    };
+    let (connection_sender, connection_receiver) =
+        mpsc::sync_channel::<TcpStream>(CONNECTION_THREADS);
+    let connection_receiver = Mutex::new(connection_receiver);
+    thread::scope(|scope| {
+        for _ in 0..CONNECTION_THREADS {
+            scope.spawn(|| loop {
+                // Not locked while answering, so that the threads answer at the same time
+                let next = connection_receiver.lock().unwrap().recv();
+                let Ok(stream) = next else {
+                    break;
+                };
+                if let Err(error) = server.handle_connection(stream) {
+                    warn!("Failed to answer a request: {}", error);
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => match connection_sender.try_send(stream) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(stream)) => {
+                        let response = Response::error(
+                            503,
+                            "Service Unavailable",
+                            "too many connections, try again later",
+                        );
+                        let _ = response.write(&stream);
+                    }
+                    Err(TrySendError::Disconnected(_)) => break,
+                },
+                Err(error) => warn!("Failed to accept a connection: {}", error),
+            }
+        }
+        drop(connection_sender);
+    });
+
+    Ok(())
+}
+
+/// What the connections share
+struct Server<'a> {
+    indexes: Vec<OpenedIndex>,
+    /// The search options that choose the indexes
+    index_options: Vec<String>,
+    data_paths: &'a DataPaths,
+    local_address: SocketAddr,
+    /// The token that the requests must have, if any
+    auth_token: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    /// Where to go instead, for redirections
+    location: Option<String>,
+    body: String,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: 200,
+            reason: "OK",
+            content_type,
+            location: None,
+            body,
+        }
+    }
+
+    fn json(body: String) -> Self {
+        Response::ok("application/json", body)
+    }
+
+    fn error(status: u16, reason: &'static str, message: &str) -> Self {
+        Response {
+            status,
+            reason,
+            content_type: "application/json",
+            location: None,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    fn redirect(location: String) -> Self {
+        Response {
+            status: 302,
+            reason: "Found",
+            content_type: "text/plain",
+            location: Some(location),
+            body: String::new(),
+        }
+    }
+
+    fn write(&self, mut stream: &TcpStream) -> anyhow::Result<()> {
+        write!(stream, "HTTP/1.1 {} {}\r\n", self.status, self.reason)?;
+        if let Some(location) = &self.location {
+            write!(stream, "Location: {}\r\n", location)?;
+        }
+        write!(
+            stream,
+            "Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.content_type,
+            self.body.len(),
+            self.body
+        )?;
+        Ok(())
+    }
+}
+
+impl Server<'_> {
+    fn handle_connection(&self, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let reader = BufReader::new((&stream).take(MAX_REQUEST_HEAD_BYTES));
+        let response = match read_request_head(reader)? {
+            Ok(head) => match head.request_line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["GET", target, _] => {
+                    self.answer(target, head.host.as_deref(), head.bearer_token.as_deref())
+                }
+                [_, _, _] => Response::error(405, "Method Not Allowed", "only GET is supported"),
+                _ => Response::error(400, "Bad Request", "invalid request line"),
+            },
+            Err(response) => response,
+        };
+        response.write(&stream)
+    }
+
+    fn answer(&self, target: &str, host: Option<&str>, bearer_token: Option<&str>) -> Response {
+        let Ok(url) = Url::parse("http://localhost").and_then(|base| base.join(target)) else {
+            return Response::error(400, "Bad Request", "invalid path");
+        };
+
+        // The search page has no data, and asks for the token itself
+        if !matches!(url.path(), "/" | "/healthz") && !self.is_authorized(&url, bearer_token) {
+            return Response::error(
+                401,
+                "Unauthorized",
+                "missing or invalid token, give it as an \"Authorization: Bearer\" header or as \
+                 a \"token\" parameter",
+            );
+        }
+
+        match url.path() {
+            "/" => Response::ok("text/html; charset=utf-8", WEB_UI.to_string()),
+            "/opensearch.xml" => Response::ok(
+                "application/opensearchdescription+xml",
+                OPENSEARCH_DESCRIPTION
+                    .replace("{base_url}", &self.base_url(host))
+                    .replace("{token_parameter}", &self.token_parameter()),
+            ),
+            // The browser searches here, showing the results in the search page
+            "/go" => {
+                let query = query_parameter(&url).unwrap_or_default();
+                Response::redirect(format!(
+                    "/?q={}",
+                    utf8_percent_encode(&query, NON_ALPHANUMERIC)
+                ))
+            }
+            "/suggest" => match self.suggest(&url) {
+                Ok(body) => Response::ok("application/x-suggestions+json", body),
+                Err(error) => Response::error(400, "Bad Request", &error.to_string()),
+            },
+            "/healthz" => Response::json(serde_json::json!({ "status": "ok" }).to_string()),
+            "/search" => match self.search(&url) {
+                Ok(body) => Response::json(body),
+                Err(error) => Response::error(400, "Bad Request", &error.to_string()),
+            },
+            "/feed.xml" => match self.feed(&url, target, host) {
+                Ok(body) => Response::ok("application/atom+xml; charset=utf-8", body),
+                Err(error) => Response::error(400, "Bad Request", &error.to_string()),
+            },
+            _ => Response::error(
+                404,
+                "Not Found",
+                "unknown path, use /, /search, /feed.xml or /healthz",
+            ),
+        }
+    }
+
+    /// Whether the request has the token, when one is required
+    fn is_authorized(&self, url: &Url, bearer_token: Option<&str>) -> bool {
+        let Some(auth_token) = &self.auth_token else {
+            return true;
+        };
+        let parameter_token = url
+            .query_pairs()
+            .find(|(name, _)| name == "token")
+            .map(|(_, value)| value.into_owned());
+        let authorized = [bearer_token, parameter_token.as_deref()]
+            .into_iter()
+            .flatten()
+            .any(|token| constant_time_eq(token.as_bytes(), auth_token.as_bytes()));
+        authorized
+    }
+
+    /// The token as a parameter to add to the URLs of the OpenSearch description, since the
+    /// browsers can't add headers to their searches
+    fn token_parameter(&self) -> String {
+        match &self.auth_token {
+            Some(auth_token) => format!(
+                "&amp;token={}",
+                utf8_percent_encode(auth_token, NON_ALPHANUMERIC)
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// The URL that the browser used to reach the server, to build the URLs of the OpenSearch
+    /// description
+    fn base_url(&self, host: Option<&str>) -> String {
+        // The host is copied into the XML, so anything unusual is ignored
+        let is_valid_host = |host: &&str| {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".-:[]".contains(c))
+        };
+        match host.filter(is_valid_host) {
+            Some(host) => format!("http://{}", host),
+            None => format!("http://{}", self.local_address),
+        }
+    }
+
+    /// Answer the OpenSearch suggestions protocol, with the query completed by the indexed words,
+    /// like `["rust asy", ["rust async", "rust asynchronous"]]`
+    fn suggest(&self, url: &Url) -> anyhow::Result<String> {
+        let query = query_parameter(url).context("missing the query parameter \"q\"")?;
+        let searchers: Vec<Searcher> = self
+            .indexes
+            .iter()
+            .map(|opened_index| opened_index.reader.searcher())
+            .collect();
+        let words = complete_last_word(
+            &searchers,
+            &query,
+            &[SearchField::Content, SearchField::Title],
+            SUGGESTIONS,
+        )?;
+
+        let start = query
+            .trim_end()
+            .rsplit_once(char::is_whitespace)
+            .map_or("", |(start, _)| start);
+        let suggestions: Vec<String> = words
+            .into_iter()
+            .map(|word| format!("{} {}", start, word).trim_start().to_string())
+            .collect();
+        Ok(serde_json::json!([query, suggestions]).to_string())
+    }
+
+    /// Run the search described by the parameters, like `/search?q=tokio&limit=5&site=docs.rs`,
+    /// and return the same JSON as `search --format json`
+    fn search(&self, url: &Url) -> anyhow::Result<String> {
+        let mut query = None;
+        let mut options = Vec::new();
+        for (name, value) in url.query_pairs() {
+            if name == "q" {
+                query = Some(value.to_string());
+                continue;
+            }
+            if name == "token" {
+                continue;
+            }
+
+            // Every other parameter is a search option, like "site" for `--site`
+            let name = name.replace('_', "-");
+            if FORBIDDEN_PARAMETERS.contains(&name.as_str()) {
+                anyhow::bail!("the parameter {:?} is not available in the server", name);
+            }
+            // Flags are given without a value, like in `/search?q=tokio&collapse-near-duplicates`
+            if value.is_empty() {
+                options.push(format!("--{}", name));
+            } else {
+                options.push(format!("--{}={}", name, value));
+            }
+        }
+        let query = query.context("missing the query parameter \"q\"")?;
+
+        let arguments = SearchArguments::parse_options(
+            self.index_options
+                .iter()
+                .chain(&options)
+                .map(String::as_str),
+        )?;
+        let results = run_search(
+            &self.indexes,
+            &SearchQuery::Text(query),
+            &arguments,
+            self.data_paths,
+        )?;
+        let body = serde_json::to_string(&json_results(&results))?;
+        Ok(body)
+    }
+
+    /// The Atom feed of the last indexed pages, like `/feed.xml?site=docs.rs&limit=20`
+    fn feed(&self, url: &Url, target: &str, host: Option<&str>) -> anyhow::Result<String> {
+        let mut site = None;
+        let mut limit = DEFAULT_FEED_ENTRIES;
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "site" => site = Some(value.to_string()),
+                "token" => {}
+                "limit" => {
+                    limit = value
+                        .parse()
+                        .with_context(|| format!("invalid limit {:?}", value))?
+                }
+                _ => anyhow::bail!("unknown parameter {:?}, use site or limit", name),
+            }
+        }
+        let feed_url = format!("{}{}", self.base_url(host), target);
+        recent_pages_feed(&self.indexes, site.as_deref(), limit, &feed_url)
+    }
+}
+
+/// What the server needs from the request line and the headers
+#[derive(Debug)]
+struct RequestHead {
+    request_line: String,
+    host: Option<String>,
+    bearer_token: Option<String>,
+}
+
+/// Read the request line and the headers, or the response to give when they are too long. The
+/// reader must stop after [MAX_REQUEST_HEAD_BYTES].
+fn read_request_head(mut reader: impl BufRead) -> anyhow::Result<Result<RequestHead, Response>> {
+    let too_large = || {
+        Response::error(
+            431,
+            "Request Header Fields Too Large",
+            "the request line and the headers are too long",
+        )
+    };
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Without a line end, the limit was reached in the middle of the line
+    if !request_line.ends_with('\n') && !request_line.is_empty() {
+        return Ok(Err(too_large()));
+    }
+    // Only the host and the token are needed from the headers, but all must be read before
+    // answering
+    let mut host = None;
+    let mut bearer_token = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        if !header.ends_with('\n') {
+            // Like for the request line
+            return Ok(Err(too_large()));
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("authorization") {
+                bearer_token = value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .map(|token| token.trim().to_string());
+            }
+        }
+    }
+    Ok(Ok(RequestHead {
+        request_line,
+        host,
+        bearer_token,
+    }))
+}
+
+/// The token saved in the data directory, generated the first time. Only the user can read it.
+fn stored_auth_token(data_paths: &DataPaths) -> anyhow::Result<String> {
+    let path = data_paths.serve_token();
+    if let Ok(auth_token) = fs::read_to_string(&path) {
+        if !auth_token.trim().is_empty() {
+            return Ok(auth_token.trim().to_string());
+        }
+    }
+
+    let mut bytes = [0; 24];
+    OsRng.fill_bytes(&mut bytes);
+    let auth_token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    // Only readable by the user from the start, and also when an empty file was already there
+    let write_token = || -> std::io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(auth_token.as_bytes())
+    };
+    write_token().with_context(|| format!("failed to write the token to {}", path.display()))?;
+    Ok(auth_token)
+}
+
+/// Compare the tokens in a time that doesn't tell how many of their first bytes are right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// The value of the "q" parameter
+fn query_parameter(url: &Url) -> Option<String> {
+    url.query_pairs()
+        .find(|(name, _)| name == "q")
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::TestData;
+
+    fn mode(data: &TestData) -> u32 {
+        let metadata = fs::metadata(data.data_paths.serve_token()).unwrap();
+        metadata.permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn stores_the_token_only_for_the_user() {
+        let data = TestData::new();
+        let auth_token = stored_auth_token(&data.data_paths).unwrap();
+        assert_eq!(auth_token.len(), 48);
+        assert_eq!(mode(&data), 0o600);
+        assert_eq!(stored_auth_token(&data.data_paths).unwrap(), auth_token);
+    }
+
+    #[test]
+    fn replaces_an_empty_token() {
+        let data = TestData::new();
+        let path = data.data_paths.serve_token();
+        fs::write(&path, "\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let auth_token = stored_auth_token(&data.data_paths).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), auth_token);
+        assert_eq!(mode(&data), 0o600);
+    }
+
+    /// Read the head like from a connection
+    fn read_head(request: &[u8]) -> Result<RequestHead, u16> {
+        let reader = BufReader::new(request.take(MAX_REQUEST_HEAD_BYTES));
+        read_request_head(reader)
+            .unwrap()
+            .map_err(|response| response.status)
+    }
+
+    #[test]
+    fn reads_the_host_and_the_token() {
+        let head = read_head(
+            b"GET /search?q=tokio HTTP/1.1\r\nHost: localhost:7700\r\n\
+              authorization: Bearer  abc \r\nAccept: */*\r\n\r\nbody",
+        )
+        .unwrap();
+        assert_eq!(head.request_line, "GET /search?q=tokio HTTP/1.1\r\n");
+        assert_eq!(head.host.as_deref(), Some("localhost:7700"));
+        assert_eq!(head.bearer_token.as_deref(), Some("abc"));
+
+        // Closed before the end of the headers
+        let head = read_head(b"GET / HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        assert_eq!(head.host.as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn refuses_the_heads_too_long() {
+        let long_target = "a".repeat(MAX_REQUEST_HEAD_BYTES as usize);
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", long_target);
+        assert_eq!(read_head(request.as_bytes()).unwrap_err(), 431);
+
+        let request = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", long_target);
+        assert_eq!(read_head(request.as_bytes()).unwrap_err(), 431);
+
+        let many_headers = "X-Header: value\r\n".repeat(2000);
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", many_headers);
+        assert_eq!(read_head(request.as_bytes()).unwrap_err(), 431);
+    }
+
+    #[test]
+    fn compares_the_tokens() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
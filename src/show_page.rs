@@ -0,0 +1,164 @@
+use crate::extract_text::extract_page_text;
+use crate::find_in_page::{match_lines, PageMatcher};
+use crate::{read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent};
+use anyhow::Context;
+use reqwest::Url;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::IndexRecordOption;
+use tantivy::{Document, Index, Term};
+
+/// Print the snapshot of a page, as it was downloaded. With `find`, print the indexed content with
+/// the words of this query marked instead, allowing up to `typos` typos like the search.
+pub fn show_page(
+    url: String,
+    raw: bool,
+    find: Option<String>,
+    typos: u8,
+    index_name: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    // Indexed URLs don't have fragments, see `extract_firefox_history()`
+    let mut parsed_url = Url::parse(&url)?;
+    parsed_url.set_fragment(None);
+    let url = parsed_url.to_string();
+
+    let index = Index::open_in_dir(data_paths.built_index_dir(index_name)?)?;
+    let schema = index.schema();
+    let url_exact_field = schema.get_field("url_exact")?;
+    let bundle_path_field = schema.get_field("bundle_path")?;
+    let bundle_record_field = schema.get_field("bundle_record")?;
+
+    let searcher = index.reader()?.searcher();
+    let query = TermQuery::new(
+        Term::from_field_text(url_exact_field, &url),
+        IndexRecordOption::Basic,
+    );
+    let (_score, hit_id) = searcher
+        .search(&query, &TopDocs::with_limit(1))?
+        .into_iter()
+        .next()
+        .with_context(|| format!("{} is not in the index", url))?;
+
+    let document = searcher.doc(hit_id)?;
+    if let Some(find) = find {
+        return print_matches(&index, &document, &find, typos);
+    }
+
+    let bundle_path = document
+        .get_first(bundle_path_field)
+        .and_then(|bundle_path| bundle_path.as_text())
+        .context("missing bundle_path")?;
+    // The stored path depends on where the data directory was when indexing, so only its file
+    // name is used
+    let bundle_path = data_paths.raw_pages_dir().join(
+        Path::new(bundle_path)
+            .file_name()
+            .context("invalid bundle_path")?,
+    );
+    let bundle_record = document
+        .get_first(bundle_record_field)
+        .and_then(|bundle_record| bundle_record.as_u64())
+        .context("missing bundle_record")?;
+
+    // The bundle may have been rewritten or removed since the index was built
+    let stale_error = || {
+        format!(
+            "the bundle {} no longer has this page, run index-contents to update the index",
+            bundle_path.display()
+        )
+    };
+    let downloaded_pages: Vec<DownloadedPage> =
+        read_compressed_json(&bundle_path).with_context(stale_error)?;
+    let page = downloaded_pages
+        .into_iter()
+        .nth(bundle_record as usize)
+        .filter(|page| page.url == url)
+        .with_context(stale_error)?;
+
+    match page.content {
+        DownloadedPageContent::Failure(_)
+        | DownloadedPageContent::Pruned
+        | DownloadedPageContent::SkippedEquivalent(_) => {
+            anyhow::bail!(stale_error())
+        }
+        DownloadedPageContent::Html(source)
+        | DownloadedPageContent::PlainText(source)
+        | DownloadedPageContent::Markdown(source)
+            if raw =>
+        {
+            println!("{}", source)
+        }
+        content => {
+            let extracted_text = extract_page_text(&content).with_context(stale_error)?;
+            if let Some(title) = extracted_text.title {
+                println!("{}\n", title);
+            }
+            println!("{}", extracted_text.content);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the indexed content of the page with the words of the query marked, and the lines where
+/// they are first
+fn print_matches(index: &Index, document: &Document, find: &str, typos: u8) -> anyhow::Result<()> {
+    let schema = index.schema();
+    let content = document
+        .get_first(schema.get_field("content")?)
+        .and_then(|content| content.as_text())
+        .context("missing content")?;
+    let title = document
+        .get_first(schema.get_field("title")?)
+        .and_then(|title| title.as_text());
+
+    let matcher = PageMatcher::new(index, find, typos, false)?;
+    if matcher.is_empty() {
+        anyhow::bail!("the query has no words to find");
+    }
+    let matches = matcher.find(content);
+    let lines = match_lines(content, &matches);
+
+    if let Some(title) = title {
+        println!("{}\n", title);
+    }
+    if matches.is_empty() {
+        println!("No match of {:?}", find);
+        return Ok(());
+    }
+    let line_list: Vec<String> = lines.iter().map(ToString::to_string).collect();
+    println!(
+        "{} matches, on the lines {}\n",
+        matches.len(),
+        line_list.join(", ")
+    );
+
+    // The marks would be garbage in a file
+    let (start_mark, end_mark) = if io::stdout().is_terminal() {
+        ("\x1b[1;33m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+    let mut matches = matches.into_iter().peekable();
+    let mut line_start = 0;
+    for (line_index, line) in content.split('\n').enumerate() {
+        let mut marked = String::new();
+        let mut position = 0;
+        while let Some(range) = matches.next_if(|range| range.end <= line_start + line.len()) {
+            let (start, end) = (range.start - line_start, range.end - line_start);
+            marked.push_str(&line[position..start]);
+            marked.push_str(start_mark);
+            marked.push_str(&line[start..end]);
+            marked.push_str(end_mark);
+            position = end;
+        }
+        marked.push_str(&line[position..]);
+        println!("{:>5}  {}", line_index + 1, marked);
+        line_start += line.len() + 1;
+    }
+
+    Ok(())
+}
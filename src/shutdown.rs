@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Turn SIGINT and SIGTERM into a request to stop at the next convenient point, checked with
+/// [shutdown_requested]. A second signal exits at once.
+///
+/// Without it, as in the short commands, the signals end the process right away.
+pub fn handle_shutdown_signals() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Whether the long work in progress should stop, leaving the data in a consistent state
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::Relaxed)
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if SHUTDOWN_REQUESTED.swap(true, Ordering::Relaxed) {
+        // Only async-signal-safe functions can be called here
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
@@ -0,0 +1,156 @@
+/// How many consecutive words form a shingle
+const SHINGLE_WORDS: usize = 3;
+
+/// Contents whose SimHash differ in at most this many bits are considered near-duplicates
+const MAX_NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+/// Compute the 64-bit SimHash of a text over its word shingles.
+///
+/// Similar texts produce hashes that differ in few bits, so the same article with different ads
+/// or date stamps is detected by a small Hamming distance.
+pub fn simhash(content: &str) -> u64 {
+    let words: Vec<String> = content
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+
+    let mut weights = [0i64; 64];
+    let mut add_shingle = |shingle: &[String]| {
+        let hash = shingle_hash(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    };
+    if words.len() < SHINGLE_WORDS {
+        add_shingle(&words);
+    } else {
+        words.windows(SHINGLE_WORDS).for_each(add_shingle);
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, &weight)| weight > 0)
+        .fold(0, |hash, (bit, _)| hash | (1 << bit))
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+pub fn is_near_duplicate(a: u64, b: u64) -> bool {
+    hamming_distance(a, b) <= MAX_NEAR_DUPLICATE_DISTANCE
+}
+
+/// Drop the items that are near-duplicates of an earlier item, keeping the order. Items without a
+/// SimHash are always kept.
+pub fn collapse_near_duplicates<T>(items: Vec<T>, simhash: impl Fn(&T) -> Option<u64>) -> Vec<T> {
+    let mut kept_hashes: Vec<u64> = Vec::new();
+    items
+        .into_iter()
+        .filter(|item| match simhash(item) {
+            None => true,
+            Some(hash) => {
+                let is_duplicate = kept_hashes
+                    .iter()
+                    .any(|&kept_hash| is_near_duplicate(kept_hash, hash));
+                if !is_duplicate {
+                    kept_hashes.push(hash);
+                }
+                !is_duplicate
+            }
+        })
+        .collect()
+}
+
+/// A hash that is stable across Rust versions, unlike the one from the standard library, since
+/// the result is stored in the index.
+///
+/// This is FNV-1a followed by the SplitMix64 finalizer, so that every bit depends on every word.
+fn shingle_hash(words: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for word in words {
+        for byte in word.bytes().chain([b' ']) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = "The borrow checker is the part of the Rust compiler that enforces the \
+        ownership rules. It makes sure that references never outlive the values they point to, \
+        that a value is not used after being moved, and that there is either one mutable \
+        reference or many shared references to a value at any time. Most new Rust programmers \
+        fight it for a few weeks before the rules become second nature.";
+
+    #[test]
+    fn detects_the_same_article_with_small_changes() {
+        let with_comments = format!("{} 42 comments", ARTICLE);
+        assert!(is_near_duplicate(simhash(ARTICLE), simhash(&with_comments)));
+        let updated = |date: &str| format!("{} Updated on {}.", ARTICLE, date);
+        assert!(is_near_duplicate(
+            simhash(&updated("2021-05-12")),
+            simhash(&updated("2022-01-30"))
+        ));
+        assert_eq!(simhash(ARTICLE), simhash(&ARTICLE.to_uppercase()));
+        assert_eq!(
+            simhash(ARTICLE),
+            simhash(&ARTICLE.split_whitespace().collect::<Vec<_>>().join("\n"))
+        );
+    }
+
+    #[test]
+    fn separates_different_articles() {
+        let other = "Tokio is an asynchronous runtime for Rust. It provides the building blocks \
+            needed for writing network applications: a multi-threaded scheduler, an event loop \
+            based on the operating system, timers and asynchronous versions of the standard \
+            library types, all designed to scale to many thousands of connections.";
+        assert!(!is_near_duplicate(simhash(ARTICLE), simhash(other)));
+    }
+
+    #[test]
+    fn is_stable_across_builds() {
+        // The hashes are stored in the index, so they must never change
+        assert_eq!(simhash(""), simhash(""));
+        assert_eq!(simhash("a b"), simhash("A  B"));
+        assert_eq!(shingle_hash(&["rust".to_string()]), 6666197574539175737);
+    }
+
+    #[test]
+    fn measures_the_distance_in_bits() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0010), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+        assert!(is_near_duplicate(0, 0b11_1111));
+        assert!(!is_near_duplicate(0, 0b111_1111));
+    }
+
+    #[test]
+    fn collapses_the_later_near_duplicates() {
+        let items = vec![
+            ("first", Some(0b0000)),
+            ("unhashed", None),
+            ("duplicate of first", Some(0b0011)),
+            ("different", Some(u64::MAX)),
+            ("also unhashed", None),
+            ("duplicate of different", Some(u64::MAX - 1)),
+        ];
+        let kept: Vec<&str> = collapse_near_duplicates(items, |(_, hash)| *hash)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(kept, ["first", "unhashed", "different", "also unhashed"]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::search::{OpenedIndex, SearchField};
+use reqwest::Url;
+use std::collections::HashMap;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::TermQuery;
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::{Searcher, Term};
+
+/// How many of the most distinctive terms of the page are searched for
+const SIMILAR_TERMS: usize = 25;
+/// Shorter terms are mostly noise, like "a" or "1"
+const MIN_TERM_CHARS: usize = 3;
+
+/// An indexed page and the terms that best describe it
+pub struct SimilarPage {
+    pub url: String,
+    /// The field name and text of each term, from the most to the least distinctive
+    pub terms: Vec<(&'static str, String)>,
+}
+
+/// Find the indexed page with this URL and extract its most distinctive terms in the searched
+/// fields, weighting how often they appear in the page by how rare they are in the index
+pub fn similar_page(
+    indexes: &[OpenedIndex],
+    url: &str,
+    search_fields: &[SearchField],
+) -> anyhow::Result<SimilarPage> {
+    // Indexed URLs don't have fragments, see `extract_firefox_history()`
+    let mut parsed_url = Url::parse(url)?;
+    parsed_url.set_fragment(None);
+    let url = parsed_url.to_string();
+
+    for opened_index in indexes {
+        let schema = opened_index.index.schema();
+        let url_exact_field = schema.get_field("url_exact")?;
+        let searcher = opened_index.reader.searcher();
+
+        let query = TermQuery::new(
+            Term::from_field_text(url_exact_field, &url),
+            IndexRecordOption::Basic,
+        );
+        let Some((_score, hit_id)) = searcher
+            .search(&query, &TopDocs::with_limit(1))?
+            .into_iter()
+            .next()
+        else {
+            continue;
+        };
+        let document = searcher.doc(hit_id)?;
+
+        let total_documents = searcher.num_docs() as f64;
+        let mut weighted_terms: Vec<(f64, &'static str, String)> = Vec::new();
+        for search_field in search_fields {
+            let field_name = search_field.field_name();
+            let Some(field) = search_field.index_field(&schema)? else {
+                continue;
+            };
+            let mut tokenizer = opened_index.index.tokenizer_for_field(field)?;
+
+            // Fields that are not stored, like the anchors, have no values here
+            let mut term_frequencies: HashMap<String, u64> = HashMap::new();
+            for text in document.get_all(field).filter_map(|value| value.as_text()) {
+                let mut token_stream = tokenizer.token_stream(text);
+                while token_stream.advance() {
+                    let text = &token_stream.token().text;
+                    if text.chars().count() >= MIN_TERM_CHARS {
+                        *term_frequencies.entry(text.clone()).or_default() += 1;
+                    }
+                }
+            }
+
+            for (text, frequency) in term_frequencies {
+                let document_frequency =
+                    searcher.doc_freq(&Term::from_field_text(field, &text))? as f64;
+                // A term only in this page can't find any other
+                if document_frequency <= 1. {
+                    continue;
+                }
+                let idf = (1f64
+                    + (total_documents - document_frequency + 0.5) / (document_frequency + 0.5))
+                    .ln();
+                weighted_terms.push((frequency as f64 * idf, field_name, text));
+            }
+        }
+
+        weighted_terms.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.2.cmp(&b.2)));
+        let terms = weighted_terms
+            .into_iter()
+            .take(SIMILAR_TERMS)
+            .map(|(_, field_name, text)| (field_name, text))
+            .collect();
+        return Ok(SimilarPage { url, terms });
+    }
+
+    match closest_indexed_url(indexes, &url)? {
+        Some(closest_url) => anyhow::bail!(
+            "{} is not in the index, the closest indexed URL is {}",
+            url,
+            closest_url
+        ),
+        None => anyhow::bail!("{} is not in the index", url),
+    }
+}
+
+/// Find the indexed URL that shares the longest prefix with this one
+fn closest_indexed_url(indexes: &[OpenedIndex], url: &str) -> anyhow::Result<Option<String>> {
+    let mut closest: Option<(usize, String)> = None;
+    for opened_index in indexes {
+        let url_exact_field = opened_index.index.schema().get_field("url_exact")?;
+        let searcher = opened_index.reader.searcher();
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader.inverted_index(url_exact_field)?;
+            let mut stream = inverted_index.terms().stream()?;
+            while stream.advance() {
+                let indexed_url = String::from_utf8_lossy(stream.key());
+                let prefix_length = common_prefix_length(&indexed_url, url);
+                if closest
+                    .as_ref()
+                    .is_none_or(|(closest_length, _)| prefix_length > *closest_length)
+                    // Deleted documents leave their terms behind until the segment is merged
+                    && is_alive(&searcher, url_exact_field, &indexed_url)?
+                {
+                    closest = Some((prefix_length, indexed_url.to_string()));
+                }
+            }
+        }
+    }
+
+    Ok(closest
+        .filter(|(prefix_length, _)| *prefix_length > 0)
+        .map(|(_, closest_url)| closest_url))
+}
+
+fn is_alive(searcher: &Searcher, field: Field, text: &str) -> anyhow::Result<bool> {
+    let query = TermQuery::new(Term::from_field_text(field, text), IndexRecordOption::Basic);
+    Ok(searcher.search(&query, &Count)? > 0)
+}
+
+fn common_prefix_length(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count()
+}
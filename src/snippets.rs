@@ -0,0 +1,151 @@
+use std::ops::Range;
+use tantivy::query::Query;
+use tantivy::schema::Field;
+use tantivy::{Searcher, Snippet, SnippetGenerator};
+
+/// Separates the fragments of a snippet taken from different parts of the content
+const FRAGMENT_SEPARATOR: &str = " … ";
+
+/// The text shown below a result, with the byte ranges of the matches in it. It is empty when the
+/// page has nothing to show.
+#[derive(Default)]
+pub struct HitSnippet {
+    pub text: String,
+    pub highlights: Vec<Range<usize>>,
+}
+
+impl HitSnippet {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn push(&mut self, snippet: &Snippet) {
+        if !self.text.is_empty() {
+            self.text.push_str(FRAGMENT_SEPARATOR);
+        }
+        let offset = self.text.len();
+        self.text.push_str(snippet.fragment());
+        self.highlights.extend(
+            snippet
+                .highlighted()
+                .iter()
+                .map(|range| range.start + offset..range.end + offset),
+        );
+    }
+}
+
+/// Builds the snippets of the results, falling back to the title and then to the start of the
+/// content when the matches are not in the content
+pub struct HitSnippetGenerator {
+    content_generator: SnippetGenerator,
+    title_generator: SnippetGenerator,
+    max_chars: usize,
+    fragments: usize,
+}
+
+impl HitSnippetGenerator {
+    pub fn create(
+        searcher: &Searcher,
+        query: &dyn Query,
+        content_field: Field,
+        title_field: Field,
+        max_chars: usize,
+        fragments: usize,
+    ) -> anyhow::Result<Self> {
+        let mut content_generator = SnippetGenerator::create(searcher, query, content_field)?;
+        content_generator.set_max_num_chars(max_chars);
+        let mut title_generator = SnippetGenerator::create(searcher, query, title_field)?;
+        title_generator.set_max_num_chars(max_chars);
+        Ok(HitSnippetGenerator {
+            content_generator,
+            title_generator,
+            max_chars,
+            fragments,
+        })
+    }
+
+    pub fn snippet(&self, content: &str, title: Option<&str>) -> HitSnippet {
+        let mut snippet = self.content_snippet(content);
+        if snippet.is_empty() {
+            let title_snippet = self.title_generator.snippet(title.unwrap_or_default());
+            if !title_snippet.is_empty() {
+                snippet.push(&title_snippet);
+            }
+        }
+        if snippet.is_empty() {
+            snippet.text = content_start(content, self.max_chars);
+        }
+        snippet
+    }
+
+    /// Up to `fragments` fragments of the content with matches, in the order of the content
+    fn content_snippet(&self, content: &str) -> HitSnippet {
+        // The parts of the content not shown yet, by their offset in the content. Each fragment
+        // is the best one of a part, which is then split around it.
+        let mut parts = vec![(0, content)];
+        let mut fragments = Vec::new();
+        while fragments.len() < self.fragments {
+            let best = parts
+                .iter()
+                .enumerate()
+                .map(|(index, (_, part))| (index, self.content_generator.snippet(part)))
+                .filter(|(_, snippet)| !snippet.is_empty())
+                .max_by_key(|(_, snippet)| snippet.highlighted().len());
+            let Some((index, snippet)) = best else {
+                break;
+            };
+
+            let (offset, part) = parts.remove(index);
+            let start = part.find(snippet.fragment()).unwrap_or_default();
+            let end = start + snippet.fragment().len();
+            parts.push((offset, &part[..start]));
+            parts.push((offset + end, &part[end..]));
+            fragments.push((offset + start, snippet));
+        }
+
+        fragments.sort_by_key(|(offset, _)| *offset);
+        let mut hit_snippet = HitSnippet::default();
+        for (_, snippet) in &fragments {
+            hit_snippet.push(snippet);
+        }
+        hit_snippet
+    }
+}
+
+/// The whole content with all the matches of the query marked, to read the page
+pub fn highlight_whole_text(
+    searcher: &Searcher,
+    query: &dyn Query,
+    content_field: Field,
+    content: &str,
+) -> anyhow::Result<HitSnippet> {
+    let mut generator = SnippetGenerator::create(searcher, query, content_field)?;
+    generator.set_max_num_chars(content.len());
+    let snippet = generator.snippet(content);
+    // The fragment starts at the first match, which is not the start of the content
+    let offset = content.find(snippet.fragment()).unwrap_or_default();
+    Ok(HitSnippet {
+        text: content.to_string(),
+        highlights: snippet
+            .highlighted()
+            .iter()
+            .map(|range| range.start + offset..range.end + offset)
+            .collect(),
+    })
+}
+
+/// The first words of the content, up to about `max_chars` chars
+fn content_start(content: &str, max_chars: usize) -> String {
+    let mut start = String::new();
+    for word in content.split_whitespace() {
+        if !start.is_empty() && start.chars().count() + 1 + word.chars().count() > max_chars {
+            start.push('…');
+            break;
+        }
+        if !start.is_empty() {
+            start.push(' ');
+        }
+        start.push_str(word);
+    }
+    start
+}
@@ -0,0 +1,221 @@
+use crate::normalize_text::normalize_text;
+use crate::query_operators::{is_operator, split_tokens};
+use crate::search::{OpenedIndex, SearchField};
+use std::collections::HashMap;
+
+/// Words of up to this many chars are only corrected by one edit, since two edits would turn them
+/// into almost any other short word
+const MAX_ONE_EDIT_CHARS: usize = 4;
+
+/// Correct the words of the query that are in no document, with the indexed word at most 2 edits
+/// away that is in the most documents. Return `None` when no word could be corrected.
+///
+/// The rest of the query is kept as it is, including its syntax and operators.
+pub fn correct_query(
+    indexes: &[OpenedIndex],
+    query: &str,
+    search_fields: &[SearchField],
+) -> anyhow::Result<Option<String>> {
+    let query = normalize_text(query);
+    let mut corrected_query = String::with_capacity(query.len());
+    let mut is_corrected = false;
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        // Operators like "-site:reddit.com" are kept as they are
+        if start == 0 || query[..start].ends_with(char::is_whitespace) {
+            let tokens = split_tokens(&query[start..]);
+            if let Some(token) = tokens.first().filter(|token| is_operator(token)) {
+                corrected_query.push_str(token);
+                let end = start + token.len();
+                while chars.next_if(|&(index, _)| index < end).is_some() {}
+                continue;
+            }
+        }
+
+        if !c.is_alphanumeric() {
+            corrected_query.push(c);
+            continue;
+        }
+
+        // Words are split like the default tokenizer does
+        let mut end = start + c.len_utf8();
+        while let Some(&(index, c)) = chars.peek() {
+            if !c.is_alphanumeric() {
+                break;
+            }
+            end = index + c.len_utf8();
+            chars.next();
+        }
+        let word = &query[start..end];
+
+        // Field names, like in "title:tokio", and operators are not words to search for
+        let is_syntax = query[end..].starts_with(':') || ["AND", "OR", "NOT"].contains(&word);
+        let correction = if is_syntax {
+            None
+        } else {
+            correct_word(indexes, &word.to_lowercase(), search_fields)?
+        };
+        match correction {
+            Some(correction) => {
+                corrected_query.push_str(&correction);
+                is_corrected = true;
+            }
+            None => corrected_query.push_str(word),
+        }
+    }
+
+    Ok(is_corrected.then_some(corrected_query))
+}
+
+/// Find the best correction of a word, if it is in no document
+fn correct_word(
+    indexes: &[OpenedIndex],
+    word: &str,
+    search_fields: &[SearchField],
+) -> anyhow::Result<Option<String>> {
+    let max_distance = if word.chars().count() <= MAX_ONE_EDIT_CHARS {
+        1
+    } else {
+        2
+    };
+
+    // Each segment has its own term dictionary, so the counts are merged
+    let mut document_counts: HashMap<String, u64> = HashMap::new();
+    let mut word_count = 0;
+    for opened_index in indexes {
+        let schema = opened_index.index.schema();
+        let searcher = opened_index.reader.searcher();
+        for search_field in search_fields {
+            let Some(field) = search_field.index_field(&schema)? else {
+                continue;
+            };
+            for segment_reader in searcher.segment_readers() {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut stream = inverted_index.terms().stream()?;
+                while stream.advance() {
+                    let Ok(term) = std::str::from_utf8(stream.key()) else {
+                        continue;
+                    };
+                    if term == word {
+                        word_count += stream.value().doc_freq as u64;
+                    } else if is_within_distance(word, term, max_distance) {
+                        *document_counts.entry(term.to_string()).or_default() +=
+                            stream.value().doc_freq as u64;
+                    }
+                }
+            }
+        }
+    }
+
+    if word_count > 0 {
+        return Ok(None);
+    }
+    Ok(document_counts
+        .into_iter()
+        .max_by(|(term_a, count_a), (term_b, count_b)| {
+            count_a.cmp(count_b).then_with(|| term_b.cmp(term_a))
+        })
+        .map(|(term, _)| term))
+}
+
+/// Whether the Levenshtein distance between the words is at most `max_distance`
+fn is_within_distance(a: &str, b: &str, max_distance: usize) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max_distance {
+        return false;
+    }
+
+    // The classic dynamic programming, one row at a time
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &a_char) in a.iter().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous_row[j] + usize::from(a_char != b_char);
+            row[j + 1] = substitution.min(previous_row[j + 1] + 1).min(row[j] + 1);
+        }
+        // The distance can only grow from the smallest value of a row
+        if row.iter().all(|&distance| distance > max_distance) {
+            return false;
+        }
+        previous_row = row;
+    }
+    previous_row[b.len()] <= max_distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{visited_page, TestData};
+
+    fn fixture() -> TestData {
+        let data = TestData::new();
+        data.index_pages(
+            vec![
+                visited_page(
+                    "https://example.com/tokio",
+                    "Async",
+                    "<p>The tokio runtime schedules the tasks</p>",
+                ),
+                visited_page(
+                    "https://example.com/cluster",
+                    "Clusters",
+                    "<p>A kubernetes cluster runs the containers of the tasks</p>",
+                ),
+            ],
+            &[],
+        );
+        data
+    }
+
+    #[test]
+    fn corrects_the_words_in_no_document() {
+        // The fuzzy matching would find the misspelled words without any correction
+        let data = fixture();
+
+        let results = data.search("kubernetse", &["--fuzzy=0"]);
+        assert_eq!(results.total_matches, 0);
+        let correction = results.correction.unwrap();
+        assert_eq!(correction.query, "kubernetes");
+        assert!(!correction.applied);
+
+        let results = data.search("kubernetse", &["--fuzzy=0", "--auto-correct"]);
+        assert_eq!(results.total_matches, 1);
+        assert_eq!(results.hits[0].url, "https://example.com/cluster");
+        assert!(results.correction.unwrap().applied);
+    }
+
+    #[test]
+    fn corrects_only_the_words_that_match_nothing() {
+        let data = fixture();
+
+        let results = data.search("+tokio +shedules", &["--fuzzy=0"]);
+        assert_eq!(results.total_matches, 0);
+        assert_eq!(results.correction.unwrap().query, "+tokio +schedules");
+
+        let results = data.search("-site:example.org kubernetse", &["--fuzzy=0"]);
+        assert_eq!(
+            results.correction.unwrap().query,
+            "-site:example.org kubernetes"
+        );
+    }
+
+    #[test]
+    fn does_not_correct_queries_with_results() {
+        let data = fixture();
+
+        let results = data.search("tokio", &["--fuzzy=0"]);
+        assert_eq!(results.total_matches, 1);
+        assert!(results.correction.is_none());
+    }
+
+    #[test]
+    fn bounds_the_edit_distance() {
+        assert!(is_within_distance("tokio", "tokio", 0));
+        assert!(is_within_distance("tokoi", "tokio", 2));
+        assert!(!is_within_distance("tokoi", "tokio", 1));
+        assert!(is_within_distance("rust", "rusts", 1));
+        assert!(!is_within_distance("rust", "trusts", 1));
+        assert!(!is_within_distance("runtime", "run", 2));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::index_stats::format_size;
+use crate::relative_date::relative_date;
+use crate::{
+    read_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent, FirefoxHistoryItem,
+    OutputFormat, DEFAULT_INDEX_NAME,
+};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tantivy::Index;
+
+#[derive(Args, Debug)]
+pub struct StatsArguments {
+    /// The name of the index to report on
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Print the overview for humans or as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+}
+
+/// An overview of all the data, from the extracted history to the index
+#[derive(Serialize)]
+struct Stats {
+    data_dir: PathBuf,
+    /// Missing before the first extraction
+    history: Option<HistoryStats>,
+    bundles: BundleStats,
+    /// The URLs of the history that no bundle has, missing without a history
+    not_downloaded: Option<usize>,
+    /// Missing before the first indexing
+    index: Option<IndexOverview>,
+}
+
+#[derive(Serialize)]
+struct HistoryStats {
+    urls: usize,
+    extracted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Default)]
+struct BundleStats {
+    files: usize,
+    size_bytes: u64,
+    unreadable_files: usize,
+    downloaded_pages: usize,
+    failed_pages: usize,
+    pruned_pages: usize,
+    skipped_equivalent_pages: usize,
+}
+
+#[derive(Serialize)]
+struct IndexOverview {
+    name: String,
+    documents: u64,
+    size_bytes: u64,
+    last_commit: Option<DateTime<Utc>>,
+}
+
+/// Report the state of the history, the downloaded bundles and the index at once
+pub fn stats(arguments: StatsArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let history_path = data_paths.history();
+    let history = if history_path.exists() {
+        Some(read_compressed_json::<Vec<FirefoxHistoryItem>>(
+            &history_path,
+        )?)
+    } else {
+        None
+    };
+
+    // The bundles have no manifest, so they are all read to count their pages
+    let bundles = data_paths.list_raw_pages_bundles()?;
+    let bundle_stats = Mutex::new(BundleStats {
+        files: bundles.len(),
+        ..BundleStats::default()
+    });
+    let downloaded_urls = Mutex::new(HashSet::new());
+    bundles
+        .par_iter()
+        .try_for_each(|bundle| -> anyhow::Result<()> {
+            let size_bytes = fs::metadata(bundle)?.len();
+            let downloaded_pages = read_compressed_json::<Vec<DownloadedPage>>(bundle).ok();
+
+            let mut bundle_stats = bundle_stats.lock().unwrap();
+            bundle_stats.size_bytes += size_bytes;
+            let Some(downloaded_pages) = downloaded_pages else {
+                bundle_stats.unreadable_files += 1;
+                return Ok(());
+            };
+            let mut downloaded_urls = downloaded_urls.lock().unwrap();
+            for page in downloaded_pages {
+                match page.content {
+                    DownloadedPageContent::Failure(_) => bundle_stats.failed_pages += 1,
+                    DownloadedPageContent::Pruned => bundle_stats.pruned_pages += 1,
+                    DownloadedPageContent::SkippedEquivalent(_) => {
+                        bundle_stats.skipped_equivalent_pages += 1
+                    }
+                    _ => bundle_stats.downloaded_pages += 1,
+                }
+                downloaded_urls.insert(page.url);
+            }
+            Ok(())
+        })?;
+    let downloaded_urls = downloaded_urls.into_inner().unwrap();
+
+    let stats = Stats {
+        data_dir: data_paths.data_dir().to_path_buf(),
+        not_downloaded: history.as_ref().map(|history| {
+            history
+                .iter()
+                .filter(|item| !downloaded_urls.contains(&item.url))
+                .count()
+        }),
+        history: history.map(|history| HistoryStats {
+            urls: history.len(),
+            extracted_at: modified_at(&history_path),
+        }),
+        bundles: bundle_stats.into_inner().unwrap(),
+        index: index_overview(&arguments.index_name, data_paths)?,
+    };
+
+    match arguments.format {
+        OutputFormat::Human => print_human(&stats),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&stats)?),
+    }
+    Ok(())
+}
+
+fn index_overview(
+    index_name: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<Option<IndexOverview>> {
+    let index_dir = data_paths.tantivy_index_dir(index_name)?;
+    // Tantivy rewrites its meta file at each commit
+    let meta_path = index_dir.join("meta.json");
+    if !meta_path.exists() {
+        return Ok(None);
+    }
+
+    let index = Index::open_in_dir(&index_dir)?;
+    let mut size_bytes = 0;
+    for maybe_entry in fs::read_dir(&index_dir)? {
+        let metadata = maybe_entry?.metadata()?;
+        if metadata.is_file() {
+            size_bytes += metadata.len();
+        }
+    }
+
+    Ok(Some(IndexOverview {
+        name: index_name.to_string(),
+        documents: index.reader()?.searcher().num_docs(),
+        size_bytes,
+        last_commit: modified_at(&meta_path),
+    }))
+}
+
+fn modified_at(path: &Path) -> Option<DateTime<Utc>> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.into())
+}
+
+fn print_human(stats: &Stats) {
+    let now = Utc::now();
+    let describe_date = |date: Option<DateTime<Utc>>| match date {
+        Some(date) => relative_date(date, now),
+        None => "at an unknown date".to_string(),
+    };
+
+    println!("Data directory: {}", stats.data_dir.display());
+
+    match &stats.history {
+        Some(history) => println!(
+            "History: {} URLs, extracted {}",
+            history.urls,
+            describe_date(history.extracted_at)
+        ),
+        None => println!("History: not extracted yet, see extract-firefox-history"),
+    }
+
+    let bundles = &stats.bundles;
+    println!(
+        "Bundles: {} files, {}",
+        bundles.files,
+        format_size(bundles.size_bytes)
+    );
+    if bundles.unreadable_files > 0 {
+        println!("  Unreadable: {}", bundles.unreadable_files);
+    }
+    println!(
+        "Downloaded pages: {} succeeded, {} failed",
+        bundles.downloaded_pages, bundles.failed_pages
+    );
+    if bundles.pruned_pages > 0 {
+        println!("  Pruned: {}", bundles.pruned_pages);
+    }
+    if bundles.skipped_equivalent_pages > 0 {
+        println!(
+            "  Skipped, with the same content as another URL: {}",
+            bundles.skipped_equivalent_pages
+        );
+    }
+    if let Some(not_downloaded) = stats.not_downloaded {
+        println!("Not downloaded yet: {} URLs", not_downloaded);
+    }
+
+    match &stats.index {
+        Some(index) => println!(
+            "Index {:?}: {} documents, {}, last committed {}",
+            index.name,
+            index.documents,
+            format_size(index.size_bytes),
+            describe_date(index.last_commit)
+        ),
+        None => println!("Index: not built yet, see index-contents"),
+    }
+}
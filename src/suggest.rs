@@ -0,0 +1,84 @@
+use crate::normalize_text::normalize_text;
+use crate::search::SearchField;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use clap::Args;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use tantivy::{Index, Searcher};
+
+#[derive(Args, Debug)]
+pub struct SuggestArguments {
+    /// The query typed so far, whose last word is completed
+    query: String,
+    /// How many suggestions to print
+    #[arg(long, default_value_t = 10)]
+    limit: usize,
+    /// Only suggest words of these fields
+    #[arg(long, value_enum, value_delimiter = ',', default_values = ["content", "title"])]
+    field: Vec<SearchField>,
+    /// The name of the index to take the words from
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+}
+
+/// Print the indexed words that start with the last word of the query, one per line, from the
+/// one in the most documents
+pub fn suggest(arguments: SuggestArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    let index = Index::open_in_dir(data_paths.built_index_dir(&arguments.index_name)?)?;
+    let searcher = index.reader()?.searcher();
+    for word in complete_last_word(
+        &[searcher],
+        &arguments.query,
+        &arguments.field,
+        arguments.limit,
+    )? {
+        println!("{}", word);
+    }
+    Ok(())
+}
+
+/// Find the indexed words that start with the last word of the query, from the one in the most
+/// documents
+pub fn complete_last_word(
+    searchers: &[Searcher],
+    query: &str,
+    fields: &[SearchField],
+    limit: usize,
+) -> anyhow::Result<Vec<String>> {
+    // The indexed text is normalized, and then lowercased by the default tokenizer
+    let query = normalize_text(query).to_lowercase();
+    let prefix = match query.rsplit(char::is_whitespace).next() {
+        Some(prefix) if !prefix.is_empty() => prefix,
+        _ => return Ok(Vec::new()),
+    };
+
+    // Each segment has its own term dictionary, so the counts are merged. Deleted documents are
+    // still counted until their segment is merged
+    let mut document_counts: HashMap<String, u64> = HashMap::new();
+    for searcher in searchers {
+        for search_field in fields {
+            let Some(field) = search_field.index_field(searcher.schema())? else {
+                continue;
+            };
+            for segment_reader in searcher.segment_readers() {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut stream = inverted_index.terms().range().ge(prefix).into_stream()?;
+                while stream.advance() {
+                    if !stream.key().starts_with(prefix.as_bytes()) {
+                        break;
+                    }
+                    let term = String::from_utf8_lossy(stream.key()).to_string();
+                    *document_counts.entry(term).or_default() += stream.value().doc_freq as u64;
+                }
+            }
+        }
+    }
+
+    let mut words: Vec<(String, u64)> = document_counts.into_iter().collect();
+    words.sort_by_key(|(word, count)| (Reverse(*count), word.clone()));
+    Ok(words
+        .into_iter()
+        .take(limit)
+        .map(|(word, _)| word)
+        .collect())
+}
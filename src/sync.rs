@@ -0,0 +1,143 @@
+use crate::download_pages::{download_pages, DownloadPagesArguments};
+use crate::extract_firefox_history::extract_firefox_history;
+use crate::index_contents::{index_contents, IndexContentsArguments};
+use crate::run_metrics::RunMetrics;
+use crate::run_report::StageReport;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use clap::Args;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Args, Debug)]
+pub struct SyncArguments {
+    /// The path to your Firefox profile. You can obtain it in the page "about:profiles" in your
+    /// Firefox
+    #[arg(long)]
+    profile_path: Option<PathBuf>,
+    /// Don't extract the history again, only download and index the pages of the history
+    /// extracted before
+    #[arg(long, conflicts_with = "profile_path")]
+    no_extract: bool,
+    #[command(flatten)]
+    download: DownloadPagesArguments,
+    /// The name of the index to build
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// When a page doesn't declare its publication date, use dates in the URL path like
+    /// "/2021/05/12/"
+    #[arg(long)]
+    infer_date_from_url: bool,
+    /// Remove the lines repeated across many pages of the same site, like headers and footers
+    #[arg(long)]
+    strip_repeated_boilerplate: bool,
+    /// Merge the index segments into one at the end, which makes the first queries faster
+    #[arg(long)]
+    optimize: bool,
+}
+
+/// Run the extraction, the download and the indexing one after the other, stopping at the first
+/// stage that fails. What the stages did is added to the metrics.
+pub fn sync(
+    arguments: SyncArguments,
+    data_paths: &DataPaths,
+    metrics: &mut RunMetrics,
+) -> anyhow::Result<()> {
+    if arguments.profile_path.is_none() && !arguments.no_extract {
+        anyhow::bail!(
+            "give the --profile-path of Firefox, or --no-extract to use the history extracted \
+            before"
+        );
+    }
+
+    let mut reports = Vec::new();
+    let result = run_stages(&arguments, data_paths, &mut reports, metrics);
+    print_reports(&reports);
+
+    let completed: Vec<String> = reports.iter().map(|report| report.name.clone()).collect();
+    for report in reports {
+        metrics.add_stage(report);
+    }
+    result.map_err(|(stage, error)| {
+        let completed = if completed.is_empty() {
+            "no stage completed".to_string()
+        } else {
+            format!("after {} completed", completed.join(" and "))
+        };
+        error.context(format!("the {} stage failed, {}", stage, completed))
+    })
+}
+
+fn run_stages(
+    arguments: &SyncArguments,
+    data_paths: &DataPaths,
+    reports: &mut Vec<StageReport>,
+    metrics: &mut RunMetrics,
+) -> Result<(), (&'static str, anyhow::Error)> {
+    if let Some(profile_path) = &arguments.profile_path {
+        let start = Instant::now();
+        let summary = extract_firefox_history(profile_path.clone(), false, data_paths)
+            .map_err(|error| ("extract", error))?;
+        reports.push(StageReport::extract(&summary, start.elapsed()));
+        metrics.count("new URLs", summary.new_urls);
+    }
+
+    let start = Instant::now();
+    let summary =
+        download_pages(&arguments.download, data_paths).map_err(|error| ("download", error))?;
+    reports.push(StageReport::download(&summary, start.elapsed()));
+    metrics.count("pages fetched", summary.downloaded);
+    metrics.count("failed downloads", summary.failed);
+    metrics.count("requests avoided", summary.skipped_equivalent);
+
+    let start = Instant::now();
+    let mut options = vec![format!("--index-name={}", arguments.index_name)];
+    for (enabled, flag) in [
+        (arguments.infer_date_from_url, "--infer-date-from-url"),
+        (
+            arguments.strip_repeated_boilerplate,
+            "--strip-repeated-boilerplate",
+        ),
+        (arguments.optimize, "--optimize"),
+    ] {
+        if enabled {
+            options.push(flag.to_string());
+        }
+    }
+    // With the data paths, so that the index goes to --indexes-dir when it's given
+    let summary = IndexContentsArguments::parse_options(options.iter().map(String::as_str))
+        .and_then(|index_arguments| index_contents(index_arguments, data_paths))
+        .map_err(|error| ("index", error))?;
+    metrics.processed("documents added", summary.indexed_pages);
+    let mut report = StageReport::index(&summary, start.elapsed());
+    // The index is rebuilt from the same pages when the other stages brought nothing new
+    report.new_data &= reports.iter().any(|report| report.new_data);
+    reports.push(report);
+
+    Ok(())
+}
+
+fn print_reports(reports: &[StageReport]) {
+    for report in reports {
+        println!(
+            "{:<8}  {:>8}  {}",
+            report.name,
+            format_elapsed(Duration::from_secs_f64(report.elapsed_seconds)),
+            report.describe_counts()
+        );
+        for warning in &report.warnings {
+            println!("{:<8}  {:>8}  warning: {}", "", "", warning);
+        }
+    }
+}
+
+/// Like "0.4s" or "2m05s"
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    } else if seconds < 3600 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    }
+}
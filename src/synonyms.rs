@@ -0,0 +1,169 @@
+use crate::query_operators::{is_operator, split_tokens};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// How much more the word of the query counts than its synonyms
+const ORIGINAL_BOOST: &str = "2";
+
+/// Groups of words that mean the same, like "js, javascript, ecmascript"
+pub struct Synonyms {
+    /// The other words of the groups of each word, all lowercase
+    synonyms_by_word: HashMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    /// Read the groups from a file with one group per line, its words separated by commas. Blank
+    /// lines and lines starting with "#" are skipped. Return `None` if the file doesn't exist.
+    pub fn read(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Some(Synonyms::parse(&content)))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut synonyms_by_word: HashMap<String, Vec<String>> = HashMap::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let group: Vec<String> = line
+                .split(',')
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect();
+            // A word can be in several groups, and then it has the synonyms of all of them
+            for word in &group {
+                let synonyms = synonyms_by_word.entry(word.clone()).or_default();
+                for synonym in &group {
+                    if synonym != word && !synonyms.contains(synonym) {
+                        synonyms.push(synonym.clone());
+                    }
+                }
+            }
+        }
+        Synonyms { synonyms_by_word }
+    }
+
+    /// Replace each word of the query that has synonyms by the group of them, like "js" by
+    /// `(js^2 OR javascript OR ecmascript)`.
+    ///
+    /// Only plain words are expanded: phrases, words restricted to a field like "title:js",
+    /// operators like "-js" and words mixed with the query syntax are kept as they are.
+    pub fn expand(&self, query: &str) -> String {
+        let tokens: Vec<String> = split_tokens(query)
+            .into_iter()
+            .map(|token| {
+                let is_plain_word = !is_operator(token)
+                    && token
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "-_.+#".contains(c));
+                let synonyms = is_plain_word
+                    .then(|| self.synonyms_by_word.get(&token.to_lowercase()))
+                    .flatten();
+                match synonyms {
+                    None => token.to_string(),
+                    Some(synonyms) => {
+                        let mut group = format!("({}^{}", token, ORIGINAL_BOOST);
+                        for synonym in synonyms {
+                            if synonym.contains(char::is_whitespace) {
+                                group.push_str(&format!(" OR \"{}\"", synonym));
+                            } else {
+                                group.push_str(&format!(" OR {}", synonym));
+                            }
+                        }
+                        group.push(')');
+                        group
+                    }
+                }
+            })
+            .collect();
+        tokens.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{visited_page, TestData};
+
+    const SYNONYMS: &str = "
+        # Languages
+        js, JavaScript, ecmascript
+
+        k8s, kubernetes
+        kubernetes, container orchestration
+        ,
+    ";
+
+    #[test]
+    fn parses_the_groups() {
+        let synonyms = Synonyms::parse(SYNONYMS);
+        assert_eq!(
+            synonyms.synonyms_by_word["js"],
+            ["javascript", "ecmascript"]
+        );
+        assert_eq!(
+            synonyms.synonyms_by_word["javascript"],
+            ["js", "ecmascript"]
+        );
+        // In two groups
+        assert_eq!(
+            synonyms.synonyms_by_word["kubernetes"],
+            ["k8s", "container orchestration"]
+        );
+        assert!(!synonyms.synonyms_by_word.contains_key("# languages"));
+        assert!(!synonyms.synonyms_by_word.contains_key(""));
+    }
+
+    #[test]
+    fn expands_only_the_plain_words() {
+        let synonyms = Synonyms::parse(SYNONYMS);
+        assert_eq!(
+            synonyms.expand("JS closures"),
+            "(JS^2 OR javascript OR ecmascript) closures"
+        );
+        assert_eq!(
+            synonyms.expand("kubernetes"),
+            r#"(kubernetes^2 OR k8s OR "container orchestration")"#
+        );
+        for query in [r#""js closures""#, "title:js", "-js", "js*", "site:js"] {
+            assert_eq!(synonyms.expand(query), query);
+        }
+    }
+
+    #[test]
+    fn reads_no_synonyms_without_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("synonyms.txt");
+        assert!(Synonyms::read(&path).unwrap().is_none());
+        fs::write(&path, SYNONYMS).unwrap();
+        assert!(Synonyms::read(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn finds_the_pages_with_a_synonym() {
+        let data = TestData::new();
+        fs::write(data.data_paths.synonyms(), SYNONYMS).unwrap();
+        data.index_pages(
+            vec![visited_page(
+                "https://example.com/closures",
+                "Closures",
+                "<p>Closures in JavaScript capture their environment</p>",
+            )],
+            &[],
+        );
+
+        assert_eq!(
+            data.search_urls("js", &[]),
+            ["https://example.com/closures"]
+        );
+        assert!(data.search_urls("js", &["--no-synonyms"]).is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+/// Path segments that say nothing about the page
+const MEANINGLESS_SEGMENTS: &[&str] = &["index", "default", "home"];
+
+/// Build a readable title from the URL, for pages without a title anywhere, like
+/// "docs.rs — Struct Url" for "https://docs.rs/url/latest/url/struct.Url.html".
+///
+/// The last meaningful path segment is used, falling back to the query string values and then to
+/// the domain alone.
+pub fn synthesize_title(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    let host = parsed_url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let path_words = parsed_url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(segment_words)
+        .find(|words| {
+            !words.is_empty() && !MEANINGLESS_SEGMENTS.contains(&words.to_lowercase().as_str())
+        });
+    let words = path_words.or_else(|| {
+        let values: Vec<String> = parsed_url
+            .query_pairs()
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect();
+        Some(values.join(" ")).filter(|words| !words.is_empty())
+    });
+
+    Some(match words {
+        None => host.to_string(),
+        Some(words) => format!("{} — {}", host, title_case(&words)),
+    })
+}
+
+/// Turn a path segment like "struct.Url.html" or "my_first%20post" into words
+fn segment_words(segment: &str) -> String {
+    let segment = percent_decode_str(segment).decode_utf8_lossy();
+    let segment = match segment.rsplit_once('.') {
+        Some((stem, extension))
+            if extension.len() <= 5
+                && extension.starts_with(|c: char| c.is_ascii_alphabetic())
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            stem
+        }
+        _ => &segment,
+    };
+
+    segment
+        .split(['-', '_', '.', '+', ' '])
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn title_case(words: &str) -> String {
+    words
+        .split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                None => String::new(),
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(url: &str) -> Option<String> {
+        synthesize_title(url)
+    }
+
+    #[test]
+    fn uses_the_last_path_segment() {
+        assert_eq!(
+            title("https://docs.rs/url/latest/url/struct.Url.html").as_deref(),
+            Some("docs.rs — Struct Url")
+        );
+        assert_eq!(
+            title("https://www.example.com/blog/my-first_post").as_deref(),
+            Some("example.com — My First Post")
+        );
+        assert_eq!(
+            title("https://example.com/files/annual%20report%202021.pdf").as_deref(),
+            Some("example.com — Annual Report 2021")
+        );
+    }
+
+    #[test]
+    fn skips_the_trailing_slashes_and_index_pages() {
+        assert_eq!(
+            title("https://example.com/guides/getting-started/").as_deref(),
+            Some("example.com — Getting Started")
+        );
+        assert_eq!(
+            title("https://example.com/guides/async/index.html").as_deref(),
+            Some("example.com — Async")
+        );
+        assert_eq!(
+            title("https://example.com/Default.aspx").as_deref(),
+            Some("example.com")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_query_string() {
+        assert_eq!(
+            title("https://example.com/?q=borrow+checker&page=2").as_deref(),
+            Some("example.com — Borrow Checker 2")
+        );
+        assert_eq!(
+            title("https://example.com/index.php?id=").as_deref(),
+            Some("example.com")
+        );
+    }
+
+    #[test]
+    fn keeps_the_domain_alone() {
+        assert_eq!(
+            title("https://www.example.com/").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(title("https://example.com").as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn needs_a_host() {
+        assert_eq!(title("file:///home/user/notes.txt"), None);
+        assert_eq!(title("not a url"), None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Data directories with a few pages, for the tests that need a real index
+
+use crate::index_contents::{index_contents, IndexContentsArguments, IndexSummary};
+use crate::search::{open_indexes, run_search, SearchArguments, SearchQuery};
+use crate::search_output::SearchResults;
+use crate::{
+    write_compressed_json, DataPaths, DownloadedPage, DownloadedPageContent, FirefoxHistoryItem,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::Connection;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// A data directory removed when dropped
+pub struct TestData {
+    _dir: TempDir,
+    pub data_paths: DataPaths,
+}
+
+impl TestData {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let data_paths = DataPaths::new(dir.path().join("data"));
+        data_paths.create_data_dir().unwrap();
+        TestData {
+            _dir: dir,
+            data_paths,
+        }
+    }
+
+    /// Write the extracted history
+    pub fn write_history(&self, items: &[FirefoxHistoryItem]) {
+        write_compressed_json(&self.data_paths.history(), &items).unwrap();
+    }
+
+    /// Write a bundle of downloaded pages, returning its path
+    pub fn write_bundle(&self, name: &str, pages: &[DownloadedPage]) -> PathBuf {
+        let raw_pages_dir = self.data_paths.raw_pages_dir();
+        std::fs::create_dir_all(&raw_pages_dir).unwrap();
+        let path = raw_pages_dir.join(name);
+        write_compressed_json(&path, &pages).unwrap();
+        path
+    }
+
+    /// Write the history and a bundle with the pages, and index them with the options
+    pub fn index_pages(&self, pages: Vec<(FirefoxHistoryItem, DownloadedPage)>, options: &[&str]) {
+        let (items, pages): (Vec<_>, Vec<_>) = pages.into_iter().unzip();
+        self.write_history(&items);
+        self.write_bundle("0-0", &pages);
+        self.index(options);
+    }
+
+    pub fn index(&self, options: &[&str]) -> IndexSummary {
+        let arguments = IndexContentsArguments::parse_options(
+            ["--writer-memory-mb=50", "--indexing-threads=1"]
+                .into_iter()
+                .chain(options.iter().copied()),
+        )
+        .unwrap();
+        index_contents(arguments, &self.data_paths).unwrap()
+    }
+
+    pub fn search(&self, query: &str, options: &[&str]) -> SearchResults {
+        let arguments = SearchArguments::parse_options(options.iter().copied()).unwrap();
+        let indexes = open_indexes(&arguments, &self.data_paths).unwrap();
+        let query = if query.is_empty() {
+            SearchQuery::All
+        } else {
+            SearchQuery::Text(query.to_string())
+        };
+        run_search(&indexes, &query, &arguments, &self.data_paths).unwrap()
+    }
+
+    /// The URLs of the results, from the best one
+    pub fn search_urls(&self, query: &str, options: &[&str]) -> Vec<String> {
+        self.search(query, options)
+            .hits
+            .into_iter()
+            .map(|hit| hit.url)
+            .collect()
+    }
+}
+
+/// A fixed date, so that the tests don't depend on when they run
+pub fn date(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap()
+}
+
+/// A visited page of the history, with its HTML as downloaded
+pub fn visited_page(url: &str, title: &str, html: &str) -> (FirefoxHistoryItem, DownloadedPage) {
+    (
+        history_item(url, title),
+        downloaded_page(url, DownloadedPageContent::Html(html.to_string())),
+    )
+}
+
+pub fn history_item(url: &str, title: &str) -> FirefoxHistoryItem {
+    FirefoxHistoryItem {
+        url: url.to_string(),
+        title: Some(title.to_string()).filter(|title| !title.is_empty()),
+        last_visit: Some(date(2023, 7, 14)),
+        visit_count: Some(1),
+        source: None,
+        bookmarked: false,
+        bookmark_folders: Vec::new(),
+    }
+}
+
+pub fn downloaded_page(url: &str, content: DownloadedPageContent) -> DownloadedPage {
+    DownloadedPage {
+        url: url.to_string(),
+        loaded_at: date(2023, 7, 15),
+        content,
+        final_url: None,
+        canonical_url: None,
+    }
+}
+
+/// A Firefox profile with these places, as their URL and last visit date in microseconds, and no
+/// bookmarks
+pub fn firefox_profile(places: &[(&str, Option<i64>)]) -> TempDir {
+    let profile = tempfile::tempdir().unwrap();
+    let connection = Connection::open(profile.path().join("places.sqlite")).unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE moz_places (
+                id INTEGER PRIMARY KEY, url TEXT, title TEXT, last_visit_date INTEGER,
+                visit_count INTEGER
+            );
+            CREATE TABLE moz_bookmarks (
+                id INTEGER PRIMARY KEY, type INTEGER, fk INTEGER, parent INTEGER, title TEXT,
+                guid TEXT
+            );",
+        )
+        .unwrap();
+    for (url, last_visit_date) in places {
+        connection
+            .execute(
+                "INSERT INTO moz_places (url, title, last_visit_date, visit_count)
+                VALUES (?1, NULL, ?2, 1)",
+                (url, last_visit_date),
+            )
+            .unwrap();
+    }
+    profile
+}
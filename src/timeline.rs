@@ -0,0 +1,159 @@
+use chrono::{Datelike, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tantivy::collector::{Collector, SegmentCollector};
+use tantivy::columnar::Column;
+use tantivy::{DocAddress, DocId, Score, SegmentOrdinal, SegmentReader};
+
+/// How many of the best matches to keep for each month
+pub const HITS_PER_MONTH: usize = 3;
+
+/// A month as its year and its number, from 1
+pub type Month = (i32, u32);
+
+/// The matches last visited in one month
+#[derive(Default)]
+pub struct MonthMatches {
+    pub count: u64,
+    /// The best matches, from the best
+    pub best: Vec<(Score, DocAddress)>,
+}
+
+impl MonthMatches {
+    fn add(&mut self, score: Score, doc_address: DocAddress) {
+        self.count += 1;
+        self.keep_best(score, doc_address);
+    }
+
+    fn merge(&mut self, other: MonthMatches) {
+        self.count += other.count;
+        for (score, doc_address) in other.best {
+            self.keep_best(score, doc_address);
+        }
+    }
+
+    fn keep_best(&mut self, score: Score, doc_address: DocAddress) {
+        let position = self
+            .best
+            .partition_point(|(best_score, _)| *best_score >= score);
+        if position < HITS_PER_MONTH {
+            self.best.insert(position, (score, doc_address));
+            self.best.truncate(HITS_PER_MONTH);
+        }
+    }
+}
+
+/// Counts all the matches by the month of their last visit, keeping the best ones of each month.
+/// Matches without a last visit are not counted.
+pub struct TimelineCollector;
+
+pub struct TimelineSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    last_visits: Column<tantivy::DateTime>,
+    months: BTreeMap<Month, MonthMatches>,
+}
+
+impl Collector for TimelineCollector {
+    type Fruit = BTreeMap<Month, MonthMatches>;
+    type Child = TimelineSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        Ok(TimelineSegmentCollector {
+            segment_ord,
+            last_visits: segment_reader.fast_fields().date("last_visit")?,
+            months: BTreeMap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<Self::Fruit>) -> tantivy::Result<Self::Fruit> {
+        let mut months: BTreeMap<Month, MonthMatches> = BTreeMap::new();
+        for segment_months in segment_fruits {
+            for (month, matches) in segment_months {
+                months.entry(month).or_default().merge(matches);
+            }
+        }
+        Ok(months)
+    }
+}
+
+impl SegmentCollector for TimelineSegmentCollector {
+    type Fruit = BTreeMap<Month, MonthMatches>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let Some(last_visit) = self.last_visits.first(doc) else {
+            return;
+        };
+        let Some(last_visit) = Utc
+            .timestamp_millis_opt(last_visit.into_timestamp_millis())
+            .single()
+        else {
+            return;
+        };
+        self.months
+            .entry((last_visit.year(), last_visit.month()))
+            .or_default()
+            .add(score, DocAddress::new(self.segment_ord, doc));
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        self.months
+    }
+}
+
+/// The matches of one month, ready to display
+#[derive(Serialize, Default)]
+pub struct TimelineMonth {
+    /// Like "2023-07"
+    pub month: String,
+    pub count: u64,
+    pub hits: Vec<TimelineHit>,
+}
+
+#[derive(Serialize)]
+pub struct TimelineHit {
+    pub url: String,
+    pub title: Option<String>,
+    #[serde(skip)]
+    pub score: Score,
+}
+
+impl TimelineMonth {
+    /// Add the matches of the same month in another index
+    pub fn merge(&mut self, other: Self) {
+        self.month = other.month;
+        self.count += other.count;
+        self.hits.extend(other.hits);
+        self.hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        self.hits.truncate(HITS_PER_MONTH);
+    }
+}
+
+/// The months from the first to the last one of the timeline, including the ones without matches
+pub fn fill_months(mut timeline: BTreeMap<Month, TimelineMonth>) -> Vec<TimelineMonth> {
+    let (Some(&first), Some(&last)) = (timeline.keys().next(), timeline.keys().next_back()) else {
+        return Vec::new();
+    };
+
+    let mut months = Vec::new();
+    let mut month = first;
+    while month <= last {
+        months.push(timeline.remove(&month).unwrap_or_else(|| TimelineMonth {
+            month: format!("{}-{:02}", month.0, month.1),
+            ..TimelineMonth::default()
+        }));
+        month = if month.1 == 12 {
+            (month.0 + 1, 1)
+        } else {
+            (month.0, month.1 + 1)
+        };
+    }
+    months
+}
@@ -0,0 +1,719 @@
+use crate::clipboard::set_clipboard_text;
+use crate::data_lock::ServersLock;
+use crate::domain::registrable_domain;
+use crate::find_in_page::PageMatcher;
+use crate::relative_date::relative_date;
+use crate::search::{
+    hit_text, open_indexes, run_search, OpenedIndex, SearchArguments, SearchQuery,
+};
+use crate::search_output::SearchResults;
+use crate::snippets::HitSnippet;
+use crate::{DataPaths, DEFAULT_INDEX_NAME};
+use anyhow::Context;
+use chrono::{Local, Utc};
+use clap::Args;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Paragraph;
+use ratatui::Frame;
+use std::io::{self, IsTerminal};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// How long to wait after a keystroke before searching, so that typing stays fluid
+const DEBOUNCE: Duration = Duration::from_millis(200);
+/// How many results to fetch for each query
+const RESULTS: usize = 50;
+/// Lines of the screen above the results: the query, the status and a separator
+const HEADER_LINES: usize = 3;
+/// How long to wait for a key when no search is planned, before checking again
+const IDLE_POLL: Duration = Duration::from_secs(60);
+/// The style of the matches of the query or of the words to find in the preview
+const MATCH_STYLE: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+
+const HELP: &str = "↑↓ move · Enter open · Tab preview · ↓ then j/k move, f filter, y copy URL, \
+    / find in preview, n next match · Esc quit";
+
+#[derive(Args, Debug)]
+pub struct TuiArguments {
+    /// The query to start with
+    query: Option<String>,
+    /// The name of the index to search in
+    #[arg(long, default_value = DEFAULT_INDEX_NAME)]
+    index_name: String,
+    /// Search in all the indexes and merge their results by score
+    #[arg(long, conflicts_with = "index_name")]
+    all_indexes: bool,
+}
+
+/// Browse the results interactively in the terminal, searching again as the query is typed
+pub fn tui(tui_arguments: TuiArguments, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        anyhow::bail!("the TUI needs a terminal, use the search command otherwise");
+    }
+
+    let mut index_options = vec![format!("--limit={}", RESULTS)];
+    if tui_arguments.all_indexes {
+        index_options.push("--all-indexes".to_string());
+    } else {
+        index_options.push(format!("--index-name={}", tui_arguments.index_name));
+    }
+    let _servers_lock = ServersLock::acquire_shared(data_paths)?;
+    let indexes = open_indexes(
+        &SearchArguments::parse_options(index_options.iter().map(String::as_str))?,
+        data_paths,
+    )?;
+
+    let mut terminal = ratatui::try_init()?;
+    // The terminal is restored when this is dropped, even after an error
+    let _restore_terminal = RestoreTerminal;
+    let mut state = TuiState {
+        indexes: &indexes,
+        index_options,
+        data_paths,
+        query: tui_arguments.query.unwrap_or_default(),
+        results: None,
+        message: None,
+        selected: 0,
+        list_focused: false,
+        preview: None,
+        preview_scroll: 0,
+        preview_match: 0,
+        find: None,
+        finding: false,
+        find_highlights: None,
+        filter: QuickFilter::None,
+    };
+    let mut search_at = Some(Instant::now());
+    loop {
+        terminal.draw(|frame| state.draw(frame))?;
+
+        // The searches wait for the typing to pause
+        let timeout = search_at.map_or(IDLE_POLL, |search_at| {
+            search_at.saturating_duration_since(Instant::now())
+        });
+        if event::poll(timeout)? {
+            // Other events, like a resize, only need the screen to be drawn again
+            let Event::Key(key_event) = event::read()? else {
+                continue;
+            };
+            let Some(key) = Key::of_event(key_event) else {
+                continue;
+            };
+            match state.handle_key(key) {
+                Action::None => {}
+                Action::Search => search_at = Some(Instant::now() + DEBOUNCE),
+                Action::SearchNow => search_at = Some(Instant::now()),
+                Action::Quit => return Ok(()),
+            }
+        } else if search_at.take().is_some() {
+            state.search();
+        }
+    }
+}
+
+/// Restores the terminal as it was before `ratatui::try_init()` when dropped
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    /// Ctrl+U, which clears the query like in a shell
+    ClearLine,
+    Interrupt,
+}
+
+impl Key {
+    /// The key that was pressed, if it's one that the TUI handles
+    fn of_event(event: KeyEvent) -> Option<Key> {
+        // Some terminals also report the releases of the keys
+        if event.kind == KeyEventKind::Release {
+            return None;
+        }
+        let key = match event.code {
+            KeyCode::Char('c' | 'd') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                Key::Interrupt
+            }
+            KeyCode::Char('u') if event.modifiers.contains(KeyModifiers::CONTROL) => Key::ClearLine,
+            _ if event
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                return None
+            }
+            KeyCode::Char(c) => Key::Char(c),
+            KeyCode::Enter => Key::Enter,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Tab => Key::Tab,
+            KeyCode::Esc => Key::Escape,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// Narrow the results in one keystroke
+enum QuickFilter {
+    None,
+    LastWeek,
+    LastMonth,
+    LastYear,
+    Site(String),
+}
+
+impl QuickFilter {
+    fn option(&self) -> Option<String> {
+        match self {
+            QuickFilter::None => None,
+            QuickFilter::LastWeek => Some("--last=1w".to_string()),
+            QuickFilter::LastMonth => Some("--last=1m".to_string()),
+            QuickFilter::LastYear => Some("--last=1y".to_string()),
+            QuickFilter::Site(site) => Some(format!("--site={}", site)),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            QuickFilter::None => "no filter".to_string(),
+            QuickFilter::LastWeek => "last week".to_string(),
+            QuickFilter::LastMonth => "last month".to_string(),
+            QuickFilter::LastYear => "last year".to_string(),
+            QuickFilter::Site(site) => format!("only {}", site),
+        }
+    }
+}
+
+enum Action {
+    None,
+    /// Search once the typing pauses
+    Search,
+    SearchNow,
+    Quit,
+}
+
+struct TuiState<'a> {
+    indexes: &'a [OpenedIndex],
+    /// The options of every search, before the quick filter
+    index_options: Vec<String>,
+    data_paths: &'a DataPaths,
+    query: String,
+    results: Option<SearchResults>,
+    /// An error or a notice, shown in the status line
+    message: Option<String>,
+    selected: usize,
+    /// Whether the keys move in the results instead of editing the query
+    list_focused: bool,
+    /// The text of the selected result, while the preview is shown
+    preview: Option<HitSnippet>,
+    preview_scroll: usize,
+    /// Which match the preview shows, counting the wrapped lines with matches
+    preview_match: usize,
+    /// The words to find in the preview, instead of the ones of the query
+    find: Option<String>,
+    /// Whether the keys edit the words to find
+    finding: bool,
+    /// The matches of the words to find in the preview
+    find_highlights: Option<Vec<Range<usize>>>,
+    filter: QuickFilter,
+}
+
+impl TuiState<'_> {
+    fn arguments(&self) -> anyhow::Result<SearchArguments> {
+        let filter_option = self.filter.option();
+        SearchArguments::parse_options(
+            self.index_options
+                .iter()
+                .map(String::as_str)
+                .chain(filter_option.as_deref()),
+        )
+    }
+
+    fn search(&mut self) {
+        self.selected = 0;
+        self.message = None;
+        self.results = None;
+        if !self.query.trim().is_empty() {
+            let results = self.arguments().and_then(|arguments| {
+                run_search(
+                    self.indexes,
+                    &SearchQuery::Text(self.query.clone()),
+                    &arguments,
+                    self.data_paths,
+                )
+            });
+            match results {
+                Ok(results) => self.results = Some(results),
+                Err(error) => self.message = Some(format!("Error: {}", error)),
+            }
+        }
+        self.update_preview();
+    }
+
+    fn hit_count(&self) -> usize {
+        self.results
+            .as_ref()
+            .map_or(0, |results| results.hits.len())
+    }
+
+    /// Load the text of the selected result, if the preview is shown
+    fn update_preview(&mut self) {
+        if self.preview.is_none() {
+            return;
+        }
+        self.preview = Some(self.selected_text().unwrap_or_else(|error| HitSnippet {
+            text: format!("Failed to load the page: {}", error),
+            highlights: Vec::new(),
+        }));
+        self.update_find();
+    }
+
+    /// Mark the words to find in the preview, and show their first match
+    fn update_find(&mut self) {
+        self.preview_scroll = 0;
+        self.preview_match = 0;
+        let find_highlights = match (&self.find, &self.preview) {
+            (Some(find), Some(preview)) if !find.trim().is_empty() => {
+                Some(self.find_in_preview(find, &preview.text))
+            }
+            _ => None,
+        };
+        self.find_highlights = match find_highlights {
+            None => None,
+            Some(Ok(highlights)) => Some(highlights),
+            Some(Err(error)) => {
+                self.message = Some(format!("Error: {}", error));
+                None
+            }
+        };
+    }
+
+    /// The matches of the words in the text of the selected result, found like the search does
+    fn find_in_preview(&self, find: &str, text: &str) -> anyhow::Result<Vec<Range<usize>>> {
+        let hit = self
+            .results
+            .as_ref()
+            .and_then(|results| results.hits.get(self.selected))
+            .context("no result is selected")?;
+        let opened_index = self
+            .indexes
+            .iter()
+            .find(|opened_index| opened_index.name == hit.index_name)
+            .context("missing index")?;
+        let (typos, prefix) = self.arguments()?.content_typos();
+        Ok(PageMatcher::new(&opened_index.index, find, typos, prefix)?.find(text))
+    }
+
+    /// Edit the words to find in the preview
+    fn handle_find_key(&mut self, key: Key) -> Action {
+        let find = self.find.get_or_insert_with(String::new);
+        match key {
+            Key::Interrupt => return Action::Quit,
+            Key::Enter => self.finding = false,
+            Key::Escape => {
+                self.finding = false;
+                self.find = None;
+            }
+            Key::Char(c) => find.push(c),
+            Key::Backspace => {
+                find.pop();
+            }
+            Key::ClearLine => find.clear(),
+            _ => return Action::None,
+        }
+        self.update_find();
+        Action::None
+    }
+
+    fn selected_text(&self) -> anyhow::Result<HitSnippet> {
+        let Some(hit) = self
+            .results
+            .as_ref()
+            .and_then(|results| results.hits.get(self.selected))
+        else {
+            return Ok(HitSnippet::default());
+        };
+        hit_text(
+            self.indexes,
+            &SearchQuery::Text(self.query.clone()),
+            &self.arguments()?,
+            hit,
+        )
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        let last = self.hit_count().saturating_sub(1);
+        self.selected = if down {
+            (self.selected + 1).min(last)
+        } else {
+            self.selected.saturating_sub(1)
+        };
+        self.update_preview();
+    }
+
+    fn handle_key(&mut self, key: Key) -> Action {
+        if self.finding {
+            return self.handle_find_key(key);
+        }
+        match key {
+            Key::Interrupt => return Action::Quit,
+            Key::Escape if self.find.is_some() => {
+                self.find = None;
+                self.update_find();
+            }
+            Key::Escape if self.list_focused => self.list_focused = false,
+            Key::Escape => return Action::Quit,
+            Key::Up | Key::Down => {
+                self.list_focused = true;
+                self.move_selection(key == Key::Down);
+            }
+            Key::PageUp => self.preview_scroll = self.preview_scroll.saturating_sub(10),
+            Key::PageDown => self.preview_scroll += 10,
+            Key::Tab => {
+                self.preview = match self.preview {
+                    None => Some(HitSnippet::default()),
+                    Some(_) => None,
+                };
+                self.find = None;
+                self.update_preview();
+            }
+            Key::Enter => {
+                let hit = self
+                    .results
+                    .as_ref()
+                    .and_then(|results| results.hits.get(self.selected));
+                // The messages of `open_url()` would be drawn over the screen
+                if let Some(hit) = hit {
+                    self.message = Some(match webbrowser::open(&hit.url) {
+                        Ok(()) => format!("Opened {}", hit.url),
+                        Err(error) => format!("Failed to open {}: {}", hit.url, error),
+                    });
+                }
+            }
+            Key::Char('j') if self.list_focused => self.move_selection(true),
+            Key::Char('k') if self.list_focused => self.move_selection(false),
+            Key::Char('q') if self.list_focused => return Action::Quit,
+            Key::Char('/') if self.list_focused && self.preview.is_some() => {
+                self.finding = true;
+                self.find = Some(String::new());
+                self.update_find();
+            }
+            Key::Char('n') if self.list_focused && self.preview.is_some() => {
+                self.preview_scroll = 0;
+                self.preview_match += 1;
+            }
+            Key::Char('y') if self.list_focused => {
+                let hit = self
+                    .results
+                    .as_ref()
+                    .and_then(|results| results.hits.get(self.selected));
+                // Like for Enter, the messages of `copy_url()` would be drawn over the screen
+                if let Some(hit) = hit {
+                    self.message = Some(match set_clipboard_text(&hit.url) {
+                        Ok(()) => format!("Copied {}", hit.url),
+                        // The URL comes first, to be selected by hand
+                        Err(error) => format!("{} (failed to copy: {})", hit.url, error),
+                    });
+                }
+            }
+            Key::Char('f') if self.list_focused => {
+                self.filter = self.next_filter();
+                return Action::SearchNow;
+            }
+            Key::Char(c) => {
+                self.list_focused = false;
+                self.query.push(c);
+                return Action::Search;
+            }
+            Key::Backspace => {
+                self.list_focused = false;
+                self.query.pop();
+                return Action::Search;
+            }
+            Key::ClearLine => {
+                self.list_focused = false;
+                self.query.clear();
+                return Action::Search;
+            }
+        }
+        Action::None
+    }
+
+    /// The filter after the current one, ending with the site of the selected result
+    fn next_filter(&self) -> QuickFilter {
+        match self.filter {
+            QuickFilter::None => QuickFilter::LastWeek,
+            QuickFilter::LastWeek => QuickFilter::LastMonth,
+            QuickFilter::LastMonth => QuickFilter::LastYear,
+            QuickFilter::LastYear => {
+                let site = self
+                    .results
+                    .as_ref()
+                    .and_then(|results| results.hits.get(self.selected))
+                    .and_then(|hit| registrable_domain(&hit.url));
+                match site {
+                    Some(site) => QuickFilter::Site(site),
+                    None => QuickFilter::None,
+                }
+            }
+            QuickFilter::Site(_) => QuickFilter::None,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let area = frame.area();
+        let (width, height) = (usize::from(area.width), usize::from(area.height));
+        let mut lines: Vec<Line> = Vec::with_capacity(height);
+
+        lines.push(Line::from(vec![
+            Span::styled("Search:", Style::new().add_modifier(Modifier::BOLD)),
+            Span::raw(format!(" {}", self.query)),
+        ]));
+        let status = match (&self.find, &self.message, &self.results) {
+            (Some(find), _, _) => format!(
+                "Find in preview: {} ({} matches)",
+                find,
+                self.find_highlights.as_ref().map_or(0, Vec::len)
+            ),
+            (None, Some(message), _) => message.clone(),
+            (None, None, None) => "Type to search".to_string(),
+            (None, None, Some(results)) if results.hits.is_empty() => match &results.correction {
+                Some(correction) => format!("No results, did you mean: {}?", correction.query),
+                None => "No results".to_string(),
+            },
+            (None, None, Some(results)) => format!("About {} results", results.total_matches),
+        };
+        lines.push(Line::styled(
+            truncate(
+                &format!("{}, {} · {}", status, self.filter.label(), HELP),
+                width,
+            ),
+            Style::new().add_modifier(Modifier::DIM),
+        ));
+        lines.push(Line::raw("─".repeat(width)));
+
+        let available = height.saturating_sub(HEADER_LINES);
+        // The preview takes the bottom half, and then each result takes a single line
+        let (list_height, lines_per_hit) = match self.preview {
+            Some(_) => (available / 2, 1),
+            None => (available, 3),
+        };
+        let visible_hits = (list_height / lines_per_hit).max(1);
+        let first_hit = (self.selected + 1).saturating_sub(visible_hits);
+        let now = Utc::now();
+        let hits = self
+            .results
+            .as_ref()
+            .map_or(&[][..], |results| &results.hits);
+        for (index, hit) in hits.iter().enumerate().skip(first_hit).take(visible_hits) {
+            let marker = if index == self.selected { "▶ " } else { "  " };
+            let title = hit
+                .title
+                .as_deref()
+                .or(hit.synthetic_title.as_deref())
+                .unwrap_or(&hit.url);
+            lines.push(Line::styled(
+                truncate(&format!("{}{}", marker, title), width),
+                Style::new().add_modifier(Modifier::BOLD),
+            ));
+            if lines_per_hit > 1 {
+                let last_visit = match hit.last_visit {
+                    None => "never visited".to_string(),
+                    Some(last_visit) => format!(
+                        "{} ({})",
+                        last_visit.with_timezone(&Local).format("%Y-%m-%d"),
+                        relative_date(last_visit, now)
+                    ),
+                };
+                lines.push(Line::styled(
+                    truncate(&format!("  {} · {}", hit.url, last_visit), width),
+                    Style::new().add_modifier(Modifier::DIM),
+                ));
+                let mut snippet_line = wrap(&hit.snippet, width.saturating_sub(2))
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+                snippet_line.spans.insert(0, Span::raw("  "));
+                lines.push(snippet_line);
+            }
+        }
+
+        if let Some(preview) = &self.preview {
+            lines.resize(HEADER_LINES + list_height, Line::default());
+            lines.push(Line::raw("─".repeat(width)));
+            let preview_lines = match &self.find_highlights {
+                Some(highlights) => wrap(
+                    &HitSnippet {
+                        text: preview.text.clone(),
+                        highlights: highlights.clone(),
+                    },
+                    width,
+                ),
+                None => wrap(preview, width),
+            };
+            // Start at the current match, unless scrolled
+            let match_lines: Vec<usize> = preview_lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.spans.iter().any(|span| span.style == MATCH_STYLE))
+                .map(|(index, _)| index)
+                .collect();
+            let match_line = match_lines
+                .get(self.preview_match % match_lines.len().max(1))
+                .copied()
+                .unwrap_or_default();
+            let first_line = match_line.saturating_sub(2) + self.preview_scroll;
+            lines.extend(preview_lines.into_iter().skip(first_line));
+        }
+        lines.truncate(height);
+        frame.render_widget(Paragraph::new(lines), area);
+
+        // The cursor is only shown while typing the query
+        if !self.list_focused {
+            let column = "Search: ".len() + self.query.chars().count();
+            frame.set_cursor_position((area.x + u16::try_from(column).unwrap_or(u16::MAX), area.y));
+        }
+    }
+}
+
+/// Cut the text to the width, in chars
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Break the text into lines of at most `width` chars, at spaces when possible, with the matches
+/// styled
+fn wrap(snippet: &HitSnippet, width: usize) -> Vec<Line<'static>> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in split_paragraphs(snippet) {
+        let mut start = 0;
+        while start < paragraph.len() {
+            let mut end = (start + width).min(paragraph.len());
+            if end < paragraph.len() {
+                if let Some(space) = paragraph[start..end].iter().rposition(|(c, _)| *c == ' ') {
+                    end = start + space.max(1);
+                }
+            }
+            lines.push(render_chars(&paragraph[start..end]));
+            start = end;
+            while paragraph.get(start).is_some_and(|(c, _)| *c == ' ') {
+                start += 1;
+            }
+        }
+    }
+    lines
+}
+
+/// The lines of the text, as chars flagged by whether they are in a match, with the runs of
+/// whitespace collapsed
+fn split_paragraphs(snippet: &HitSnippet) -> Vec<Vec<(char, bool)>> {
+    let mut paragraphs = vec![Vec::new()];
+    let mut highlights = snippet.highlights.iter().peekable();
+    for (index, c) in snippet.text.char_indices() {
+        while highlights.peek().is_some_and(|range| index >= range.end) {
+            highlights.next();
+        }
+        let highlighted = highlights.peek().is_some_and(|range| index >= range.start);
+        let paragraph = paragraphs.last_mut().expect("there is always a paragraph");
+        if c == '\n' {
+            paragraphs.push(Vec::new());
+        } else if c.is_whitespace() {
+            if paragraph.last().is_some_and(|(last, _)| *last != ' ') {
+                paragraph.push((' ', false));
+            }
+        } else {
+            paragraph.push((c, highlighted));
+        }
+    }
+    paragraphs
+}
+
+fn render_chars(chars: &[(char, bool)]) -> Line<'static> {
+    let mut spans = Vec::new();
+    for run in chars.chunk_by(|(_, a), (_, b)| a == b) {
+        let text: String = run.iter().map(|(c, _)| c).collect();
+        spans.push(if run[0].1 {
+            Span::styled(text, MATCH_STYLE)
+        } else {
+            Span::raw(text)
+        });
+    }
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_the_keys_of_the_tui() {
+        let key = |code, modifiers| Key::of_event(KeyEvent::new(code, modifiers));
+        assert_eq!(key(KeyCode::Up, KeyModifiers::NONE), Some(Key::Up));
+        assert_eq!(
+            key(KeyCode::Char('j'), KeyModifiers::NONE),
+            Some(Key::Char('j'))
+        );
+        assert_eq!(
+            key(KeyCode::Char('J'), KeyModifiers::SHIFT),
+            Some(Key::Char('J'))
+        );
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Some(Key::Interrupt)
+        );
+        assert_eq!(
+            key(KeyCode::Char('u'), KeyModifiers::CONTROL),
+            Some(Key::ClearLine)
+        );
+        assert_eq!(key(KeyCode::Char('a'), KeyModifiers::CONTROL), None);
+        assert_eq!(key(KeyCode::F(1), KeyModifiers::NONE), None);
+
+        let mut release = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(Key::of_event(release), None);
+    }
+
+    #[test]
+    fn wraps_at_the_spaces_and_styles_the_matches() {
+        let snippet = HitSnippet {
+            text: "The tokio   runtime\nschedules tasks".to_string(),
+            highlights: vec![4..9, 20..29],
+        };
+        let lines = wrap(&snippet, 12);
+        let texts: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(texts, ["The tokio", "runtime", "schedules", "tasks"]);
+        let styles: Vec<Style> = lines[0].spans.iter().map(|span| span.style).collect();
+        assert_eq!(styles, [Style::new(), MATCH_STYLE]);
+        assert_eq!(lines[2].spans[0].style, MATCH_STYLE);
+    }
+
+    #[test]
+    fn truncates_to_the_width() {
+        assert_eq!(truncate("tokio", 5), "tokio");
+        assert_eq!(truncate("tokio runtime", 8), "tokio r…");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::data_lock::{DataLock, LockMode};
+use crate::doctor::dir_size;
+use crate::index_stats::format_size;
+use crate::{DataPaths, MissingStep, DEFAULT_WORKSPACE};
+use clap::Subcommand;
+use std::fs;
+
+#[derive(Subcommand, Debug)]
+pub enum WorkspaceCommand {
+    /// List the workspaces, marking the one in use with "*"
+    List,
+    /// Create an empty workspace, to use it with `--workspace NAME`
+    Create { name: String },
+    /// Delete a workspace with all its data, after telling what would be deleted
+    Delete {
+        name: String,
+        /// Delete the data. Without it, only tell what would be deleted
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+/// The paths of the workspace to use, failing with a [MissingStep] if it was not created
+pub fn workspace_paths(data_paths: &DataPaths, name: &str) -> anyhow::Result<DataPaths> {
+    let workspace_paths = data_paths.workspace(name)?;
+    if name != DEFAULT_WORKSPACE && !workspace_paths.data_dir().is_dir() {
+        return Err(MissingStep {
+            missing: format!("the workspace {} doesn't exist", name),
+            command: format!("workspace create {}", name),
+        }
+        .into());
+    }
+    Ok(workspace_paths)
+}
+
+/// Run a workspace subcommand. `current` is the workspace given with `--workspace`.
+pub fn workspace(
+    command: WorkspaceCommand,
+    current: &str,
+    data_paths: &DataPaths,
+) -> anyhow::Result<()> {
+    match command {
+        WorkspaceCommand::List => {
+            let names = workspace_names(data_paths)?;
+            let width = names.iter().map(String::len).max().unwrap_or_default();
+            for name in names {
+                let marker = if name == current { "*" } else { " " };
+                let dir = data_paths.workspace(&name)?.data_dir().to_path_buf();
+                println!("{} {:<width$}  {}", marker, name, dir.display());
+            }
+            Ok(())
+        }
+        WorkspaceCommand::Create { name } => {
+            let workspace_paths = data_paths.workspace(&name)?;
+            if name == DEFAULT_WORKSPACE || workspace_paths.data_dir().exists() {
+                anyhow::bail!("the workspace {} already exists", name);
+            }
+            fs::create_dir_all(workspace_paths.data_dir())?;
+            println!(
+                "Created the workspace {} in {}, use it with --workspace {}",
+                name,
+                workspace_paths.data_dir().display(),
+                name
+            );
+            Ok(())
+        }
+        WorkspaceCommand::Delete { name, yes } => delete_workspace(&name, yes, data_paths),
+    }
+}
+
+/// The default workspace, then the others by name
+fn workspace_names(data_paths: &DataPaths) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if data_paths.workspaces_dir().is_dir() {
+        for maybe_entry in fs::read_dir(data_paths.workspaces_dir())? {
+            let entry = maybe_entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    names.insert(0, DEFAULT_WORKSPACE.to_string());
+    Ok(names)
+}
+
+fn delete_workspace(name: &str, yes: bool, data_paths: &DataPaths) -> anyhow::Result<()> {
+    if name == DEFAULT_WORKSPACE {
+        anyhow::bail!(
+            "the default workspace can't be deleted, since it's the data directory itself"
+        );
+    }
+    let workspace_paths = workspace_paths(data_paths, name)?;
+    let dir = workspace_paths.data_dir();
+
+    if !yes {
+        println!(
+            "Would delete the workspace {}: {} in {}",
+            name,
+            format_size(dir_size(dir)),
+            dir.display()
+        );
+        println!("Run again with --yes to delete it");
+        return Ok(());
+    }
+
+    // Not while another command is using it
+    let _lock = DataLock::acquire(&workspace_paths, LockMode::Exclusive, false)?;
+    fs::remove_dir_all(dir)?;
+    println!("Deleted the workspace {}", name);
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! A data directory with a few pages indexed by the binary itself, for the end-to-end tests
+
+// Each test file uses only some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// The pages of the fixture: their URL, title, visit date in seconds since the epoch and HTML
+pub const PAGES: &[(&str, &str, i64, &str)] = &[
+    (
+        "https://docs.rs/tokio/latest/tokio/runtime/index.html",
+        "tokio::runtime - Rust",
+        1_689_336_000,
+        "<html><body><h1>Module tokio::runtime</h1>\
+            <p>The Tokio runtime schedules the asynchronous tasks on a pool of worker threads.</p>\
+            </body></html>",
+    ),
+    (
+        "https://example.com/blog/borrow-checker",
+        "Fighting the borrow checker",
+        1_689_422_400,
+        "<html><body><h1>Fighting the borrow checker</h1>\
+            <p>The error borrow of moved value happens when a value is used after a move.</p>\
+            </body></html>",
+    ),
+];
+
+/// A data directory with the fixture pages extracted, imported and indexed, removed when dropped
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let fixture = Fixture::empty();
+        fixture.extract_history();
+        fixture.import_pages();
+        fixture.run_ok(&["index-contents", "--indexing-threads=1"]);
+        fixture
+    }
+
+    /// A data directory that no command created yet
+    pub fn empty() -> Self {
+        Fixture {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    /// Extract the visits of the pages from a SQLite history
+    pub fn extract_history(&self) {
+        let db = self.dir.path().join("history.sqlite");
+        let conn = rusqlite::Connection::open(&db).unwrap();
+        conn.execute(
+            "CREATE TABLE visits (url TEXT, title TEXT, atime INTEGER)",
+            [],
+        )
+        .unwrap();
+        for (url, title, visited_at, _) in PAGES {
+            conn.execute(
+                "INSERT INTO visits VALUES (?1, ?2, ?3)",
+                (url, title, visited_at),
+            )
+            .unwrap();
+        }
+        drop(conn);
+        self.run_ok(&[
+            "extract-sqlite-history",
+            "--db",
+            db.to_str().unwrap(),
+            "--query",
+            "SELECT url, title, atime FROM visits",
+        ]);
+    }
+
+    /// Import the pages from a WARC file, instead of downloading them
+    pub fn import_pages(&self) {
+        let warc = self.dir.path().join("pages.warc");
+        fs::write(&warc, warc_records()).unwrap();
+        self.run_ok(&["import-warc", warc.to_str().unwrap()]);
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.dir.path().join("data")
+    }
+
+    /// The binary, run on the fixture data directory and away from the user's configuration
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mind-search"));
+        command
+            .current_dir(self.dir.path())
+            .env("HOME", self.dir.path())
+            .env("XDG_CONFIG_HOME", self.dir.path().join("config"))
+            .env("MIND_SEARCH_DATA_DIR", self.data_dir())
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("MIND_SEARCH_WORKSPACE")
+            .env_remove("MIND_SEARCH_INDEXES_DIR");
+        command
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command().args(args).output().unwrap()
+    }
+
+    /// Run the command, failing the test with its output if it fails
+    pub fn run_ok(&self, args: &[&str]) -> Output {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{:?} failed with {}:\n{}{}",
+            args,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+}
+
+/// A WARC response record for each page
+fn warc_records() -> Vec<u8> {
+    let mut warc = Vec::new();
+    for (url, _, _, html) in PAGES {
+        let block = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            html.len(),
+            html
+        );
+        warc.extend_from_slice(
+            format!(
+                "WARC/1.0\r\nWARC-Type: response\r\nWARC-Target-URI: {}\r\n\
+                    WARC-Date: 2023-07-15T12:00:00Z\r\nContent-Type: application/http; msgtype=response\r\n\
+                    Content-Length: {}\r\n\r\n{}\r\n\r\n",
+                url,
+                block.len(),
+                block
+            )
+            .as_bytes(),
+        );
+    }
+    warc
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::Fixture;
+
+/// The completion script of the shell
+fn script(shell: &str) -> String {
+    let output = Fixture::empty().run_ok(&["completions", shell]);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn completes_the_values_with_bash() {
+    let script = script("bash");
+    // The value of the option is completed in the line after its branch
+    let lines: Vec<&str> = script.lines().collect();
+    for option in ["site", "saved"] {
+        let branches: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i].trim() == format!("--{})", option))
+            .collect();
+        assert!(!branches.is_empty(), "no --{} option", option);
+        for i in branches {
+            assert_eq!(
+                lines[i + 1].trim(),
+                format!(
+                    "COMPREPLY=($(compgen -W \"$(mind-search complete-value {} 2>/dev/null)\" -- \"${{cur}}\"))",
+                    option
+                )
+            );
+        }
+    }
+}
+
+#[test]
+fn completes_the_values_with_zsh() {
+    let script = script("zsh");
+    assert!(script.contains("_mind-search_values() {"));
+    assert!(script.contains("$(mind-search complete-value $1 2>/dev/null)"));
+    for option in ["site", "saved"] {
+        let options: Vec<&str> = script
+            .lines()
+            .filter(|line| line.starts_with(&format!("'--{}=[", option)))
+            .collect();
+        assert!(!options.is_empty(), "no --{} option", option);
+        for line in options {
+            assert!(
+                line.ends_with(&format!(":_mind-search_values {}' \\", option)),
+                "{}",
+                line
+            );
+        }
+    }
+}
+
+#[test]
+fn completes_the_values_with_fish() {
+    let script = script("fish");
+    for option in ["site", "saved"] {
+        let options: Vec<&str> = script
+            .lines()
+            .filter(|line| line.contains(&format!(" -l {} ", option)))
+            .collect();
+        assert!(!options.is_empty(), "no --{} option", option);
+        for line in options {
+            assert!(
+                line.ends_with(&format!(
+                    " -r -f -a \"(mind-search complete-value {})\"",
+                    option
+                )),
+                "{}",
+                line
+            );
+        }
+    }
+}
+
+#[test]
+fn prints_the_values() {
+    let fixture = Fixture::new();
+    let output = fixture.run_ok(&["complete-value", "site"]);
+    let sites = String::from_utf8(output.stdout).unwrap();
+    assert!(!sites.is_empty());
+    let first_site = sites.lines().next().unwrap();
+    let output = fixture.run_ok(&["complete-value", "site", &first_site[..1]]);
+    let completed = String::from_utf8(output.stdout).unwrap();
+    assert!(completed.lines().any(|site| site == first_site));
+    assert!(completed
+        .lines()
+        .all(|site| site.starts_with(&first_site[..1])));
+
+    fixture.run_ok(&["save-search", "rusty", "rust"]);
+    let output = fixture.run_ok(&["complete-value", "saved", "ru"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "rusty\n");
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::Fixture;
+use std::path::Path;
+use std::process::Command;
+
+/// The data directory that the binary uses when run in `dir`, with these variables
+fn where_data(dir: &Path, args: &[&str], vars: &[(&str, &Path)]) -> String {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_mind-search"));
+    command
+        .current_dir(dir)
+        .env("HOME", dir.join("home"))
+        .env("XDG_DATA_HOME", dir.join("xdg"))
+        .env_remove("MIND_SEARCH_DATA_DIR")
+        .env_remove("MIND_SEARCH_WORKSPACE")
+        .envs(vars.iter().copied())
+        .args(args)
+        .arg("where-data");
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn decides_the_data_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name: &str| dir.path().join(name).display().to_string();
+
+    if cfg!(target_os = "linux") {
+        assert_eq!(where_data(dir.path(), &[], &[]), path("xdg/mind-search"));
+    }
+    assert_eq!(
+        where_data(dir.path(), &["--data-dir", "option"], &[]),
+        path("option")
+    );
+    let variable = dir.path().join("variable");
+    assert_eq!(
+        where_data(dir.path(), &[], &[("MIND_SEARCH_DATA_DIR", &variable)]),
+        path("variable")
+    );
+    // The option takes precedence over the variable
+    assert_eq!(
+        where_data(
+            dir.path(),
+            &["--data-dir", "option"],
+            &[("MIND_SEARCH_DATA_DIR", &variable)]
+        ),
+        path("option")
+    );
+
+    // The data directory of the older versions is still used
+    std::fs::create_dir(dir.path().join("data")).unwrap();
+    assert_eq!(where_data(dir.path(), &[], &[]), path("data"));
+}
+
+#[test]
+fn keeps_all_the_data_in_the_data_dir() {
+    let fixture = Fixture::new();
+    let data_dir = fixture.data_dir();
+    for name in ["history", "raw_pages", "indexes"] {
+        assert!(data_dir.join(name).exists(), "{} is missing", name);
+    }
+
+    // The working directory only has the inputs of the fixture
+    let mut names: Vec<String> = std::fs::read_dir(data_dir.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["data", "history.sqlite", "pages.warc"]);
+
+    let output = fixture.run_ok(&["search", "--count", "tokio"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1");
+}
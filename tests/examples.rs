@@ -0,0 +1,62 @@
+mod common;
+
+use common::Fixture;
+
+/// The commands of the examples at the end of the help, without the program name nor what they
+/// are piped into. Only single quotes are supported.
+fn help_examples(fixture: &Fixture, command: &str) -> Vec<Vec<String>> {
+    let help = fixture.run_ok(&[command, "--help"]).stdout;
+    let help = String::from_utf8(help).unwrap();
+    let (_, examples) = help.split_once("Examples:").unwrap();
+    examples
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mind-search "))
+        .map(|line| {
+            let line = line.split(" | ").next().unwrap();
+            line.split('\'')
+                .enumerate()
+                .flat_map(|(index, part)| {
+                    if index % 2 == 1 {
+                        vec![part.to_string()]
+                    } else {
+                        part.split_whitespace().map(str::to_string).collect()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The examples that only work on the data directory run on the fixture. The others, which read
+/// browser profiles or download pages, are only parsed by the unit tests.
+#[test]
+fn runs_the_examples_on_a_fixture() {
+    let fixture = Fixture::new();
+    for command in ["index-contents", "search"] {
+        let examples = help_examples(&fixture, command);
+        assert!(examples.len() >= 3, "{:?}", examples);
+        for example in examples {
+            let args: Vec<&str> = example.iter().map(String::as_str).collect();
+            let output = fixture.run(&args);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // 4 is for nothing new to index, and 1 without an error for --quiet finding nothing
+            assert!(
+                match output.status.code() {
+                    Some(0 | 4) => true,
+                    Some(1) => !stderr.contains("Error"),
+                    _ => false,
+                },
+                "{:?} failed with {}:\n{}",
+                example,
+                output.status,
+                stderr
+            );
+        }
+    }
+
+    let search = fixture.run_ok(&["search", "tokio", "--site", "docs.rs", "--quiet"]);
+    assert_eq!(
+        String::from_utf8(search.stdout).unwrap().trim(),
+        "https://docs.rs/tokio/latest/tokio/runtime/index.html"
+    );
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::Fixture;
+use serde_json::Value;
+
+/// Run the command with a report, returning its exit code and the outcome in the report
+fn run_with_report(fixture: &Fixture, args: &[&str]) -> (Option<i32>, String) {
+    let report = fixture.data_dir().with_file_name("report.json");
+    let output = fixture.run(&[args, &["--report", report.to_str().unwrap()]].concat());
+    let report: Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    (
+        output.status.code(),
+        report["outcome"].as_str().unwrap().to_string(),
+    )
+}
+
+#[test]
+fn tells_the_outcome_to_scripts() {
+    let fixture = Fixture::new();
+
+    assert_eq!(
+        run_with_report(&fixture, &["index-contents", "--indexing-threads=1"]),
+        (Some(0), "success".to_string())
+    );
+    assert_eq!(
+        run_with_report(&fixture, &["index-contents", "--only-new"]),
+        (Some(4), "nothing_to_do".to_string())
+    );
+
+    let bundle = fixture.data_dir().join("raw_pages/unreadable");
+    std::fs::write(bundle, "not a bundle").unwrap();
+    assert_eq!(
+        run_with_report(&fixture, &["index-contents", "--indexing-threads=1"]),
+        (Some(5), "warnings".to_string())
+    );
+}
+
+#[test]
+fn fails_the_searches_that_find_nothing_for_scripts() {
+    let fixture = Fixture::new();
+    for option in ["--count", "--quiet"] {
+        let code = |query| fixture.run(&["search", option, query]).status.code();
+        assert_eq!(code("tokio"), Some(0));
+        assert_eq!(code("zzzqqq"), Some(1));
+    }
+}
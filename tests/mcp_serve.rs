@@ -0,0 +1,113 @@
+mod common;
+
+use common::Fixture;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::Stdio;
+
+/// Send the messages to `mcp-serve` on its standard input, and read its answers until it exits at
+/// the end of the input
+fn exchange(fixture: &Fixture, messages: &[Value]) -> Vec<Value> {
+    let mut child = fixture
+        .command()
+        .arg("mcp-serve")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for message in messages {
+            writeln!(stdin, "{}", message).unwrap();
+        }
+    }
+    let responses = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+    responses
+}
+
+fn call_tool(id: u64, name: &str, arguments: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments },
+    })
+}
+
+/// The text returned by a tool call
+fn tool_text(response: &Value) -> &str {
+    response["result"]["content"][0]["text"].as_str().unwrap()
+}
+
+#[test]
+fn answers_an_assistant_session() {
+    let fixture = Fixture::new();
+    let responses = exchange(
+        &fixture,
+        &[
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2025-06-18",
+                    "capabilities": {},
+                    "clientInfo": { "name": "test", "version": "1.0" },
+                },
+            }),
+            json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+            call_tool(3, "search_history", json!({ "query": "moved value" })),
+            call_tool(
+                4,
+                "get_page_text",
+                json!({ "url": "https://docs.rs/tokio/latest/tokio/runtime/index.html#fragment" }),
+            ),
+        ],
+    );
+
+    // The notification gets no response
+    let ids: Vec<&Value> = responses.iter().map(|response| &response["id"]).collect();
+    assert_eq!(ids, [&json!(1), &json!(2), &json!(3), &json!(4)]);
+
+    assert_eq!(responses[0]["result"]["protocolVersion"], "2025-06-18");
+
+    let tool_names: Vec<&str> = responses[1]["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(tool_names, ["search_history", "get_page_text"]);
+
+    let hits: Vec<Value> = serde_json::from_str(tool_text(&responses[2])).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["url"], "https://example.com/blog/borrow-checker");
+    assert_eq!(hits[0]["title"], "Fighting the borrow checker");
+
+    let page_text = tool_text(&responses[3]);
+    assert!(page_text.contains("schedules the asynchronous tasks"));
+}
+
+#[test]
+fn reports_the_invalid_requests() {
+    let fixture = Fixture::new();
+    let responses = exchange(
+        &fixture,
+        &[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "resources/list" }),
+            call_tool(2, "search_history", json!({})),
+            call_tool(3, "delete_history", json!({})),
+        ],
+    );
+
+    assert_eq!(responses[0]["error"]["code"], -32601);
+    // Errors of the tools are results, shown to the model
+    assert_eq!(responses[1]["result"]["isError"], true);
+    assert!(tool_text(&responses[1]).contains("query"));
+    assert_eq!(responses[2]["error"]["code"], -32602);
+}
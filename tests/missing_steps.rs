@@ -0,0 +1,57 @@
+mod common;
+
+use common::Fixture;
+
+/// Run the command, which must fail for a missing step, and return the command to run first
+fn missing_step(fixture: &Fixture, args: &[&str]) -> String {
+    let output = fixture.run(args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(3), "{:?}: {}", args, stderr);
+    let (_, command) = stderr
+        .split_once("run `mind-search ")
+        .unwrap_or_else(|| panic!("{:?} doesn't tell what to run: {}", args, stderr));
+    command.split_once("` first").unwrap().0.to_string()
+}
+
+#[test]
+fn tells_which_command_to_run_first() {
+    let fixture = Fixture::empty();
+    let extract = "extract-firefox-history --profile-path PROFILE";
+    assert_eq!(missing_step(&fixture, &["download-pages"]), extract);
+    assert_eq!(missing_step(&fixture, &["index-contents"]), extract);
+    assert_eq!(
+        missing_step(&fixture, &["search", "tokio"]),
+        "index-contents"
+    );
+    assert_eq!(
+        missing_step(&fixture, &["search", "--all-indexes", "tokio"]),
+        "index-contents"
+    );
+    assert_eq!(
+        missing_step(&fixture, &["search", "--index-name=work", "tokio"]),
+        "index-contents --index-name work"
+    );
+
+    fixture.extract_history();
+    assert_eq!(
+        missing_step(&fixture, &["index-contents"]),
+        "download-pages"
+    );
+    assert_eq!(
+        missing_step(&fixture, &["search", "tokio"]),
+        "index-contents"
+    );
+
+    fixture.import_pages();
+    fixture.run_ok(&["index-contents", "--indexing-threads=1"]);
+    fixture.run_ok(&["search", "tokio"]);
+}
+
+#[test]
+fn fails_with_another_code_for_other_errors() {
+    let fixture = Fixture::empty();
+    let output = fixture.run(&["search", "--strict-syntax", "tokio"]);
+    assert_eq!(output.status.code(), Some(3));
+    let output = fixture.run(&["search", "--index-name=no/such/name", "tokio"]);
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::Fixture;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Stdio};
+
+/// A server on a free port, killed when dropped
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    fn start(fixture: &Fixture) -> Self {
+        let mut child = fixture
+            .command()
+            .args(["serve", "--port=0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .unwrap()
+            .to_string();
+        Server { child, address }
+    }
+
+    /// Send the request and return the status line of the response
+    fn status(&self, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        // The server may answer before reading everything
+        let _ = stream.write_all(request);
+        // When the server closes the connection without reading everything, the rest of the
+        // response may be lost
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        response.lines().next().unwrap_or_default().to_string()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn answers_the_searches() {
+    let fixture = Fixture::new();
+    let server = Server::start(&fixture);
+    assert_eq!(
+        server.status(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+    assert_eq!(
+        server.status(b"GET /search?q=tokio HTTP/1.1\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+    assert_eq!(
+        server.status(b"POST /search HTTP/1.1\r\n\r\n"),
+        "HTTP/1.1 405 Method Not Allowed"
+    );
+}
+
+#[test]
+fn refuses_the_endless_headers() {
+    let fixture = Fixture::new();
+    let server = Server::start(&fixture);
+    let mut request = b"GET /healthz HTTP/1.1\r\nX-Endless: ".to_vec();
+    request.extend(vec![b'a'; 1024 * 1024]);
+    let status = server.status(&request);
+    assert!(
+        ["", "HTTP/1.1 431 Request Header Fields Too Large"].contains(&status.as_str()),
+        "{}",
+        status
+    );
+
+    // And it still answers the others
+    assert_eq!(
+        server.status(b"GET /healthz HTTP/1.1\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+}